@@ -136,27 +136,26 @@ if [[ "$(uname -s)" != "MINGW"* ]]; then
     done
 fi
 
+if [[ ! -f dist-assets/relay_list_signing_key.pem ]]; then
+    echo "dist-assets/relay_list_signing_key.pem is missing. The daemon rejects all relay lists"
+    echo "without the public key the relay list is signed with."
+    exit 1
+fi
+
 echo "Updating relay list..."
-set +e
-read -d '' JSONRPC_CODE <<-JSONRPC_CODE
-var buff = "";
-process.stdin.on('data', function (chunk) {
-    buff += chunk;
-})
-process.stdin.on('end', function () {
-    var obj = JSON.parse(buff);
-    var output = JSON.stringify(obj.result, null, '    ');
-    process.stdout.write(output);
-})
-JSONRPC_CODE
-set -e
-
-JSONRPC_RESPONSE="$(curl -X POST \
-    --fail \
-     -H "Content-Type: application/json" \
-     -d '{"jsonrpc": "2.0", "id": "0", "method": "relay_list"}' \
-     https://api.mullvad.net/rpc/)"
-echo $JSONRPC_RESPONSE | node -e "$JSONRPC_CODE" >  dist-assets/relays.json
+RELAY_LIST_HEADERS="$(mktemp)"
+# The relay list is stored exactly as it was signed, with its detached signature next to it
+curl --fail \
+    --dump-header "$RELAY_LIST_HEADERS" \
+    --output dist-assets/relays.json \
+    https://api.mullvad.net/app/v1/relays
+grep -i "^X-Relay-List-Signature:" "$RELAY_LIST_HEADERS" | cut -d ' ' -f 2 | tr -d '\r\n' \
+    > dist-assets/relays.sig
+rm "$RELAY_LIST_HEADERS"
+if [[ ! -s dist-assets/relays.sig ]]; then
+    echo "The downloaded relay list has no signature."
+    exit 1
+fi
 
 
 pushd "$SCRIPT_DIR/gui"
@@ -246,19 +246,21 @@ export default class AppRenderer {
       const tunnel = normal.tunnel;
       const location = normal.location;
 
-      if (location === 'any') {
-        payload.location = 'any';
-      } else {
+      // Sets, negations and port ranges can only be configured with the CLI. Such constraints
+      // are shown as 'any' since the GUI can't represent them.
+      if (location !== 'any' && location.only) {
         payload.location = location.only;
+      } else {
+        payload.location = 'any';
       }
 
-      if (tunnel === 'any') {
+      if (tunnel !== 'any' && tunnel.only) {
+        const { port, protocol } = tunnel.only.openvpn;
+        payload.port = port !== 'any' && typeof port.only === 'number' ? port.only : 'any';
+        payload.protocol = protocol !== 'any' && protocol.only ? protocol.only : 'any';
+      } else {
         payload.port = 'any';
         payload.protocol = 'any';
-      } else {
-        const { port, protocol } = tunnel.only.openvpn;
-        payload.port = port === 'any' ? port : port.only;
-        payload.protocol = protocol === 'any' ? protocol : protocol.only;
       }
 
       actions.settings.updateRelay({
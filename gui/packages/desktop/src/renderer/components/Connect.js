@@ -53,6 +53,8 @@ function getBlockReasonMessage(blockReason: BlockReason): string {
       return 'Failed to start tunnel connection';
     case 'no_matching_relay':
       return 'No relay server matches the current settings';
+    case 'account_expired':
+      return 'The account has no time left';
     default:
       return `Unknown error: ${(blockReason.reason: empty)}`;
   }
@@ -47,7 +47,8 @@ export type BlockReason =
         | 'ipv6_unavailable'
         | 'set_security_policy_error'
         | 'start_tunnel_error'
-        | 'no_matching_relay',
+        | 'no_matching_relay'
+        | 'account_expired',
     }
   | { reason: 'auth_failed', details: ?string };
 
@@ -66,9 +67,15 @@ export type RelayLocation =
   | {| city: [string, string] |}
   | {| country: string |};
 
+// Constraints that the GUI can't represent, such as sets and negations. They are only shown as
+// the values they are set to. Like all other keys, the daemon's `one_of` is converted to `oneOf` by
+// `camelCaseObjectKeys` before it reaches these types, and back by `underscoreObjectKeys`.
+type UnsupportedConstraint<T> = { oneOf: Array<T> } | { not: mixed };
+type PortRange = number | { start: number, end: number };
+
 type OpenVpnConstraints = {
-  port: 'any' | { only: number },
-  protocol: 'any' | { only: RelayProtocol },
+  port: 'any' | { only: PortRange } | UnsupportedConstraint<PortRange>,
+  protocol: 'any' | { only: RelayProtocol } | UnsupportedConstraint<RelayProtocol>,
 };
 
 type TunnelConstraints<TOpenVpnConstraints> = {
@@ -80,12 +87,14 @@ type RelaySettingsNormal<TTunnelConstraints> = {
     | 'any'
     | {
         only: RelayLocation,
-      },
+      }
+    | UnsupportedConstraint<RelayLocation>,
   tunnel:
     | 'any'
     | {
         only: TTunnelConstraints,
-      },
+      }
+    | UnsupportedConstraint<TTunnelConstraints>,
 };
 
 // types describing the structure of RelaySettings
@@ -118,15 +127,36 @@ export type RelaySettingsUpdate =
       customTunnelEndpoint: RelaySettingsCustom,
     |};
 
-const constraint = <T>(constraintValue: SchemaNode<T>) => {
+const simpleConstraint = <T>(constraintValue: SchemaNode<T>) => {
   return oneOf(
     string, // any
     object({
       only: constraintValue,
     }),
+    object({
+      one_of: arrayOf(constraintValue),
+    }),
+  );
+};
+
+// The daemon removes double negations, so a negated constraint never contains another negation.
+const constraint = <T>(constraintValue: SchemaNode<T>) => {
+  return oneOf(
+    simpleConstraint(constraintValue),
+    object({
+      not: simpleConstraint(constraintValue),
+    }),
   );
 };
 
+const portRange = oneOf(
+  number,
+  object({
+    start: number,
+    end: number,
+  }),
+);
+
 const RelaySettingsSchema = oneOf(
   object({
     normal: object({
@@ -146,7 +176,7 @@ const RelaySettingsSchema = oneOf(
       tunnel: constraint(
         object({
           openvpn: object({
-            port: constraint(number),
+            port: constraint(portRange),
             protocol: constraint(enumeration('udp', 'tcp')),
           }),
         }),
@@ -187,9 +217,11 @@ export type RelayListCity = {
 export type RelayListHostname = {
   hostname: string,
   ipv4AddrIn: string,
+  ipv6AddrIn: ?string,
   ipv4AddrExit: string,
   includeInCountry: boolean,
   weight: number,
+  userDefined: boolean,
 };
 
 const RelayListSchema = object({
@@ -207,9 +239,11 @@ const RelayListSchema = object({
             object({
               hostname: string,
               ipv4_addr_in: string,
+              ipv6_addr_in: maybe(string),
               ipv4_addr_exit: string,
               include_in_country: boolean,
               weight: number,
+              user_defined: boolean,
             }),
           ),
         }),
@@ -232,6 +266,14 @@ const TunnelOptionsSchema = object({
   }),
 });
 
+export type RemoteManagementSettings = {
+  address: ?string,
+};
+
+const RemoteManagementSettingsSchema = object({
+  address: maybe(string),
+});
+
 const AccountDataSchema = object({
   expiry: string,
 });
@@ -250,6 +292,7 @@ const TunnelStateTransitionSchema = oneOf(
           'set_security_policy_error',
           'start_tunnel_error',
           'no_matching_relay',
+          'account_expired',
         ),
       }),
       object({ reason: enumeration('auth_failed'), details: maybe(string) }),
@@ -313,19 +356,37 @@ export class SubscriptionListener<T> {
 }
 
 export type Settings = {
+  settingsVersion: number,
+  revision: number,
+  activeProfile: ?string,
   accountToken: AccountToken,
   allowLan: boolean,
   autoConnect: boolean,
+  accountExpiryWarnings: Array<number>,
+  accountHistoryLimit: number,
   relaySettings: RelaySettings,
+  relayIpVersion: 'auto' | 'ipv4' | 'ipv6',
+  rotateRelayInterval: ?number,
   tunnelOptions: TunnelOptions,
+  remoteManagement: RemoteManagementSettings,
+  lockedKeys: Array<string>,
 };
 
 const SettingsSchema = object({
+  settings_version: number,
+  revision: number,
+  active_profile: maybe(string),
   account_token: maybe(string),
   allow_lan: boolean,
   auto_connect: boolean,
+  account_expiry_warnings: arrayOf(number),
+  account_history_limit: number,
   relay_settings: RelaySettingsSchema,
+  relay_ip_version: enumeration('auto', 'ipv4', 'ipv6'),
+  rotate_relay_interval: maybe(number),
   tunnel_options: TunnelOptionsSchema,
+  remote_management: RemoteManagementSettingsSchema,
+  locked_keys: arrayOf(string),
 });
 
 export interface DaemonRpcProtocol {
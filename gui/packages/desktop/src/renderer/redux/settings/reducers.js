@@ -25,6 +25,7 @@ export type RelayLocationRelayRedux = {
   ipv4AddrExit: string,
   includeInCountry: boolean,
   weight: number,
+  userDefined: boolean,
 };
 
 export type RelayLocationCityRedux = {
@@ -33,6 +33,7 @@ describe('components/SelectLocation', () => {
                 ipv4AddrExit: '192.168.1.100',
                 includeInCountry: true,
                 weight: 1,
+                userDefined: false,
               },
               {
                 hostname: 'fake2.mullvad.net',
@@ -40,6 +41,7 @@ describe('components/SelectLocation', () => {
                 ipv4AddrExit: '192.168.1.101',
                 includeInCountry: true,
                 weight: 1,
+                userDefined: false,
               },
             ],
           },
@@ -56,6 +58,7 @@ describe('components/SelectLocation', () => {
                 ipv4AddrExit: '192.168.1.101',
                 includeInCountry: true,
                 weight: 1,
+                userDefined: false,
               },
             ],
           },
@@ -22,6 +22,47 @@ impl Command for Account {
                             .help("The Mullvad account token to configure the client with")
                             .required(true),
                     ),
+            ).subcommand(
+                clap::SubCommand::with_name("create")
+                    .about("Create a new account and configure the client with it"),
+            ).subcommand(
+                clap::SubCommand::with_name("redeem")
+                    .about("Redeem a voucher on the current account")
+                    .arg(
+                        clap::Arg::with_name("voucher")
+                            .help("The voucher code to add time from")
+                            .required(true),
+                    ),
+            ).subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List the accounts in the account history, most recently used first"),
+            ).subcommand(
+                clap::SubCommand::with_name("label")
+                    .about("Give an account in the account history a label")
+                    .arg(
+                        clap::Arg::with_name("token")
+                            .help("The Mullvad account token to label")
+                            .required(true),
+                    ).arg(
+                        clap::Arg::with_name("label")
+                            .help("The label to give the account. Removes the label if left out"),
+                    ),
+            ).subcommand(
+                clap::SubCommand::with_name("switch")
+                    .about("Switch to an account in the account history")
+                    .arg(
+                        clap::Arg::with_name("account")
+                            .help("The label or account token of the account to switch to")
+                            .required(true),
+                    ),
+            ).subcommand(
+                clap::SubCommand::with_name("history-limit")
+                    .about("Set how many accounts to remember in the account history")
+                    .arg(
+                        clap::Arg::with_name("limit")
+                            .help("The number of most recently used accounts to remember")
+                            .required(true),
+                    ),
             ).subcommand(
                 clap::SubCommand::with_name("get")
                     .about("Display information about the currently configured account"),
@@ -37,6 +78,22 @@ impl Command for Account {
             self.set(Some(token))
         } else if let Some(_matches) = matches.subcommand_matches("unset") {
             self.set(None)
+        } else if let Some(_matches) = matches.subcommand_matches("create") {
+            self.create()
+        } else if let Some(redeem_matches) = matches.subcommand_matches("redeem") {
+            let voucher = value_t_or_exit!(redeem_matches.value_of("voucher"), String);
+            self.redeem(voucher)
+        } else if let Some(_matches) = matches.subcommand_matches("list") {
+            self.list()
+        } else if let Some(label_matches) = matches.subcommand_matches("label") {
+            let token = value_t_or_exit!(label_matches.value_of("token"), String);
+            let label = label_matches.value_of("label").map(str::to_owned);
+            self.label(token, label)
+        } else if let Some(switch_matches) = matches.subcommand_matches("switch") {
+            self.switch(switch_matches.value_of("account").unwrap())
+        } else if let Some(limit_matches) = matches.subcommand_matches("history-limit") {
+            let limit = value_t_or_exit!(limit_matches.value_of("limit"), usize);
+            self.set_history_limit(limit)
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get()
         } else {
@@ -57,6 +114,85 @@ impl Account {
         Ok(())
     }
 
+    fn create(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let token = rpc.create_account()?;
+        println!("New Mullvad account \"{}\" created and set", token);
+        Ok(())
+    }
+
+    fn redeem(&self, voucher: String) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let submission = rpc.submit_voucher(voucher)?;
+        println!(
+            "Added {} days to the account",
+            submission.time_added / (24 * 60 * 60)
+        );
+        println!("Expires at: {}", submission.new_expiry);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let current_account = rpc.get_settings()?.get_account_token();
+        let accounts = rpc.list_accounts()?;
+        if accounts.is_empty() {
+            println!("No accounts in the account history");
+        }
+        for entry in accounts.iter().rev() {
+            let marker = if Some(&entry.account_token) == current_account.as_ref() {
+                "*"
+            } else {
+                " "
+            };
+            match entry.label {
+                Some(ref label) => println!("{} {} ({})", marker, entry.account_token, label),
+                None => println!("{} {}", marker, entry.account_token),
+            }
+            println!("    First used : {}", entry.first_used);
+            println!("    Last used  : {}", entry.last_used);
+            if let Some(expiry) = entry.last_known_expiry {
+                println!("    Expires at : {}", expiry);
+            }
+        }
+        Ok(())
+    }
+
+    fn label(&self, token: AccountToken, label: Option<String>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_account_label(token.clone(), label.clone())?;
+        match label {
+            Some(label) => println!("Labelled account \"{}\" {}", token, label),
+            None => println!("Removed the label of account \"{}\"", token),
+        }
+        Ok(())
+    }
+
+    /// Switches to the account with the given label, or with the given token if no account has
+    /// that label.
+    fn switch(&self, account: &str) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let accounts = rpc.list_accounts()?;
+        let entry = accounts
+            .iter()
+            .find(|entry| entry.label.as_ref().map(String::as_str) == Some(account))
+            .or_else(|| accounts.iter().find(|entry| entry.account_token == account));
+        match entry {
+            Some(entry) => self.set(Some(entry.account_token.clone())),
+            None => bail!(
+                "No account in the account history is labelled or numbered {}",
+                account
+            ),
+        }
+    }
+
+    fn set_history_limit(&self, limit: usize) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_account_history_limit(limit)?;
+        println!("Remembering at most {} accounts", limit);
+        Ok(())
+    }
+
     fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client()?;
         let settings = rpc.get_settings()?;
@@ -16,12 +16,21 @@ pub use self::connect::Connect;
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
+mod profile;
+pub use self::profile::Profile;
+
 mod relay;
 pub use self::relay::Relay;
 
 mod lan;
 pub use self::lan::Lan;
 
+mod remote_management;
+pub use self::remote_management::RemoteManagement;
+
+mod settings;
+pub use self::settings::Settings;
+
 mod tunnel;
 pub use self::tunnel::Tunnel;
 
@@ -36,8 +45,11 @@ pub fn get_commands() -> HashMap<&'static str, Box<Command>> {
         Box::new(Status),
         Box::new(Connect),
         Box::new(Disconnect),
+        Box::new(Profile),
         Box::new(Relay),
         Box::new(Lan),
+        Box::new(RemoteManagement),
+        Box::new(Settings),
         Box::new(Tunnel),
         Box::new(Version),
     ];
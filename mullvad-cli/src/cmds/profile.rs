@@ -0,0 +1,86 @@
+use clap;
+use {new_rpc_client, Command, Result};
+
+pub struct Profile;
+
+impl Command for Profile {
+    fn name(&self) -> &'static str {
+        "profile"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage named sets of settings that can be switched between")
+            .setting(clap::AppSettings::SubcommandRequired)
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List all profiles. The active profile is marked with a star"),
+            ).subcommand(
+                clap::SubCommand::with_name("save")
+                    .about(
+                        "Save the current settings, except the account token, as a profile. \
+                         Replaces any profile with the same name",
+                    ).arg(clap::Arg::with_name("name").required(true).index(1)),
+            ).subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Remove a profile")
+                    .arg(clap::Arg::with_name("name").required(true).index(1)),
+            ).subcommand(
+                clap::SubCommand::with_name("switch")
+                    .about("Replace the current settings with the ones in a profile")
+                    .arg(clap::Arg::with_name("name").required(true).index(1)),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(_) = matches.subcommand_matches("list") {
+            self.list()
+        } else if let Some(save_matches) = matches.subcommand_matches("save") {
+            self.save(value_t_or_exit!(save_matches.value_of("name"), String))
+        } else if let Some(remove_matches) = matches.subcommand_matches("remove") {
+            self.remove(value_t_or_exit!(remove_matches.value_of("name"), String))
+        } else if let Some(switch_matches) = matches.subcommand_matches("switch") {
+            self.switch(value_t_or_exit!(switch_matches.value_of("name"), String))
+        } else {
+            unreachable!("No profile command given");
+        }
+    }
+}
+
+impl Profile {
+    fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let settings = rpc.get_settings()?;
+        let active_profile = settings.get_active_profile();
+        for name in rpc.get_profiles()? {
+            let marker = if Some(name.as_str()) == active_profile {
+                "*"
+            } else {
+                " "
+            };
+            println!("{} {}", marker, name);
+        }
+        Ok(())
+    }
+
+    fn save(&self, name: String) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.save_profile(name.clone())?;
+        println!("Saved the current settings as profile {}", name);
+        Ok(())
+    }
+
+    fn remove(&self, name: String) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.remove_profile(name.clone())?;
+        println!("Removed profile {}", name);
+        Ok(())
+    }
+
+    fn switch(&self, name: String) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.switch_profile(name.clone())?;
+        println!("Switched to profile {}", name);
+        Ok(())
+    }
+}
@@ -1,16 +1,24 @@
 use clap;
+use serde_json;
+use std::fmt;
+use std::fs::File;
+use std::io;
 use std::str::FromStr;
-use {new_rpc_client, Command, Result, ResultExt};
+use {new_rpc_client, Command, Error, Result, ResultExt};
 
 use mullvad_types::relay_constraints::{
-    Constraint, LocationConstraint, OpenVpnConstraints, RelayConstraintsUpdate,
-    RelaySettingsUpdate, TunnelConstraints,
+    Constraint, IpVersionPreference, LocationConstraint, OpenVpnConstraints, PortRange,
+    RelayConstraintsUpdate, RelaySettings, RelaySettingsUpdate, TunnelConstraints,
 };
+use mullvad_types::relay_list::RelayList;
 use mullvad_types::CustomTunnelEndpoint;
 use talpid_types::net::{
     OpenVpnEndpointData, TransportProtocol, TunnelEndpointData, WireguardEndpointData,
 };
 
+/// Prefix that negates a constraint given on the command line.
+const NEGATION_PREFIX: &str = "not:";
+
 pub struct Relay;
 
 impl Command for Relay {
@@ -60,7 +68,10 @@ impl Command for Relay {
                             ).arg(
                                 clap::Arg::with_name("country")
                                     .help(
-                                        "The two letter country code, or 'any' for no preference.",
+                                        "The two letter country code, or 'any' for no preference. \
+                                         Several countries can be given separated by commas, \
+                                         and prefixing with 'not:' excludes the countries. \
+                                         Such as 'se,no' or 'not:us'.",
                                     ).required(true)
                                     .index(1)
                                     .validator(country_code_validator),
@@ -77,17 +88,73 @@ impl Command for Relay {
                     ).subcommand(
                         clap::SubCommand::with_name("tunnel")
                             .about("Set tunnel constraints")
-                            .arg(clap::Arg::with_name("port").required(true).index(1))
+                            .arg(
+                                clap::Arg::with_name("port")
+                                    .help(
+                                        "A port, a range of ports or 'any'. Several can be \
+                                         given separated by commas, and prefixing with 'not:' \
+                                         excludes them. Such as '1194-1197,53' or 'not:443'.",
+                                    ).required(true)
+                                    .index(1),
+                            )
                             .arg(
                                 clap::Arg::with_name("protocol")
                                     .required(true)
                                     .index(2)
                                     .possible_values(&["any", "udp", "tcp"]),
                             ),
+                    ).subcommand(
+                        clap::SubCommand::with_name("ip-version")
+                            .about("Set which IP version to use when connecting to relays")
+                            .arg(
+                                clap::Arg::with_name("version")
+                                    .required(true)
+                                    .index(1)
+                                    .possible_values(&["auto", "ipv4", "ipv6"]),
+                            ),
+                    ).subcommand(
+                        clap::SubCommand::with_name("rotation-interval")
+                            .about("Set how often to switch to another relay while connected")
+                            .arg(
+                                clap::Arg::with_name("interval")
+                                    .help("The interval in minutes, or 'off' to never rotate")
+                                    .required(true)
+                                    .index(1),
+                            ),
                     ),
             ).subcommand(clap::SubCommand::with_name("get"))
             .subcommand(
-                clap::SubCommand::with_name("list").about("List available countries and cities"),
+                clap::SubCommand::with_name("update")
+                    .about("Download the newest list of relays from the Mullvad API"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("user")
+                    .about("Manage relays added by the user, such as self-hosted servers")
+                    .setting(clap::AppSettings::SubcommandRequired)
+                    .subcommand(clap::SubCommand::with_name("list").about("List the user relays"))
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about(
+                                "Replace the user relays with the ones in a JSON file. The file \
+                                 has the same format as the relay list downloaded from the API.",
+                            ).arg(clap::Arg::with_name("file").required(true).index(1)),
+                    ).subcommand(
+                        clap::SubCommand::with_name("clear").about("Remove all user relays"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List available countries and cities")
+                    .arg(
+                        clap::Arg::with_name("constraints")
+                            .long("constraints")
+                            .help("List the relays matching the current relay constraints"),
+                    ).arg(
+                        clap::Arg::with_name("explain")
+                            .long("explain")
+                            .requires("constraints")
+                            .help("Also list the relays that were filtered out, and why"),
+                    ),
             )
     }
 
@@ -98,6 +165,10 @@ impl Command for Relay {
             self.get()
         } else if let Some(list_matches) = matches.subcommand_matches("list") {
             self.list(list_matches)
+        } else if let Some(_) = matches.subcommand_matches("update") {
+            self.update()
+        } else if let Some(user_matches) = matches.subcommand_matches("user") {
+            self.user(user_matches)
         } else {
             unreachable!("No relay command given");
         }
@@ -119,6 +190,10 @@ impl Relay {
             self.set_location(location_matches)
         } else if let Some(tunnel_matches) = matches.subcommand_matches("tunnel") {
             self.set_tunnel(tunnel_matches)
+        } else if let Some(ip_version_matches) = matches.subcommand_matches("ip-version") {
+            self.set_ip_version(ip_version_matches)
+        } else if let Some(interval_matches) = matches.subcommand_matches("rotation-interval") {
+            self.set_rotation_interval(interval_matches)
         } else {
             unreachable!("No set relay command given");
         }
@@ -151,8 +226,14 @@ impl Relay {
                 "City can't be given when selecting 'any' country",
                 clap::ErrorKind::InvalidValue,
             ).exit(),
-            (country, None, None) => {
-                Constraint::Only(LocationConstraint::Country(country.to_owned()))
+            (country, None, None) => parse_constraint(country, |country| {
+                Ok(LocationConstraint::Country(country.to_owned()))
+            })?,
+            (country, Some(_), _) if is_constraint_expression(country) => {
+                clap::Error::with_description(
+                    "A city can only be given together with a single country",
+                    clap::ErrorKind::InvalidValue,
+                ).exit()
             }
             (country, Some(city), None) => Constraint::Only(LocationConstraint::City(
                 country.to_owned(),
@@ -189,15 +270,50 @@ impl Relay {
         }))
     }
 
+    fn set_ip_version(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let ip_version = match matches.value_of("version").unwrap() {
+            "auto" => IpVersionPreference::Auto,
+            "ipv4" => IpVersionPreference::Ipv4,
+            "ipv6" => IpVersionPreference::Ipv6,
+            _ => unreachable!("Invalid IP version"),
+        };
+        let mut rpc = new_rpc_client()?;
+        rpc.set_relay_ip_version(ip_version)?;
+        println!("Relay IP version set to {}", ip_version);
+        Ok(())
+    }
+
+    fn set_rotation_interval(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let interval = match matches.value_of("interval").unwrap() {
+            "off" => None,
+            minutes => Some(u64::from_str(minutes).chain_err(|| "Invalid interval")? * 60),
+        };
+        let mut rpc = new_rpc_client()?;
+        rpc.set_rotate_relay_interval(interval)?;
+        match interval {
+            Some(interval) => println!("Rotating relays every {} minutes", interval / 60),
+            None => println!("Relay rotation turned off"),
+        }
+        Ok(())
+    }
+
     fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client()?;
-        let constraints = rpc.get_settings()?.get_relay_settings();
-        println!("Current constraints: {:#?}", constraints);
+        let settings = rpc.get_settings()?;
+        println!("Current constraints: {:#?}", settings.get_relay_settings());
+        println!("Relay IP version: {}", settings.get_relay_ip_version());
+        match settings.get_rotate_relay_interval() {
+            Some(interval) => println!("Relay rotation interval: {} minutes", interval / 60),
+            None => println!("Relay rotation interval: off"),
+        }
 
         Ok(())
     }
 
-    fn list(&self, _matches: &clap::ArgMatches) -> Result<()> {
+    fn list(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if matches.is_present("constraints") {
+            return self.list_matching(matches.is_present("explain"));
+        }
         let mut rpc = new_rpc_client()?;
         let mut locations = rpc.get_relay_locations()?;
         locations.countries.sort_by(|c1, c2| c1.name.cmp(&c2.name));
@@ -214,16 +330,139 @@ impl Relay {
         }
         Ok(())
     }
+
+    fn update(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.update_relay_list()?;
+        println!("Updating relay list in the background");
+        Ok(())
+    }
+
+    fn user(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(_) = matches.subcommand_matches("list") {
+            self.list_user_relays()
+        } else if let Some(set_matches) = matches.subcommand_matches("set") {
+            let file = File::open(set_matches.value_of("file").unwrap())?;
+            let user_relays = serde_json::from_reader(io::BufReader::new(file))
+                .chain_err(|| "Invalid user relays file")?;
+            self.set_user_relays(user_relays)
+        } else if let Some(_) = matches.subcommand_matches("clear") {
+            self.set_user_relays(RelayList::empty())
+        } else {
+            unreachable!("No user relay command given");
+        }
+    }
+
+    fn list_user_relays(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let user_relays = rpc.get_user_relays()?;
+        for country in &user_relays.countries {
+            println!("{} ({})", country.name, country.code);
+            for city in &country.cities {
+                println!("\t{} ({})", city.name, city.code);
+                for relay in &city.relays {
+                    println!("\t\t{} ({})", relay.hostname, relay.ipv4_addr_in);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_user_relays(&self, user_relays: RelayList) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_user_relays(user_relays)?;
+        println!("User relays updated");
+        Ok(())
+    }
+
+    fn list_matching(&self, explain: bool) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let constraints = match rpc.get_settings()?.get_relay_settings() {
+            RelaySettings::Normal(constraints) => constraints,
+            RelaySettings::CustomTunnelEndpoint(endpoint) => {
+                println!(
+                    "A custom tunnel endpoint is used, no relays are selected: {:?}",
+                    endpoint
+                );
+                return Ok(());
+            }
+        };
+        let mut matching_relays = rpc.get_matching_relays(constraints, explain)?;
+        matching_relays
+            .relays
+            .sort_by(|r1, r2| r1.hostname.cmp(&r2.hostname));
+
+        println!("{} matching relays", matching_relays.relays.len());
+        for relay in &matching_relays.relays {
+            println!(
+                "\t{} ({}) weight {}{}",
+                relay.hostname,
+                relay.ipv4_addr_in,
+                relay.weight,
+                if relay.user_defined {
+                    " (user defined)"
+                } else {
+                    ""
+                }
+            );
+            for endpoint in &relay.tunnels.openvpn {
+                println!("\t\tOpenVPN {} {}", endpoint.port, endpoint.protocol);
+            }
+            for endpoint in &relay.tunnels.wireguard {
+                println!("\t\tWireguard {}", endpoint.port);
+            }
+        }
+
+        if explain {
+            matching_relays
+                .rejected
+                .sort_by(|r1, r2| r1.hostname.cmp(&r2.hostname));
+            println!();
+            println!("{} rejected relays", matching_relays.rejected.len());
+            for rejected in &matching_relays.rejected {
+                println!("\t{}: {}", rejected.hostname, rejected.reason);
+            }
+        }
+        Ok(())
+    }
 }
 
 
-fn parse_port_constraint(raw_port: &str) -> Result<Constraint<u16>> {
-    match raw_port.to_lowercase().as_str() {
-        "any" => Ok(Constraint::Any),
-        port => Ok(Constraint::Only(
-            u16::from_str(port).chain_err(|| "Invalid port")?,
-        )),
+fn parse_port_constraint(raw_port: &str) -> Result<Constraint<PortRange>> {
+    parse_constraint(raw_port, |port| {
+        PortRange::from_str(port).map_err(Error::from)
+    })
+}
+
+/// Parses a constraint expression. Values are parsed with `parse_value`. The expression can be
+/// 'any', a single value, several values separated by commas, or any of those prefixed with
+/// 'not:' to negate it.
+fn parse_constraint<T, F>(raw_constraint: &str, parse_value: F) -> Result<Constraint<T>>
+where
+    T: fmt::Debug + Clone + Eq + PartialEq,
+    F: Fn(&str) -> Result<T>,
+{
+    let raw_constraint = raw_constraint.trim();
+    if raw_constraint.to_lowercase().starts_with(NEGATION_PREFIX) {
+        let negated_constraint =
+            parse_constraint(&raw_constraint[NEGATION_PREFIX.len()..], parse_value)?;
+        if negated_constraint.is_any() {
+            bail!("'any' can't be negated");
+        }
+        return Ok(Constraint::Not(Box::new(negated_constraint)).normalize());
     }
+    if raw_constraint.to_lowercase() == "any" {
+        return Ok(Constraint::Any);
+    }
+    let values = raw_constraint
+        .split(',')
+        .map(|value| parse_value(value.trim()))
+        .collect::<Result<Vec<T>>>()?;
+    Ok(Constraint::OneOf(values).normalize())
+}
+
+fn is_constraint_expression(raw_constraint: &str) -> bool {
+    raw_constraint.contains(',') || raw_constraint.to_lowercase().starts_with(NEGATION_PREFIX)
 }
 
 /// Parses a protocol constraint string. Can be infallible because the possible values are limited
@@ -238,7 +477,13 @@ fn parse_protocol_constraint(raw_protocol: &str) -> Constraint<TransportProtocol
 }
 
 fn country_code_validator(code: String) -> ::std::result::Result<(), String> {
-    if code.len() == 2 || code == "any" {
+    let code = code.to_lowercase();
+    let codes = if code.starts_with(NEGATION_PREFIX) {
+        &code[NEGATION_PREFIX.len()..]
+    } else {
+        &code[..]
+    };
+    if codes == "any" || codes.split(',').all(|code| code.trim().len() == 2) {
         Ok(())
     } else {
         Err(String::from("Country codes must be two letters, or 'any'."))
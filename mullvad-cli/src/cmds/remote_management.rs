@@ -0,0 +1,99 @@
+use clap;
+use std::net::SocketAddr;
+use {new_rpc_client, Command, Result};
+
+use mullvad_types::settings::RemoteManagementSettings;
+
+pub struct RemoteManagement;
+
+impl Command for RemoteManagement {
+    fn name(&self) -> &'static str {
+        "remote-management"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Control the management of the daemon over WebSocket from other machines")
+            .setting(clap::AppSettings::SubcommandRequired)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Listen for remote clients on the given address, or stop listening")
+                    .arg(
+                        clap::Arg::with_name("address")
+                            .help("The address and port to listen on, or \"off\"")
+                            .required(true)
+                            .validator(address_validator),
+                    ),
+            ).subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current remote management setting"),
+            ).subcommand(
+                clap::SubCommand::with_name("token")
+                    .about("Display the bearer token remote clients authenticate with")
+                    .arg(
+                        clap::Arg::with_name("reset")
+                            .long("reset")
+                            .help("Replace the token, disconnecting all remote clients"),
+                    ),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let address = match set_matches.value_of("address") {
+                Some("off") => None,
+                _ => Some(value_t_or_exit!(
+                    set_matches.value_of("address"),
+                    SocketAddr
+                )),
+            };
+            self.set(RemoteManagementSettings { address })
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get()
+        } else if let Some(token_matches) = matches.subcommand_matches("token") {
+            self.token(token_matches.is_present("reset"))
+        } else {
+            unreachable!("No remote-management command given");
+        }
+    }
+}
+
+impl RemoteManagement {
+    fn set(&self, remote_management: RemoteManagementSettings) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_remote_management(remote_management)?;
+        println!("Changed remote management setting");
+        Ok(())
+    }
+
+    fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let remote_management = rpc.get_settings()?.get_remote_management();
+        match remote_management.address {
+            Some(address) => println!("Remote management: listening on {}", address),
+            None => println!("Remote management: off"),
+        }
+        Ok(())
+    }
+
+    fn token(&self, reset: bool) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let token = if reset {
+            rpc.reset_remote_management_token()?
+        } else {
+            rpc.get_remote_management_token()?
+        };
+        println!("{}", token);
+        Ok(())
+    }
+}
+
+fn address_validator(address: String) -> ::std::result::Result<(), String> {
+    if address == "off" || address.parse::<SocketAddr>().is_ok() {
+        Ok(())
+    } else {
+        Err(String::from(
+            "Not a valid address and port, such as 0.0.0.0:5051",
+        ))
+    }
+}
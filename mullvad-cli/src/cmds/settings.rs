@@ -0,0 +1,110 @@
+use clap;
+use serde_json;
+use std::fs::File;
+use std::io::{self, Write};
+use {new_rpc_client, Command, Result, ResultExt};
+
+pub struct Settings;
+
+impl Command for Settings {
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Export, import or update several settings at once")
+            .setting(clap::AppSettings::SubcommandRequired)
+            .subcommand(
+                clap::SubCommand::with_name("export")
+                    .about("Write all settings to a JSON file")
+                    .arg(
+                        clap::Arg::with_name("file")
+                            .help("The file to write to. Prints to stdout if left out or \"-\"")
+                            .index(1),
+                    ).arg(
+                        clap::Arg::with_name("account")
+                            .long("account")
+                            .help("Include the account token in the exported settings"),
+                    ),
+            ).subcommand(
+                clap::SubCommand::with_name("import")
+                    .about(
+                        "Replace all settings with the ones in a JSON file created by export. \
+                         The current account token is kept if the file has none",
+                    ).arg(
+                        clap::Arg::with_name("file")
+                            .help("The file to read from. Reads from stdin if \"-\"")
+                            .required(true)
+                            .index(1),
+                    ),
+            ).subcommand(
+                clap::SubCommand::with_name("update")
+                    .about(
+                        "Change the settings given as a JSON merge patch, such as \
+                         '{\"allow_lan\": true}'. Nothing is changed if any setting is invalid",
+                    ).arg(clap::Arg::with_name("patch").required(true).index(1)),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(export_matches) = matches.subcommand_matches("export") {
+            self.export(
+                export_matches.value_of("file"),
+                export_matches.is_present("account"),
+            )
+        } else if let Some(import_matches) = matches.subcommand_matches("import") {
+            self.import(import_matches.value_of("file").unwrap())
+        } else if let Some(update_matches) = matches.subcommand_matches("update") {
+            self.update(update_matches.value_of("patch").unwrap())
+        } else {
+            unreachable!("No settings command given");
+        }
+    }
+}
+
+impl Settings {
+    fn export(&self, path: Option<&str>, include_account_token: bool) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let settings = rpc.export_settings(include_account_token)?;
+        match path {
+            None | Some("-") => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                serde_json::to_writer_pretty(&mut stdout, &settings)
+                    .chain_err(|| "Unable to serialize settings")?;
+                writeln!(stdout)?;
+            }
+            Some(path) => {
+                let file = File::create(path)?;
+                serde_json::to_writer_pretty(io::BufWriter::new(file), &settings)
+                    .chain_err(|| "Unable to serialize settings")?;
+                println!("Exported settings to {}", path);
+            }
+        }
+        Ok(())
+    }
+
+    fn import(&self, path: &str) -> Result<()> {
+        let settings: serde_json::Value = if path == "-" {
+            serde_json::from_reader(io::stdin())
+        } else {
+            serde_json::from_reader(io::BufReader::new(File::open(path)?))
+        }.chain_err(|| "Invalid settings file")?;
+
+        let mut rpc = new_rpc_client()?;
+        rpc.import_settings(settings)?;
+        println!("Imported settings");
+        Ok(())
+    }
+
+    fn update(&self, patch: &str) -> Result<()> {
+        let patch: serde_json::Value =
+            serde_json::from_str(patch).chain_err(|| "Invalid JSON merge patch")?;
+
+        let mut rpc = new_rpc_client()?;
+        rpc.update_settings(patch)?;
+        println!("Updated settings");
+        Ok(())
+    }
+}
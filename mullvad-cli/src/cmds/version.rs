@@ -17,6 +17,8 @@ impl Command for Version {
         let mut rpc = new_rpc_client()?;
         let current_version = rpc.get_current_version()?;
         println!("Current version: {}", current_version);
+        let api_info = rpc.get_api_info()?;
+        println!("Management interface version: {}", api_info.version);
         let version_info = rpc.get_version_info()?;
         println!("Supported: {}", version_info.current_is_supported);
         println!("Latest releases:");
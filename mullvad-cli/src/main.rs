@@ -16,6 +16,7 @@ extern crate mullvad_ipc_client;
 extern crate mullvad_paths;
 extern crate mullvad_types;
 extern crate serde;
+extern crate serde_json;
 extern crate talpid_types;
 
 mod cmds;
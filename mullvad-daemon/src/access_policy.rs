@@ -0,0 +1,275 @@
+#[cfg(unix)]
+use libc;
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+
+use talpid_ipc::PeerCredentials;
+
+error_chain! {
+    errors {
+        UnknownGroup(name: String) {
+            description("Unknown management group")
+            display("There is no group named {}", name)
+        }
+        GroupsNotSupported {
+            description("Management groups are not supported on this platform")
+        }
+    }
+}
+
+/// Methods that return secrets that only privileged clients may know, such as account tokens.
+const SECRET_GETTERS: &[&str] = &[
+    "get_remote_management_token",
+    "reset_remote_management_token",
+    "get_account_history",
+    "list_accounts",
+    "create_account",
+];
+
+/// Methods that remote clients may not call even though they have presented the token. A daemon
+/// that is shut down can't be started again remotely.
+const LOCAL_ONLY: &[&str] = &["shutdown"];
+
+/// A client of the management interface, as far as the access policy is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Client {
+    /// A process on this machine connected to the local socket, with its credentials if they are
+    /// available.
+    Local(Option<PeerCredentials>),
+    /// A client of the remote management interface that has presented the token.
+    Remote,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::Local(None)
+    }
+}
+
+/// Decides which clients of the management interface may call which methods. Without a
+/// management group every client that can connect may call every method. With one, methods that
+/// only read the state of the daemon, and return nothing secret, may still be called by anyone,
+/// but all other methods may only be called by root and the members of the group. The settings
+/// are readable by anyone, but the account token is left out of them for clients that are not
+/// privileged.
+///
+/// Remote clients may manage the daemon, but never know its secrets, and can't shut it down.
+///
+/// On Windows the credentials of local clients are not available, so every local client may call
+/// every method.
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    group: Option<ManagementGroup>,
+}
+
+#[derive(Debug, Clone)]
+struct ManagementGroup {
+    name: String,
+    gid: u32,
+    member_uids: Vec<u32>,
+}
+
+impl AccessPolicy {
+    /// Creates a policy that only lets root and the members of the group named `group` call every
+    /// method, or that lets everyone do so if `group` is `None`. The members of the group are
+    /// looked up once, so changes to the group apply when the daemon is restarted.
+    pub fn new(group: Option<&str>) -> Result<Self> {
+        let group = match group {
+            Some(name) => Some(ManagementGroup::lookup(name)?),
+            None => None,
+        };
+        Ok(AccessPolicy { group })
+    }
+
+    /// Returns true if the method can not change the state of the daemon, and returns nothing
+    /// secret.
+    fn is_read_only(method: &str) -> bool {
+        (method.starts_with("get_") || method.starts_with("list_"))
+            && !SECRET_GETTERS.contains(&method)
+    }
+
+    /// Returns true if `client` may call `method`.
+    pub fn allows(&self, method: &str, client: &Client) -> bool {
+        match *client {
+            Client::Local(ref credentials) => {
+                Self::is_read_only(method) || self.is_privileged_local(credentials.as_ref())
+            }
+            Client::Remote => !SECRET_GETTERS.contains(&method) && !LOCAL_ONLY.contains(&method),
+        }
+    }
+
+    /// Returns true if `client` may know every secret, such as the account token in the settings.
+    pub fn is_privileged(&self, client: &Client) -> bool {
+        match *client {
+            Client::Local(ref credentials) => self.is_privileged_local(credentials.as_ref()),
+            Client::Remote => false,
+        }
+    }
+
+    /// Returns true if the local client with the given credentials may call every method.
+    fn is_privileged_local(&self, credentials: Option<&PeerCredentials>) -> bool {
+        let group = match self.group {
+            Some(ref group) => group,
+            None => return true,
+        };
+        match credentials {
+            Some(credentials) => credentials.uid == 0 || group.contains(credentials),
+            None => cfg!(windows),
+        }
+    }
+
+    /// Describes who may call `method`, for when `client` was refused.
+    pub fn describe_denial(&self, method: &str, client: &Client) -> String {
+        match (*client, &self.group) {
+            (Client::Remote, _) => format!("{} can't be called over remote management", method),
+            (Client::Local(_), &Some(ref group)) => format!(
+                "{} may only be called by root and members of the group {}",
+                method, group.name
+            ),
+            (Client::Local(_), &None) => format!("{} may not be called by this client", method),
+        }
+    }
+}
+
+impl ManagementGroup {
+    #[cfg(unix)]
+    fn lookup(name: &str) -> Result<Self> {
+        let c_name = CString::new(name).chain_err(|| ErrorKind::UnknownGroup(name.to_owned()))?;
+        let group = unsafe { libc::getgrnam(c_name.as_ptr()) };
+        if group.is_null() {
+            bail!(ErrorKind::UnknownGroup(name.to_owned()));
+        }
+        let gid = unsafe { (*group).gr_gid };
+
+        // The names have to be copied out before `getpwnam` is called, since it may reuse the
+        // memory returned by `getgrnam`.
+        let mut member_names = Vec::new();
+        let mut member = unsafe { (*group).gr_mem };
+        while !member.is_null() && !unsafe { *member }.is_null() {
+            member_names.push(unsafe { CStr::from_ptr(*member) }.to_owned());
+            member = unsafe { member.offset(1) };
+        }
+        let member_uids = member_names
+            .iter()
+            .filter_map(|member_name| {
+                let user = unsafe { libc::getpwnam(member_name.as_ptr()) };
+                if user.is_null() {
+                    None
+                } else {
+                    Some(unsafe { (*user).pw_uid })
+                }
+            }).collect();
+
+        Ok(ManagementGroup {
+            name: name.to_owned(),
+            gid,
+            member_uids,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn lookup(_name: &str) -> Result<Self> {
+        bail!(ErrorKind::GroupsNotSupported)
+    }
+
+    /// Returns true if the group is the primary group of the client, or its user is a member.
+    fn contains(&self, credentials: &PeerCredentials) -> bool {
+        credentials.gid == self.gid || self.member_uids.contains(&credentials.uid)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(uid: u32, gid: u32) -> Client {
+        Client::Local(Some(PeerCredentials {
+            uid,
+            gid,
+            pid: None,
+        }))
+    }
+
+    fn policy_with_group() -> AccessPolicy {
+        AccessPolicy {
+            group: Some(ManagementGroup {
+                name: "mullvad".to_owned(),
+                gid: 500,
+                member_uids: vec![1001],
+            }),
+        }
+    }
+
+    #[test]
+    fn unprivileged_clients_can_only_read_public_state() {
+        let policy = policy_with_group();
+        let user = local(1000, 1000);
+
+        assert!(policy.allows("get_state", &user));
+        assert!(policy.allows("get_settings", &user));
+        assert!(!policy.allows("connect", &user));
+        assert!(!policy.is_privileged(&user));
+    }
+
+    #[test]
+    fn unprivileged_clients_can_not_read_account_tokens() {
+        let policy = policy_with_group();
+        let user = local(1000, 1000);
+
+        assert!(!policy.allows("get_account_history", &user));
+        assert!(!policy.allows("list_accounts", &user));
+        assert!(!policy.allows("get_remote_management_token", &user));
+    }
+
+    #[test]
+    fn root_and_group_members_are_privileged() {
+        let policy = policy_with_group();
+
+        for client in &[local(0, 0), local(1001, 1000), local(1002, 500)] {
+            assert!(policy.is_privileged(client));
+            assert!(policy.allows("list_accounts", client));
+            assert!(policy.allows("connect", client));
+        }
+    }
+
+    #[test]
+    fn everyone_is_privileged_without_a_group() {
+        let policy = AccessPolicy::default();
+
+        for client in &[local(0, 0), local(1000, 1000), Client::Local(None)] {
+            assert!(policy.is_privileged(client));
+            assert!(policy.allows("list_accounts", client));
+            assert!(policy.allows("connect", client));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn clients_without_credentials_are_unprivileged() {
+        let policy = policy_with_group();
+
+        assert!(!policy.is_privileged(&Client::Local(None)));
+        assert!(policy.allows("get_state", &Client::Local(None)));
+        assert!(!policy.allows("connect", &Client::Local(None)));
+    }
+
+    #[test]
+    fn remote_clients_can_manage_but_not_read_secrets_or_shut_down() {
+        for policy in &[AccessPolicy::default(), policy_with_group()] {
+            assert!(!policy.is_privileged(&Client::Remote));
+
+            assert!(policy.allows("get_state", &Client::Remote));
+            assert!(policy.allows("get_settings", &Client::Remote));
+            assert!(policy.allows("connect", &Client::Remote));
+            assert!(policy.allows("update_settings", &Client::Remote));
+
+            assert!(!policy.allows("shutdown", &Client::Remote));
+            assert!(!policy.allows("get_remote_management_token", &Client::Remote));
+            assert!(!policy.allows("reset_remote_management_token", &Client::Remote));
+            assert!(!policy.allows("get_account_history", &Client::Remote));
+            assert!(!policy.allows("list_accounts", &Client::Remote));
+            assert!(!policy.allows("create_account", &Client::Remote));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Decisions the daemon makes from the expiry of the current account: what to tell subscribers,
+//! whether the tunnel has to block, and when to fetch the expiry again.
+
+use chrono::{DateTime, Utc};
+use mullvad_types::account::AccountExpiry;
+use std::time::Duration;
+use talpid_types::tunnel::BlockReason;
+
+/// How often to check when the current account expires.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before checking the account expiry again after failing to fetch it.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+
+/// Returns the `account_expiry` event to send to subscribers for an account expiring at
+/// `expiry`, or `None` if it would be the same as the `previous` one sent.
+pub fn expiry_event(
+    previous: Option<&AccountExpiry>,
+    expiry: DateTime<Utc>,
+    now: DateTime<Utc>,
+    warning_thresholds: &[u64],
+) -> Option<AccountExpiry> {
+    let account_expiry = AccountExpiry::new(expiry, now, warning_thresholds);
+    if previous == Some(&account_expiry) {
+        None
+    } else {
+        Some(account_expiry)
+    }
+}
+
+/// Returns the reason to block the tunnel for at the time `now`, if the account has expired.
+pub fn block_reason(
+    account_expiry: Option<&AccountExpiry>,
+    now: DateTime<Utc>,
+) -> Option<BlockReason> {
+    match account_expiry {
+        Some(account_expiry) if account_expiry.expiry <= now => Some(BlockReason::AccountExpired),
+        _ => None,
+    }
+}
+
+/// Returns how long to wait before checking the expiry again. That is when the account expires,
+/// or passes one of the warning thresholds, if that happens before the next regular check.
+pub fn next_check_delay(
+    expiry: DateTime<Utc>,
+    now: DateTime<Utc>,
+    warning_thresholds: &[u64],
+) -> Duration {
+    match (expiry - now).to_std() {
+        Ok(time_left) => warning_thresholds
+            .iter()
+            .filter_map(|&threshold| time_left.checked_sub(Duration::from_secs(threshold)))
+            .chain(Some(time_left))
+            .filter(|&delay| delay > Duration::from_secs(0))
+            .min()
+            .map_or(CHECK_INTERVAL, |delay| delay.min(CHECK_INTERVAL)),
+        Err(_) => CHECK_INTERVAL,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono;
+
+    const DAY: u64 = 24 * 60 * 60;
+    const THRESHOLDS: [u64; 2] = [3 * DAY, DAY];
+
+    #[test]
+    fn passing_warning_thresholds_produces_events() {
+        let now = Utc::now();
+        let expiry = now + chrono::Duration::days(5);
+
+        let first = expiry_event(None, expiry, now, &THRESHOLDS).unwrap();
+        assert_eq!(first.warning_threshold, None);
+        assert!(expiry_event(Some(&first), expiry, now, &THRESHOLDS).is_none());
+
+        let two_days_left = expiry - chrono::Duration::days(2);
+        let warning = expiry_event(Some(&first), expiry, two_days_left, &THRESHOLDS).unwrap();
+        assert_eq!(warning.warning_threshold, Some(3 * DAY));
+        assert!(expiry_event(Some(&warning), expiry, two_days_left, &THRESHOLDS).is_none());
+
+        let hours_left = expiry - chrono::Duration::hours(5);
+        let last_warning = expiry_event(Some(&warning), expiry, hours_left, &THRESHOLDS).unwrap();
+        assert_eq!(last_warning.warning_threshold, Some(DAY));
+
+        let expired = expiry_event(Some(&last_warning), expiry, expiry, &THRESHOLDS).unwrap();
+        assert_eq!(expired.warning_threshold, None);
+        assert!(expired.expired);
+    }
+
+    #[test]
+    fn expired_account_blocks_with_account_expired() {
+        let now = Utc::now();
+        let active = AccountExpiry::new(now + chrono::Duration::hours(1), now, &THRESHOLDS);
+        let expired = AccountExpiry::new(now - chrono::Duration::hours(1), now, &THRESHOLDS);
+
+        assert_eq!(block_reason(None, now), None);
+        assert_eq!(block_reason(Some(&active), now), None);
+        assert_eq!(
+            block_reason(Some(&expired), now),
+            Some(BlockReason::AccountExpired)
+        );
+        // The account expires while the cached expiry is still in use.
+        assert_eq!(
+            block_reason(Some(&active), now + chrono::Duration::hours(2)),
+            Some(BlockReason::AccountExpired)
+        );
+    }
+
+    #[test]
+    fn checks_again_when_passing_a_threshold_or_expiring() {
+        let now = Utc::now();
+
+        let far_away = now + chrono::Duration::days(30);
+        assert_eq!(next_check_delay(far_away, now, &THRESHOLDS), CHECK_INTERVAL);
+
+        let near_threshold = now + chrono::Duration::seconds(DAY as i64 + 10 * 60);
+        assert_eq!(
+            next_check_delay(near_threshold, now, &THRESHOLDS),
+            Duration::from_secs(10 * 60)
+        );
+
+        let near_expiry = now + chrono::Duration::minutes(20);
+        assert_eq!(
+            next_check_delay(near_expiry, now, &THRESHOLDS),
+            Duration::from_secs(20 * 60)
+        );
+
+        let expired = now - chrono::Duration::minutes(20);
+        assert_eq!(next_check_delay(expired, now, &THRESHOLDS), CHECK_INTERVAL);
+    }
+}
@@ -1,10 +1,15 @@
 extern crate serde_json;
 
+use chrono::offset::Utc;
+use chrono::DateTime;
+use error_chain::ChainedError;
+
 use std::fs::File;
 use std::io;
 use std::path::{Path, PathBuf};
 
-use mullvad_types::account::AccountToken;
+use mullvad_types::account::{AccountHistoryEntry, AccountToken};
+use mullvad_types::{atomic_file, secret_file};
 
 error_chain! {
     errors {
@@ -23,86 +28,343 @@ error_chain! {
 }
 
 static ACCOUNT_HISTORY_FILE: &str = "account-history.json";
-static ACCOUNT_HISTORY_LIMIT: usize = 3;
 
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+/// The accounts used on this device, least recently used first. At most `limit` accounts are
+/// remembered.
+#[derive(Debug, Clone, Serialize)]
 pub struct AccountHistory {
-    accounts: Vec<AccountToken>,
+    accounts: Vec<AccountHistoryEntry>,
+    #[serde(skip)]
+    limit: usize,
     #[serde(skip)]
     cache_path: PathBuf,
 }
 
+/// The account history as stored on disk. Older versions only stored the account tokens.
+#[derive(Deserialize)]
+struct StoredAccountHistory {
+    accounts: Vec<StoredAccount>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredAccount {
+    Entry(AccountHistoryEntry),
+    Token(AccountToken),
+}
+
 impl AccountHistory {
     /// Returns a new empty `AccountHistory` ready to load from, or save to, the given cache dir.
-    pub fn new(cache_dir: &Path) -> AccountHistory {
+    /// It remembers at most `limit` accounts.
+    pub fn new(cache_dir: &Path, limit: usize) -> AccountHistory {
         AccountHistory {
             accounts: Vec::new(),
+            limit,
             cache_path: cache_dir.join(ACCOUNT_HISTORY_FILE),
         }
     }
 
-    /// Loads account history from file. If no file is present this does nothing.
+    /// Loads account history from file. If no file is present this does nothing. If the file is
+    /// corrupt, it's moved aside and the backup of the previous history is used instead, or an
+    /// empty history if that can't be read either.
+    ///
+    /// The history is only readable by the daemon. A history file written by an older version is
+    /// restricted before it's read, and the bare account tokens in it are turned into entries
+    /// first and last used now. They are written in the new format the next time the history is
+    /// saved.
     pub fn load(&mut self) -> Result<()> {
-        match File::open(&self.cache_path).map(io::BufReader::new) {
-            Ok(mut file) => {
-                info!(
-                    "Loading account history from {}",
-                    &self.cache_path.display()
-                );
-                self.accounts = Self::parse(&mut file)?.accounts;
+        secret_file::restrict_permissions(&self.cache_path)
+            .chain_err(|| ErrorKind::ReadError(self.cache_path.clone()))?;
+        match Self::load_file(&self.cache_path) {
+            Ok(accounts) => {
+                self.accounts = accounts;
                 Ok(())
             }
-            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
-                info!("No account history file at {}", &self.cache_path.display());
+            Err(Error(ErrorKind::ParseError, _)) => {
+                error!("The account history file is corrupt, falling back to the backup");
+                if let Err(e) = atomic_file::set_aside_corrupt(&self.cache_path) {
+                    warn!("Unable to move the corrupt account history file: {}", e);
+                }
+                self.accounts = Self::load_backup(&self.cache_path);
                 Ok(())
             }
-            Err(e) => Err(e).chain_err(|| ErrorKind::ReadError(self.cache_path.clone())),
+            Err(e) => Err(e),
         }
     }
 
-    fn parse(file: &mut impl io::Read) -> Result<AccountHistory> {
-        serde_json::from_reader(file).chain_err(|| ErrorKind::ParseError)
+    fn load_file(path: &Path) -> Result<Vec<AccountHistoryEntry>> {
+        match File::open(path).map(io::BufReader::new) {
+            Ok(mut file) => {
+                info!("Loading account history from {}", path.display());
+                Self::parse(&mut file, Utc::now())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                info!("No account history file at {}", path.display());
+                Ok(Vec::new())
+            }
+            Err(e) => Err(e).chain_err(|| ErrorKind::ReadError(path.to_owned())),
+        }
+    }
+
+    fn load_backup(path: &Path) -> Vec<AccountHistoryEntry> {
+        let backup_path = atomic_file::backup_path(path);
+        match Self::load_file(&backup_path) {
+            Ok(accounts) => accounts,
+            Err(error) => {
+                let chained_error = error.chain_err(|| "Unable to load account history backup");
+                error!("{}", chained_error.display_chain());
+                Vec::new()
+            }
+        }
+    }
+
+    fn parse(file: &mut impl io::Read, now: DateTime<Utc>) -> Result<Vec<AccountHistoryEntry>> {
+        let history: StoredAccountHistory =
+            serde_json::from_reader(file).chain_err(|| ErrorKind::ParseError)?;
+        Ok(history
+            .accounts
+            .into_iter()
+            .map(|account| match account {
+                StoredAccount::Entry(entry) => entry,
+                StoredAccount::Token(account_token) => {
+                    debug!("Migrating account history entry");
+                    AccountHistoryEntry::new(account_token, now)
+                }
+            }).collect())
     }
 
-    pub fn get_accounts(&self) -> &[AccountToken] {
+    pub fn get_entries(&self) -> &[AccountHistoryEntry] {
         &self.accounts
     }
 
-    /// Add account token to the account history removing duplicate entries
-    pub fn add_account_token(&mut self, account_token: AccountToken) -> Result<()> {
+    pub fn get_accounts(&self) -> Vec<AccountToken> {
         self.accounts
-            .retain(|existing_token| existing_token != &account_token);
-        self.accounts.push(account_token);
-
-        let num_accounts = self.accounts.len();
-        if num_accounts > ACCOUNT_HISTORY_LIMIT {
-            self.accounts = self
-                .accounts
-                .split_off(num_accounts - ACCOUNT_HISTORY_LIMIT);
-        }
+            .iter()
+            .map(|entry| entry.account_token.clone())
+            .collect()
+    }
 
+    /// Marks the account as used now, adding it to the history if it's not already there. The
+    /// least recently used accounts are forgotten if there are more than `limit` of them.
+    pub fn add_account_token(&mut self, account_token: AccountToken) -> Result<()> {
+        let now = Utc::now();
+        let mut entry = match self
+            .accounts
+            .iter()
+            .position(|entry| entry.account_token == account_token)
+        {
+            Some(index) => self.accounts.remove(index),
+            None => AccountHistoryEntry::new(account_token, now),
+        };
+        entry.last_used = now;
+        self.accounts.push(entry);
+        self.truncate();
         self.save()
     }
 
     /// Remove account token from the account history
     pub fn remove_account_token(&mut self, account_token: AccountToken) -> Result<()> {
         self.accounts
-            .retain(|existing_token| existing_token != &account_token);
+            .retain(|entry| entry.account_token != account_token);
         self.save()
     }
 
-    /// Serializes the account history and saves it to the file it was loaded from.
+    /// Gives the account a label, or removes its label if `label` is `None` or empty. Returns
+    /// false if the account is not in the history.
+    pub fn set_label(&mut self, account_token: &str, label: Option<String>) -> Result<bool> {
+        let label = label.filter(|label| !label.trim().is_empty());
+        {
+            let entry = match self.get_entry_mut(account_token) {
+                Some(entry) => entry,
+                None => return Ok(false),
+            };
+            if entry.label == label {
+                return Ok(true);
+            }
+            entry.label = label;
+        }
+        self.save().map(|_| true)
+    }
+
+    /// Records the expiry of the account, if it's in the history.
+    pub fn set_expiry(&mut self, account_token: &str, expiry: DateTime<Utc>) -> Result<()> {
+        {
+            let entry = match self.get_entry_mut(account_token) {
+                Some(entry) => entry,
+                None => return Ok(()),
+            };
+            if entry.last_known_expiry == Some(expiry) {
+                return Ok(());
+            }
+            entry.last_known_expiry = Some(expiry);
+        }
+        self.save()
+    }
+
+    /// Changes how many accounts are remembered, forgetting the least recently used accounts if
+    /// there are too many.
+    pub fn set_limit(&mut self, limit: usize) -> Result<()> {
+        self.limit = limit;
+        if self.accounts.len() > limit {
+            self.truncate();
+            self.save()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn get_entry_mut(&mut self, account_token: &str) -> Option<&mut AccountHistoryEntry> {
+        self.accounts
+            .iter_mut()
+            .find(|entry| entry.account_token == account_token)
+    }
+
+    fn truncate(&mut self) {
+        let num_accounts = self.accounts.len();
+        if num_accounts > self.limit {
+            self.accounts = self.accounts.split_off(num_accounts - self.limit);
+        }
+    }
+
+    /// Serializes the account history and saves it to the file it was loaded from. The file is
+    /// replaced atomically, so it's never left half written, and only the daemon can read it.
     fn save(&self) -> Result<()> {
         debug!("Writing account history to {}", self.cache_path.display());
-        let mut file = File::create(&self.cache_path)
-            .map(io::BufWriter::new)
+        let history = serde_json::to_vec_pretty(self)
             .chain_err(|| ErrorKind::WriteError(self.cache_path.clone()))?;
+        secret_file::write(&self.cache_path, &history)
+            .chain_err(|| ErrorKind::WriteError(self.cache_path.clone()))
+    }
+}
 
-        serde_json::to_writer_pretty(&mut file, self)
-            .chain_err(|| ErrorKind::WriteError(self.cache_path.clone()))?;
 
-        file.get_mut()
-            .sync_all()
-            .chain_err(|| ErrorKind::WriteError(self.cache_path.clone()))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile;
+
+    fn history_with(cache_dir: &Path, limit: usize, accounts: &[&str]) -> AccountHistory {
+        let mut history = AccountHistory::new(cache_dir, limit);
+        for account in accounts {
+            history.add_account_token(account.to_string()).unwrap();
+        }
+        history
+    }
+
+    fn load(cache_dir: &Path, limit: usize) -> AccountHistory {
+        let mut history = AccountHistory::new(cache_dir, limit);
+        history.load().unwrap();
+        history
+    }
+
+    #[test]
+    fn migrates_bare_account_tokens() {
+        let now = Utc::now();
+        let stored = br#"{ "accounts": ["1111", "2222"] }"#;
+
+        let accounts = AccountHistory::parse(&mut &stored[..], now).unwrap();
+
+        assert_eq!(
+            accounts,
+            vec![
+                AccountHistoryEntry::new("1111".to_owned(), now),
+                AccountHistoryEntry::new("2222".to_owned(), now),
+            ]
+        );
+    }
+
+    #[test]
+    fn migrates_old_history_file_on_save() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join(ACCOUNT_HISTORY_FILE);
+        fs::write(&cache_path, r#"{ "accounts": ["1111", "2222"] }"#).unwrap();
+
+        let mut history = load(cache_dir.path(), 3);
+        assert_eq!(history.get_accounts(), vec!["1111", "2222"]);
+        history.set_label("1111", Some("Work".to_owned())).unwrap();
+
+        let reloaded = load(cache_dir.path(), 3);
+        assert_eq!(reloaded.get_entries(), history.get_entries());
+        assert_eq!(reloaded.get_entries()[0].label, Some("Work".to_owned()));
+    }
+
+    #[test]
+    fn forgets_least_recently_used_accounts_over_the_limit() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut history = history_with(cache_dir.path(), 2, &["1111", "2222"]);
+
+        history.add_account_token("1111".to_owned()).unwrap();
+        history.add_account_token("3333".to_owned()).unwrap();
+
+        assert_eq!(history.get_accounts(), vec!["1111", "3333"]);
+        assert_eq!(
+            load(cache_dir.path(), 2).get_accounts(),
+            vec!["1111", "3333"]
+        );
+    }
+
+    #[test]
+    fn lowering_the_limit_prunes_the_history() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut history = history_with(cache_dir.path(), 3, &["1111", "2222", "3333"]);
+
+        history.set_limit(1).unwrap();
+
+        assert_eq!(history.get_accounts(), vec!["3333"]);
+        assert_eq!(load(cache_dir.path(), 3).get_accounts(), vec!["3333"]);
+    }
+
+    #[test]
+    fn using_an_account_again_updates_last_used_only() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut history = history_with(cache_dir.path(), 3, &["1111", "2222"]);
+        let first_entry = history.get_entries()[0].clone();
+
+        history.add_account_token("1111".to_owned()).unwrap();
+
+        assert_eq!(history.get_accounts(), vec!["2222", "1111"]);
+        let entry = &history.get_entries()[1];
+        assert_eq!(entry.first_used, first_entry.first_used);
+        assert!(entry.last_used >= first_entry.last_used);
+    }
+
+    #[test]
+    fn sets_and_clears_labels() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut history = history_with(cache_dir.path(), 3, &["1111"]);
+
+        assert!(history.set_label("1111", Some("Work".to_owned())).unwrap());
+        assert_eq!(
+            load(cache_dir.path(), 3).get_entries()[0].label,
+            Some("Work".to_owned())
+        );
+
+        assert!(history.set_label("1111", Some(" ".to_owned())).unwrap());
+        assert_eq!(history.get_entries()[0].label, None);
+        assert!(!history.set_label("2222", Some("Home".to_owned())).unwrap());
+    }
+
+    #[test]
+    fn falls_back_to_the_backup_if_corrupt() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join(ACCOUNT_HISTORY_FILE);
+        // The second write keeps the first history as the backup.
+        history_with(cache_dir.path(), 3, &["1111", "2222"]);
+        fs::write(&cache_path, "{ \"accounts\": [").unwrap();
+
+        let history = load(cache_dir.path(), 3);
+
+        assert_eq!(history.get_accounts(), vec!["1111"]);
+        assert!(!cache_path.exists());
+    }
+
+    #[test]
+    fn corrupt_history_without_backup_is_empty() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join(ACCOUNT_HISTORY_FILE);
+        fs::write(&cache_path, "{ \"accounts\": [").unwrap();
+
+        assert!(load(cache_dir.path(), 3).get_accounts().is_empty());
     }
 }
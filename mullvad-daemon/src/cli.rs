@@ -1,5 +1,6 @@
-use clap::{App, Arg};
+use clap::{self, App, Arg};
 use log;
+use std::net::SocketAddr;
 
 use version;
 
@@ -9,6 +10,8 @@ pub struct Config {
     pub log_stdout_timestamps: bool,
     pub run_as_service: bool,
     pub register_service: bool,
+    pub management_group: Option<String>,
+    pub metrics_address: Option<SocketAddr>,
 }
 
 pub fn get_config() -> Config {
@@ -25,6 +28,24 @@ pub fn get_config() -> Config {
 
     let run_as_service = cfg!(windows) && matches.is_present("run_as_service");
     let register_service = cfg!(windows) && matches.is_present("register_service");
+    let management_group = matches.value_of("management_group").map(str::to_owned);
+    let metrics_address = if matches.is_present("metrics_address") {
+        Some(value_t_or_exit!(matches, "metrics_address", SocketAddr))
+    } else {
+        None
+    };
+    if let Some(address) = metrics_address {
+        if !address.ip().is_loopback() && !matches.is_present("metrics_allow_remote") {
+            clap::Error::with_description(
+                &format!(
+                    "{} is not a loopback address, add --metrics-allow-remote to serve metrics \
+                     to other machines",
+                    address
+                ),
+                clap::ErrorKind::InvalidValue,
+            ).exit();
+        }
+    }
 
     Config {
         log_level,
@@ -32,6 +53,8 @@ pub fn get_config() -> Config {
         log_stdout_timestamps,
         run_as_service,
         register_service,
+        management_group,
+        metrics_address,
     }
 }
 
@@ -55,7 +78,20 @@ fn create_app() -> App<'static, 'static> {
             Arg::with_name("disable_stdout_timestamps")
                 .long("disable-stdout-timestamps")
                 .help("Don't log timestamps when logging to stdout, useful when running as a systemd service")
-            );
+            )
+        .arg(
+            Arg::with_name("metrics_address")
+                .long("metrics-address")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .help("Serve Prometheus metrics over HTTP on this address and port, such as 127.0.0.1:9551. Off by default"),
+        )
+        .arg(
+            Arg::with_name("metrics_allow_remote")
+                .long("metrics-allow-remote")
+                .requires("metrics_address")
+                .help("Allow serving metrics on addresses other than loopback addresses. The metrics are not authenticated"),
+        );
 
     if cfg!(windows) {
         app.arg(
@@ -68,6 +104,12 @@ fn create_app() -> App<'static, 'static> {
                 .help("Register itself as a system service"),
         )
     } else {
-        app
+        app.arg(
+            Arg::with_name("management_group")
+                .long("management-group")
+                .takes_value(true)
+                .value_name("GROUP")
+                .help("Only let root and members of this group change settings and connect or disconnect. Everyone can if no group is given"),
+        )
     }
 }
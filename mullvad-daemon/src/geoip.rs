@@ -28,6 +28,6 @@ pub fn send_location_request(
         .map_err(|e| Error::with_chain(e, ErrorKind::NoResponse))
         .and_then(|_| response_rx.map_err(|e| Error::with_chain(e, ErrorKind::NoResponse)))
         .and_then(|response_result| response_result.map_err(Error::from))
-        .and_then(|response| serde_json::from_slice(&response).map_err(Error::from));
+        .and_then(|response| serde_json::from_slice(&response.body).map_err(Error::from));
     Box::new(future)
 }
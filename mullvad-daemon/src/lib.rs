@@ -6,6 +6,7 @@
 //! GNU General Public License as published by the Free Software Foundation, either version 3 of
 //! the License, or (at your option) any later version.
 
+extern crate base64;
 extern crate chrono;
 #[macro_use]
 extern crate error_chain;
@@ -24,6 +25,8 @@ extern crate jsonrpc_core;
 extern crate jsonrpc_macros;
 extern crate jsonrpc_ipc_server;
 extern crate jsonrpc_pubsub;
+extern crate jsonrpc_ws_server;
+extern crate openssl;
 extern crate rand;
 extern crate tokio_core;
 extern crate tokio_timer;
@@ -37,31 +40,68 @@ extern crate talpid_core;
 extern crate talpid_ipc;
 extern crate talpid_types;
 
+#[cfg(test)]
+extern crate filetime;
+#[cfg(test)]
+extern crate hyper;
+#[cfg(test)]
+extern crate tempfile;
+
+mod access_policy;
+mod account_expiry;
 mod account_history;
 mod geoip;
 mod management_interface;
+mod metrics;
+mod network_activity;
+mod profiles;
+mod relay_list_signature;
 mod relays;
+mod remote_management;
 mod rpc_uniqueness_check;
+mod settings_validation;
+mod timer;
+mod user_relays;
 
 use error_chain::ChainedError;
 use futures::sync::mpsc::UnboundedSender;
 use futures::{Future, Sink};
 use jsonrpc_core::futures::sync::oneshot::{self, Sender as OneshotSender};
 
-use management_interface::{BoxFuture, ManagementCommand, ManagementInterfaceServer};
+use access_policy::AccessPolicy;
+use account_history::AccountHistory;
+use management_interface::{
+    BoxFuture, ManagementCommand, ManagementInterfaceServer, RemoteManagementServer,
+    SwitchProfileError, UpdateSettingsError,
+};
+use metrics::Metrics;
 use mullvad_rpc::{AccountsProxy, AppVersionProxy, HttpHandle};
+use profiles::Profiles;
+use remote_management::RemoteManagementToken;
+use timer::CancellableTimer;
+use user_relays::UserRelays;
 
+use chrono::{DateTime, Utc};
 use mullvad_types::{
-    account::{AccountData, AccountToken},
+    account::{AccountData, AccountExpiry, AccountHistoryEntry, AccountToken, VoucherSubmission},
     location::GeoIpLocation,
-    relay_constraints::{RelaySettings, RelaySettingsUpdate},
-    relay_list::{Relay, RelayList},
-    settings::Settings,
+    relay_constraints::{
+        IpVersionPreference, RelayConstraints, RelaySettings, RelaySettingsUpdate,
+    },
+    relay_list::{MatchingRelays, Relay, RelayInvalidated, RelayList},
+    settings::{policy::Policy, FieldError, RemoteManagementSettings, Settings, REVISION_KEY},
     states::TargetState,
     version::{AppVersion, AppVersionInfo},
 };
 
-use std::{mem, net::IpAddr, path::PathBuf, sync::mpsc, thread, time::Duration};
+use std::{
+    mem,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
 
 use talpid_core::{
     mpsc::IntoSender,
@@ -93,6 +133,9 @@ error_chain!{
 
 type SyncUnboundedSender<T> = ::futures::sink::Wait<UnboundedSender<T>>;
 
+/// How long to wait for the network to become idle before rotating relays anyway.
+const MAX_ROTATION_IDLE_WAIT: Duration = Duration::from_secs(5 * 60);
+
 /// All events that can happen in the daemon. Sent from various threads and exposed interfaces.
 pub enum DaemonEvent {
     /// Tunnel has changed state.
@@ -101,6 +144,21 @@ pub enum DaemonEvent {
     ManagementInterfaceEvent(ManagementCommand),
     /// Triggered if the server hosting the JSONRPC-2.0 management interface dies unexpectedly.
     ManagementInterfaceExited,
+    /// A new relay list has been downloaded.
+    RelayListUpdated(RelayList),
+    /// It's time to switch to another relay. Ignored unless the id is the one of the latest
+    /// scheduled rotation.
+    RotateRelay(u64),
+    /// It's time to check when the account expires. Ignored unless the id is the one of the
+    /// latest scheduled check.
+    CheckAccountExpiry(u64),
+    /// The expiry of the given account has been fetched.
+    AccountExpiryFetched(
+        AccountToken,
+        ::std::result::Result<DateTime<Utc>, mullvad_rpc::Error>,
+    ),
+    /// The location was looked up after disconnecting.
+    LocationFetched(GeoIpLocation),
     /// Daemon shutdown triggered by a signal, ctrl-c or similar.
     TriggerShutdown,
 }
@@ -117,6 +175,12 @@ impl From<ManagementCommand> for DaemonEvent {
     }
 }
 
+impl From<RelayList> for DaemonEvent {
+    fn from(relay_list: RelayList) -> Self {
+        DaemonEvent::RelayListUpdated(relay_list)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum DaemonExecutionState {
     Running,
@@ -172,12 +236,34 @@ pub struct Daemon {
     #[cfg(unix)]
     management_interface_socket_path: String,
     settings: Settings,
+    /// Settings locked by the system administrator. Enforced on top of `settings`.
+    policy: Policy,
+    access_policy: AccessPolicy,
     accounts_proxy: AccountsProxy<HttpHandle>,
     version_proxy: AppVersionProxy<HttpHandle>,
     https_handle: mullvad_rpc::rest::RequestSender,
     tokio_remote: tokio_core::reactor::Remote,
     relay_selector: relays::RelaySelector,
+    user_relays: UserRelays,
+    profiles: Profiles,
+    account_history: AccountHistory,
+    remote_management_token: RemoteManagementToken,
+    remote_management_server: Option<RemoteManagementServer>,
+    metrics: Arc<Metrics>,
     current_relay: Option<Relay>,
+    /// The endpoint on `current_relay` that the tunnel uses.
+    current_endpoint: Option<TunnelEndpoint>,
+    /// Identifies the latest scheduled relay rotation. Increased whenever a rotation is
+    /// scheduled or cancelled, so a rotation sent just before it was cancelled is ignored.
+    relay_rotation_id: u64,
+    /// The timer of the rotation identified by `relay_rotation_id`, if one is scheduled.
+    relay_rotation_timer: Option<CancellableTimer>,
+    /// The expiry of the current account, as last fetched from the API.
+    account_expiry: Option<AccountExpiry>,
+    /// Identifies the latest scheduled account expiry check, like `relay_rotation_id`.
+    account_expiry_check_id: u64,
+    /// The timer of the check identified by `account_expiry_check_id`, if one is scheduled.
+    account_expiry_check_timer: Option<CancellableTimer>,
     log_dir: Option<PathBuf>,
     resource_dir: PathBuf,
     version: String,
@@ -189,6 +275,8 @@ impl Daemon {
         resource_dir: PathBuf,
         cache_dir: PathBuf,
         version: String,
+        management_group: Option<String>,
+        metrics_address: Option<SocketAddr>,
     ) -> Result<Self> {
         ensure!(
             !rpc_uniqueness_check::is_another_instance_running(),
@@ -198,27 +286,83 @@ impl Daemon {
 
         let mut rpc_manager = mullvad_rpc::MullvadRpcFactory::with_cache_dir(&cache_dir, &ca_path);
 
-        let (rpc_handle, https_handle, tokio_remote) =
+        let (rpc_handle, https_handle, relay_list_client, tokio_remote) =
             mullvad_rpc::event_loop::create(move |core| {
                 let handle = core.handle();
                 let rpc = rpc_manager.new_connection_on_event_loop(&handle);
                 let https_handle = mullvad_rpc::rest::create_https_client(&ca_path, &handle);
+                let relay_list_client = rpc_manager.new_relay_list_client_on_event_loop(&handle);
                 let remote = core.remote();
-                (rpc, https_handle, remote)
+                (rpc, https_handle, relay_list_client, remote)
             }).chain_err(|| "Unable to initialize network event loop")?;
         let rpc_handle = rpc_handle.chain_err(|| "Unable to create RPC client")?;
         let https_handle = https_handle.chain_err(|| "Unable to create am.i.mullvad client")?;
+        let relay_list_client =
+            relay_list_client.chain_err(|| "Unable to create relay list client")?;
+
+        let settings_dir =
+            mullvad_paths::settings_dir().chain_err(|| "Unable to get settings dir")?;
+        let mut user_relays = UserRelays::new(&settings_dir);
+        if let Err(error) = user_relays.load() {
+            let chained_error = error.chain_err(|| "Unable to load user relays");
+            error!("{}", chained_error.display_chain());
+        }
+
+        let remote_management_token = RemoteManagementToken::new(&settings_dir);
+
+        let mut profiles = Profiles::new(&settings_dir);
+        if let Err(error) = profiles.load() {
+            let chained_error = error.chain_err(|| "Unable to load profiles");
+            error!("{}", chained_error.display_chain());
+        }
 
-        let relay_selector =
-            relays::RelaySelector::new(rpc_handle.clone(), &resource_dir, &cache_dir);
+        let access_policy = AccessPolicy::new(management_group.as_ref().map(String::as_str))
+            .chain_err(|| "Unable to set up access to the management interface")?;
+        // A broken policy file must not keep the daemon from starting, and with it the tunnel.
+        let policy = Policy::load().unwrap_or_else(|error| {
+            let chained_error = error.chain_err(|| "Unable to load system policy, ignoring it");
+            error!("{}", chained_error.display_chain());
+            Policy::default()
+        });
+        let mut settings = Settings::load().chain_err(|| "Unable to read settings")?;
+        if let Err(error) = policy.enforce(&mut settings) {
+            let chained_error = error.chain_err(|| "Unable to apply system policy");
+            error!("{}", chained_error.display_chain());
+        }
+
+        let mut account_history =
+            AccountHistory::new(&cache_dir, settings.get_account_history_limit());
+        if let Err(error) = account_history.load() {
+            let chained_error = error.chain_err(|| "Unable to load account history");
+            error!("{}", chained_error.display_chain());
+        }
 
         let (tx, rx) = mpsc::channel();
+        let relay_selector = relays::RelaySelector::new(
+            relay_list_client,
+            IntoSender::from(tx.clone()),
+            user_relays.get_relays().clone(),
+            &resource_dir,
+            &cache_dir,
+        );
+
         let tunnel_command_tx =
             tunnel_state_machine::spawn(cache_dir.clone(), IntoSender::from(tx.clone()))?;
 
+        let metrics = Arc::new(Metrics::default());
+        metrics.set_relay_list_updated(relay_selector.get_last_updated());
+        if let Some(address) = metrics_address {
+            metrics::serve(address, metrics.clone())
+                .chain_err(|| format!("Unable to serve metrics on {}", address))?;
+        }
+
         let target_state = TargetState::Unsecured;
-        let management_interface_result =
-            Self::start_management_interface(tx.clone(), cache_dir.clone())?;
+        let management_interface_result = Self::start_management_interface(
+            tx.clone(),
+            policy.clone(),
+            access_policy.clone(),
+            &metrics,
+        )?;
 
         // Attempt to download a fresh relay list
         relay_selector.update();
@@ -233,13 +377,27 @@ impl Daemon {
             management_interface_broadcaster: management_interface_result.0,
             #[cfg(unix)]
             management_interface_socket_path: management_interface_result.1,
-            settings: Settings::load().chain_err(|| "Unable to read settings")?,
+            settings,
+            policy,
+            access_policy,
             accounts_proxy: AccountsProxy::new(rpc_handle.clone()),
             version_proxy: AppVersionProxy::new(rpc_handle),
             https_handle,
             tokio_remote,
             relay_selector,
+            user_relays,
+            profiles,
+            account_history,
+            remote_management_token,
+            remote_management_server: None,
+            metrics,
             current_relay: None,
+            current_endpoint: None,
+            relay_rotation_id: 0,
+            relay_rotation_timer: None,
+            account_expiry: None,
+            account_expiry_check_id: 0,
+            account_expiry_check_timer: None,
             log_dir,
             resource_dir,
             version,
@@ -250,10 +408,17 @@ impl Daemon {
     // Returns a handle that allows notifying all subscribers on events.
     fn start_management_interface(
         event_tx: mpsc::Sender<DaemonEvent>,
-        cache_dir: PathBuf,
+        policy: Policy,
+        access_policy: AccessPolicy,
+        metrics: &Arc<Metrics>,
     ) -> Result<(management_interface::EventBroadcaster, String)> {
         let multiplex_event_tx = IntoSender::from(event_tx.clone());
-        let server = Self::start_management_interface_server(multiplex_event_tx, cache_dir)?;
+        let server = Self::start_management_interface_server(
+            multiplex_event_tx,
+            policy,
+            access_policy,
+            metrics,
+        )?;
         let event_broadcaster = server.event_broadcaster();
         let socket_path = server.socket_path().to_owned();
         Self::spawn_management_interface_wait_thread(server, event_tx);
@@ -262,9 +427,11 @@ impl Daemon {
 
     fn start_management_interface_server(
         event_tx: IntoSender<ManagementCommand, DaemonEvent>,
-        cache_dir: PathBuf,
+        policy: Policy,
+        access_policy: AccessPolicy,
+        metrics: &Arc<Metrics>,
     ) -> Result<ManagementInterfaceServer> {
-        let server = ManagementInterfaceServer::start(event_tx, cache_dir)
+        let server = ManagementInterfaceServer::start(event_tx, policy, access_policy, metrics)
             .chain_err(|| ErrorKind::ManagementInterfaceError("Failed to start server"))?;
         info!(
             "Mullvad management interface listening on {}",
@@ -288,6 +455,8 @@ impl Daemon {
     /// Consume the `Daemon` and run the main event loop. Blocks until an error happens or a
     /// shutdown event is received.
     pub fn run(mut self) -> Result<()> {
+        self.check_account_expiry();
+        self.restart_remote_management_server();
         if self.settings.get_auto_connect() {
             info!("Automatically connecting since auto-connect is turned on");
             if self.set_target_state(TargetState::Secured).is_err() {
@@ -311,6 +480,13 @@ impl Daemon {
             }
             ManagementInterfaceEvent(event) => Ok(self.handle_management_interface_event(event)),
             ManagementInterfaceExited => self.handle_management_interface_exited(),
+            RelayListUpdated(relay_list) => Ok(self.handle_relay_list_updated(relay_list)),
+            RotateRelay(rotation_id) => Ok(self.handle_rotate_relay(rotation_id)),
+            CheckAccountExpiry(check_id) => Ok(self.handle_check_account_expiry(check_id)),
+            AccountExpiryFetched(account_token, result) => {
+                Ok(self.handle_account_expiry_fetched(account_token, result))
+            }
+            LocationFetched(location) => Ok(self.handle_location_fetched(location)),
             TriggerShutdown => Ok(self.handle_trigger_shutdown_event()),
         }
     }
@@ -319,25 +495,310 @@ impl Daemon {
         use self::TunnelStateTransition::*;
 
         debug!("New tunnel state: {:?}", tunnel_state);
+        self.metrics.set_tunnel_state(&tunnel_state);
         match tunnel_state {
             Disconnected => {
                 self.state.disconnected();
                 self.current_relay = None;
+                self.current_endpoint = None;
             }
             Blocked(ref reason) => {
                 info!("Blocking all network connections, reason: {}", reason);
 
                 match reason {
-                    BlockReason::AuthFailed(_) => self.schedule_reconnect(Duration::from_secs(60)),
+                    BlockReason::AuthFailed(_) => {
+                        // The account might have run out of time. If so, the reconnect blocks
+                        // with `AccountExpired` once the expiry has been fetched.
+                        self.check_account_expiry();
+                        self.metrics.count_reconnect("auth_failed");
+                        self.schedule_reconnect(Duration::from_secs(60));
+                    }
                     _ => {}
                 }
             }
             _ => {}
         }
 
+        if tunnel_state == TunnelStateTransition::Connected {
+            self.schedule_relay_rotation();
+        } else {
+            self.cancel_relay_rotation();
+        }
+
         self.tunnel_state = tunnel_state.clone();
         self.management_interface_broadcaster
             .notify_new_state(tunnel_state);
+        self.update_location();
+    }
+
+    /// Tells `events` subscribers about the new location after connecting or disconnecting. The
+    /// location of the relay is known right away, while the location outside the tunnel has to
+    /// be looked up.
+    fn update_location(&mut self) {
+        match self.tunnel_state {
+            TunnelStateTransition::Connected => {
+                if let Some(location) = self.get_relay_location() {
+                    self.management_interface_broadcaster
+                        .notify_location(location);
+                }
+            }
+            TunnelStateTransition::Disconnected => {
+                let event_tx = self.tx.clone();
+                let https_handle = self.https_handle.clone();
+                self.tokio_remote.spawn(move |_| {
+                    geoip::send_location_request(https_handle)
+                        .map(move |location| {
+                            let _ = event_tx.send(DaemonEvent::LocationFetched(location));
+                        }).map_err(|e| {
+                            warn!("Unable to fetch GeoIP location: {}", e.display_chain());
+                        })
+                });
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_location_fetched(&mut self, location: GeoIpLocation) {
+        // The tunnel might have been connected while the location was looked up.
+        if self.tunnel_state == TunnelStateTransition::Disconnected {
+            self.management_interface_broadcaster
+                .notify_location(location);
+        }
+    }
+
+    fn handle_relay_list_updated(&mut self, relay_list: RelayList) {
+        self.metrics
+            .set_relay_list_updated(self.relay_selector.get_last_updated());
+        self.management_interface_broadcaster
+            .notify_relay_list(relay_list);
+        if self.tunnel_state == TunnelStateTransition::Blocked(BlockReason::NoMatchingRelay) {
+            info!("Retrying to connect since the relay list was updated");
+            self.reconnect_tunnel("relay_list_updated");
+        } else {
+            self.verify_current_relay();
+        }
+    }
+
+    /// Reconnects to another relay if the relay in use is no longer in the relay list, or no
+    /// longer matches the relay constraints, and notifies clients about why.
+    fn verify_current_relay(&mut self) {
+        match self.tunnel_state {
+            TunnelStateTransition::Connecting | TunnelStateTransition::Connected => (),
+            _ => return,
+        }
+        let constraints = match self.settings.get_relay_settings() {
+            RelaySettings::Normal(constraints) => constraints,
+            RelaySettings::CustomTunnelEndpoint(_) => return,
+        };
+        let (hostname, endpoint) = match (&self.current_relay, self.current_endpoint) {
+            (Some(relay), Some(endpoint)) => (relay.hostname.clone(), endpoint),
+            _ => return,
+        };
+
+        if let Err(reason) = self.relay_selector.validate_relay(
+            &hostname,
+            &endpoint,
+            &constraints,
+            self.settings.get_relay_ip_version(),
+        ) {
+            info!("Reconnecting since {} can't be used: {}", hostname, reason);
+            self.management_interface_broadcaster
+                .notify_relay_invalidated(RelayInvalidated { hostname, reason });
+            self.reconnect_tunnel("relay_invalidated");
+        }
+    }
+
+    /// Schedules a switch to another relay after the rotation interval, if one is set. Replaces
+    /// any earlier scheduled rotation.
+    fn schedule_relay_rotation(&mut self) {
+        self.cancel_relay_rotation();
+        let interval = match self.settings.get_rotate_relay_interval() {
+            Some(interval) => Duration::from_secs(interval),
+            None => return,
+        };
+        let rotation_id = self.relay_rotation_id;
+        let event_tx = self.tx.clone();
+
+        self.relay_rotation_timer = Some(CancellableTimer::spawn(interval, move |cancellation| {
+            if network_activity::wait_for_idle(MAX_ROTATION_IDLE_WAIT, cancellation) {
+                let _ = event_tx.send(DaemonEvent::RotateRelay(rotation_id));
+            }
+        }));
+    }
+
+    /// Stops the timer of the scheduled rotation, if any.
+    fn cancel_relay_rotation(&mut self) {
+        self.relay_rotation_id = self.relay_rotation_id.wrapping_add(1);
+        if let Some(timer) = self.relay_rotation_timer.take() {
+            timer.cancel();
+        }
+    }
+
+    fn handle_rotate_relay(&mut self, rotation_id: u64) {
+        if rotation_id != self.relay_rotation_id
+            || self.tunnel_state != TunnelStateTransition::Connected
+        {
+            return;
+        }
+        let constraints = match self.settings.get_relay_settings() {
+            RelaySettings::Normal(constraints) => constraints,
+            RelaySettings::CustomTunnelEndpoint(_) => return,
+        };
+        let (current_hostname, account_token) =
+            match (&self.current_relay, self.settings.get_account_token()) {
+                (Some(relay), Some(account_token)) => (relay.hostname.clone(), account_token),
+                _ => return,
+            };
+
+        match self.relay_selector.get_other_tunnel_endpoint(
+            &constraints,
+            self.settings.get_relay_ip_version(),
+            &current_hostname,
+        ) {
+            Ok((relay, endpoint)) => {
+                info!(
+                    "Rotating relay from {} to {}",
+                    current_hostname, relay.hostname
+                );
+                self.current_relay = Some(relay);
+                self.current_endpoint = Some(endpoint);
+                self.metrics.count_reconnect("relay_rotation");
+                // Connecting directly from the connected state keeps the security policy in
+                // place until the tunnel to the new relay has been set up.
+                let parameters = self.build_tunnel_parameters(account_token, endpoint);
+                self.send_tunnel_command(TunnelCommand::Connect(parameters));
+            }
+            Err(error) => {
+                let chained_error = error.chain_err(|| "Unable to rotate relay");
+                warn!("{}", chained_error.display_chain());
+                self.schedule_relay_rotation();
+            }
+        }
+    }
+
+    /// Fetches the expiry of the current account. The next check is scheduled once it has been
+    /// fetched.
+    fn check_account_expiry(&mut self) {
+        self.cancel_account_expiry_check();
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => return,
+        };
+        let event_tx = self.tx.clone();
+        let request = self
+            .accounts_proxy
+            .get_expiry(account_token.clone())
+            .then(move |result| {
+                event_tx
+                    .send(DaemonEvent::AccountExpiryFetched(account_token, result))
+                    .map_err(|_| ())
+            });
+        self.tokio_remote.spawn(move |_| request);
+    }
+
+    fn handle_check_account_expiry(&mut self, check_id: u64) {
+        if check_id == self.account_expiry_check_id {
+            self.check_account_expiry();
+        }
+    }
+
+    /// Caches the fetched account expiry and notifies subscribers if the account expired, or
+    /// passed one of the warning thresholds, since the last check. Blocks if the account has
+    /// expired, and connects again if it got more time.
+    fn handle_account_expiry_fetched(
+        &mut self,
+        account_token: AccountToken,
+        result: ::std::result::Result<DateTime<Utc>, mullvad_rpc::Error>,
+    ) {
+        if self.settings.get_account_token().as_ref() != Some(&account_token) {
+            return;
+        }
+        let expiry = match result {
+            Ok(expiry) => expiry,
+            Err(error) => {
+                let chained_error = Error::with_chain(error, "Unable to fetch account expiry");
+                warn!("{}", chained_error.display_chain());
+                self.schedule_account_expiry_check(account_expiry::RETRY_INTERVAL);
+                return;
+            }
+        };
+        if let Err(error) = self.account_history.set_expiry(&account_token, expiry) {
+            let chained_error = error.chain_err(|| "Unable to save the account expiry to history");
+            self.report_error(&chained_error);
+        }
+        let now = Utc::now();
+        let warning_thresholds = self.settings.get_account_expiry_warnings().to_vec();
+        let was_expired = self.account_has_expired();
+
+        if let Some(account_expiry) = account_expiry::expiry_event(
+            self.account_expiry.as_ref(),
+            expiry,
+            now,
+            &warning_thresholds,
+        ) {
+            if let Some(threshold) = account_expiry.warning_threshold {
+                info!("The account expires within {} seconds", threshold);
+            }
+            self.account_expiry = Some(account_expiry.clone());
+            self.management_interface_broadcaster
+                .notify_account_expiry(account_expiry);
+        }
+
+        let expired = self.account_has_expired();
+        if expired && !was_expired {
+            info!("Blocking since the account has expired");
+            self.reconnect_tunnel("account_expired");
+        } else if !expired
+            && self.tunnel_state == TunnelStateTransition::Blocked(BlockReason::AccountExpired)
+        {
+            info!("Connecting since the account has time left again");
+            self.reconnect_tunnel("account_time_added");
+        }
+
+        let next_check = account_expiry::next_check_delay(expiry, now, &warning_thresholds);
+        self.schedule_account_expiry_check(next_check);
+    }
+
+    /// Schedules a check of the account expiry after `delay`. Replaces any earlier scheduled
+    /// check.
+    fn schedule_account_expiry_check(&mut self, delay: Duration) {
+        self.cancel_account_expiry_check();
+        let check_id = self.account_expiry_check_id;
+        let event_tx = self.tx.clone();
+
+        self.account_expiry_check_timer = Some(CancellableTimer::spawn(delay, move |_| {
+            let _ = event_tx.send(DaemonEvent::CheckAccountExpiry(check_id));
+        }));
+    }
+
+    /// Stops the timer of the scheduled account expiry check, if any.
+    fn cancel_account_expiry_check(&mut self) {
+        self.account_expiry_check_id = self.account_expiry_check_id.wrapping_add(1);
+        if let Some(timer) = self.account_expiry_check_timer.take() {
+            timer.cancel();
+        }
+    }
+
+    /// Forgets the expiry of the previous account and checks the expiry of the new one. The new
+    /// account is marked as used in the account history.
+    fn handle_account_changed(&mut self) {
+        self.account_expiry = None;
+        if let Some(account_token) = self.settings.get_account_token() {
+            if let Err(error) = self.account_history.add_account_token(account_token) {
+                let chained_error =
+                    error.chain_err(|| "Unable to add an account into the account history");
+                self.report_error(&chained_error);
+            }
+        }
+        self.check_account_expiry();
+    }
+
+    fn account_has_expired(&self) -> bool {
+        self.account_block_reason().is_some()
+    }
+
+    fn account_block_reason(&self) -> Option<BlockReason> {
+        account_expiry::block_reason(self.account_expiry.as_ref(), Utc::now())
     }
 
     fn schedule_reconnect(&mut self, delay: Duration) {
@@ -361,14 +822,47 @@ impl Daemon {
             GetState(tx) => self.on_get_state(tx),
             GetCurrentLocation(tx) => self.on_get_current_location(tx),
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token),
+            GetAccountExpiry(tx) => self.on_get_account_expiry(tx),
+            CreateAccount(tx) => self.on_create_account(tx),
+            SubmitVoucher(tx, voucher_code) => self.on_submit_voucher(tx, voucher_code),
             GetRelayLocations(tx) => self.on_get_relay_locations(tx),
+            GetMatchingRelays(tx, constraints, explain) => {
+                self.on_get_matching_relays(tx, constraints, explain)
+            }
+            UpdateRelayList(tx) => self.on_update_relay_list(tx),
+            GetUserRelays(tx) => self.on_get_user_relays(tx),
+            SetUserRelays(tx, user_relays) => self.on_set_user_relays(tx, user_relays),
             SetAccount(tx, account_token) => self.on_set_account(tx, account_token),
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update),
+            SetRelayIpVersion(tx, ip_version) => self.on_set_relay_ip_version(tx, ip_version),
+            SetRotateRelayInterval(tx, interval) => self.on_set_rotate_relay_interval(tx, interval),
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan),
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect),
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg),
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6),
+            GetAccountHistory(tx) => self.on_get_account_history(tx),
+            RemoveAccountFromHistory(tx, account_token) => {
+                self.on_remove_account_from_history(tx, account_token)
+            }
+            SetAccountLabel(tx, account_token, label) => {
+                self.on_set_account_label(tx, account_token, label)
+            }
+            SetAccountHistoryLimit(tx, limit) => self.on_set_account_history_limit(tx, limit),
             GetSettings(tx) => self.on_get_settings(tx),
+            ExportSettings(tx, include_account_token) => {
+                self.on_export_settings(tx, include_account_token)
+            }
+            ImportSettings(tx, settings) => self.on_import_settings(tx, settings),
+            UpdateSettings(tx, patch) => self.on_update_settings(tx, patch),
+            GetProfiles(tx) => self.on_get_profiles(tx),
+            SaveProfile(tx, name) => self.on_save_profile(tx, name),
+            RemoveProfile(tx, name) => self.on_remove_profile(tx, name),
+            SwitchProfile(tx, name) => self.on_switch_profile(tx, name),
+            SetRemoteManagement(tx, remote_management) => {
+                self.on_set_remote_management(tx, remote_management)
+            }
+            GetRemoteManagementToken(tx) => self.on_get_remote_management_token(tx),
+            ResetRemoteManagementToken(tx) => self.on_reset_remote_management_token(tx),
             GetVersionInfo(tx) => self.on_get_version_info(tx),
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
             Shutdown => self.handle_trigger_shutdown_event(),
@@ -393,16 +887,7 @@ impl Daemon {
     }
 
     fn on_get_current_location(&self, tx: OneshotSender<GeoIpLocation>) {
-        if let Some(ref relay) = self.current_relay {
-            let location = relay.location.as_ref().cloned().unwrap();
-            let geo_ip_location = GeoIpLocation {
-                ip: IpAddr::V4(relay.ipv4_addr_exit),
-                country: location.country,
-                city: Some(location.city),
-                latitude: location.latitude,
-                longitude: location.longitude,
-                mullvad_exit_ip: true,
-            };
+        if let Some(geo_ip_location) = self.get_relay_location() {
             Self::oneshot_send(tx, geo_ip_location, "current location");
         } else {
             let https_handle = self.https_handle.clone();
@@ -416,6 +901,21 @@ impl Daemon {
         }
     }
 
+    /// Returns the location of the relay in use, if any.
+    fn get_relay_location(&self) -> Option<GeoIpLocation> {
+        self.current_relay.as_ref().map(|relay| {
+            let location = relay.location.as_ref().cloned().unwrap();
+            GeoIpLocation {
+                ip: IpAddr::V4(relay.ipv4_addr_exit),
+                country: location.country,
+                city: Some(location.city),
+                latitude: location.latitude,
+                longitude: location.longitude,
+                mullvad_exit_ip: true,
+            }
+        })
+    }
+
     fn on_get_account_data(
         &mut self,
         tx: OneshotSender<BoxFuture<AccountData, mullvad_rpc::Error>>,
@@ -428,10 +928,98 @@ impl Daemon {
         Self::oneshot_send(tx, Box::new(rpc_call), "account data")
     }
 
+    fn on_create_account(
+        &mut self,
+        tx: OneshotSender<BoxFuture<AccountToken, mullvad_rpc::Error>>,
+    ) {
+        let rpc_call = self.accounts_proxy.create_account();
+        Self::oneshot_send(tx, Box::new(rpc_call), "create_account response")
+    }
+
+    /// Redeems a voucher on the current account. The new expiry is handled as if it had been
+    /// fetched, so a blocked tunnel connects right away.
+    fn on_submit_voucher(
+        &mut self,
+        tx: OneshotSender<
+            ::std::result::Result<BoxFuture<VoucherSubmission, mullvad_rpc::Error>, ()>,
+        >,
+        voucher_code: String,
+    ) {
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => {
+                Self::oneshot_send(tx, Err(()), "submit_voucher response");
+                return;
+            }
+        };
+        let event_tx = self.tx.clone();
+        let rpc_call = self
+            .accounts_proxy
+            .submit_voucher(account_token.clone(), voucher_code)
+            .map(move |submission| {
+                let _ = event_tx.send(DaemonEvent::AccountExpiryFetched(
+                    account_token,
+                    Ok(submission.new_expiry),
+                ));
+                submission
+            });
+        Self::oneshot_send(tx, Ok(Box::new(rpc_call)), "submit_voucher response")
+    }
+
+    fn on_get_account_expiry(&self, tx: OneshotSender<Option<AccountExpiry>>) {
+        let account_expiry = self.account_expiry.clone();
+        Self::oneshot_send(tx, account_expiry, "get_account_expiry response");
+    }
+
     fn on_get_relay_locations(&mut self, tx: OneshotSender<RelayList>) {
         Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
     }
 
+    fn on_get_matching_relays(
+        &mut self,
+        tx: OneshotSender<MatchingRelays>,
+        constraints: RelayConstraints,
+        explain: bool,
+    ) {
+        let matching_relays = self
+            .relay_selector
+            .get_matching_relays(&constraints, explain);
+        Self::oneshot_send(tx, matching_relays, "matching relays");
+    }
+
+    fn on_update_relay_list(&mut self, tx: OneshotSender<()>) {
+        self.relay_selector.force_update();
+        Self::oneshot_send(tx, (), "update_relay_list response");
+    }
+
+    fn on_get_user_relays(&self, tx: OneshotSender<RelayList>) {
+        Self::oneshot_send(
+            tx,
+            self.user_relays.get_relays().clone(),
+            "get_user_relays response",
+        );
+    }
+
+    fn on_set_user_relays(
+        &mut self,
+        tx: OneshotSender<Result<(), String>>,
+        user_relays: RelayList,
+    ) {
+        if let Some(hostname) = self.relay_selector.find_shadowing_user_relay(&user_relays) {
+            Self::oneshot_send(tx, Err(hostname), "set_user_relays response");
+            return;
+        }
+        let save_result = self.user_relays.set_relays(user_relays.clone());
+        match save_result.chain_err(|| "Unable to save user relays") {
+            Ok(()) => {
+                Self::oneshot_send(tx, Ok(()), "set_user_relays response");
+                let locations = self.relay_selector.set_user_relays(user_relays);
+                self.handle_relay_list_updated(locations);
+            }
+            Err(e) => self.report_error(&e),
+        }
+    }
+
 
     fn on_set_account(&mut self, tx: OneshotSender<()>, account_token: Option<String>) {
         let account_token_cleared = account_token.is_none();
@@ -443,16 +1031,17 @@ impl Daemon {
                 if account_changed {
                     self.management_interface_broadcaster
                         .notify_settings(&self.settings);
+                    self.handle_account_changed();
                     if account_token_cleared {
                         info!("Disconnecting because account token was cleared");
                         let _ = self.set_target_state(TargetState::Unsecured);
                     } else {
                         info!("Initiating tunnel restart because the account token changed");
-                        self.reconnect_tunnel();
+                        self.reconnect_tunnel("account_changed");
                     }
                 }
             }
-            Err(e) => error!("{}", e.display_chain()),
+            Err(e) => self.report_error(&e),
         }
     }
 
@@ -484,10 +1073,43 @@ impl Daemon {
                     self.management_interface_broadcaster
                         .notify_settings(&self.settings);
                     info!("Initiating tunnel restart because the relay settings changed");
-                    self.reconnect_tunnel();
+                    self.reconnect_tunnel("settings_changed");
+                }
+            }
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_set_relay_ip_version(&mut self, tx: OneshotSender<()>, ip_version: IpVersionPreference) {
+        let save_result = self.settings.set_relay_ip_version(ip_version);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_relay_ip_version response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    info!("Initiating tunnel restart because the relay IP version changed");
+                    self.reconnect_tunnel("settings_changed");
+                }
+            }
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_set_rotate_relay_interval(&mut self, tx: OneshotSender<()>, interval: Option<u64>) {
+        let save_result = self.settings.set_rotate_relay_interval(interval);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_rotate_relay_interval response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    if self.tunnel_state == TunnelStateTransition::Connected {
+                        self.schedule_relay_rotation();
+                    }
                 }
             }
-            Err(e) => error!("{}", e.display_chain()),
+            Err(e) => self.report_error(&e),
         }
     }
 
@@ -502,7 +1124,7 @@ impl Daemon {
                     self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
                 }
             }
-            Err(e) => error!("{}", e.display_chain()),
+            Err(e) => self.report_error(&e),
         }
     }
 
@@ -516,7 +1138,7 @@ impl Daemon {
                         .notify_settings(&self.settings);
                 }
             }
-            Err(e) => error!("{}", e.display_chain()),
+            Err(e) => self.report_error(&e),
         }
     }
 
@@ -530,7 +1152,7 @@ impl Daemon {
                         .notify_settings(&self.settings);
                 }
             }
-            Err(e) => error!("{}", e.display_chain()),
+            Err(e) => self.report_error(&e),
         }
     }
 
@@ -543,10 +1165,55 @@ impl Daemon {
                     self.management_interface_broadcaster
                         .notify_settings(&self.settings);
                     info!("Initiating tunnel restart because the enable IPv6 setting changed");
-                    self.reconnect_tunnel();
+                    self.reconnect_tunnel("settings_changed");
+                }
+            }
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_get_account_history(&self, tx: OneshotSender<Vec<AccountHistoryEntry>>) {
+        let entries = self.account_history.get_entries().to_vec();
+        Self::oneshot_send(tx, entries, "get_account_history response");
+    }
+
+    fn on_remove_account_from_history(&mut self, tx: OneshotSender<()>, account_token: String) {
+        let save_result = self.account_history.remove_account_token(account_token);
+        match save_result.chain_err(|| "Unable to remove account from history") {
+            Ok(()) => Self::oneshot_send(tx, (), "remove_account_from_history response"),
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_set_account_label(
+        &mut self,
+        tx: OneshotSender<bool>,
+        account_token: String,
+        label: Option<String>,
+    ) {
+        let save_result = self.account_history.set_label(&account_token, label);
+        match save_result.chain_err(|| "Unable to save account label") {
+            Ok(found) => Self::oneshot_send(tx, found, "set_account_label response"),
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_set_account_history_limit(&mut self, tx: OneshotSender<()>, limit: usize) {
+        let save_result = self.settings.set_account_history_limit(limit);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_account_history_limit response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    if let Err(error) = self.account_history.set_limit(limit) {
+                        let chained_error =
+                            error.chain_err(|| "Unable to apply account history limit");
+                        self.report_error(&chained_error);
+                    }
                 }
             }
-            Err(e) => error!("{}", e.display_chain()),
+            Err(e) => self.report_error(&e),
         }
     }
 
@@ -554,6 +1221,332 @@ impl Daemon {
         Self::oneshot_send(tx, self.settings.clone(), "get_settings response");
     }
 
+    fn on_export_settings(&self, tx: OneshotSender<Settings>, include_account_token: bool) {
+        let settings = self.settings.export(include_account_token);
+        Self::oneshot_send(tx, settings, "export_settings response");
+    }
+
+    fn on_import_settings(
+        &mut self,
+        tx: OneshotSender<::std::result::Result<(), UpdateSettingsError>>,
+        mut settings: Settings,
+    ) {
+        // Unlike patches, imported settings replace all settings, so all of them are validated.
+        let locations = self.relay_selector.get_locations();
+        let mut errors = settings_validation::validate(&settings, &locations);
+        // Exported settings never contain it, and a shared settings file shouldn't be able to
+        // turn on remote management.
+        if settings.get_remote_management() != RemoteManagementSettings::default() {
+            errors.push(FieldError::new(
+                "remote_management",
+                "Can't be imported, use set_remote_management",
+            ));
+        }
+        if !errors.is_empty() {
+            let error = UpdateSettingsError::InvalidSettings(errors);
+            Self::oneshot_send(tx, Err(error), "import_settings response");
+            return;
+        }
+
+        let old_settings = self.settings.clone();
+        let save_result = self
+            .policy
+            .enforce(&mut settings)
+            .and_then(|_| self.settings.import(settings));
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "import_settings response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    self.apply_changed_settings(&old_settings);
+                }
+            }
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_update_settings(
+        &mut self,
+        tx: OneshotSender<::std::result::Result<(), UpdateSettingsError>>,
+        patch: serde_json::Value,
+    ) {
+        let revision = self.settings.get_revision();
+        if let Some(expected_revision) = patch.get(REVISION_KEY) {
+            if expected_revision.as_u64() != Some(revision) {
+                info!(
+                    "Refusing to update settings at revision {} based on revision {}",
+                    revision, expected_revision
+                );
+                let error = UpdateSettingsError::Conflict(revision);
+                Self::oneshot_send(tx, Err(error), "update_settings response");
+                return;
+            }
+        }
+        let settings = match self.settings.patched(&patch) {
+            Ok(settings) => settings,
+            Err(errors) => {
+                let error = UpdateSettingsError::InvalidSettings(errors);
+                Self::oneshot_send(tx, Err(error), "update_settings response");
+                return;
+            }
+        };
+        // Only settings changed by the patch are validated, so settings that were valid when they
+        // were set, such as a relay that has since been removed, don't block other changes.
+        let locations = self.relay_selector.get_locations();
+        let current_errors = settings_validation::validate(&self.settings, &locations);
+        let errors: Vec<_> = settings_validation::validate(&settings, &locations)
+            .into_iter()
+            .filter(|error| !current_errors.contains(error))
+            .collect();
+        if !errors.is_empty() {
+            let error = UpdateSettingsError::InvalidSettings(errors);
+            Self::oneshot_send(tx, Err(error), "update_settings response");
+            return;
+        }
+
+        let old_settings = self.settings.clone();
+        let save_result = self.settings.update(settings);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "update_settings response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    self.apply_changed_settings(&old_settings);
+                }
+            }
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_get_profiles(&self, tx: OneshotSender<Vec<String>>) {
+        Self::oneshot_send(tx, self.profiles.names(), "get_profiles response");
+    }
+
+    fn on_save_profile(&mut self, tx: OneshotSender<()>, name: String) {
+        let save_result = self.profiles.set(name, &self.settings);
+        match save_result.chain_err(|| "Unable to save profile") {
+            Ok(()) => Self::oneshot_send(tx, (), "save_profile response"),
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_remove_profile(
+        &mut self,
+        tx: OneshotSender<::std::result::Result<(), ()>>,
+        name: String,
+    ) {
+        let remove_result = self.profiles.remove(&name);
+        match remove_result.chain_err(|| "Unable to remove profile") {
+            Ok(removed) => {
+                let response = if removed { Ok(()) } else { Err(()) };
+                Self::oneshot_send(tx, response, "remove_profile response");
+            }
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_switch_profile(
+        &mut self,
+        tx: OneshotSender<::std::result::Result<(), SwitchProfileError>>,
+        name: String,
+    ) {
+        let mut profile = match self.profiles.get(&name) {
+            Some(profile) => profile.clone(),
+            None => {
+                let error = SwitchProfileError::NoSuchProfile;
+                Self::oneshot_send(tx, Err(error), "switch_profile response");
+                return;
+            }
+        };
+        // Profiles were valid when saved, but may refer to relays that have since been removed.
+        let locations = self.relay_selector.get_locations();
+        let errors = settings_validation::validate(&profile, &locations);
+        if !errors.is_empty() {
+            let error = SwitchProfileError::InvalidSettings(errors);
+            Self::oneshot_send(tx, Err(error), "switch_profile response");
+            return;
+        }
+        let old_settings = self.settings.clone();
+        let save_result = self
+            .policy
+            .enforce(&mut profile)
+            .and_then(|_| self.settings.switch_profile(name, profile));
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "switch_profile response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    self.apply_changed_settings(&old_settings);
+                }
+            }
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_set_remote_management(
+        &mut self,
+        tx: OneshotSender<()>,
+        remote_management: RemoteManagementSettings,
+    ) {
+        let save_result = self.settings.set_remote_management(remote_management);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_remote_management response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    self.restart_remote_management_server();
+                }
+            }
+            Err(e) => self.report_error(&e),
+        }
+    }
+
+    fn on_get_remote_management_token(
+        &mut self,
+        tx: OneshotSender<::std::result::Result<String, ()>>,
+    ) {
+        let result = self.remote_management_token.get().map_err(|error| {
+            let chained_error = error.chain_err(|| "Unable to get remote management token");
+            error!("{}", chained_error.display_chain());
+        });
+        Self::oneshot_send(tx, result, "get_remote_management_token response");
+    }
+
+    fn on_reset_remote_management_token(
+        &mut self,
+        tx: OneshotSender<::std::result::Result<String, ()>>,
+    ) {
+        let result = self.remote_management_token.reset().map_err(|error| {
+            let chained_error = error.chain_err(|| "Unable to reset remote management token");
+            error!("{}", chained_error.display_chain());
+        });
+        let token_changed = result.is_ok();
+        Self::oneshot_send(tx, result, "reset_remote_management_token response");
+        if token_changed && self.remote_management_server.is_some() {
+            info!("Restarting the remote management interface since its token changed");
+            self.restart_remote_management_server();
+        }
+    }
+
+    /// Restarts the remote management interface on the configured address, if any. Incoming
+    /// connections to it are only accepted in the firewall while it's running.
+    fn restart_remote_management_server(&mut self) {
+        if let Some(server) = self.remote_management_server.take() {
+            info!(
+                "Stopping remote management interface on {}",
+                server.address()
+            );
+            server.close();
+        }
+        self.remote_management_server = self.start_remote_management_server();
+        let allowed_incoming = self
+            .remote_management_server
+            .as_ref()
+            .map(|server| *server.address());
+        self.send_tunnel_command(TunnelCommand::AllowIncoming(allowed_incoming));
+    }
+
+    fn start_remote_management_server(&mut self) -> Option<RemoteManagementServer> {
+        let address = self.settings.get_remote_management().address?;
+        let token = match self.remote_management_token.get() {
+            Ok(token) => token,
+            Err(error) => {
+                let chained_error = error.chain_err(|| "Unable to start remote management");
+                self.report_error(&chained_error);
+                return None;
+            }
+        };
+        let start_result = RemoteManagementServer::start(
+            IntoSender::from(self.tx.clone()),
+            self.policy.clone(),
+            self.access_policy.clone(),
+            &self.management_interface_broadcaster,
+            address,
+            token,
+            &self.metrics,
+        );
+        match start_result {
+            Ok(server) => {
+                info!(
+                    "Remote management interface listening on {}",
+                    server.address()
+                );
+                Some(server)
+            }
+            Err(error) => {
+                let message = format!(
+                    "Unable to start remote management interface on {}: {}",
+                    address, error
+                );
+                error!("{}", message);
+                self.management_interface_broadcaster.notify_error(message);
+                None
+            }
+        }
+    }
+
+    /// Acts on the settings that differ from `old_settings` after several settings were replaced
+    /// at once, the same way as when the settings are changed one at a time. Only reconnects if
+    /// a setting used to connect the tunnel changed.
+    fn apply_changed_settings(&mut self, old_settings: &Settings) {
+        if self.settings.get_account_token() != old_settings.get_account_token() {
+            self.handle_account_changed();
+        }
+
+        let account_history_limit = self.settings.get_account_history_limit();
+        if account_history_limit != old_settings.get_account_history_limit() {
+            if let Err(error) = self.account_history.set_limit(account_history_limit) {
+                let chained_error = error.chain_err(|| "Unable to apply account history limit");
+                self.report_error(&chained_error);
+            }
+        }
+
+        let allow_lan = self.settings.get_allow_lan();
+        if allow_lan != old_settings.get_allow_lan() {
+            self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
+        }
+
+        if self.settings.get_remote_management() != old_settings.get_remote_management() {
+            self.restart_remote_management_server();
+        }
+
+        let reconnect = self.settings.get_account_token() != old_settings.get_account_token()
+            || self.settings.get_relay_settings() != old_settings.get_relay_settings()
+            || self.settings.get_relay_ip_version() != old_settings.get_relay_ip_version()
+            || self.settings.get_tunnel_options().enable_ipv6
+                != old_settings.get_tunnel_options().enable_ipv6;
+        let account_token_cleared = self.settings.get_account_token().is_none()
+            && old_settings.get_account_token().is_some();
+        if account_token_cleared {
+            info!("Disconnecting because account token was cleared");
+            let _ = self.set_target_state(TargetState::Unsecured);
+        } else if reconnect {
+            info!("Initiating tunnel restart because the tunnel settings changed");
+            self.reconnect_tunnel("settings_changed");
+        } else if self.settings.get_rotate_relay_interval()
+            != old_settings.get_rotate_relay_interval()
+            && self.tunnel_state == TunnelStateTransition::Connected
+        {
+            self.schedule_relay_rotation();
+        }
+    }
+
+    /// Logs an error that no client would otherwise be told about, and sends it to `events`
+    /// subscribers.
+    fn report_error<E: ChainedError>(&self, error: &E) {
+        error!("{}", error.display_chain());
+        let message = error
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>()
+            .join(": ");
+        self.management_interface_broadcaster.notify_error(message);
+    }
+
     fn oneshot_send<T>(tx: OneshotSender<T>, t: T, msg: &'static str) {
         if let Err(_) = tx.send(t) {
             warn!("Unable to send {} to management interface client", msg);
@@ -565,6 +1558,7 @@ impl Daemon {
     }
 
     fn handle_trigger_shutdown_event(&mut self) {
+        self.management_interface_broadcaster.notify_shutting_down();
         self.state.shutdown(&self.tunnel_state);
         self.disconnect_tunnel();
     }
@@ -590,17 +1584,27 @@ impl Daemon {
         Ok(())
     }
 
+    /// Connects with the current relay settings. Blocks instead if the account is known to have
+    /// expired, and fetches the expiry again right away, since time may have been added since the
+    /// last check. The tunnel connects as soon as the account turns out to have time left.
     fn connect_tunnel(&mut self, account_token: AccountToken) {
+        if let Some(block_reason) = self.account_block_reason() {
+            let allow_lan = self.settings.get_allow_lan();
+            self.send_tunnel_command(TunnelCommand::Block(block_reason, allow_lan));
+            self.check_account_expiry();
+            return;
+        }
         let command = match self.settings.get_relay_settings() {
             RelaySettings::CustomTunnelEndpoint(custom_relay) => custom_relay
                 .to_tunnel_endpoint()
                 .chain_err(|| "Custom tunnel endpoint could not be resolved"),
             RelaySettings::Normal(constraints) => self
                 .relay_selector
-                .get_tunnel_endpoint(&constraints)
+                .get_tunnel_endpoint(&constraints, self.settings.get_relay_ip_version())
                 .chain_err(|| "No valid relay servers match the current settings")
                 .map(|(relay, endpoint)| {
                     self.current_relay = Some(relay);
+                    self.current_endpoint = Some(endpoint);
                     endpoint
                 }),
         }.map(|endpoint| self.build_tunnel_parameters(account_token, endpoint))
@@ -616,9 +1620,12 @@ impl Daemon {
         self.send_tunnel_command(TunnelCommand::Disconnect);
     }
 
-    fn reconnect_tunnel(&mut self) {
+    /// Reconnects the tunnel if it should be secured. `reason` labels the reconnect in the
+    /// metrics.
+    fn reconnect_tunnel(&mut self, reason: &'static str) {
         if self.target_state == TargetState::Secured {
             if let Some(account_token) = self.settings.get_account_token() {
+                self.metrics.count_reconnect(reason);
                 self.connect_tunnel(account_token);
             }
         }
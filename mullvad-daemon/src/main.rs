@@ -146,6 +146,8 @@ fn create_daemon(config: cli::Config) -> Result<Daemon> {
         resource_dir,
         cache_dir,
         version::CURRENT.to_owned(),
+        config.management_group,
+        config.metrics_address,
     ).chain_err(|| "Unable to initialize daemon")
 }
 
@@ -1,3 +1,5 @@
+use access_policy::{AccessPolicy, Client};
+use chrono::Utc;
 use error_chain::ChainedError;
 use jsonrpc_core::futures::sync::oneshot::Sender as OneshotSender;
 use jsonrpc_core::futures::{future, sync, Future};
@@ -5,36 +7,51 @@ use jsonrpc_core::{Error, ErrorCode, MetaIoHandler, Metadata};
 use jsonrpc_ipc_server;
 use jsonrpc_macros::pubsub;
 use jsonrpc_pubsub::{PubSubHandler, PubSubMetadata, Session, SubscriptionId};
+use jsonrpc_ws_server::{self, ws};
+use metrics::{self, Metrics};
 use mullvad_rpc;
-use mullvad_types::account::{AccountData, AccountToken};
+use mullvad_types::account::{
+    AccountData, AccountExpiry, AccountHistoryEntry, AccountToken, VoucherSubmission,
+};
+use mullvad_types::api::ApiInfo;
+use mullvad_types::events::{DaemonEvent, DaemonEventKind};
 use mullvad_types::location::GeoIpLocation;
 
 use mullvad_paths;
-use mullvad_types::relay_constraints::RelaySettingsUpdate;
-use mullvad_types::relay_list::RelayList;
-use mullvad_types::settings::Settings;
+use mullvad_types::relay_constraints::{
+    IpVersionPreference, RelayConstraints, RelaySettingsUpdate,
+};
+use mullvad_types::relay_list::{MatchingRelays, RelayInvalidated, RelayList};
+use mullvad_types::settings::policy::Policy;
+use mullvad_types::settings::{FieldError, RemoteManagementSettings, Settings};
 use mullvad_types::states::TargetState;
 use mullvad_types::version;
 
 use serde;
+use serde_json;
 
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex, RwLock};
 
 use talpid_core::mpsc::IntoSender;
-use talpid_ipc;
+use talpid_ipc::{self, PeerCredentials};
 use talpid_types::tunnel::TunnelStateTransition;
 use uuid;
 
-use account_history::{AccountHistory, Error as AccountHistoryError};
+use profiles;
+use user_relays;
 
 /// FIXME(linus): This is here just because the futures crate has deprecated it and jsonrpc_core
 /// did not introduce their own yet (https://github.com/paritytech/jsonrpc/pull/196).
 /// Remove this and use the one in jsonrpc_core when that is released.
 pub type BoxFuture<T, E> = Box<Future<Item = T, Error = E> + Send>;
 
+/// The shortest allowed relay rotation interval, in seconds. Rotating more often than this would
+/// mostly keep the tunnel reconnecting.
+pub const MIN_ROTATE_RELAY_INTERVAL: u64 = 60;
+
 build_rpc_trait! {
     pub trait ManagementInterfaceApi {
         type Metadata;
@@ -44,10 +61,50 @@ build_rpc_trait! {
         #[rpc(meta, name = "get_account_data")]
         fn get_account_data(&self, Self::Metadata, AccountToken) -> BoxFuture<AccountData, Error>;
 
+        /// Returns the expiry of the current account, as last checked by the daemon. `None` if no
+        /// account is set or its expiry hasn't been fetched yet.
+        #[rpc(meta, name = "get_account_expiry")]
+        fn get_account_expiry(&self, Self::Metadata) -> BoxFuture<Option<AccountExpiry>, Error>;
+
         /// Returns available countries.
         #[rpc(meta, name = "get_relay_locations")]
         fn get_relay_locations(&self, Self::Metadata) -> BoxFuture<RelayList, Error>;
 
+        /// Returns the relays, with their endpoints and weights, that match the given
+        /// constraints. If the boolean is true the relays that were filtered out are also
+        /// returned, together with the rule that rejected them.
+        #[rpc(meta, name = "get_matching_relays")]
+        fn get_matching_relays(
+            &self,
+            Self::Metadata,
+            RelayConstraints,
+            bool
+            ) -> BoxFuture<MatchingRelays, Error>;
+
+        /// Triggers a download of the newest relay list. Subscribers of `relay_list_updated` are
+        /// notified if the relay list changed.
+        #[rpc(meta, name = "update_relay_list")]
+        fn update_relay_list(&self, Self::Metadata) -> BoxFuture<(), Error>;
+
+        /// Returns the relays added by the user, grouped by country and city.
+        #[rpc(meta, name = "get_user_relays")]
+        fn get_user_relays(&self, Self::Metadata) -> BoxFuture<RelayList, Error>;
+
+        /// Replaces the relays added by the user. They are merged into the relay list and
+        /// selected from just like any other relay. Subscribers of `relay_list_updated` are
+        /// notified with the new relay list. Hostnames used by relays from the API are refused.
+        #[rpc(meta, name = "set_user_relays")]
+        fn set_user_relays(&self, Self::Metadata, RelayList) -> BoxFuture<(), Error>;
+
+        /// Creates a new account and sets it as the account to connect with. Returns the new
+        /// account token.
+        #[rpc(meta, name = "create_account")]
+        fn create_account(&self, Self::Metadata) -> BoxFuture<AccountToken, Error>;
+
+        /// Redeems a voucher on the current account and returns the time it added.
+        #[rpc(meta, name = "submit_voucher")]
+        fn submit_voucher(&self, Self::Metadata, String) -> BoxFuture<VoucherSubmission, Error>;
+
         /// Set which account to connect with.
         #[rpc(meta, name = "set_account")]
         fn set_account(&self, Self::Metadata, Option<AccountToken>) -> BoxFuture<(), Error>;
@@ -59,6 +116,19 @@ build_rpc_trait! {
             Self::Metadata, RelaySettingsUpdate
             ) -> BoxFuture<(), Error>;
 
+        /// Set which IP version to use when connecting to relays.
+        #[rpc(meta, name = "set_relay_ip_version")]
+        fn set_relay_ip_version(
+            &self,
+            Self::Metadata,
+            IpVersionPreference
+            ) -> BoxFuture<(), Error>;
+
+        /// Set how often, in seconds, to switch to another relay while connected. `null` turns
+        /// rotation off. The interval must be at least `MIN_ROTATE_RELAY_INTERVAL` seconds.
+        #[rpc(meta, name = "set_rotate_relay_interval")]
+        fn set_rotate_relay_interval(&self, Self::Metadata, Option<u64>) -> BoxFuture<(), Error>;
+
         /// Set if the client should allow communication with the LAN while in secured state.
         #[rpc(meta, name = "set_allow_lan")]
         fn set_allow_lan(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
@@ -90,10 +160,29 @@ build_rpc_trait! {
         #[rpc(meta, name = "shutdown")]
         fn shutdown(&self, Self::Metadata) -> BoxFuture<(), Error>;
 
-        /// Get previously used account tokens from the account history
+        /// Get previously used account tokens from the account history, least recently used
+        /// first. Only for privileged clients.
         #[rpc(meta, name = "get_account_history")]
         fn get_account_history(&self, Self::Metadata) -> BoxFuture<Vec<AccountToken>, Error>;
 
+        /// Returns the accounts in the account history, least recently used first, with their
+        /// labels, when they were used and their last known expiry. Only for privileged clients.
+        #[rpc(meta, name = "list_accounts")]
+        fn list_accounts(&self, Self::Metadata) -> BoxFuture<Vec<AccountHistoryEntry>, Error>;
+
+        /// Gives an account in the account history a label. `null` or an empty label removes it.
+        #[rpc(meta, name = "set_account_label")]
+        fn set_account_label(
+            &self,
+            Self::Metadata,
+            AccountToken,
+            Option<String>
+            ) -> BoxFuture<(), Error>;
+
+        /// Set how many of the most recently used accounts to remember in the account history.
+        #[rpc(meta, name = "set_account_history_limit")]
+        fn set_account_history_limit(&self, Self::Metadata, usize) -> BoxFuture<(), Error>;
+
         /// Remove given account token from the account history
         #[rpc(meta, name = "remove_account_from_history")]
         fn remove_account_from_history(&self, Self::Metadata, AccountToken) -> BoxFuture<(), Error>;
@@ -106,10 +195,79 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_enable_ipv6")]
         fn set_enable_ipv6(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
-        /// Returns the current daemon settings
+        /// Returns the current daemon settings. The account token is left out for clients that
+        /// are not privileged.
         #[rpc(meta, name = "get_settings")]
         fn get_settings(&self, Self::Metadata) -> BoxFuture<Settings, Error>;
 
+        /// Returns the daemon settings in the format they can be imported in. The account token is
+        /// only included if the argument is true.
+        #[rpc(meta, name = "export_settings")]
+        fn export_settings(&self, Self::Metadata, bool) -> BoxFuture<Settings, Error>;
+
+        /// Replaces all daemon settings with the given ones, as exported by `export_settings`.
+        /// Settings exported by older versions are migrated first. Nothing is changed if any
+        /// setting is invalid, in which case the error data lists the invalid settings like for
+        /// `update_settings`. The current account token is kept if none is given.
+        #[rpc(meta, name = "import_settings")]
+        fn import_settings(&self, Self::Metadata, serde_json::Value) -> BoxFuture<(), Error>;
+
+        /// Changes several settings at once by applying a JSON merge patch (RFC 7396) to the
+        /// settings, as returned by `get_settings`. Nothing is changed if any setting is invalid,
+        /// in which case the error data lists the invalid settings as `{ field, message }`
+        /// objects. Sends a single `settings` notification.
+        ///
+        /// If the patch contains a `revision`, the update is refused with a conflict error if the
+        /// settings have been saved since that revision, for example by another client. The error
+        /// data is then the current revision.
+        #[rpc(meta, name = "update_settings")]
+        fn update_settings(&self, Self::Metadata, serde_json::Value) -> BoxFuture<(), Error>;
+
+        /// Returns the names of all profiles. The active profile is reported in the settings.
+        #[rpc(meta, name = "get_profiles")]
+        fn get_profiles(&self, Self::Metadata) -> BoxFuture<Vec<String>, Error>;
+
+        /// Saves the current settings, except the account token, as a profile with the given
+        /// name. Replaces any profile with the same name.
+        #[rpc(meta, name = "save_profile")]
+        fn save_profile(&self, Self::Metadata, String) -> BoxFuture<(), Error>;
+
+        /// Removes the profile with the given name.
+        #[rpc(meta, name = "remove_profile")]
+        fn remove_profile(&self, Self::Metadata, String) -> BoxFuture<(), Error>;
+
+        /// Replaces all settings, except the account token, with the ones in the profile with the
+        /// given name in one step. Only reconnects if settings used by the tunnel changed. Nothing
+        /// is changed if any setting in the profile is no longer valid, such as a removed relay.
+        #[rpc(meta, name = "switch_profile")]
+        fn switch_profile(&self, Self::Metadata, String) -> BoxFuture<(), Error>;
+
+        /// Configures the remote management interface, which accepts the same calls as this
+        /// interface over WebSocket from clients presenting the token returned by
+        /// `get_remote_management_token` in an `Authorization: Bearer <token>` header. Incoming
+        /// connections to it are accepted in the firewall while it's running.
+        #[rpc(meta, name = "set_remote_management")]
+        fn set_remote_management(
+            &self,
+            Self::Metadata,
+            RemoteManagementSettings
+            ) -> BoxFuture<(), Error>;
+
+        /// Returns the token clients of the remote management interface authenticate with. The
+        /// token is generated the first time it's needed.
+        #[rpc(meta, name = "get_remote_management_token")]
+        fn get_remote_management_token(&self, Self::Metadata) -> BoxFuture<String, Error>;
+
+        /// Replaces the token clients of the remote management interface authenticate with and
+        /// returns the new one. Clients connected with the old token are disconnected.
+        #[rpc(meta, name = "reset_remote_management_token")]
+        fn reset_remote_management_token(&self, Self::Metadata) -> BoxFuture<String, Error>;
+
+        /// Returns the version of this interface and the names of its methods and events, so
+        /// clients can tell if they understand the daemon before making other calls.
+        #[rpc(meta, name = "get_api_info")]
+        fn get_api_info(&self, Self::Metadata) -> BoxFuture<ApiInfo, Error>;
+
         /// Retreive version of the app
         #[rpc(meta, name = "get_current_version")]
         fn get_current_version(&self, Self::Metadata) -> BoxFuture<String, Error>;
@@ -134,7 +292,8 @@ build_rpc_trait! {
 
         #[pubsub(name = "settings")] {
             /// Subscribes to the `settings` event notifications. Getting notified as soon as any
-            /// daemon settings change.
+            /// daemon settings change. The account token is left out for clients that are not
+            /// privileged, here and in the settings sent to `events` subscribers.
             #[rpc(name = "settings_subscribe")]
             fn settings_subscribe(&self, Self::Metadata, pubsub::Subscriber<Settings>);
 
@@ -142,6 +301,68 @@ build_rpc_trait! {
             #[rpc(name = "settings_unsubscribe")]
             fn settings_unsubscribe(&self, SubscriptionId) -> BoxFuture<(), Error>;
         }
+
+        #[pubsub(name = "relay_list_updated")] {
+            /// Subscribes to the `relay_list_updated` event notifications. Getting notified with
+            /// the available countries and cities every time a new relay list is downloaded.
+            #[rpc(name = "relay_list_updated_subscribe")]
+            fn relay_list_updated_subscribe(
+                &self,
+                Self::Metadata,
+                pubsub::Subscriber<RelayList>
+            );
+
+            /// Unsubscribes from the `relay_list_updated` event notifications.
+            #[rpc(name = "relay_list_updated_unsubscribe")]
+            fn relay_list_updated_unsubscribe(&self, SubscriptionId) -> BoxFuture<(), Error>;
+        }
+
+        #[pubsub(name = "relay_invalidated")] {
+            /// Subscribes to the `relay_invalidated` event notifications. Getting notified with
+            /// the hostname of the relay in use and the reason, every time the daemon reconnects
+            /// because that relay was removed from, or no longer matches, the relay list.
+            #[rpc(name = "relay_invalidated_subscribe")]
+            fn relay_invalidated_subscribe(
+                &self,
+                Self::Metadata,
+                pubsub::Subscriber<RelayInvalidated>
+            );
+
+            /// Unsubscribes from the `relay_invalidated` event notifications.
+            #[rpc(name = "relay_invalidated_unsubscribe")]
+            fn relay_invalidated_unsubscribe(&self, SubscriptionId) -> BoxFuture<(), Error>;
+        }
+
+        #[pubsub(name = "account_expiry")] {
+            /// Subscribes to the `account_expiry` event notifications. Getting notified with the
+            /// expiry of the current account when it's first fetched, when it changes, when the
+            /// account gets within one of the warning thresholds in the settings and when it
+            /// expires.
+            #[rpc(name = "account_expiry_subscribe")]
+            fn account_expiry_subscribe(
+                &self,
+                Self::Metadata,
+                pubsub::Subscriber<AccountExpiry>
+            );
+
+            /// Unsubscribes from the `account_expiry` event notifications.
+            #[rpc(name = "account_expiry_unsubscribe")]
+            fn account_expiry_unsubscribe(&self, SubscriptionId) -> BoxFuture<(), Error>;
+        }
+
+        #[pubsub(name = "events")] {
+            /// Subscribes to all events of the daemon. Getting notified with every state
+            /// transition, settings change, relay list update, relay invalidation, location
+            /// update, account expiry event and error, and when the daemon shuts down. Each event
+            /// has a sequence number that is increased by one for every event, so gaps mean
+            /// events were missed, and a timestamp.
+            #[rpc(name = "events_subscribe")]
+            fn events_subscribe(&self, Self::Metadata, pubsub::Subscriber<DaemonEvent>);
+
+            /// Unsubscribes from the `events` event notifications.
+            #[rpc(name = "events_unsubscribe")]
+            fn events_unsubscribe(&self, SubscriptionId) -> BoxFuture<(), Error>;
+        }
     }
 }
 
@@ -159,12 +380,33 @@ pub enum ManagementCommand {
         OneshotSender<BoxFuture<AccountData, mullvad_rpc::Error>>,
         AccountToken,
     ),
+    /// Get the cached expiry of the current account.
+    GetAccountExpiry(OneshotSender<Option<AccountExpiry>>),
+    /// Request a new account from the API.
+    CreateAccount(OneshotSender<BoxFuture<AccountToken, mullvad_rpc::Error>>),
+    /// Redeem a voucher on the current account. Responds with an error if no account is set.
+    SubmitVoucher(
+        OneshotSender<Result<BoxFuture<VoucherSubmission, mullvad_rpc::Error>, ()>>,
+        String,
+    ),
     /// Get the list of countries and cities where there are relays.
     GetRelayLocations(OneshotSender<RelayList>),
+    /// Get the relays matching the given constraints, optionally with the rejected ones.
+    GetMatchingRelays(OneshotSender<MatchingRelays>, RelayConstraints, bool),
+    /// Download the newest relay list.
+    UpdateRelayList(OneshotSender<()>),
+    /// Get the relays added by the user.
+    GetUserRelays(OneshotSender<RelayList>),
+    /// Replace the relays added by the user.
+    SetUserRelays(OneshotSender<Result<(), String>>, RelayList),
     /// Set which account token to use for subsequent connection attempts.
     SetAccount(OneshotSender<()>, Option<AccountToken>),
     /// Place constraints on the type of tunnel and relay
     UpdateRelaySettings(OneshotSender<()>, RelaySettingsUpdate),
+    /// Set which IP version to use when connecting to relays.
+    SetRelayIpVersion(OneshotSender<()>, IpVersionPreference),
+    /// Set how often to switch to another relay while connected.
+    SetRotateRelayInterval(OneshotSender<()>, Option<u64>),
     /// Set the allow LAN setting.
     SetAllowLan(OneshotSender<()>, bool),
     /// Set the auto-connect setting.
@@ -173,8 +415,44 @@ pub enum ManagementCommand {
     SetOpenVpnMssfix(OneshotSender<()>, Option<u16>),
     /// Set if IPv6 should be enabled in the tunnel
     SetEnableIpv6(OneshotSender<()>, bool),
+    /// Get the accounts in the account history.
+    GetAccountHistory(OneshotSender<Vec<AccountHistoryEntry>>),
+    /// Remove an account from the account history.
+    RemoveAccountFromHistory(OneshotSender<()>, AccountToken),
+    /// Label an account in the account history. Responds with false if the account is not in
+    /// the history.
+    SetAccountLabel(OneshotSender<bool>, AccountToken, Option<String>),
+    /// Set how many accounts to remember in the account history.
+    SetAccountHistoryLimit(OneshotSender<()>, usize),
     /// Get the daemon settings
     GetSettings(OneshotSender<Settings>),
+    /// Get the daemon settings for exporting, optionally with the account token.
+    ExportSettings(OneshotSender<Settings>, bool),
+    /// Replace all daemon settings with imported ones. Responds with the invalid settings, if any.
+    ImportSettings(
+        OneshotSender<Result<(), UpdateSettingsError>>,
+        Settings,
+    ),
+    /// Apply a JSON merge patch to the settings. Responds with the invalid settings, if any.
+    UpdateSettings(
+        OneshotSender<Result<(), UpdateSettingsError>>,
+        serde_json::Value,
+    ),
+    /// Get the names of all profiles.
+    GetProfiles(OneshotSender<Vec<String>>),
+    /// Save the current settings as a profile with the given name.
+    SaveProfile(OneshotSender<()>, String),
+    /// Remove a profile. Responds with an error if there is no profile with the given name.
+    RemoveProfile(OneshotSender<Result<(), ()>>, String),
+    /// Switch to a profile. Responds with an error if there is no profile with the given name or
+    /// its settings are invalid.
+    SwitchProfile(OneshotSender<Result<(), SwitchProfileError>>, String),
+    /// Configure the remote management interface.
+    SetRemoteManagement(OneshotSender<()>, RemoteManagementSettings),
+    /// Get the token of the remote management interface, generating it if there is none.
+    GetRemoteManagementToken(OneshotSender<Result<String, ()>>),
+    /// Replace the token of the remote management interface with a new one.
+    ResetRemoteManagementToken(OneshotSender<Result<String, ()>>),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(OneshotSender<BoxFuture<version::AppVersionInfo, mullvad_rpc::Error>>),
     /// Get current version of the app
@@ -183,10 +461,37 @@ pub enum ManagementCommand {
     Shutdown,
 }
 
+/// Why the daemon refused to update the settings.
+pub enum UpdateSettingsError {
+    /// The settings have been saved since the given revision. Contains the current revision.
+    Conflict(u64),
+    /// The settings in the patch are invalid.
+    InvalidSettings(Vec<FieldError>),
+}
+
+/// Why the daemon refused to switch profile.
+pub enum SwitchProfileError {
+    /// There is no profile with the given name.
+    NoSuchProfile,
+    /// The settings in the profile are invalid.
+    InvalidSettings(Vec<FieldError>),
+}
+
 #[derive(Default)]
 struct ActiveSubscriptions {
     new_state_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<TunnelStateTransition>>>,
     settings_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<Settings>>>,
+    /// Subscribers that may not know the account token, and get the settings without it.
+    public_settings_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<Settings>>>,
+    relay_list_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<RelayList>>>,
+    relay_invalidated_subscriptions:
+        RwLock<HashMap<SubscriptionId, pubsub::Sink<RelayInvalidated>>>,
+    account_expiry_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<AccountExpiry>>>,
+    events_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<DaemonEvent>>>,
+    /// Subscribers that may not know the account token, and get settings events without it.
+    public_events_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<DaemonEvent>>>,
+    /// The sequence number of the last event sent to `events` subscribers.
+    last_event_sequence: Mutex<u64>,
 }
 
 pub struct ManagementInterfaceServer {
@@ -197,19 +502,21 @@ pub struct ManagementInterfaceServer {
 impl ManagementInterfaceServer {
     pub fn start<T>(
         tunnel_tx: IntoSender<ManagementCommand, T>,
-        cache_dir: PathBuf,
+        policy: Policy,
+        access_policy: AccessPolicy,
+        metrics: &Arc<Metrics>,
     ) -> talpid_ipc::Result<Self>
     where
         T: From<ManagementCommand> + 'static + Send,
     {
-        let rpc = ManagementInterface::new(tunnel_tx, cache_dir);
-        let subscriptions = rpc.subscriptions.clone();
+        let subscriptions = Arc::new(ActiveSubscriptions::default());
+        let rpc = ManagementInterface::new(tunnel_tx, policy, access_policy, subscriptions.clone());
 
         let mut io = PubSubHandler::default();
-        io.extend_with(rpc.to_delegate());
+        io.extend_with(metrics::instrument(rpc.to_delegate().into(), metrics));
         let meta_io: MetaIoHandler<Meta> = io.into();
         let path = mullvad_paths::get_rpc_socket_path();
-        let server = talpid_ipc::IpcServer::start_with_metadata(
+        let server = talpid_ipc::IpcServer::start_with_peer_credentials(
             meta_io,
             meta_extractor,
             path.to_string_lossy().to_string(),
@@ -237,6 +544,83 @@ impl ManagementInterfaceServer {
     }
 }
 
+/// The management interface for clients on other machines. It accepts JSON-RPC over WebSocket,
+/// but only from clients presenting the token of the daemon as a bearer token. Subscribers are
+/// notified of the same events as the subscribers of the local interface.
+pub struct RemoteManagementServer {
+    server: jsonrpc_ws_server::Server,
+}
+
+impl RemoteManagementServer {
+    pub fn start<T>(
+        tunnel_tx: IntoSender<ManagementCommand, T>,
+        policy: Policy,
+        access_policy: AccessPolicy,
+        event_broadcaster: &EventBroadcaster,
+        address: SocketAddr,
+        token: String,
+        metrics: &Arc<Metrics>,
+    ) -> jsonrpc_ws_server::Result<Self>
+    where
+        T: From<ManagementCommand> + 'static + Send,
+    {
+        let rpc = ManagementInterface::new(
+            tunnel_tx,
+            policy,
+            access_policy,
+            event_broadcaster.subscriptions.clone(),
+        );
+
+        let mut io = PubSubHandler::default();
+        io.extend_with(metrics::instrument(rpc.to_delegate().into(), metrics));
+        let meta_io: MetaIoHandler<Meta> = io.into();
+        let server =
+            jsonrpc_ws_server::ServerBuilder::with_meta_extractor(meta_io, remote_meta_extractor)
+                .request_middleware(move |request: &ws::Request| {
+                    if is_authorized(request, &token) {
+                        None
+                    } else {
+                        warn!("Refused remote management client without a valid token");
+                        Some(ws::Response::new(401, "Unauthorized", Vec::new()))
+                    }
+                }).start(&address)?;
+        Ok(RemoteManagementServer { server })
+    }
+
+    pub fn address(&self) -> &SocketAddr {
+        self.server.addr()
+    }
+
+    /// Stops listening and disconnects all clients.
+    pub fn close(self) {
+        self.server.close()
+    }
+}
+
+/// Returns true if the request carries `token` as its bearer token.
+fn is_authorized(request: &ws::Request, token: &str) -> bool {
+    const BEARER_PREFIX: &[u8] = b"bearer ";
+    match request.header("authorization") {
+        Some(value) if value.len() > BEARER_PREFIX.len() => {
+            let (scheme, credentials) = value.split_at(BEARER_PREFIX.len());
+            scheme.eq_ignore_ascii_case(BEARER_PREFIX)
+                && constant_time_eq(credentials, token.as_bytes())
+        }
+        _ => false,
+    }
+}
+
+/// Compares the byte strings in a time that doesn't depend on where they differ, so the token
+/// can't be guessed one byte at a time by timing the responses.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a
+            .iter()
+            .zip(b)
+            .fold(0, |difference, (x, y)| difference | (x ^ y))
+            == 0
+}
+
 /// A handle that allows broadcasting messages to all subscribers of the management interface.
 pub struct EventBroadcaster {
     subscriptions: Arc<ActiveSubscriptions>,
@@ -246,12 +630,80 @@ impl EventBroadcaster {
     /// Sends a new state update to all `new_state` subscribers of the management interface.
     pub fn notify_new_state(&self, new_state: TunnelStateTransition) {
         debug!("Broadcasting new state to listeners: {:?}", new_state);
+        self.notify_event(DaemonEventKind::StateTransition(new_state.clone()));
         self.notify(&self.subscriptions.new_state_subscriptions, new_state);
     }
 
-    /// Sends settings to all `settings` subscribers of the management interface.
+    /// Sends settings to all `settings` subscribers of the management interface. Subscribers that
+    /// are not privileged get them without the account token.
     pub fn notify_settings(&self, settings: &Settings) {
+        self.notify_event(DaemonEventKind::Settings(settings.clone()));
         self.notify(&self.subscriptions.settings_subscriptions, settings.clone());
+        self.notify(
+            &self.subscriptions.public_settings_subscriptions,
+            settings.without_account_token(),
+        );
+    }
+
+    /// Sends the new relay list to all `relay_list_updated` subscribers of the management
+    /// interface.
+    pub fn notify_relay_list(&self, relay_list: RelayList) {
+        self.notify_event(DaemonEventKind::RelayListUpdated(relay_list.clone()));
+        self.notify(&self.subscriptions.relay_list_subscriptions, relay_list);
+    }
+
+    /// Sends the invalidated relay, and why it was invalidated, to all `relay_invalidated`
+    /// subscribers of the management interface.
+    pub fn notify_relay_invalidated(&self, relay_invalidated: RelayInvalidated) {
+        self.notify_event(DaemonEventKind::RelayInvalidated(relay_invalidated.clone()));
+        self.notify(
+            &self.subscriptions.relay_invalidated_subscriptions,
+            relay_invalidated,
+        );
+    }
+
+    /// Sends the expiry of the current account to all `account_expiry` subscribers of the
+    /// management interface.
+    pub fn notify_account_expiry(&self, account_expiry: AccountExpiry) {
+        self.notify_event(DaemonEventKind::AccountExpiry(account_expiry.clone()));
+        self.notify(
+            &self.subscriptions.account_expiry_subscriptions,
+            account_expiry,
+        );
+    }
+
+    /// Sends the new location to all `events` subscribers of the management interface.
+    pub fn notify_location(&self, location: GeoIpLocation) {
+        self.notify_event(DaemonEventKind::LocationUpdated(location));
+    }
+
+    /// Sends an error that no client would otherwise be told about to all `events` subscribers
+    /// of the management interface.
+    pub fn notify_error(&self, message: String) {
+        self.notify_event(DaemonEventKind::Error(message));
+    }
+
+    /// Tells all `events` subscribers of the management interface that the daemon is shutting
+    /// down.
+    pub fn notify_shutting_down(&self) {
+        self.notify_event(DaemonEventKind::ShuttingDown);
+    }
+
+    /// Numbers and timestamps the event and sends it to all `events` subscribers. The sequence
+    /// stays locked until the event is sent, so subscribers get the events in order.
+    fn notify_event(&self, kind: DaemonEventKind) {
+        let mut last_event_sequence = self.subscriptions.last_event_sequence.lock().unwrap();
+        *last_event_sequence += 1;
+        let event = DaemonEvent {
+            sequence: *last_event_sequence,
+            timestamp: Utc::now(),
+            kind,
+        };
+        self.notify(&self.subscriptions.events_subscriptions, event.clone());
+        self.notify(
+            &self.subscriptions.public_events_subscriptions,
+            public_event(event),
+        );
     }
 
     fn notify<T>(
@@ -268,18 +720,45 @@ impl EventBroadcaster {
     }
 }
 
+/// Returns `event` as sent to subscribers that may not know the account token.
+fn public_event(event: DaemonEvent) -> DaemonEvent {
+    let DaemonEvent {
+        sequence,
+        timestamp,
+        kind,
+    } = event;
+    let kind = match kind {
+        DaemonEventKind::Settings(settings) => {
+            DaemonEventKind::Settings(settings.without_account_token())
+        }
+        kind => kind,
+    };
+    DaemonEvent {
+        sequence,
+        timestamp,
+        kind,
+    }
+}
+
 struct ManagementInterface<T: From<ManagementCommand> + 'static + Send> {
     subscriptions: Arc<ActiveSubscriptions>,
     tx: Mutex<IntoSender<ManagementCommand, T>>,
-    cache_dir: PathBuf,
+    policy: Policy,
+    access_policy: AccessPolicy,
 }
 
 impl<T: From<ManagementCommand> + 'static + Send> ManagementInterface<T> {
-    pub fn new(tx: IntoSender<ManagementCommand, T>, cache_dir: PathBuf) -> Self {
+    pub fn new(
+        tx: IntoSender<ManagementCommand, T>,
+        policy: Policy,
+        access_policy: AccessPolicy,
+        subscriptions: Arc<ActiveSubscriptions>,
+    ) -> Self {
         ManagementInterface {
-            subscriptions: Default::default(),
+            subscriptions,
             tx: Mutex::new(tx),
-            cache_dir,
+            policy,
+            access_policy,
         }
     }
 
@@ -300,6 +779,19 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterface<T> {
         }
     }
 
+    /// Unsubscribes `id` from whichever of the privileged and public subscriptions it's in.
+    fn unsubscribe_either<V>(
+        id: SubscriptionId,
+        privileged_lock: &RwLock<HashMap<SubscriptionId, pubsub::Sink<V>>>,
+        public_lock: &RwLock<HashMap<SubscriptionId, pubsub::Sink<V>>>,
+    ) -> BoxFuture<(), Error> {
+        if public_lock.read().unwrap().contains_key(&id) {
+            Self::unsubscribe(id, public_lock)
+        } else {
+            Self::unsubscribe(id, privileged_lock)
+        }
+    }
+
     fn unsubscribe<V>(
         id: SubscriptionId,
         subscriptions_lock: &RwLock<HashMap<SubscriptionId, pubsub::Sink<V>>>,
@@ -344,10 +836,109 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterface<T> {
         }
     }
 
-    fn load_history(&self) -> Result<AccountHistory, AccountHistoryError> {
-        let mut account_history = AccountHistory::new(&self.cache_dir);
-        account_history.load()?;
-        Ok(account_history)
+    fn check_rotate_relay_interval(interval: Option<u64>) -> Result<(), Error> {
+        if interval.map_or(false, |interval| interval < MIN_ROTATE_RELAY_INTERVAL) {
+            Err(Error {
+                code: ErrorCode::InvalidParams,
+                message: format!(
+                    "The relay rotation interval must be at least {} seconds",
+                    MIN_ROTATE_RELAY_INTERVAL
+                ),
+                data: None,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Parses settings to import. They are validated by the daemon, like patched settings.
+    fn parse_imported_settings(settings: serde_json::Value) -> Result<Settings, Error> {
+        Settings::from_json(settings).map_err(|error| Error {
+            code: ErrorCode::InvalidParams,
+            message: error
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>()
+                .join(": "),
+            data: None,
+        })
+    }
+
+    /// Returns true if the client may know every secret, such as the account token.
+    fn is_privileged(&self, meta: &Meta) -> bool {
+        self.access_policy.is_privileged(&meta.client)
+    }
+
+    /// Refuses to call `method` unless the client is allowed to by the access policy.
+    fn check_permitted(&self, meta: &Meta, method: &str) -> Result<(), Error> {
+        if self.access_policy.allows(method, &meta.client) {
+            Ok(())
+        } else {
+            warn!("Denied client {:?} access to {}", meta.client, method);
+            Err(Self::permission_denied(
+                self.access_policy.describe_denial(method, &meta.client),
+            ))
+        }
+    }
+
+    fn permission_denied(reason: String) -> Error {
+        Error {
+            code: ErrorCode::ServerError(-903),
+            message: format!("Permission denied, {}", reason),
+            data: None,
+        }
+    }
+
+    /// Refuses to change the setting `key` if it's locked by the system policy.
+    fn check_unlocked(&self, key: &str) -> Result<(), Error> {
+        if self.policy.is_locked(key) {
+            Err(Error {
+                code: ErrorCode::ServerError(-901),
+                message: format!("The setting {} is locked by the system policy", key),
+                data: None,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn map_update_settings_error(error: UpdateSettingsError) -> Error {
+        match error {
+            UpdateSettingsError::Conflict(revision) => Error {
+                code: ErrorCode::ServerError(-902),
+                message: "The settings have been changed by another client".to_owned(),
+                data: Some(serde_json::Value::from(revision)),
+            },
+            UpdateSettingsError::InvalidSettings(errors) => Self::invalid_settings(errors),
+        }
+    }
+
+    fn map_switch_profile_error(error: SwitchProfileError, name: &str) -> Error {
+        match error {
+            SwitchProfileError::NoSuchProfile => Self::no_such_profile(name),
+            SwitchProfileError::InvalidSettings(errors) => Self::invalid_settings(errors),
+        }
+    }
+
+    /// Lists the invalid settings in the message, and as `{ field, message }` in the error data.
+    fn invalid_settings(errors: Vec<FieldError>) -> Error {
+        Error {
+            code: ErrorCode::InvalidParams,
+            message: errors
+                .iter()
+                .map(|error| format!("{}: {}", error.field, error.message))
+                .collect::<Vec<_>>()
+                .join(", "),
+            data: serde_json::to_value(errors).ok(),
+        }
+    }
+
+    fn no_such_profile(name: &str) -> Error {
+        Error {
+            code: ErrorCode::InvalidParams,
+            message: format!("No profile named {}", name),
+            data: None,
+        }
     }
 }
 
@@ -378,6 +969,15 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn get_account_expiry(&self, _: Self::Metadata) -> BoxFuture<Option<AccountExpiry>, Error> {
+        debug!("get_account_expiry");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetAccountExpiry(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn get_relay_locations(&self, _: Self::Metadata) -> BoxFuture<RelayList, Error> {
         debug!("get_relay_locations");
         let (tx, rx) = sync::oneshot::channel();
@@ -387,37 +987,167 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
-    fn set_account(
+    fn get_matching_relays(
         &self,
         _: Self::Metadata,
-        account_token: Option<AccountToken>,
-    ) -> BoxFuture<(), Error> {
-        debug!("set_account");
+        constraints: RelayConstraints,
+        explain: bool,
+    ) -> BoxFuture<MatchingRelays, Error> {
+        debug!("get_matching_relays");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetMatchingRelays(
+                tx,
+                constraints,
+                explain,
+            )).and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn update_relay_list(&self, meta: Self::Metadata) -> BoxFuture<(), Error> {
+        debug!("update_relay_list");
+        if let Err(error) = self.check_permitted(&meta, "update_relay_list") {
+            return Box::new(future::err(error));
+        }
         let (tx, rx) = sync::oneshot::channel();
         let future = self
-            .send_command_to_daemon(ManagementCommand::SetAccount(tx, account_token.clone()))
+            .send_command_to_daemon(ManagementCommand::UpdateRelayList(tx))
             .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
 
-        if let Some(new_account_token) = account_token {
-            if let Err(e) = self.load_history().and_then(|mut account_history| {
-                account_history.add_account_token(new_account_token)
-            }) {
-                error!(
-                    "Unable to add an account into the account history: {}",
-                    e.display_chain()
-                );
-            }
+    fn get_user_relays(&self, _: Self::Metadata) -> BoxFuture<RelayList, Error> {
+        debug!("get_user_relays");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetUserRelays(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_user_relays(
+        &self,
+        meta: Self::Metadata,
+        user_relays: RelayList,
+    ) -> BoxFuture<(), Error> {
+        debug!("set_user_relays");
+        if let Err(error) = self.check_permitted(&meta, "set_user_relays") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = user_relays::validate(&user_relays) {
+            return Box::new(future::err(Error {
+                code: ErrorCode::InvalidParams,
+                message: error.to_string(),
+                data: None,
+            }));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetUserRelays(tx, user_relays))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|hostname| Error {
+                    code: ErrorCode::InvalidParams,
+                    message: format!(
+                        "The relay list already has a relay with the hostname {}",
+                        hostname
+                    ),
+                    data: None,
+                })
+            });
+        Box::new(future)
+    }
+
+    fn create_account(&self, meta: Self::Metadata) -> BoxFuture<AccountToken, Error> {
+        debug!("create_account");
+        if let Err(error) = self.check_permitted(&meta, "create_account") {
+            return Box::new(future::err(error));
         }
+        if let Err(error) = self.check_unlocked("account_token") {
+            return Box::new(future::err(error));
+        }
+        let daemon_tx = self.tx.lock().unwrap().clone();
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::CreateAccount(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|rpc_future| {
+                rpc_future.map_err(|error: mullvad_rpc::Error| {
+                    error!("Unable to create an account: {}", error.display_chain());
+                    Self::map_rpc_error(error)
+                })
+            }).and_then(move |account_token| {
+                info!("Created a new account");
+                let (tx, rx) = sync::oneshot::channel();
+                let command = ManagementCommand::SetAccount(tx, Some(account_token.clone()));
+                future::result(daemon_tx.send(command))
+                    .map_err(|_| Error::internal_error())
+                    .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                    .map(|_| account_token)
+            });
+        Box::new(future)
+    }
+
+    fn submit_voucher(
+        &self,
+        meta: Self::Metadata,
+        voucher_code: String,
+    ) -> BoxFuture<VoucherSubmission, Error> {
+        debug!("submit_voucher");
+        if let Err(error) = self.check_permitted(&meta, "submit_voucher") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SubmitVoucher(tx, voucher_code))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| match result {
+                Ok(rpc_future) => {
+                    future::Either::A(rpc_future.map_err(|error: mullvad_rpc::Error| {
+                        error!("Unable to submit voucher: {}", error.display_chain());
+                        Self::map_rpc_error(error)
+                    }))
+                }
+                Err(()) => future::Either::B(future::err(Error {
+                    code: ErrorCode::ServerError(-900),
+                    message: "No account token configured".to_owned(),
+                    data: None,
+                })),
+            });
+        Box::new(future)
+    }
 
+    fn set_account(
+        &self,
+        meta: Self::Metadata,
+        account_token: Option<AccountToken>,
+    ) -> BoxFuture<(), Error> {
+        debug!("set_account");
+        if let Err(error) = self.check_permitted(&meta, "set_account") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self.check_unlocked("account_token") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetAccount(tx, account_token))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
         Box::new(future)
     }
 
     fn update_relay_settings(
         &self,
-        _: Self::Metadata,
+        meta: Self::Metadata,
         constraints_update: RelaySettingsUpdate,
     ) -> BoxFuture<(), Error> {
         debug!("update_relay_settings");
+        if let Err(error) = self.check_permitted(&meta, "update_relay_settings") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self.check_unlocked("relay_settings") {
+            return Box::new(future::err(error));
+        }
         let (tx, rx) = sync::oneshot::channel();
 
         let message = ManagementCommand::UpdateRelaySettings(tx, constraints_update);
@@ -427,8 +1157,55 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
-    fn set_allow_lan(&self, _: Self::Metadata, allow_lan: bool) -> BoxFuture<(), Error> {
+    fn set_relay_ip_version(
+        &self,
+        meta: Self::Metadata,
+        ip_version: IpVersionPreference,
+    ) -> BoxFuture<(), Error> {
+        debug!("set_relay_ip_version({})", ip_version);
+        if let Err(error) = self.check_permitted(&meta, "set_relay_ip_version") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self.check_unlocked("relay_ip_version") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetRelayIpVersion(tx, ip_version))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_rotate_relay_interval(
+        &self,
+        meta: Self::Metadata,
+        interval: Option<u64>,
+    ) -> BoxFuture<(), Error> {
+        debug!("set_rotate_relay_interval({:?})", interval);
+        if let Err(error) = self.check_permitted(&meta, "set_rotate_relay_interval") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self
+            .check_unlocked("rotate_relay_interval")
+            .and_then(|_| Self::check_rotate_relay_interval(interval))
+        {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetRotateRelayInterval(tx, interval))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_allow_lan(&self, meta: Self::Metadata, allow_lan: bool) -> BoxFuture<(), Error> {
         debug!("set_allow_lan({})", allow_lan);
+        if let Err(error) = self.check_permitted(&meta, "set_allow_lan") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self.check_unlocked("allow_lan") {
+            return Box::new(future::err(error));
+        }
         let (tx, rx) = sync::oneshot::channel();
         let future = self
             .send_command_to_daemon(ManagementCommand::SetAllowLan(tx, allow_lan))
@@ -436,8 +1213,14 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
-    fn set_auto_connect(&self, _: Self::Metadata, auto_connect: bool) -> BoxFuture<(), Error> {
+    fn set_auto_connect(&self, meta: Self::Metadata, auto_connect: bool) -> BoxFuture<(), Error> {
         debug!("set_auto_connect({})", auto_connect);
+        if let Err(error) = self.check_permitted(&meta, "set_auto_connect") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self.check_unlocked("auto_connect") {
+            return Box::new(future::err(error));
+        }
         let (tx, rx) = sync::oneshot::channel();
         let future = self
             .send_command_to_daemon(ManagementCommand::SetAutoConnect(tx, auto_connect))
@@ -445,8 +1228,11 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
-    fn connect(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
+    fn connect(&self, meta: Self::Metadata) -> BoxFuture<(), Error> {
         debug!("connect");
+        if let Err(error) = self.check_permitted(&meta, "connect") {
+            return Box::new(future::err(error));
+        }
         let (tx, rx) = sync::oneshot::channel();
         let future = self
             .send_command_to_daemon(ManagementCommand::SetTargetState(tx, TargetState::Secured))
@@ -462,8 +1248,11 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
-    fn disconnect(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
+    fn disconnect(&self, meta: Self::Metadata) -> BoxFuture<(), Error> {
         debug!("disconnect");
+        if let Err(error) = self.check_permitted(&meta, "disconnect") {
+            return Box::new(future::err(error));
+        }
         let (tx, _) = sync::oneshot::channel();
         let future = self
             .send_command_to_daemon(ManagementCommand::SetTargetState(
@@ -491,44 +1280,121 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
-    fn shutdown(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
+    fn shutdown(&self, meta: Self::Metadata) -> BoxFuture<(), Error> {
         debug!("shutdown");
+        if let Err(error) = self.check_permitted(&meta, "shutdown") {
+            return Box::new(future::err(error));
+        }
         self.send_command_to_daemon(ManagementCommand::Shutdown)
     }
 
-    fn get_account_history(&self, _: Self::Metadata) -> BoxFuture<Vec<AccountToken>, Error> {
+    fn get_account_history(&self, meta: Self::Metadata) -> BoxFuture<Vec<AccountToken>, Error> {
         debug!("get_account_history");
-        Box::new(future::result(
-            self.load_history()
-                .map(|history| history.get_accounts().to_vec())
-                .map_err(|error| {
-                    error!("Unable to get account history: {}", error.display_chain());
-                    Error::internal_error()
-                }),
-        ))
+        if let Err(error) = self.check_permitted(&meta, "get_account_history") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetAccountHistory(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .map(|entries| {
+                entries
+                    .into_iter()
+                    .map(|entry| entry.account_token)
+                    .collect()
+            });
+        Box::new(future)
+    }
+
+    fn list_accounts(&self, meta: Self::Metadata) -> BoxFuture<Vec<AccountHistoryEntry>, Error> {
+        debug!("list_accounts");
+        if let Err(error) = self.check_permitted(&meta, "list_accounts") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetAccountHistory(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
     }
 
     fn remove_account_from_history(
         &self,
-        _: Self::Metadata,
+        meta: Self::Metadata,
         account_token: AccountToken,
     ) -> BoxFuture<(), Error> {
         debug!("remove_account_from_history");
-        Box::new(future::result(
-            self.load_history()
-                .and_then(|mut history| history.remove_account_token(account_token))
-                .map_err(|error| {
-                    error!(
-                        "Unable to remove account from history: {}",
-                        error.display_chain()
-                    );
-                    Error::internal_error()
-                }),
-        ))
+        if let Err(error) = self.check_permitted(&meta, "remove_account_from_history") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::RemoveAccountFromHistory(
+                tx,
+                account_token,
+            )).and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_account_label(
+        &self,
+        meta: Self::Metadata,
+        account_token: AccountToken,
+        label: Option<String>,
+    ) -> BoxFuture<(), Error> {
+        debug!("set_account_label");
+        if let Err(error) = self.check_permitted(&meta, "set_account_label") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetAccountLabel(tx, account_token, label))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|found| {
+                if found {
+                    Ok(())
+                } else {
+                    Err(Error {
+                        code: ErrorCode::InvalidParams,
+                        message: "The account is not in the account history".to_owned(),
+                        data: None,
+                    })
+                }
+            });
+        Box::new(future)
+    }
+
+    fn set_account_history_limit(
+        &self,
+        meta: Self::Metadata,
+        limit: usize,
+    ) -> BoxFuture<(), Error> {
+        debug!("set_account_history_limit({})", limit);
+        if let Err(error) = self.check_permitted(&meta, "set_account_history_limit") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self.check_unlocked("account_history_limit") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetAccountHistoryLimit(tx, limit))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
     }
 
-    fn set_openvpn_mssfix(&self, _: Self::Metadata, mssfix: Option<u16>) -> BoxFuture<(), Error> {
+    fn set_openvpn_mssfix(
+        &self,
+        meta: Self::Metadata,
+        mssfix: Option<u16>,
+    ) -> BoxFuture<(), Error> {
         debug!("set_openvpn_mssfix({:?})", mssfix);
+        if let Err(error) = self.check_permitted(&meta, "set_openvpn_mssfix") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self.check_unlocked("tunnel_options") {
+            return Box::new(future::err(error));
+        }
         let (tx, rx) = sync::oneshot::channel();
         let future = self
             .send_command_to_daemon(ManagementCommand::SetOpenVpnMssfix(tx, mssfix))
@@ -537,8 +1403,14 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
-    fn set_enable_ipv6(&self, _: Self::Metadata, enable_ipv6: bool) -> BoxFuture<(), Error> {
+    fn set_enable_ipv6(&self, meta: Self::Metadata, enable_ipv6: bool) -> BoxFuture<(), Error> {
         debug!("set_enable_ipv6({})", enable_ipv6);
+        if let Err(error) = self.check_permitted(&meta, "set_enable_ipv6") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self.check_unlocked("tunnel_options") {
+            return Box::new(future::err(error));
+        }
         let (tx, rx) = sync::oneshot::channel();
         let future = self
             .send_command_to_daemon(ManagementCommand::SetEnableIpv6(tx, enable_ipv6))
@@ -547,15 +1419,205 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
-    fn get_settings(&self, _: Self::Metadata) -> BoxFuture<Settings, Error> {
+    fn get_settings(&self, meta: Self::Metadata) -> BoxFuture<Settings, Error> {
         debug!("get_settings");
+        let is_privileged = self.is_privileged(&meta);
         let (tx, rx) = sync::oneshot::channel();
         let future = self
             .send_command_to_daemon(ManagementCommand::GetSettings(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .map(move |settings| {
+                if is_privileged {
+                    settings
+                } else {
+                    settings.without_account_token()
+                }
+            });
+        Box::new(future)
+    }
+
+    fn export_settings(
+        &self,
+        meta: Self::Metadata,
+        include_account_token: bool,
+    ) -> BoxFuture<Settings, Error> {
+        debug!("export_settings({})", include_account_token);
+        if let Err(error) = self.check_permitted(&meta, "export_settings") {
+            return Box::new(future::err(error));
+        }
+        if include_account_token && !self.is_privileged(&meta) {
+            return Box::new(future::err(Self::permission_denied(
+                "the account token can't be exported by this client".to_owned(),
+            )));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ExportSettings(tx, include_account_token))
             .and_then(|_| rx.map_err(|_| Error::internal_error()));
         Box::new(future)
     }
 
+    fn import_settings(
+        &self,
+        meta: Self::Metadata,
+        settings: serde_json::Value,
+    ) -> BoxFuture<(), Error> {
+        debug!("import_settings");
+        if let Err(error) = self.check_permitted(&meta, "import_settings") {
+            return Box::new(future::err(error));
+        }
+        let settings = match Self::parse_imported_settings(settings) {
+            Ok(settings) => settings,
+            Err(error) => return Box::new(future::err(error)),
+        };
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ImportSettings(tx, settings))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(Self::map_update_settings_error));
+        Box::new(future)
+    }
+
+    fn update_settings(
+        &self,
+        meta: Self::Metadata,
+        patch: serde_json::Value,
+    ) -> BoxFuture<(), Error> {
+        debug!("update_settings");
+        if let Err(error) = self.check_permitted(&meta, "update_settings") {
+            return Box::new(future::err(error));
+        }
+        let locked_key = patch.as_object().and_then(|patch| {
+            patch
+                .keys()
+                .filter_map(|key| self.check_unlocked(key).err())
+                .next()
+        });
+        if let Some(error) = locked_key {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::UpdateSettings(tx, patch))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(Self::map_update_settings_error));
+        Box::new(future)
+    }
+
+    fn get_profiles(&self, _: Self::Metadata) -> BoxFuture<Vec<String>, Error> {
+        debug!("get_profiles");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetProfiles(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn save_profile(&self, meta: Self::Metadata, name: String) -> BoxFuture<(), Error> {
+        debug!("save_profile({})", name);
+        if let Err(error) = self.check_permitted(&meta, "save_profile") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = profiles::validate_name(&name) {
+            return Box::new(future::err(Error {
+                code: ErrorCode::InvalidParams,
+                message: error.to_string(),
+                data: None,
+            }));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SaveProfile(tx, name))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn remove_profile(&self, meta: Self::Metadata, name: String) -> BoxFuture<(), Error> {
+        debug!("remove_profile({})", name);
+        if let Err(error) = self.check_permitted(&meta, "remove_profile") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::RemoveProfile(tx, name.clone()))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(move |result| {
+                result.map_err(|error| Self::map_switch_profile_error(error, &name))
+            });
+        Box::new(future)
+    }
+
+    fn switch_profile(&self, meta: Self::Metadata, name: String) -> BoxFuture<(), Error> {
+        debug!("switch_profile({})", name);
+        if let Err(error) = self.check_permitted(&meta, "switch_profile") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SwitchProfile(tx, name.clone()))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(move |result| {
+                result.map_err(|error| Self::map_switch_profile_error(error, &name))
+            });
+        Box::new(future)
+    }
+
+    fn set_remote_management(
+        &self,
+        meta: Self::Metadata,
+        remote_management: RemoteManagementSettings,
+    ) -> BoxFuture<(), Error> {
+        debug!("set_remote_management({:?})", remote_management);
+        if let Err(error) = self.check_permitted(&meta, "set_remote_management") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = self.check_unlocked("remote_management") {
+            return Box::new(future::err(error));
+        }
+        if let Err(error) = Self::check_remote_management(&remote_management) {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetRemoteManagement(
+                tx,
+                remote_management,
+            ))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn get_remote_management_token(&self, meta: Self::Metadata) -> BoxFuture<String, Error> {
+        debug!("get_remote_management_token");
+        if let Err(error) = self.check_permitted(&meta, "get_remote_management_token") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetRemoteManagementToken(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(|()| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn reset_remote_management_token(&self, meta: Self::Metadata) -> BoxFuture<String, Error> {
+        debug!("reset_remote_management_token");
+        if let Err(error) = self.check_permitted(&meta, "reset_remote_management_token") {
+            return Box::new(future::err(error));
+        }
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ResetRemoteManagementToken(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| result.map_err(|()| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn get_api_info(&self, _: Self::Metadata) -> BoxFuture<ApiInfo, Error> {
+        debug!("get_api_info");
+        Box::new(future::ok(ApiInfo::current()))
+    }
+
     fn get_current_version(&self, _: Self::Metadata) -> BoxFuture<String, Error> {
         debug!("get_current_version");
         let (tx, rx) = sync::oneshot::channel();
@@ -599,14 +1661,88 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Self::unsubscribe(id, &self.subscriptions.new_state_subscriptions)
     }
 
-    fn settings_subscribe(&self, _: Self::Metadata, subscriber: pubsub::Subscriber<Settings>) {
+    fn settings_subscribe(&self, meta: Self::Metadata, subscriber: pubsub::Subscriber<Settings>) {
         debug!("settings_subscribe");
-        Self::subscribe(subscriber, &self.subscriptions.settings_subscriptions);
+        if self.is_privileged(&meta) {
+            Self::subscribe(subscriber, &self.subscriptions.settings_subscriptions);
+        } else {
+            Self::subscribe(
+                subscriber,
+                &self.subscriptions.public_settings_subscriptions,
+            );
+        }
     }
 
     fn settings_unsubscribe(&self, id: SubscriptionId) -> BoxFuture<(), Error> {
         debug!("settings_unsubscribe");
-        Self::unsubscribe(id, &self.subscriptions.settings_subscriptions)
+        Self::unsubscribe_either(
+            id,
+            &self.subscriptions.settings_subscriptions,
+            &self.subscriptions.public_settings_subscriptions,
+        )
+    }
+
+    fn relay_list_updated_subscribe(
+        &self,
+        _: Self::Metadata,
+        subscriber: pubsub::Subscriber<RelayList>,
+    ) {
+        debug!("relay_list_updated_subscribe");
+        Self::subscribe(subscriber, &self.subscriptions.relay_list_subscriptions);
+    }
+
+    fn relay_list_updated_unsubscribe(&self, id: SubscriptionId) -> BoxFuture<(), Error> {
+        debug!("relay_list_updated_unsubscribe");
+        Self::unsubscribe(id, &self.subscriptions.relay_list_subscriptions)
+    }
+
+    fn relay_invalidated_subscribe(
+        &self,
+        _: Self::Metadata,
+        subscriber: pubsub::Subscriber<RelayInvalidated>,
+    ) {
+        debug!("relay_invalidated_subscribe");
+        Self::subscribe(
+            subscriber,
+            &self.subscriptions.relay_invalidated_subscriptions,
+        );
+    }
+
+    fn relay_invalidated_unsubscribe(&self, id: SubscriptionId) -> BoxFuture<(), Error> {
+        debug!("relay_invalidated_unsubscribe");
+        Self::unsubscribe(id, &self.subscriptions.relay_invalidated_subscriptions)
+    }
+
+    fn account_expiry_subscribe(
+        &self,
+        _: Self::Metadata,
+        subscriber: pubsub::Subscriber<AccountExpiry>,
+    ) {
+        debug!("account_expiry_subscribe");
+        Self::subscribe(subscriber, &self.subscriptions.account_expiry_subscriptions);
+    }
+
+    fn account_expiry_unsubscribe(&self, id: SubscriptionId) -> BoxFuture<(), Error> {
+        debug!("account_expiry_unsubscribe");
+        Self::unsubscribe(id, &self.subscriptions.account_expiry_subscriptions)
+    }
+
+    fn events_subscribe(&self, meta: Self::Metadata, subscriber: pubsub::Subscriber<DaemonEvent>) {
+        debug!("events_subscribe");
+        if self.is_privileged(&meta) {
+            Self::subscribe(subscriber, &self.subscriptions.events_subscriptions);
+        } else {
+            Self::subscribe(subscriber, &self.subscriptions.public_events_subscriptions);
+        }
+    }
+
+    fn events_unsubscribe(&self, id: SubscriptionId) -> BoxFuture<(), Error> {
+        debug!("events_unsubscribe");
+        Self::unsubscribe_either(
+            id,
+            &self.subscriptions.events_subscriptions,
+            &self.subscriptions.public_events_subscriptions,
+        )
     }
 }
 
@@ -617,6 +1753,8 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
 #[derive(Clone, Debug, Default)]
 pub struct Meta {
     session: Option<Arc<Session>>,
+    /// Who opened the connection. The credentials of local clients are always `None` on Windows.
+    client: Client,
 }
 
 /// Make the `Meta` type possible to use as jsonrpc metadata type.
@@ -630,8 +1768,88 @@ impl PubSubMetadata for Meta {
 }
 
 /// Metadata extractor function for `Meta`.
-fn meta_extractor(context: &jsonrpc_ipc_server::RequestContext) -> Meta {
+fn meta_extractor(
+    context: &jsonrpc_ipc_server::RequestContext,
+    credentials: Option<PeerCredentials>,
+) -> Meta {
     Meta {
         session: Some(Arc::new(Session::new(context.sender.clone()))),
+        client: Client::Local(credentials),
+    }
+}
+
+/// Metadata extractor function for `Meta` on the remote management interface.
+fn remote_meta_extractor(context: &jsonrpc_ws_server::RequestContext) -> Meta {
+    Meta {
+        session: Some(Arc::new(Session::new(context.sender()))),
+        client: Client::Remote,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_core::RemoteProcedure;
+
+    fn event(kind: DaemonEventKind) -> DaemonEvent {
+        DaemonEvent {
+            sequence: 1,
+            timestamp: Utc::now(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn public_events_leave_out_account_token() {
+        let settings =
+            Settings::from_json(serde_json::from_str(r#"{ "account_token": "1234" }"#).unwrap())
+                .unwrap();
+        assert_eq!(settings.get_account_token(), Some("1234".to_owned()));
+
+        match public_event(event(DaemonEventKind::Settings(settings))).kind {
+            DaemonEventKind::Settings(settings) => assert_eq!(settings.get_account_token(), None),
+            kind => panic!("Unexpected event: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn public_events_keep_other_events() {
+        let error = event(DaemonEventKind::Error("1234".to_owned()));
+
+        match public_event(error).kind {
+            DaemonEventKind::Error(message) => assert_eq!(message, "1234"),
+            kind => panic!("Unexpected event: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn api_info_lists_every_method_and_event() {
+        let (tx, _rx) = ::std::sync::mpsc::channel::<ManagementCommand>();
+        let rpc = ManagementInterface::new(
+            IntoSender::from(tx),
+            Policy::default(),
+            AccessPolicy::default(),
+            Arc::new(ActiveSubscriptions::default()),
+        );
+        let procedures: HashMap<String, RemoteProcedure<Meta>> = rpc.to_delegate().into();
+
+        let mut methods: Vec<String> = procedures
+            .keys()
+            .filter(|name| !name.ends_with("_subscribe") && !name.ends_with("_unsubscribe"))
+            .cloned()
+            .collect();
+        let mut events: Vec<String> = procedures
+            .keys()
+            .filter(|name| name.ends_with("_subscribe"))
+            .map(|name| name.replace("_subscribe", ""))
+            .collect();
+        let mut api_info = ApiInfo::current();
+        methods.sort();
+        events.sort();
+        api_info.methods.sort();
+        api_info.events.sort();
+        assert_eq!(methods, api_info.methods);
+        assert_eq!(events, api_info.events);
     }
 }
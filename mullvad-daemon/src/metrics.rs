@@ -0,0 +1,569 @@
+//! Collects metrics about the daemon and serves them over HTTP in the Prometheus text format, so
+//! they can be scraped by a local Prometheus server.
+
+use jsonrpc_core::futures::Future;
+use jsonrpc_core::{self, Metadata, Params, RemoteProcedure, RpcMethod, Value};
+use talpid_types::tunnel::{BlockReason, TunnelStateTransition};
+
+use network_activity;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long to wait for a scraper to send its request before giving up on it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const TUNNEL_STATES: &[&str] = &[
+    "disconnected",
+    "connecting",
+    "connected",
+    "disconnecting",
+    "blocked",
+];
+
+/// The metrics of the daemon. Updated by the daemon as it handles events, and by the management
+/// interface for every call made to it.
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+struct MetricsState {
+    tunnel_state: &'static str,
+    tunnel_state_since: Instant,
+    reconnects: BTreeMap<&'static str, u64>,
+    blocks: BTreeMap<&'static str, u64>,
+    relay_list_updated: Option<SystemTime>,
+    rpc_calls: BTreeMap<String, RpcCallStats>,
+}
+
+#[derive(Default)]
+struct RpcCallStats {
+    count: u64,
+    errors: u64,
+    total_duration: Duration,
+}
+
+impl Default for MetricsState {
+    fn default() -> Self {
+        MetricsState {
+            tunnel_state: "disconnected",
+            tunnel_state_since: Instant::now(),
+            reconnects: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            relay_list_updated: None,
+            rpc_calls: BTreeMap::new(),
+        }
+    }
+}
+
+impl Metrics {
+    /// Records that the tunnel entered a new state, and why if it's blocking.
+    pub fn set_tunnel_state(&self, tunnel_state: &TunnelStateTransition) {
+        let mut state = self.state.lock().unwrap();
+        state.tunnel_state = tunnel_state_label(tunnel_state);
+        state.tunnel_state_since = Instant::now();
+        if let TunnelStateTransition::Blocked(ref reason) = *tunnel_state {
+            *state.blocks.entry(block_reason_label(reason)).or_insert(0) += 1;
+        }
+    }
+
+    /// Records that the daemon reconnected the tunnel for the given reason.
+    pub fn count_reconnect(&self, reason: &'static str) {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .reconnects
+            .entry(reason)
+            .or_insert(0) += 1;
+    }
+
+    /// Records when the relay list in use was last updated.
+    pub fn set_relay_list_updated(&self, last_updated: SystemTime) {
+        self.state.lock().unwrap().relay_list_updated = Some(last_updated);
+    }
+
+    /// Records a finished call to the management interface.
+    pub fn record_rpc_call(&self, method: &str, duration: Duration, succeeded: bool) {
+        let mut state = self.state.lock().unwrap();
+        let stats = state
+            .rpc_calls
+            .entry(method.to_owned())
+            .or_insert_with(RpcCallStats::default);
+        stats.count += 1;
+        stats.total_duration += duration;
+        if !succeeded {
+            stats.errors += 1;
+        }
+    }
+
+    /// Returns all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut output = String::new();
+
+        write_header(
+            &mut output,
+            "mullvad_tunnel_state",
+            "gauge",
+            "1 for the state the tunnel is in, 0 for the others",
+        );
+        for tunnel_state in TUNNEL_STATES {
+            let value = if *tunnel_state == state.tunnel_state {
+                1
+            } else {
+                0
+            };
+            writeln!(
+                output,
+                "mullvad_tunnel_state{{state=\"{}\"}} {}",
+                tunnel_state, value
+            ).unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "mullvad_tunnel_state_seconds",
+            "gauge",
+            "Seconds the tunnel has been in its current state",
+        );
+        writeln!(
+            output,
+            "mullvad_tunnel_state_seconds {}",
+            as_seconds(state.tunnel_state_since.elapsed())
+        ).unwrap();
+
+        write_header(
+            &mut output,
+            "mullvad_reconnects_total",
+            "counter",
+            "Times the daemon reconnected the tunnel, by reason",
+        );
+        for (reason, count) in &state.reconnects {
+            writeln!(
+                output,
+                "mullvad_reconnects_total{{reason=\"{}\"}} {}",
+                reason, count
+            ).unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "mullvad_blocks_total",
+            "counter",
+            "Times the tunnel started blocking all traffic, by reason",
+        );
+        for (reason, count) in &state.blocks {
+            writeln!(
+                output,
+                "mullvad_blocks_total{{reason=\"{}\"}} {}",
+                reason, count
+            ).unwrap();
+        }
+
+        let relay_list_age = state
+            .relay_list_updated
+            .and_then(|last_updated| SystemTime::now().duration_since(last_updated).ok());
+        if let Some(relay_list_age) = relay_list_age {
+            write_header(
+                &mut output,
+                "mullvad_relay_list_age_seconds",
+                "gauge",
+                "Seconds since the relay list in use was downloaded",
+            );
+            writeln!(
+                output,
+                "mullvad_relay_list_age_seconds {}",
+                as_seconds(relay_list_age)
+            ).unwrap();
+        }
+
+        if let Some(transferred_bytes) = network_activity::transferred_bytes() {
+            write_header(
+                &mut output,
+                "mullvad_network_received_bytes_total",
+                "counter",
+                "Bytes received on all interfaces except loopback since boot",
+            );
+            writeln!(
+                output,
+                "mullvad_network_received_bytes_total {}",
+                transferred_bytes.received
+            ).unwrap();
+            write_header(
+                &mut output,
+                "mullvad_network_transmitted_bytes_total",
+                "counter",
+                "Bytes transmitted on all interfaces except loopback since boot",
+            );
+            writeln!(
+                output,
+                "mullvad_network_transmitted_bytes_total {}",
+                transferred_bytes.transmitted
+            ).unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "mullvad_rpc_calls_total",
+            "counter",
+            "Calls made to the management interface, by method",
+        );
+        for (method, stats) in &state.rpc_calls {
+            writeln!(
+                output,
+                "mullvad_rpc_calls_total{{method=\"{}\"}} {}",
+                method, stats.count
+            ).unwrap();
+        }
+        write_header(
+            &mut output,
+            "mullvad_rpc_call_errors_total",
+            "counter",
+            "Calls made to the management interface that failed, by method",
+        );
+        for (method, stats) in &state.rpc_calls {
+            writeln!(
+                output,
+                "mullvad_rpc_call_errors_total{{method=\"{}\"}} {}",
+                method, stats.errors
+            ).unwrap();
+        }
+        write_header(
+            &mut output,
+            "mullvad_rpc_call_duration_seconds",
+            "summary",
+            "Time taken to answer calls made to the management interface, by method",
+        );
+        for (method, stats) in &state.rpc_calls {
+            writeln!(
+                output,
+                "mullvad_rpc_call_duration_seconds_sum{{method=\"{}\"}} {}",
+                method,
+                as_seconds(stats.total_duration)
+            ).unwrap();
+            writeln!(
+                output,
+                "mullvad_rpc_call_duration_seconds_count{{method=\"{}\"}} {}",
+                method, stats.count
+            ).unwrap();
+        }
+
+        output
+    }
+}
+
+fn write_header(output: &mut String, name: &str, metric_type: &str, help: &str) {
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} {}", name, metric_type).unwrap();
+}
+
+fn as_seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+fn tunnel_state_label(tunnel_state: &TunnelStateTransition) -> &'static str {
+    match *tunnel_state {
+        TunnelStateTransition::Disconnected => "disconnected",
+        TunnelStateTransition::Connecting => "connecting",
+        TunnelStateTransition::Connected => "connected",
+        TunnelStateTransition::Disconnecting(_) => "disconnecting",
+        TunnelStateTransition::Blocked(_) => "blocked",
+    }
+}
+
+fn block_reason_label(reason: &BlockReason) -> &'static str {
+    match *reason {
+        BlockReason::AuthFailed(_) => "auth_failed",
+        BlockReason::Ipv6Unavailable => "ipv6_unavailable",
+        BlockReason::SetSecurityPolicyError => "set_security_policy_error",
+        BlockReason::StartTunnelError => "start_tunnel_error",
+        BlockReason::NoMatchingRelay => "no_matching_relay",
+        BlockReason::AccountExpired => "account_expired",
+    }
+}
+
+/// Wraps the methods of an RPC interface so every call to them is recorded in `metrics`.
+pub fn instrument<M: Metadata>(
+    methods: HashMap<String, RemoteProcedure<M>>,
+    metrics: &Arc<Metrics>,
+) -> HashMap<String, RemoteProcedure<M>> {
+    methods
+        .into_iter()
+        .map(|(name, procedure)| {
+            let procedure = match procedure {
+                RemoteProcedure::Method(method) => {
+                    RemoteProcedure::Method(Arc::new(InstrumentedMethod {
+                        name: name.clone(),
+                        method,
+                        metrics: metrics.clone(),
+                    }))
+                }
+                procedure => procedure,
+            };
+            (name, procedure)
+        }).collect()
+}
+
+struct InstrumentedMethod<M: Metadata> {
+    name: String,
+    method: Arc<RpcMethod<M>>,
+    metrics: Arc<Metrics>,
+}
+
+impl<M: Metadata> RpcMethod<M> for InstrumentedMethod<M> {
+    fn call(&self, params: Params, meta: M) -> jsonrpc_core::BoxFuture<Value> {
+        let start = Instant::now();
+        let name = self.name.clone();
+        let metrics = self.metrics.clone();
+        Box::new(self.method.call(params, meta).then(move |result| {
+            metrics.record_rpc_call(&name, start.elapsed(), result.is_ok());
+            result
+        }))
+    }
+}
+
+/// Serves the metrics at `/metrics` over HTTP on `address`, on a thread of its own. Scrapers are
+/// answered one at a time.
+pub fn serve(address: SocketAddr, metrics: Arc<Metrics>) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving metrics on http://{}/metrics", address);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(stream, &metrics));
+            if let Err(error) = result {
+                debug!("Unable to answer metrics request: {}", error);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    // Only the request line is used, but the headers are read so the scraper isn't cut off
+    // while sending them.
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 16 * 1024 {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let response = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = metrics.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        (Some("GET"), Some(_)) => {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+        }
+        _ => "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nContent-Length: 0\r\n\
+              Connection: close\r\n\r\n"
+            .to_owned(),
+    };
+    stream.write_all(response.as_bytes())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered_lines(metrics: &Metrics) -> Vec<String> {
+        metrics.render().lines().map(str::to_owned).collect()
+    }
+
+    fn contains_line(lines: &[String], line: &str) -> bool {
+        lines.iter().any(|rendered| rendered == line)
+    }
+
+    #[test]
+    fn renders_the_current_tunnel_state() {
+        let metrics = Metrics::default();
+        metrics.set_tunnel_state(&TunnelStateTransition::Connected);
+        let lines = rendered_lines(&metrics);
+
+        assert!(contains_line(&lines, "# TYPE mullvad_tunnel_state gauge"));
+        assert!(contains_line(
+            &lines,
+            "mullvad_tunnel_state{state=\"connected\"} 1"
+        ));
+        for tunnel_state in &["disconnected", "connecting", "disconnecting", "blocked"] {
+            let line = format!("mullvad_tunnel_state{{state=\"{}\"}} 0", tunnel_state);
+            assert!(contains_line(&lines, &line));
+        }
+    }
+
+    #[test]
+    fn counts_reconnects_and_blocks_by_reason() {
+        let metrics = Metrics::default();
+        metrics.count_reconnect("relay_rotation");
+        metrics.count_reconnect("relay_rotation");
+        metrics.count_reconnect("auth_failed");
+        metrics.set_tunnel_state(&TunnelStateTransition::Blocked(BlockReason::AccountExpired));
+        metrics.set_tunnel_state(&TunnelStateTransition::Disconnected);
+        metrics.set_tunnel_state(&TunnelStateTransition::Blocked(
+            BlockReason::NoMatchingRelay,
+        ));
+        let lines = rendered_lines(&metrics);
+
+        assert!(contains_line(
+            &lines,
+            "# TYPE mullvad_reconnects_total counter"
+        ));
+        assert!(contains_line(
+            &lines,
+            "mullvad_reconnects_total{reason=\"relay_rotation\"} 2"
+        ));
+        assert!(contains_line(
+            &lines,
+            "mullvad_reconnects_total{reason=\"auth_failed\"} 1"
+        ));
+        assert!(contains_line(&lines, "# TYPE mullvad_blocks_total counter"));
+        assert!(contains_line(
+            &lines,
+            "mullvad_blocks_total{reason=\"account_expired\"} 1"
+        ));
+        assert!(contains_line(
+            &lines,
+            "mullvad_blocks_total{reason=\"no_matching_relay\"} 1"
+        ));
+        assert!(contains_line(
+            &lines,
+            "mullvad_tunnel_state{state=\"blocked\"} 1"
+        ));
+    }
+
+    #[test]
+    fn renders_rpc_calls_as_counters_and_a_summary() {
+        let metrics = Metrics::default();
+        metrics.record_rpc_call("get_state", Duration::from_millis(1500), true);
+        metrics.record_rpc_call("get_state", Duration::from_millis(500), false);
+        metrics.record_rpc_call("connect", Duration::from_millis(250), true);
+        let lines = rendered_lines(&metrics);
+
+        assert!(contains_line(
+            &lines,
+            "mullvad_rpc_calls_total{method=\"get_state\"} 2"
+        ));
+        assert!(contains_line(
+            &lines,
+            "mullvad_rpc_call_errors_total{method=\"get_state\"} 1"
+        ));
+        assert!(contains_line(
+            &lines,
+            "mullvad_rpc_call_errors_total{method=\"connect\"} 0"
+        ));
+        assert!(contains_line(
+            &lines,
+            "# TYPE mullvad_rpc_call_duration_seconds summary"
+        ));
+        assert!(contains_line(
+            &lines,
+            "mullvad_rpc_call_duration_seconds_sum{method=\"get_state\"} 2"
+        ));
+        assert!(contains_line(
+            &lines,
+            "mullvad_rpc_call_duration_seconds_count{method=\"get_state\"} 2"
+        ));
+        assert!(contains_line(
+            &lines,
+            "mullvad_rpc_call_duration_seconds_sum{method=\"connect\"} 0.25"
+        ));
+    }
+
+    #[test]
+    fn leaves_out_the_relay_list_age_until_it_is_known() {
+        let metrics = Metrics::default();
+        assert!(!metrics.render().contains("mullvad_relay_list_age_seconds"));
+
+        metrics.set_relay_list_updated(SystemTime::now() - Duration::from_secs(60));
+        let lines = rendered_lines(&metrics);
+        let age_line = lines
+            .iter()
+            .find(|line| line.starts_with("mullvad_relay_list_age_seconds "))
+            .expect("No relay list age");
+        let age: f64 = age_line.split(' ').nth(1).unwrap().parse().unwrap();
+        assert!(age >= 60.0);
+        assert!(age < 120.0);
+    }
+
+    #[test]
+    fn every_metric_has_help_and_type() {
+        let metrics = Metrics::default();
+        metrics.count_reconnect("relay_rotation");
+        metrics.record_rpc_call("get_state", Duration::from_millis(1), true);
+        let lines = rendered_lines(&metrics);
+
+        let mut family = None;
+        for (index, line) in lines.iter().enumerate() {
+            if line.starts_with("# TYPE ") {
+                let name = line.split(' ').nth(2).unwrap();
+                assert!(lines[index - 1].starts_with(&format!("# HELP {} ", name)));
+                family = Some(name);
+            } else if !line.starts_with('#') {
+                let family = family.expect("Sample before the first TYPE");
+                assert!(line.starts_with(family), "{} is not in {}", line, family);
+            }
+        }
+    }
+
+    /// Sends `request` to `respond` over a local TCP connection and returns the response.
+    fn request(metrics: &Metrics, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        respond(server, metrics).unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn responds_with_metrics() {
+        let metrics = Metrics::default();
+        let response = request(
+            &metrics,
+            "GET /metrics HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n",
+        );
+
+        let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap() + 4);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert!(body.contains("mullvad_tunnel_state{state=\"disconnected\"} 1"));
+    }
+
+    #[test]
+    fn responds_not_found_to_other_paths() {
+        let response = request(&Metrics::default(), "GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("Content-Length: 0\r\nConnection: close\r\n\r\n"));
+    }
+
+    #[test]
+    fn responds_method_not_allowed_to_other_methods() {
+        let response = request(&Metrics::default(), "POST /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(response.contains("Allow: GET\r\n"));
+    }
+}
@@ -0,0 +1,96 @@
+//! Detects when the network is idle, so disruptive actions such as switching relays can be timed
+//! to when they are least noticeable, and measures how much data has been transferred.
+
+use std::time::{Duration, Instant};
+
+use timer::Cancellation;
+
+/// How often the amount of transferred data is sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// The network is considered idle if less than this many bytes were transferred during one
+/// sample interval.
+const IDLE_THRESHOLD_BYTES: u64 = 16 * 1024;
+
+/// The number of bytes received and transmitted on all interfaces, except loopback, since boot.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferredBytes {
+    pub received: u64,
+    pub transmitted: u64,
+}
+
+/// Returns how much data has been transferred, or `None` on platforms where network activity
+/// can't be measured.
+pub fn transferred_bytes() -> Option<TransferredBytes> {
+    imp::transferred_bytes()
+}
+
+/// Blocks until the network has been idle for one sample interval, or until `max_wait` has
+/// passed. Returns immediately on platforms where network activity can't be measured. Returns
+/// `false` if the wait was cancelled through `cancellation`, `true` otherwise.
+pub fn wait_for_idle(max_wait: Duration, cancellation: &Cancellation) -> bool {
+    let give_up_at = Instant::now() + max_wait;
+    let mut previous_total = match total_transferred_bytes() {
+        Some(total) => total,
+        None => return true,
+    };
+    while Instant::now() < give_up_at {
+        if !cancellation.sleep(SAMPLE_INTERVAL) {
+            return false;
+        }
+        let total = match total_transferred_bytes() {
+            Some(total) => total,
+            None => return true,
+        };
+        if total.saturating_sub(previous_total) < IDLE_THRESHOLD_BYTES {
+            debug!("Network is idle");
+            return true;
+        }
+        previous_total = total;
+    }
+    debug!("Network did not become idle within {:?}", max_wait);
+    true
+}
+
+fn total_transferred_bytes() -> Option<u64> {
+    imp::transferred_bytes().map(|bytes| bytes.received.wrapping_add(bytes.transmitted))
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::TransferredBytes;
+    use std::fs;
+
+    pub fn transferred_bytes() -> Option<TransferredBytes> {
+        let statistics = fs::read_to_string("/proc/net/dev").ok()?;
+        let mut total = TransferredBytes {
+            received: 0,
+            transmitted: 0,
+        };
+        // The first two lines are column headers.
+        for line in statistics.lines().skip(2) {
+            let mut parts = line.splitn(2, ':');
+            let interface = parts.next()?.trim();
+            if interface == "lo" {
+                continue;
+            }
+            let counters: Vec<u64> = parts
+                .next()?
+                .split_whitespace()
+                .map(|counter| counter.parse().unwrap_or(0))
+                .collect();
+            // Received bytes is the first column, transmitted bytes the ninth.
+            total.received = total.received.wrapping_add(*counters.get(0)?);
+            total.transmitted = total.transmitted.wrapping_add(*counters.get(8)?);
+        }
+        Some(total)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::TransferredBytes;
+
+    pub fn transferred_bytes() -> Option<TransferredBytes> {
+        None
+    }
+}
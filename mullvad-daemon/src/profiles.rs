@@ -0,0 +1,230 @@
+extern crate serde_json;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use error_chain::ChainedError;
+use mullvad_types::atomic_file;
+use mullvad_types::settings::Settings;
+
+error_chain! {
+    errors {
+        ReadError(path: PathBuf) {
+            description("Unable to read profiles file")
+            display("Unable to read profiles from {}", path.display())
+        }
+        WriteError(path: PathBuf) {
+            description("Unable to write profiles file")
+            display("Unable to write profiles to {}", path.display())
+        }
+        ParseError {
+            description("Malformed profiles")
+        }
+        InvalidProfile(name: String) {
+            description("Invalid settings in profile")
+            display("Invalid settings in profile {}", name)
+        }
+        InvalidName {
+            description("Profile names can't be empty or start or end with whitespace")
+        }
+    }
+}
+
+static PROFILES_FILE: &str = "profiles.json";
+
+/// Named sets of settings that can be switched between, such as one for the office and one for
+/// travelling. Stored next to the settings file. Profiles never contain an account token.
+#[derive(Debug)]
+pub struct Profiles {
+    profiles: BTreeMap<String, Settings>,
+    /// Profiles that could not be read. They are kept as they are stored, so they are not lost
+    /// when the other profiles are saved.
+    unreadable: BTreeMap<String, serde_json::Value>,
+    path: PathBuf,
+}
+
+impl Profiles {
+    /// Returns a new `Profiles` without any profiles, ready to load from, or save to, the given
+    /// settings dir.
+    pub fn new(settings_dir: &Path) -> Profiles {
+        Profiles {
+            profiles: BTreeMap::new(),
+            unreadable: BTreeMap::new(),
+            path: settings_dir.join(PROFILES_FILE),
+        }
+    }
+
+    /// Loads the profiles from file. If no file is present this does nothing. Profiles stored
+    /// in an older settings format are migrated. Profiles that can't be read are logged and left
+    /// out, without affecting the others.
+    pub fn load(&mut self) -> Result<()> {
+        match File::open(&self.path).map(io::BufReader::new) {
+            Ok(file) => {
+                info!("Loading profiles from {}", self.path.display());
+                let profiles: BTreeMap<String, serde_json::Value> =
+                    serde_json::from_reader(file).chain_err(|| ErrorKind::ParseError)?;
+                self.profiles.clear();
+                self.unreadable.clear();
+                for (name, profile) in profiles {
+                    match Settings::from_json(profile.clone())
+                        .chain_err(|| ErrorKind::InvalidProfile(name.clone()))
+                    {
+                        Ok(settings) => {
+                            self.profiles.insert(name, settings);
+                        }
+                        Err(error) => {
+                            error!("{}", error.display_chain());
+                            self.unreadable.insert(name, profile);
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                info!("No profiles file at {}", self.path.display());
+                Ok(())
+            }
+            Err(e) => Err(e).chain_err(|| ErrorKind::ReadError(self.path.clone())),
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Settings> {
+        self.profiles.get(name)
+    }
+
+    /// Stores `settings` as the profile `name`, replacing any profile with the same name, and
+    /// saves all profiles to disk.
+    pub fn set(&mut self, name: String, settings: &Settings) -> Result<()> {
+        validate_name(&name)?;
+        self.unreadable.remove(&name);
+        self.profiles.insert(name, settings.export(false));
+        self.save()
+    }
+
+    /// Removes the profile `name`, even if it could not be read, and saves the remaining profiles
+    /// to disk. Returns false if there was no such profile.
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        let removed = self.profiles.remove(name).is_some();
+        let removed_unreadable = self.unreadable.remove(name).is_some();
+        if removed || removed_unreadable {
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        debug!("Writing profiles to {}", self.path.display());
+        let mut profiles = self.unreadable.clone();
+        for (name, settings) in &self.profiles {
+            let settings = serde_json::to_value(settings)
+                .chain_err(|| ErrorKind::WriteError(self.path.clone()))?;
+            profiles.insert(name.clone(), settings);
+        }
+        let profiles = serde_json::to_vec_pretty(&profiles)
+            .chain_err(|| ErrorKind::WriteError(self.path.clone()))?;
+        atomic_file::write(&self.path, &profiles)
+            .chain_err(|| ErrorKind::WriteError(self.path.clone()))
+    }
+}
+
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.trim() != name {
+        bail!(ErrorKind::InvalidName);
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mullvad-profiles-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn stored_profiles(dir: &Path) -> BTreeMap<String, serde_json::Value> {
+        serde_json::from_slice(&fs::read(dir.join(PROFILES_FILE)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn skips_unreadable_profiles() {
+        let dir = test_dir("unreadable");
+        fs::write(
+            dir.join(PROFILES_FILE),
+            r#"{ "home": { "allow_lan": true }, "office": { "allow_lan": "yes" } }"#,
+        ).unwrap();
+        let mut profiles = Profiles::new(&dir);
+
+        profiles.load().unwrap();
+
+        assert_eq!(profiles.names(), ["home"]);
+        assert!(profiles.get("home").unwrap().get_allow_lan());
+        assert!(profiles.get("office").is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_unreadable_profiles_when_saving() {
+        let dir = test_dir("keep");
+        let profiles_json = r#"{ "office": { "allow_lan": "yes" } }"#;
+        fs::write(dir.join(PROFILES_FILE), profiles_json).unwrap();
+        let mut profiles = Profiles::new(&dir);
+        profiles.load().unwrap();
+
+        profiles
+            .set("home".to_owned(), &Settings::default())
+            .unwrap();
+
+        let stored = stored_profiles(&dir);
+        assert_eq!(stored.keys().collect::<Vec<_>>(), ["home", "office"]);
+        assert_eq!(stored["office"]["allow_lan"], "yes");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removes_unreadable_profiles() {
+        let dir = test_dir("remove");
+        let profiles_json = r#"{ "office": { "allow_lan": "yes" } }"#;
+        fs::write(dir.join(PROFILES_FILE), profiles_json).unwrap();
+        let mut profiles = Profiles::new(&dir);
+        profiles.load().unwrap();
+
+        assert!(profiles.remove("office").unwrap());
+        assert!(!profiles.remove("office").unwrap());
+
+        assert!(stored_profiles(&dir).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fails_on_malformed_file() {
+        let dir = test_dir("malformed");
+        fs::write(dir.join(PROFILES_FILE), "[").unwrap();
+        let mut profiles = Profiles::new(&dir);
+
+        assert!(profiles.load().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert!(validate_name("home").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name(" home").is_err());
+        assert!(validate_name("home\n").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use base64;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Public};
+use openssl::sign::Verifier;
+
+use error_chain::ChainedError;
+
+use std::fs;
+use std::path::Path;
+
+error_chain! {
+    errors {
+        ReadKeyError { description("Unable to read the relay list signing key") }
+        NoKey { description("No key to verify the relay list signature with") }
+        InvalidSignatureEncoding { description("The relay list signature is not valid base64") }
+        VerifyError { description("Error while verifying the relay list signature") }
+        InvalidSignature { description("The relay list signature does not match its content") }
+    }
+}
+
+
+/// Verifies the detached signatures of relay lists against the public key bundled in the
+/// resource directory.
+pub struct RelayListVerifier {
+    key: Option<PKey<Public>>,
+}
+
+impl RelayListVerifier {
+    /// Loads the PEM encoded public key at `path`. If the key can't be loaded the error is
+    /// logged, and the returned verifier rejects all relay lists.
+    pub fn load(path: &Path) -> Self {
+        debug!("Reading relay list signing key from {}", path.display());
+        let key = match Self::read_key(path) {
+            Ok(key) => Some(key),
+            Err(error) => {
+                let chained_error = error.chain_err(|| "All relay lists will be rejected");
+                error!("{}", chained_error.display_chain());
+                None
+            }
+        };
+        RelayListVerifier { key }
+    }
+
+    fn read_key(path: &Path) -> Result<PKey<Public>> {
+        let pem = fs::read(path).chain_err(|| ErrorKind::ReadKeyError)?;
+        PKey::public_key_from_pem(&pem).chain_err(|| ErrorKind::ReadKeyError)
+    }
+
+    /// Checks that `signature`, a base64 encoded signature, is a valid signature of
+    /// `relay_list` made with the private part of the bundled key.
+    pub fn verify(&self, relay_list: &[u8], signature: &str) -> Result<()> {
+        let key = self.key.as_ref().ok_or(ErrorKind::NoKey)?;
+        let signature =
+            base64::decode(signature.trim()).chain_err(|| ErrorKind::InvalidSignatureEncoding)?;
+
+        let mut verifier =
+            Verifier::new(MessageDigest::sha256(), key).chain_err(|| ErrorKind::VerifyError)?;
+        verifier
+            .update(relay_list)
+            .chain_err(|| ErrorKind::VerifyError)?;
+        if verifier
+            .verify(&signature)
+            .chain_err(|| ErrorKind::VerifyError)?
+        {
+            Ok(())
+        } else {
+            bail!(ErrorKind::InvalidSignature)
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::sign::Signer;
+    use tempfile;
+
+    /// Generates a signing key, and returns it together with a verifier of its signatures.
+    pub fn signing_key() -> (PKey<Private>, RelayListVerifier) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let key_dir = tempfile::tempdir().unwrap();
+        let key_path = key_dir.path().join("key.pem");
+        fs::write(&key_path, key.public_key_to_pem().unwrap()).unwrap();
+        let verifier = RelayListVerifier::load(&key_path);
+        (key, verifier)
+    }
+
+    /// Returns the base64 encoded signature of `data` made with `key`.
+    pub fn sign(key: &PKey<Private>, data: &[u8]) -> String {
+        let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+        signer.update(data).unwrap();
+        base64::encode(&signer.sign_to_vec().unwrap())
+    }
+
+    #[test]
+    fn accepts_signed_relay_list() {
+        let (key, verifier) = signing_key();
+        let relay_list = br#"{ "countries": [] }"#;
+
+        assert!(verifier.verify(relay_list, &sign(&key, relay_list)).is_ok());
+    }
+
+    #[test]
+    fn rejects_modified_relay_list() {
+        let (key, verifier) = signing_key();
+        let signature = sign(&key, br#"{ "countries": [] }"#);
+
+        match verifier.verify(br#"{ "countries": [{}] }"#, &signature) {
+            Err(Error(ErrorKind::InvalidSignature, _)) => (),
+            result => panic!("Modified relay list was not rejected: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_relay_list_signed_by_another_key() {
+        let (_, verifier) = signing_key();
+        let (other_key, _) = signing_key();
+        let relay_list = br#"{ "countries": [] }"#;
+
+        assert!(verifier
+            .verify(relay_list, &sign(&other_key, relay_list))
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_all_relay_lists_without_key() {
+        let key_dir = tempfile::tempdir().unwrap();
+        let verifier = RelayListVerifier::load(&key_dir.path().join("missing.pem"));
+        let (key, _) = signing_key();
+        let relay_list = br#"{ "countries": [] }"#;
+
+        match verifier.verify(relay_list, &sign(&key, relay_list)) {
+            Err(Error(ErrorKind::NoKey, _)) => (),
+            result => panic!("Relay list was not rejected: {:?}", result),
+        }
+    }
+}
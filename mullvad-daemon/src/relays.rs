@@ -2,32 +2,49 @@ use chrono::{DateTime, Local};
 use error_chain::ChainedError;
 use futures::Future;
 
-use mullvad_rpc::{HttpHandle, RelayListProxy};
+use mullvad_paths::resources::RELAY_LIST_KEY_FILENAME;
+use mullvad_rpc::relay_list::{DownloadedRelayList, RelayListClient};
+use mullvad_types::atomic_file;
 use mullvad_types::location::Location;
 use mullvad_types::relay_constraints::{
-    Constraint, LocationConstraint, Match, OpenVpnConstraints, RelayConstraints, TunnelConstraints,
+    Constraint, IpVersionPreference, LocationConstraint, Match, OpenVpnConstraints,
+    RelayConstraints, TunnelConstraints,
+};
+use mullvad_types::relay_list::{
+    MatchingRelays, RejectReason, RejectedRelay, Relay, RelayInvalidReason, RelayList,
+    RelayListCity, RelayListCountry, RelayTunnels,
 };
-use mullvad_types::relay_list::{Relay, RelayList, RelayTunnels};
 
 use serde_json;
 
 use talpid_types::net::{TransportProtocol, TunnelEndpoint, TunnelEndpointData};
 
-use std::fs::File;
-use std::net::IpAddr;
+use std::collections::HashSet;
+use std::fs;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc, Mutex, MutexGuard};
 use std::time::{self, Duration, SystemTime};
 use std::{io, thread};
 
 use rand::{self, Rng, ThreadRng};
+use talpid_core::mpsc::IntoSender;
 use tokio_timer::{TimeoutError, Timer};
 
+use relay_list_signature::RelayListVerifier;
+use DaemonEvent;
+
 const DATE_TIME_FORMAT_STR: &str = "[%Y-%m-%d %H:%M:%S%.3f]";
 const RELAYS_FILENAME: &str = "relays.json";
 const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(15);
 const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 const MAX_CACHE_AGE: Duration = Duration::from_secs(60 * 60 * 24);
+/// How old the relay list has to be before it's refreshed because no relay matched the
+/// constraints.
+const NO_RELAY_MAX_CACHE_AGE: Duration = Duration::from_secs(60 * 10);
+/// Arbitrary port used when checking if there is a route to a relay.
+const ROUTE_CHECK_PORT: u16 = 1194;
 
 error_chain! {
     errors {
@@ -36,6 +53,9 @@ error_chain! {
         DownloadTimeoutError { description("Timed out when trying to download the list of relays") }
         NoRelay { description("No relays matching current constraints") }
         SerializationError { description("Error in serialization of relaylist") }
+        RelayListRejected {
+            description("The relay list was rejected, its signature could not be verified")
+        }
     }
 }
 
@@ -47,6 +67,10 @@ impl<F> From<TimeoutError<F>> for Error {
 
 struct ParsedRelays {
     last_updated: SystemTime,
+    /// The entity tag the API identified `relay_list` by, if it was downloaded.
+    etag: Option<String>,
+    relay_list: RelayList,
+    user_relays: RelayList,
     locations: RelayList,
     relays: Vec<Relay>,
 }
@@ -55,12 +79,109 @@ impl ParsedRelays {
     pub fn empty() -> Self {
         ParsedRelays {
             last_updated: time::UNIX_EPOCH,
+            etag: None,
+            relay_list: RelayList::empty(),
+            user_relays: RelayList::empty(),
             locations: RelayList::empty(),
             relays: Vec::new(),
         }
     }
 
-    pub fn from_relay_list(mut relay_list: RelayList, last_updated: SystemTime) -> Self {
+    /// Parses `relay_list`, with the relays in `user_relays` merged into it. The user relays are
+    /// flagged as user defined, but are otherwise treated just like any other relay. User relays
+    /// with the same hostname as a relay in `relay_list` are left out, so they can't take the
+    /// place of a relay from the API.
+    pub fn from_relay_list(
+        relay_list: RelayList,
+        etag: Option<String>,
+        user_relays: RelayList,
+        last_updated: SystemTime,
+    ) -> Self {
+        let mut merged_relay_list = relay_list.clone();
+        Self::merge_user_relays(&mut merged_relay_list, &user_relays);
+        let (locations, relays) = Self::parse_relay_list(merged_relay_list);
+        ParsedRelays {
+            last_updated,
+            etag,
+            relay_list,
+            user_relays,
+            locations,
+            relays,
+        }
+    }
+
+    /// Replaces the user relays, keeping the relay list they are merged into.
+    pub fn set_user_relays(&mut self, user_relays: RelayList) {
+        let relay_list = mem::replace(&mut self.relay_list, RelayList::empty());
+        let etag = self.etag.take();
+        *self = Self::from_relay_list(relay_list, etag, user_relays, self.last_updated);
+    }
+
+    fn merge_user_relays(relay_list: &mut RelayList, user_relays: &RelayList) {
+        let hostnames = Self::hostnames(relay_list);
+        for user_country in &user_relays.countries {
+            let country_index = match relay_list
+                .countries
+                .iter()
+                .position(|country| country.code == user_country.code)
+            {
+                Some(index) => index,
+                None => {
+                    relay_list.countries.push(RelayListCountry {
+                        cities: Vec::new(),
+                        ..user_country.clone()
+                    });
+                    relay_list.countries.len() - 1
+                }
+            };
+            let country = &mut relay_list.countries[country_index];
+            for user_city in &user_country.cities {
+                let city_index = match country
+                    .cities
+                    .iter()
+                    .position(|city| city.code == user_city.code)
+                {
+                    Some(index) => index,
+                    None => {
+                        country.cities.push(RelayListCity {
+                            relays: Vec::new(),
+                            ..user_city.clone()
+                        });
+                        country.cities.len() - 1
+                    }
+                };
+                let city = &mut country.cities[city_index];
+                for user_relay in &user_city.relays {
+                    if hostnames.contains(&user_relay.hostname) {
+                        warn!(
+                            "Ignoring user relay {} since the relay list has a relay with the \
+                             same hostname",
+                            user_relay.hostname
+                        );
+                        continue;
+                    }
+                    city.relays.push(Relay {
+                        user_defined: true,
+                        ..user_relay.clone()
+                    });
+                }
+            }
+        }
+    }
+
+    fn hostnames(relay_list: &RelayList) -> HashSet<String> {
+        relay_list
+            .countries
+            .iter()
+            .flat_map(|country| country.cities.iter())
+            .flat_map(|city| city.relays.iter())
+            .map(|relay| relay.hostname.clone())
+            .collect()
+    }
+
+    /// Returns the relay list with all tunnels removed, and a flat list of all relays with their
+    /// locations set.
+    fn parse_relay_list(mut relay_list: RelayList) -> (RelayList, Vec<Relay>) {
         let mut relays = Vec::new();
         for country in &mut relay_list.countries {
             let country_name = country.name.clone();
@@ -85,27 +206,39 @@ impl ParsedRelays {
                 }
             }
         }
-        ParsedRelays {
-            last_updated,
-            locations: relay_list,
-            relays,
-        }
+        (relay_list, relays)
     }
 
-    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+    /// Reads the relay list at `path` and verifies it against the detached signature stored
+    /// next to it.
+    pub fn from_file(path: impl AsRef<Path>, verifier: &RelayListVerifier) -> Result<Self> {
         debug!("Reading relays from {}", path.as_ref().display());
-        let (last_modified, file) =
-            Self::open_file(path.as_ref()).chain_err(|| ErrorKind::RelayCacheError)?;
-        let relay_list = serde_json::from_reader(io::BufReader::new(file))
-            .chain_err(|| ErrorKind::SerializationError)?;
+        let (last_modified, relay_list) =
+            Self::read_file(path.as_ref()).chain_err(|| ErrorKind::RelayCacheError)?;
+        let signature = fs::read_to_string(signature_path(path.as_ref()))
+            .chain_err(|| ErrorKind::RelayCacheError)
+            .chain_err(|| ErrorKind::RelayListRejected)?;
+        verifier
+            .verify(&relay_list, &signature)
+            .chain_err(|| ErrorKind::RelayListRejected)?;
+        let relay_list =
+            serde_json::from_slice(&relay_list).chain_err(|| ErrorKind::SerializationError)?;
+        let etag = fs::read_to_string(etag_path(path.as_ref()))
+            .ok()
+            .map(|etag| etag.trim().to_owned());
 
-        Ok(Self::from_relay_list(relay_list, last_modified))
+        Ok(Self::from_relay_list(
+            relay_list,
+            etag,
+            RelayList::empty(),
+            last_modified,
+        ))
     }
 
-    fn open_file(path: &Path) -> io::Result<(SystemTime, File)> {
-        let file = File::open(path)?;
-        let last_modified = file.metadata()?.modified()?;
-        Ok((last_modified, file))
+    fn read_file(path: &Path) -> io::Result<(SystemTime, Vec<u8>)> {
+        let last_modified = fs::metadata(path)?.modified()?;
+        let content = fs::read(path)?;
+        Ok((last_modified, content))
     }
 
     pub fn last_updated(&self) -> SystemTime {
@@ -129,16 +262,29 @@ pub struct RelaySelector {
 
 impl RelaySelector {
     /// Returns a new `RelaySelector` backed by relays cached on disk. Use the `update` method
-    /// to refresh the relay list from the internet.
-    pub fn new(rpc_handle: HttpHandle, resource_dir: &Path, cache_dir: &Path) -> Self {
+    /// to refresh the relay list from the internet. The new relay list is sent to `on_update`
+    /// every time it changes.
+    pub fn new(
+        rpc_client: RelayListClient,
+        on_update: IntoSender<RelayList, DaemonEvent>,
+        user_relays: RelayList,
+        resource_dir: &Path,
+        cache_dir: &Path,
+    ) -> Self {
         let cache_path = cache_dir.join(RELAYS_FILENAME);
         let resource_path = resource_dir.join(RELAYS_FILENAME);
-        let unsynchronized_parsed_relays = Self::read_cached_relays(&cache_path, &resource_path)
-            .unwrap_or_else(|error| {
-                let chained_error = error.chain_err(|| "Unable to load cached relays");
-                error!("{}", chained_error.display_chain());
-                ParsedRelays::empty()
-            });
+        let verifier = Arc::new(RelayListVerifier::load(
+            &resource_dir.join(RELAY_LIST_KEY_FILENAME),
+        ));
+        let mut unsynchronized_parsed_relays =
+            Self::read_cached_relays(&cache_path, &resource_path, &verifier).unwrap_or_else(
+                |error| {
+                    let chained_error = error.chain_err(|| "Unable to load cached relays");
+                    error!("{}", chained_error.display_chain());
+                    ParsedRelays::empty()
+                },
+            );
+        unsynchronized_parsed_relays.set_user_relays(user_relays);
         info!(
             "Initialized with {} cached relays from {}",
             unsynchronized_parsed_relays.relays().len(),
@@ -146,7 +292,13 @@ impl RelaySelector {
                 .format(DATE_TIME_FORMAT_STR)
         );
         let parsed_relays = Arc::new(Mutex::new(unsynchronized_parsed_relays));
-        let updater = RelayListUpdater::spawn(rpc_handle, cache_path, parsed_relays.clone());
+        let updater = RelayListUpdater::spawn(
+            rpc_client,
+            cache_path,
+            verifier,
+            parsed_relays.clone(),
+            on_update,
+        );
         RelaySelector {
             parsed_relays,
             rng: rand::thread_rng(),
@@ -154,10 +306,19 @@ impl RelaySelector {
         }
     }
 
-    /// Download the newest relay list.
+    /// Download the newest relay list, if the current one is older than `MAX_CACHE_AGE`.
     pub fn update(&self) {
+        self.send_update_request(UpdateRequest::IfOlderThan(MAX_CACHE_AGE));
+    }
+
+    /// Download the newest relay list, regardless of the age of the current one.
+    pub fn force_update(&self) {
+        self.send_update_request(UpdateRequest::Force);
+    }
+
+    fn send_update_request(&self, request: UpdateRequest) {
         self.updater
-            .send(())
+            .send(request)
             .expect("Relay list updated thread has stopped unexpectedly");
     }
 
@@ -167,6 +328,27 @@ impl RelaySelector {
         self.lock_parsed_relays().locations().clone()
     }
 
+    /// Returns when the relay list was last downloaded, or when the cached one was written.
+    pub fn get_last_updated(&self) -> SystemTime {
+        self.lock_parsed_relays().last_updated()
+    }
+
+    /// Returns the first hostname in `user_relays` that is also used by a relay in the relay list
+    /// from the API, if any.
+    pub fn find_shadowing_user_relay(&self, user_relays: &RelayList) -> Option<String> {
+        let hostnames = ParsedRelays::hostnames(&self.lock_parsed_relays().relay_list);
+        ParsedRelays::hostnames(user_relays)
+            .into_iter()
+            .find(|hostname| hostnames.contains(hostname))
+    }
+
+    /// Replaces the relays added by the user. Returns the new countries and cities.
+    pub fn set_user_relays(&mut self, user_relays: RelayList) -> RelayList {
+        let mut parsed_relays = self.lock_parsed_relays();
+        parsed_relays.set_user_relays(user_relays);
+        parsed_relays.locations().clone()
+    }
+
     fn lock_parsed_relays(&self) -> MutexGuard<ParsedRelays> {
         self.parsed_relays
             .lock()
@@ -174,43 +356,68 @@ impl RelaySelector {
     }
 
     /// Returns a random relay and relay endpoint matching the given constraints and with
-    /// preferences applied.
+    /// preferences applied. The endpoint address is picked according to `ip_version`.
     pub fn get_tunnel_endpoint(
         &mut self,
         constraints: &RelayConstraints,
+        ip_version: IpVersionPreference,
+    ) -> Result<(Relay, TunnelEndpoint)> {
+        self.get_tunnel_endpoint_excluding(constraints, ip_version, None)
+    }
+
+    /// Same as `get_tunnel_endpoint`, but never returns the relay with the hostname
+    /// `current_hostname`.
+    pub fn get_other_tunnel_endpoint(
+        &mut self,
+        constraints: &RelayConstraints,
+        ip_version: IpVersionPreference,
+        current_hostname: &str,
+    ) -> Result<(Relay, TunnelEndpoint)> {
+        self.get_tunnel_endpoint_excluding(constraints, ip_version, Some(current_hostname))
+    }
+
+    fn get_tunnel_endpoint_excluding(
+        &mut self,
+        constraints: &RelayConstraints,
+        ip_version: IpVersionPreference,
+        excluded_hostname: Option<&str>,
     ) -> Result<(Relay, TunnelEndpoint)> {
         // Highest priority preference. Where we prefer OpenVPN using UDP. But without changing
         // any constraints that are explicitly specified.
         let tunnel_constraints1 = match constraints.tunnel {
-            Constraint::Any => TunnelConstraints::OpenVpn(OpenVpnConstraints {
+            Constraint::Any => Constraint::Only(TunnelConstraints::OpenVpn(OpenVpnConstraints {
                 port: Constraint::Any,
                 protocol: Constraint::Only(TransportProtocol::Udp),
-            }),
+            })),
             Constraint::Only(TunnelConstraints::OpenVpn(ref openvpn_constraints)) => {
-                TunnelConstraints::OpenVpn(OpenVpnConstraints {
+                Constraint::Only(TunnelConstraints::OpenVpn(OpenVpnConstraints {
                     port: openvpn_constraints.port.clone(),
-                    protocol: Constraint::Only(
-                        openvpn_constraints
-                            .protocol
-                            .clone()
-                            .unwrap_or(TransportProtocol::Udp),
-                    ),
-                })
+                    protocol: match openvpn_constraints.protocol {
+                        Constraint::Any => Constraint::Only(TransportProtocol::Udp),
+                        ref protocol => protocol.clone(),
+                    },
+                }))
             }
-            Constraint::Only(ref tunnel_constraints) => tunnel_constraints.clone(),
+            ref tunnel_constraints => tunnel_constraints.clone(),
         };
         let relay_constraints1 = RelayConstraints {
             location: constraints.location.clone(),
-            tunnel: Constraint::Only(tunnel_constraints1),
+            tunnel: tunnel_constraints1,
         };
 
-        if let Some((relay, endpoint)) = self.get_tunnel_endpoint_internal(&relay_constraints1) {
+        if let Some((relay, endpoint)) =
+            self.get_tunnel_endpoint_internal(&relay_constraints1, ip_version, excluded_hostname)
+        {
             debug!("Relay matched on highest preference");
             Ok((relay, endpoint))
-        } else if let Some((relay, endpoint)) = self.get_tunnel_endpoint_internal(constraints) {
+        } else if let Some((relay, endpoint)) =
+            self.get_tunnel_endpoint_internal(constraints, ip_version, excluded_hostname)
+        {
             debug!("Relay matched on second preference");
             Ok((relay, endpoint))
         } else {
+            // The relay list might be outdated. Refresh it, so the next attempt might succeed.
+            self.send_update_request(UpdateRequest::IfOlderThan(NO_RELAY_MAX_CACHE_AGE));
             bail!(ErrorKind::NoRelay);
         }
     }
@@ -219,24 +426,26 @@ impl RelaySelector {
     fn get_tunnel_endpoint_internal(
         &mut self,
         constraints: &RelayConstraints,
+        ip_version: IpVersionPreference,
+        excluded_hostname: Option<&str>,
     ) -> Option<(Relay, TunnelEndpoint)> {
         let matching_relays: Vec<Relay> = self
             .lock_parsed_relays()
             .relays()
             .iter()
+            .filter(|relay| Some(relay.hostname.as_str()) != excluded_hostname)
+            .filter(|relay| ip_version != IpVersionPreference::Ipv6 || relay.ipv6_addr_in.is_some())
             .filter_map(|relay| Self::matching_relay(relay, constraints))
             .collect();
 
         self.pick_random_relay(&matching_relays)
             .and_then(|selected_relay| {
-                info!(
-                    "Selected relay {} at {}",
-                    selected_relay.hostname, selected_relay.ipv4_addr_in
-                );
+                let address = Self::select_address(selected_relay, ip_version)?;
+                info!("Selected relay {} at {}", selected_relay.hostname, address);
                 self.get_random_tunnel(&selected_relay.tunnels)
                     .map(|tunnel_parameters| {
                         let endpoint = TunnelEndpoint {
-                            address: IpAddr::V4(selected_relay.ipv4_addr_in),
+                            address,
                             tunnel: tunnel_parameters,
                         };
                         (selected_relay.clone(), endpoint)
@@ -244,55 +453,155 @@ impl RelaySelector {
             })
     }
 
+    /// Returns the address to connect to on the given relay, given the IP version preference.
+    fn select_address(relay: &Relay, ip_version: IpVersionPreference) -> Option<IpAddr> {
+        let ipv4_address = IpAddr::V4(relay.ipv4_addr_in);
+        let ipv6_address = relay.ipv6_addr_in.map(IpAddr::V6);
+        match ip_version {
+            IpVersionPreference::Ipv4 => Some(ipv4_address),
+            IpVersionPreference::Ipv6 => ipv6_address,
+            IpVersionPreference::Auto => match ipv6_address {
+                Some(ipv6_address) if !has_route_to(ipv4_address) => {
+                    if has_route_to(ipv6_address) {
+                        debug!("No route to {}, using IPv6 instead", ipv4_address);
+                        Some(ipv6_address)
+                    } else {
+                        Some(ipv4_address)
+                    }
+                }
+                _ => Some(ipv4_address),
+            },
+        }
+    }
+
+    /// Returns all relays matching the given constraints. Only the tunnel endpoints matching the
+    /// constraints are kept in the returned relays. If `explain` is true, the relays that were
+    /// filtered out are also returned, together with the rule that rejected them.
+    pub fn get_matching_relays(
+        &self,
+        constraints: &RelayConstraints,
+        explain: bool,
+    ) -> MatchingRelays {
+        let mut matching_relays = MatchingRelays::empty();
+        for relay in self.lock_parsed_relays().relays() {
+            match Self::filter_relay(relay, constraints) {
+                Ok(relay) => matching_relays.relays.push(relay),
+                Err(reason) => {
+                    if explain {
+                        matching_relays.rejected.push(RejectedRelay {
+                            hostname: relay.hostname.clone(),
+                            reason,
+                        });
+                    }
+                }
+            }
+        }
+        matching_relays
+    }
+
+    /// Checks that the relay with the given hostname is still in the relay list, still has
+    /// `endpoint`, and that both still match the constraints and the IP version preference.
+    /// Returns why it can't be used otherwise.
+    pub fn validate_relay(
+        &self,
+        hostname: &str,
+        endpoint: &TunnelEndpoint,
+        constraints: &RelayConstraints,
+        ip_version: IpVersionPreference,
+    ) -> ::std::result::Result<(), RelayInvalidReason> {
+        let parsed_relays = self.lock_parsed_relays();
+        let relay = parsed_relays
+            .relays()
+            .iter()
+            .find(|relay| relay.hostname == hostname)
+            .ok_or(RelayInvalidReason::Removed)?;
+
+        let has_address = endpoint.address == IpAddr::V4(relay.ipv4_addr_in)
+            || relay.ipv6_addr_in.map(IpAddr::V6) == Some(endpoint.address);
+        if !has_address || !Self::has_tunnel(&relay.tunnels, &endpoint.tunnel) {
+            return Err(RelayInvalidReason::EndpointRemoved);
+        }
+
+        let matches_ip_version = match ip_version {
+            IpVersionPreference::Auto => true,
+            IpVersionPreference::Ipv4 => endpoint.address.is_ipv4(),
+            IpVersionPreference::Ipv6 => endpoint.address.is_ipv6(),
+        };
+        if !matches_ip_version {
+            return Err(RelayInvalidReason::NoLongerMatchesConstraints);
+        }
+
+        match Self::filter_relay(relay, constraints) {
+            Ok(ref relay) if Self::has_tunnel(&relay.tunnels, &endpoint.tunnel) => Ok(()),
+            _ => Err(RelayInvalidReason::NoLongerMatchesConstraints),
+        }
+    }
+
+    fn has_tunnel(tunnels: &RelayTunnels, tunnel: &TunnelEndpointData) -> bool {
+        match *tunnel {
+            TunnelEndpointData::OpenVpn(ref data) => tunnels.openvpn.contains(data),
+            TunnelEndpointData::Wireguard(ref data) => tunnels.wireguard.contains(data),
+        }
+    }
+
     /// Takes a `Relay` and a corresponding `RelayConstraints` and returns a new `Relay` if the
     /// given relay matches the constraints.
     fn matching_relay(relay: &Relay, constraints: &RelayConstraints) -> Option<Relay> {
-        let matches_location = match constraints.location {
-            Constraint::Any => true,
-            Constraint::Only(LocationConstraint::Country(ref country)) => {
-                relay
-                    .location
-                    .as_ref()
-                    .map_or(false, |loc| loc.country_code == *country)
-                    && relay.include_in_country
-            }
-            Constraint::Only(LocationConstraint::City(ref country, ref city)) => {
-                relay.location.as_ref().map_or(false, |loc| {
-                    loc.country_code == *country && loc.city_code == *city
-                })
-            }
-            Constraint::Only(LocationConstraint::Hostname(ref country, ref city, ref hostname)) => {
-                relay.location.as_ref().map_or(false, |loc| {
-                    loc.country_code == *country
-                        && loc.city_code == *city
-                        && relay.hostname == *hostname
-                })
-            }
-        };
-        if !matches_location {
-            return None;
+        Self::filter_relay(relay, constraints).ok()
+    }
+
+    /// Same as `matching_relay`, but returns the reason the relay was rejected if it does not
+    /// match the constraints.
+    fn filter_relay(
+        relay: &Relay,
+        constraints: &RelayConstraints,
+    ) -> ::std::result::Result<Relay, RejectReason> {
+        if !constraints.location.matches(relay) {
+            return Err(RejectReason::Location);
+        }
+        if !relay.include_in_country && !Self::selects_explicitly(&constraints.location, relay) {
+            return Err(RejectReason::NotIncludedInCountry);
         }
         let relay = match constraints.tunnel {
             Constraint::Any => relay.clone(),
-            Constraint::Only(ref tunnel_constraints) => {
+            ref tunnel_constraints => {
                 let mut relay = relay.clone();
                 relay.tunnels = Self::matching_tunnels(&relay.tunnels, tunnel_constraints);
                 relay
             }
         };
         if relay.tunnels.openvpn.is_empty() {
-            None
+            Err(RejectReason::NoMatchingTunnel)
         } else {
-            Some(relay)
+            Ok(relay)
+        }
+    }
+
+    /// Returns true if the location constraint selects the relay by more than just its country.
+    /// Relays that should not be included in their country are only used if selected this way,
+    /// or if any location is allowed. A negation only says where the relay must not be, so like a
+    /// country it doesn't select the relay explicitly.
+    fn selects_explicitly(constraint: &Constraint<LocationConstraint>, relay: &Relay) -> bool {
+        let selects_by_city_or_hostname = |location: &LocationConstraint| match *location {
+            LocationConstraint::Country(_) => false,
+            ref location => location.matches(relay),
+        };
+        match *constraint {
+            Constraint::Any => true,
+            Constraint::Not(_) => false,
+            Constraint::Only(ref location) => selects_by_city_or_hostname(location),
+            Constraint::OneOf(ref locations) => {
+                locations.iter().any(|location| selects_by_city_or_hostname(location))
+            }
         }
     }
 
     /// Takes a `RelayTunnels` object which in turn is a collection of tunnel configurations for
     /// a given relay. Then returns a new `RelayTunnels` instance with only the entries that
-    /// matches the given `TunnelConstraints`.
+    /// matches the given tunnel constraints.
     fn matching_tunnels(
         tunnels: &RelayTunnels,
-        tunnel_constraints: &TunnelConstraints,
+        tunnel_constraints: &Constraint<TunnelConstraints>,
     ) -> RelayTunnels {
         RelayTunnels {
             openvpn: tunnels
@@ -343,60 +652,119 @@ impl RelaySelector {
     }
 
     /// Try to read the relays, first from cache and if that fails from the resources.
-    fn read_cached_relays(cache_path: &Path, resource_path: &Path) -> Result<ParsedRelays> {
-        match ParsedRelays::from_file(cache_path).chain_err(|| "Unable to read relays from cache") {
+    /// Relay lists that fail signature verification are rejected and logged as errors.
+    fn read_cached_relays(
+        cache_path: &Path,
+        resource_path: &Path,
+        verifier: &RelayListVerifier,
+    ) -> Result<ParsedRelays> {
+        match ParsedRelays::from_file(cache_path, verifier) {
             Ok(value) => Ok(value),
             Err(error) => {
-                debug!("{}", error.display_chain());
-                ParsedRelays::from_file(resource_path)
+                let rejected = match error.kind() {
+                    ErrorKind::RelayListRejected => true,
+                    _ => false,
+                };
+                let chained_error = error.chain_err(|| "Unable to read relays from cache");
+                if rejected {
+                    error!("{}", chained_error.display_chain());
+                } else {
+                    debug!("{}", chained_error.display_chain());
+                }
+                ParsedRelays::from_file(resource_path, verifier)
             }
         }
     }
 }
 
-type RelayListUpdaterHandle = mpsc::Sender<()>;
+/// Returns the path of the detached signature of the relay list at `relays_path`.
+fn signature_path(relays_path: &Path) -> PathBuf {
+    relays_path.with_extension("sig")
+}
+
+/// Returns the path of the file the entity tag of the relay list at `relays_path` is stored in.
+fn etag_path(relays_path: &Path) -> PathBuf {
+    relays_path.with_extension("etag")
+}
+
+/// Checks if the host has a route to the given address. Connecting a UDP socket does not send
+/// any packets, it only makes the OS look up a route to the destination.
+fn has_route_to(address: IpAddr) -> bool {
+    let bind_address = match address {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    UdpSocket::bind(SocketAddr::new(bind_address, 0))
+        .and_then(|socket| socket.connect(SocketAddr::new(address, ROUTE_CHECK_PORT)))
+        .is_ok()
+}
+
+/// Requests that can be sent to the relay list updater thread.
+enum UpdateRequest {
+    /// Download a new relay list if the current one is older than the given age.
+    IfOlderThan(Duration),
+    /// Download a new relay list regardless of the age of the current one.
+    Force,
+}
+
+type RelayListUpdaterHandle = mpsc::Sender<UpdateRequest>;
 
 struct RelayListUpdater {
-    rpc_client: RelayListProxy<HttpHandle>,
+    rpc_client: RelayListClient,
     cache_path: PathBuf,
+    verifier: Arc<RelayListVerifier>,
     parsed_relays: Arc<Mutex<ParsedRelays>>,
-    close_handle: mpsc::Receiver<()>,
+    on_update: IntoSender<RelayList, DaemonEvent>,
+    close_handle: mpsc::Receiver<UpdateRequest>,
 }
 
 impl RelayListUpdater {
     pub fn spawn(
-        rpc_handle: HttpHandle,
+        rpc_client: RelayListClient,
         cache_path: PathBuf,
+        verifier: Arc<RelayListVerifier>,
         parsed_relays: Arc<Mutex<ParsedRelays>>,
+        on_update: IntoSender<RelayList, DaemonEvent>,
     ) -> RelayListUpdaterHandle {
         let (tx, rx) = mpsc::channel();
 
-        thread::spawn(move || Self::new(rpc_handle, cache_path, parsed_relays, rx).run());
+        thread::spawn(move || {
+            Self::new(
+                rpc_client,
+                cache_path,
+                verifier,
+                parsed_relays,
+                on_update,
+                rx,
+            ).run()
+        });
 
         tx
     }
 
     fn new(
-        rpc_handle: HttpHandle,
+        rpc_client: RelayListClient,
         cache_path: PathBuf,
+        verifier: Arc<RelayListVerifier>,
         parsed_relays: Arc<Mutex<ParsedRelays>>,
-        close_handle: mpsc::Receiver<()>,
+        on_update: IntoSender<RelayList, DaemonEvent>,
+        close_handle: mpsc::Receiver<UpdateRequest>,
     ) -> Self {
-        let rpc_client = RelayListProxy::new(rpc_handle);
-
         RelayListUpdater {
             rpc_client,
             cache_path,
+            verifier,
             parsed_relays,
+            on_update,
             close_handle,
         }
     }
 
     fn run(&mut self) {
         debug!("Starting relay list updater thread");
-        while self.wait_for_next_iteration() {
+        while let Some(request) = self.wait_for_next_iteration() {
             trace!("Relay list updater iteration");
-            if self.should_update() {
+            if self.should_update(request) {
                 match self
                     .update()
                     .chain_err(|| "Failed to update list of relays")
@@ -409,50 +777,89 @@ impl RelayListUpdater {
         debug!("Relay list updater thread has finished");
     }
 
-    fn wait_for_next_iteration(&mut self) -> bool {
+    fn wait_for_next_iteration(&mut self) -> Option<UpdateRequest> {
         use self::mpsc::RecvTimeoutError::*;
 
         match self.close_handle.recv_timeout(UPDATE_INTERVAL) {
-            Ok(()) => true,
-            Err(Timeout) => true,
-            Err(Disconnected) => false,
+            Ok(request) => Some(request),
+            Err(Timeout) => Some(UpdateRequest::IfOlderThan(MAX_CACHE_AGE)),
+            Err(Disconnected) => None,
         }
     }
 
-    fn should_update(&mut self) -> bool {
-        match SystemTime::now().duration_since(self.lock_parsed_relays().last_updated()) {
-            Ok(duration) => duration > MAX_CACHE_AGE,
-            Err(_) => false,
+    fn should_update(&mut self, request: UpdateRequest) -> bool {
+        match request {
+            UpdateRequest::Force => true,
+            UpdateRequest::IfOlderThan(max_age) => {
+                match SystemTime::now().duration_since(self.lock_parsed_relays().last_updated()) {
+                    Ok(duration) => duration > max_age,
+                    Err(_) => false,
+                }
+            }
         }
     }
 
     fn update(&mut self) -> Result<()> {
-        let new_relay_list = self
-            .download_relay_list()
-            .chain_err(|| "Failed to download relay list")?;
+        let etag = self.lock_parsed_relays().etag.clone();
+        let DownloadedRelayList {
+            relay_list,
+            signature,
+            etag,
+        } = match self
+            .download_relay_list(etag.as_ref().map(String::as_str))
+            .chain_err(|| "Failed to download relay list")?
+        {
+            Some(downloaded_relay_list) => downloaded_relay_list,
+            None => {
+                info!("Relay list has not changed since last download");
+                // The age of the cache is read from its modification time when the daemon
+                // starts, so it's rewritten to not be downloaded again after a restart.
+                if let Err(error) = self.touch_cache() {
+                    let chained_error = error.chain_err(|| "Failed to update relay cache on disk");
+                    error!("{}", chained_error.display_chain());
+                }
+                self.lock_parsed_relays().last_updated = SystemTime::now();
+                return Ok(());
+            }
+        };
+
+        let signature = signature.ok_or(ErrorKind::RelayListRejected)?;
+        self.verifier
+            .verify(&relay_list, &signature)
+            .chain_err(|| ErrorKind::RelayListRejected)?;
+        let new_relay_list: RelayList =
+            serde_json::from_slice(&relay_list).chain_err(|| ErrorKind::SerializationError)?;
 
-        if let Err(error) = self.cache_relays(&new_relay_list) {
+        if let Err(error) = self.cache_relays(&relay_list, &signature, etag.as_ref()) {
             let chained_error = error.chain_err(|| "Failed to update relay cache on disk");
             error!("{}", chained_error.display_chain());
         }
 
-        let new_parsed_relays = ParsedRelays::from_relay_list(new_relay_list, SystemTime::now());
+        let user_relays = self.lock_parsed_relays().user_relays.clone();
+        let new_parsed_relays =
+            ParsedRelays::from_relay_list(new_relay_list, etag, user_relays, SystemTime::now());
         info!(
             "Downloaded relay inventory has {} relays",
             new_parsed_relays.relays().len()
         );
+        let locations = new_parsed_relays.locations().clone();
 
         *self.lock_parsed_relays() = new_parsed_relays;
 
+        if self.on_update.send(locations).is_err() {
+            warn!("Unable to notify the daemon about the updated relay list");
+        }
+
         Ok(())
     }
 
-    fn download_relay_list(&mut self) -> Result<RelayList> {
+    /// Downloads the relay list, unless it still has the entity tag `etag`.
+    fn download_relay_list(&mut self, etag: Option<&str>) -> Result<Option<DownloadedRelayList>> {
         info!("Downloading list of relays...");
 
         let download_future = self
             .rpc_client
-            .relay_list()
+            .relay_list(etag)
             .map_err(|e| Error::with_chain(e, ErrorKind::DownloadError));
         let relay_list = Timer::default()
             .timeout(download_future, DOWNLOAD_TIMEOUT)
@@ -461,12 +868,32 @@ impl RelayListUpdater {
         Ok(relay_list)
     }
 
-    /// Write a `RelayList` to the cache file.
-    fn cache_relays(&self, relays: &RelayList) -> Result<()> {
+    /// Write a relay list, exactly as it was signed, its signature and its entity tag to the
+    /// cache. The old entity tag is removed before the relay list is written, so a stored entity
+    /// tag never belongs to another relay list.
+    fn cache_relays(&self, relays: &[u8], signature: &str, etag: Option<&String>) -> Result<()> {
         debug!("Writing relays cache to {}", self.cache_path.display());
-        let file = File::create(&self.cache_path).chain_err(|| ErrorKind::RelayCacheError)?;
-        serde_json::to_writer_pretty(io::BufWriter::new(file), relays)
-            .chain_err(|| ErrorKind::SerializationError)
+        let etag_path = etag_path(&self.cache_path);
+        if let Err(error) = fs::remove_file(&etag_path) {
+            if error.kind() != io::ErrorKind::NotFound {
+                return Err(Error::with_chain(error, ErrorKind::RelayCacheError));
+            }
+        }
+        atomic_file::write(&self.cache_path, relays).chain_err(|| ErrorKind::RelayCacheError)?;
+        atomic_file::write(&signature_path(&self.cache_path), signature.as_bytes())
+            .chain_err(|| ErrorKind::RelayCacheError)?;
+        if let Some(etag) = etag {
+            atomic_file::write(&etag_path, etag.as_bytes())
+                .chain_err(|| ErrorKind::RelayCacheError)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the cached relay list unchanged, so its modification time becomes the time it
+    /// was last known to be up to date.
+    fn touch_cache(&self) -> Result<()> {
+        let relays = fs::read(&self.cache_path).chain_err(|| ErrorKind::RelayCacheError)?;
+        atomic_file::write(&self.cache_path, &relays).chain_err(|| ErrorKind::RelayCacheError)
     }
 
     fn lock_parsed_relays(&self) -> MutexGuard<ParsedRelays> {
@@ -475,3 +902,718 @@ impl RelayListUpdater {
             .expect("A thread crashed while it held a lock to the list of relays")
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filetime::{self, FileTime};
+    use futures::Stream;
+    use hyper::header::{ETag, EntityTag, IfNoneMatch};
+    use hyper::{self, StatusCode};
+    use mullvad_rpc::rest;
+    use mullvad_types::relay_constraints::PortRange;
+    use openssl::pkey::{PKey, Private};
+    use relay_list_signature::tests::{sign, signing_key};
+    use talpid_types::net::{OpenVpnEndpointData, WireguardEndpointData};
+    use tempfile;
+
+    fn relay(hostname: &str, include_in_country: bool) -> Relay {
+        Relay {
+            hostname: hostname.to_owned(),
+            ipv4_addr_in: "10.0.0.1".parse().unwrap(),
+            ipv6_addr_in: Some("fd00::1".parse().unwrap()),
+            ipv4_addr_exit: "10.0.0.2".parse().unwrap(),
+            include_in_country,
+            weight: 100,
+            tunnels: RelayTunnels {
+                openvpn: vec![
+                    OpenVpnEndpointData {
+                        port: 1194,
+                        protocol: TransportProtocol::Udp,
+                    },
+                    OpenVpnEndpointData {
+                        port: 443,
+                        protocol: TransportProtocol::Tcp,
+                    },
+                ],
+                wireguard: vec![WireguardEndpointData { port: 51820 }],
+            },
+            user_defined: false,
+            location: None,
+        }
+    }
+
+    fn city(code: &str, relays: Vec<Relay>) -> RelayListCity {
+        RelayListCity {
+            name: code.to_owned(),
+            code: code.to_owned(),
+            latitude: 0.0,
+            longitude: 0.0,
+            relays,
+        }
+    }
+
+    fn country(code: &str, cities: Vec<RelayListCity>) -> RelayListCountry {
+        RelayListCountry {
+            name: code.to_owned(),
+            code: code.to_owned(),
+            cities,
+        }
+    }
+
+    /// se1 and se2 are in Gothenburg, but se2 should only be used if selected by city or
+    /// hostname. de1 is in Frankfurt.
+    fn relay_list() -> RelayList {
+        RelayList {
+            countries: vec![
+                country(
+                    "se",
+                    vec![city("got", vec![relay("se1", true), relay("se2", false)])],
+                ),
+                country("de", vec![city("fra", vec![relay("de1", true)])]),
+            ],
+        }
+    }
+
+    fn selector(relay_list: RelayList, user_relays: RelayList) -> RelaySelector {
+        let (updater, _) = mpsc::channel();
+        RelaySelector {
+            parsed_relays: Arc::new(Mutex::new(ParsedRelays::from_relay_list(
+                relay_list,
+                None,
+                user_relays,
+                SystemTime::now(),
+            ))),
+            rng: rand::thread_rng(),
+            updater,
+        }
+    }
+
+    fn constraints(
+        location: Constraint<LocationConstraint>,
+        tunnel: Constraint<TunnelConstraints>,
+    ) -> RelayConstraints {
+        RelayConstraints { location, tunnel }
+    }
+
+    fn openvpn(
+        port: Constraint<PortRange>,
+        protocol: Constraint<TransportProtocol>,
+    ) -> Constraint<TunnelConstraints> {
+        Constraint::Only(TunnelConstraints::OpenVpn(OpenVpnConstraints {
+            port,
+            protocol,
+        }))
+    }
+
+    fn sweden() -> Constraint<LocationConstraint> {
+        Constraint::Only(LocationConstraint::Country("se".to_owned()))
+    }
+
+    fn hostnames(relays: &[Relay]) -> Vec<&str> {
+        relays.iter().map(|relay| relay.hostname.as_str()).collect()
+    }
+
+    fn rejections(matching_relays: &MatchingRelays) -> Vec<(&str, RejectReason)> {
+        matching_relays
+            .rejected
+            .iter()
+            .map(|rejected| (rejected.hostname.as_str(), rejected.reason))
+            .collect()
+    }
+
+    #[test]
+    fn matching_relays_explain_why_other_relays_are_rejected() {
+        let selector = selector(relay_list(), RelayList::empty());
+        let matching_relays =
+            selector.get_matching_relays(&constraints(sweden(), Constraint::Any), true);
+
+        assert_eq!(hostnames(&matching_relays.relays), vec!["se1"]);
+        assert_eq!(
+            rejections(&matching_relays),
+            vec![
+                ("se2", RejectReason::NotIncludedInCountry),
+                ("de1", RejectReason::Location),
+            ]
+        );
+    }
+
+    #[test]
+    fn matching_relays_leave_out_rejections_unless_explained() {
+        let selector = selector(relay_list(), RelayList::empty());
+        let matching_relays =
+            selector.get_matching_relays(&constraints(sweden(), Constraint::Any), false);
+
+        assert_eq!(hostnames(&matching_relays.relays), vec!["se1"]);
+        assert!(matching_relays.rejected.is_empty());
+    }
+
+    #[test]
+    fn matching_relays_only_have_matching_tunnels() {
+        let selector = selector(relay_list(), RelayList::empty());
+        let tcp = openvpn(Constraint::Any, Constraint::Only(TransportProtocol::Tcp));
+        let matching_relays = selector.get_matching_relays(&constraints(sweden(), tcp), false);
+
+        let tunnels = &matching_relays.relays[0].tunnels;
+        assert_eq!(
+            tunnels.openvpn,
+            vec![OpenVpnEndpointData {
+                port: 443,
+                protocol: TransportProtocol::Tcp,
+            }]
+        );
+        assert!(tunnels.wireguard.is_empty());
+    }
+
+    #[test]
+    fn relays_without_matching_tunnels_are_rejected() {
+        let selector = selector(relay_list(), RelayList::empty());
+        let relay = selector.lock_parsed_relays().relays()[0].clone();
+        let port_53 = openvpn(Constraint::Only(PortRange::single(53)), Constraint::Any);
+
+        assert_eq!(
+            RelaySelector::filter_relay(&relay, &constraints(Constraint::Any, port_53)).err(),
+            Some(RejectReason::NoMatchingTunnel)
+        );
+    }
+
+    fn location(location: LocationConstraint) -> Constraint<LocationConstraint> {
+        Constraint::Only(location)
+    }
+
+    fn filter_relays(constraints: &RelayConstraints) -> Vec<(String, Option<RejectReason>)> {
+        let selector = selector(relay_list(), RelayList::empty());
+        let relays = selector.lock_parsed_relays().relays().clone();
+        relays
+            .iter()
+            .map(|relay| {
+                (
+                    relay.hostname.clone(),
+                    RelaySelector::filter_relay(relay, constraints).err(),
+                )
+            }).collect()
+    }
+
+    fn accepted(hostname: &str) -> (String, Option<RejectReason>) {
+        (hostname.to_owned(), None)
+    }
+
+    fn rejected(hostname: &str, reason: RejectReason) -> (String, Option<RejectReason>) {
+        (hostname.to_owned(), Some(reason))
+    }
+
+    #[test]
+    fn one_of_locations_selects_explicitly_by_any_of_them() {
+        let germany_or_se2 = Constraint::OneOf(vec![
+            LocationConstraint::Country("de".to_owned()),
+            LocationConstraint::Hostname("se".to_owned(), "got".to_owned(), "se2".to_owned()),
+        ]);
+        let germany_or_sweden = Constraint::OneOf(vec![
+            LocationConstraint::Country("de".to_owned()),
+            LocationConstraint::Country("se".to_owned()),
+        ]);
+
+        assert_eq!(
+            filter_relays(&constraints(germany_or_se2, Constraint::Any)),
+            vec![
+                rejected("se1", RejectReason::Location),
+                accepted("se2"),
+                accepted("de1"),
+            ]
+        );
+        assert_eq!(
+            filter_relays(&constraints(germany_or_sweden, Constraint::Any)),
+            vec![
+                accepted("se1"),
+                rejected("se2", RejectReason::NotIncludedInCountry),
+                accepted("de1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn negated_location_does_not_select_explicitly() {
+        let not_germany = Constraint::Not(Box::new(location(LocationConstraint::Country(
+            "de".to_owned(),
+        ))));
+        let not_frankfurt = Constraint::Not(Box::new(location(LocationConstraint::City(
+            "de".to_owned(),
+            "fra".to_owned(),
+        ))));
+
+        assert_eq!(
+            filter_relays(&constraints(not_germany, Constraint::Any)),
+            vec![
+                accepted("se1"),
+                rejected("se2", RejectReason::NotIncludedInCountry),
+                rejected("de1", RejectReason::Location),
+            ]
+        );
+        assert_eq!(
+            filter_relays(&constraints(not_frankfurt, Constraint::Any)),
+            vec![
+                accepted("se1"),
+                rejected("se2", RejectReason::NotIncludedInCountry),
+                rejected("de1", RejectReason::Location),
+            ]
+        );
+    }
+
+    #[test]
+    fn any_location_includes_all_relays() {
+        assert_eq!(
+            filter_relays(&constraints(Constraint::Any, Constraint::Any)),
+            vec![accepted("se1"), accepted("se2"), accepted("de1")]
+        );
+    }
+
+    #[test]
+    fn port_ranges_filter_tunnels() {
+        let selector = selector(relay_list(), RelayList::empty());
+        let relay = selector.lock_parsed_relays().relays()[0].clone();
+        let ports = |ranges: Vec<PortRange>| {
+            let tunnel = openvpn(Constraint::OneOf(ranges), Constraint::Any);
+            RelaySelector::filter_relay(&relay, &constraints(Constraint::Any, tunnel))
+                .map(|relay| relay.tunnels.openvpn)
+        };
+
+        assert_eq!(
+            ports(vec![PortRange::new(400, 500).unwrap()]),
+            Ok(vec![OpenVpnEndpointData {
+                port: 443,
+                protocol: TransportProtocol::Tcp,
+            }])
+        );
+        assert_eq!(
+            ports(vec![
+                PortRange::single(53),
+                PortRange::new(1194, 1197).unwrap()
+            ]),
+            Ok(vec![OpenVpnEndpointData {
+                port: 1194,
+                protocol: TransportProtocol::Udp,
+            }])
+        );
+        assert_eq!(
+            ports(vec![PortRange::new(1195, 1197).unwrap()]),
+            Err(RejectReason::NoMatchingTunnel)
+        );
+        let not_443 = openvpn(
+            Constraint::Not(Box::new(Constraint::Only(PortRange::single(443)))),
+            Constraint::Any,
+        );
+        assert_eq!(
+            RelaySelector::filter_relay(&relay, &constraints(Constraint::Any, not_443))
+                .map(|relay| relay.tunnels.openvpn.len()),
+            Ok(1)
+        );
+    }
+
+    /// The endpoint of se1 at `address`, using OpenVPN over UDP on port 1194.
+    fn se1_endpoint(address: &str) -> TunnelEndpoint {
+        TunnelEndpoint {
+            address: address.parse().unwrap(),
+            tunnel: TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                port: 1194,
+                protocol: TransportProtocol::Udp,
+            }),
+        }
+    }
+
+    fn validate_se1(
+        selector: &RelaySelector,
+        endpoint: &TunnelEndpoint,
+        constraints: &RelayConstraints,
+        ip_version: IpVersionPreference,
+    ) -> ::std::result::Result<(), RelayInvalidReason> {
+        selector.validate_relay("se1", endpoint, constraints, ip_version)
+    }
+
+    #[test]
+    fn relay_in_use_is_valid_while_listed_and_matching() {
+        let selector = selector(relay_list(), RelayList::empty());
+        let any = constraints(Constraint::Any, Constraint::Any);
+
+        assert_eq!(
+            validate_se1(
+                &selector,
+                &se1_endpoint("10.0.0.1"),
+                &constraints(sweden(), Constraint::Any),
+                IpVersionPreference::Auto,
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            validate_se1(
+                &selector,
+                &se1_endpoint("fd00::1"),
+                &any,
+                IpVersionPreference::Auto,
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn relay_in_use_is_invalidated_when_removed() {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[0].relays.remove(0);
+        let selector = selector(relay_list, RelayList::empty());
+
+        assert_eq!(
+            validate_se1(
+                &selector,
+                &se1_endpoint("10.0.0.1"),
+                &constraints(Constraint::Any, Constraint::Any),
+                IpVersionPreference::Auto,
+            ),
+            Err(RelayInvalidReason::Removed)
+        );
+    }
+
+    #[test]
+    fn relay_in_use_is_invalidated_when_its_endpoint_is_removed() {
+        let mut relay_list = relay_list();
+        relay_list.countries[0].cities[0].relays[0].ipv4_addr_in = "10.0.0.3".parse().unwrap();
+        let selector = selector(relay_list, RelayList::empty());
+        let any = constraints(Constraint::Any, Constraint::Any);
+        let removed_port = TunnelEndpoint {
+            tunnel: TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                port: 1195,
+                protocol: TransportProtocol::Udp,
+            }),
+            ..se1_endpoint("fd00::1")
+        };
+
+        assert_eq!(
+            validate_se1(
+                &selector,
+                &se1_endpoint("10.0.0.1"),
+                &any,
+                IpVersionPreference::Auto,
+            ),
+            Err(RelayInvalidReason::EndpointRemoved)
+        );
+        assert_eq!(
+            validate_se1(&selector, &removed_port, &any, IpVersionPreference::Auto),
+            Err(RelayInvalidReason::EndpointRemoved)
+        );
+    }
+
+    #[test]
+    fn relay_in_use_is_invalidated_when_no_longer_matching_constraints() {
+        let selector = selector(relay_list(), RelayList::empty());
+        let germany = location(LocationConstraint::Country("de".to_owned()));
+        let tcp = openvpn(Constraint::Any, Constraint::Only(TransportProtocol::Tcp));
+
+        assert_eq!(
+            validate_se1(
+                &selector,
+                &se1_endpoint("10.0.0.1"),
+                &constraints(germany, Constraint::Any),
+                IpVersionPreference::Auto,
+            ),
+            Err(RelayInvalidReason::NoLongerMatchesConstraints)
+        );
+        assert_eq!(
+            validate_se1(
+                &selector,
+                &se1_endpoint("10.0.0.1"),
+                &constraints(sweden(), tcp),
+                IpVersionPreference::Auto,
+            ),
+            Err(RelayInvalidReason::NoLongerMatchesConstraints)
+        );
+    }
+
+    #[test]
+    fn relay_in_use_is_invalidated_by_ip_version_preference() {
+        let selector = selector(relay_list(), RelayList::empty());
+        let any = constraints(Constraint::Any, Constraint::Any);
+        let validate =
+            |address, ip_version| validate_se1(&selector, &se1_endpoint(address), &any, ip_version);
+
+        assert_eq!(validate("10.0.0.1", IpVersionPreference::Ipv4), Ok(()));
+        assert_eq!(
+            validate("10.0.0.1", IpVersionPreference::Ipv6),
+            Err(RelayInvalidReason::NoLongerMatchesConstraints)
+        );
+        assert_eq!(validate("fd00::1", IpVersionPreference::Ipv6), Ok(()));
+        assert_eq!(
+            validate("fd00::1", IpVersionPreference::Ipv4),
+            Err(RelayInvalidReason::NoLongerMatchesConstraints)
+        );
+    }
+
+    /// Returns the relays that were added by the user, as `<country>/<city>/<hostname>`.
+    fn user_relay_locations(selector: &RelaySelector) -> Vec<String> {
+        selector
+            .lock_parsed_relays()
+            .relays()
+            .iter()
+            .filter(|relay| relay.user_defined)
+            .map(|relay| {
+                let location = relay.location.as_ref().unwrap();
+                format!(
+                    "{}/{}/{}",
+                    location.country_code, location.city_code, relay.hostname
+                )
+            }).collect()
+    }
+
+    #[test]
+    fn user_relays_are_merged_into_their_countries_and_cities() {
+        let user_relays = RelayList {
+            countries: vec![
+                country(
+                    "se",
+                    vec![
+                        city("got", vec![relay("user1", true)]),
+                        city("sto", vec![relay("user2", true)]),
+                    ],
+                ),
+                country("no", vec![city("osl", vec![relay("user3", true)])]),
+            ],
+        };
+        let selector = selector(relay_list(), user_relays);
+
+        assert_eq!(
+            user_relay_locations(&selector),
+            vec!["se/got/user1", "se/sto/user2", "no/osl/user3"]
+        );
+        let locations = selector.lock_parsed_relays().locations().clone();
+        let country_codes: Vec<_> = locations
+            .countries
+            .iter()
+            .map(|country| country.code.as_str())
+            .collect();
+        assert_eq!(country_codes, vec!["se", "de", "no"]);
+        let swedish_cities: Vec<_> = locations.countries[0]
+            .cities
+            .iter()
+            .map(|city| city.code.as_str())
+            .collect();
+        assert_eq!(swedish_cities, vec!["got", "sto"]);
+    }
+
+    #[test]
+    fn user_relays_with_hostnames_from_the_relay_list_are_ignored() {
+        let user_relays = RelayList {
+            countries: vec![country(
+                "de",
+                vec![city("fra", vec![relay("se1", true), relay("user1", true)])],
+            )],
+        };
+        let selector = selector(relay_list(), user_relays);
+
+        assert_eq!(user_relay_locations(&selector), vec!["de/fra/user1"]);
+        let se1_locations: Vec<_> = selector
+            .lock_parsed_relays()
+            .relays()
+            .iter()
+            .filter(|relay| relay.hostname == "se1")
+            .map(|relay| relay.location.as_ref().unwrap().country_code.clone())
+            .collect();
+        assert_eq!(se1_locations, vec!["se"]);
+    }
+
+    #[test]
+    fn user_relays_shadowing_the_relay_list_are_found() {
+        let selector = selector(relay_list(), RelayList::empty());
+        let shadowing = RelayList {
+            countries: vec![country("de", vec![city("ber", vec![relay("de1", true)])])],
+        };
+        let unique = RelayList {
+            countries: vec![country("de", vec![city("ber", vec![relay("user1", true)])])],
+        };
+
+        assert_eq!(
+            selector.find_shadowing_user_relay(&shadowing),
+            Some("de1".to_owned())
+        );
+        assert_eq!(selector.find_shadowing_user_relay(&unique), None);
+    }
+
+    #[test]
+    fn replacing_user_relays_keeps_the_relay_list() {
+        let user_relays = RelayList {
+            countries: vec![country("no", vec![city("osl", vec![relay("user1", true)])])],
+        };
+        let mut selector = selector(relay_list(), user_relays);
+
+        let locations = selector.set_user_relays(RelayList::empty());
+
+        assert!(user_relay_locations(&selector).is_empty());
+        assert_eq!(
+            hostnames(selector.lock_parsed_relays().relays()),
+            vec!["se1", "se2", "de1"]
+        );
+        assert_eq!(locations.countries.len(), 2);
+    }
+
+    /// Writes `relay_list` and its signature made with `key` to `path`.
+    fn write_signed_relay_list(path: &Path, key: &PKey<Private>, relay_list: &[u8]) {
+        fs::write(path, relay_list).unwrap();
+        fs::write(signature_path(path), sign(key, relay_list)).unwrap();
+    }
+
+    /// Returns an updater caching `parsed_relays` in `cache_dir`, whose requests for the relay
+    /// list are answered by `respond`, and the receiver of the events it sends.
+    fn updater<F>(
+        parsed_relays: &Arc<Mutex<ParsedRelays>>,
+        cache_dir: &Path,
+        verifier: RelayListVerifier,
+        respond: F,
+    ) -> (RelayListUpdater, mpsc::Receiver<DaemonEvent>)
+    where
+        F: Fn(&hyper::Request) -> rest::Result<rest::Response> + Send + 'static,
+    {
+        let (request_tx, request_rx) = ::futures::sync::mpsc::unbounded();
+        let rpc_client = RelayListClient::new(request_tx, "127.0.0.1");
+        thread::spawn(move || {
+            for (request, response_tx) in request_rx.wait().filter_map(|request| request.ok()) {
+                let _ = response_tx.send(respond(&request));
+            }
+        });
+        let (event_tx, event_rx) = mpsc::channel();
+        let (_, close_handle) = mpsc::channel();
+        let updater = RelayListUpdater::new(
+            rpc_client,
+            cache_dir.join(RELAYS_FILENAME),
+            Arc::new(verifier),
+            parsed_relays.clone(),
+            IntoSender::from(event_tx),
+            close_handle,
+        );
+        (updater, event_rx)
+    }
+
+    fn relay_list_response(relay_list: Vec<u8>, signature: &str, etag: &str) -> rest::Response {
+        let mut headers = hyper::Headers::new();
+        headers.set_raw("X-Relay-List-Signature", signature.to_owned());
+        headers.set(ETag(EntityTag::strong(etag.to_owned())));
+        rest::Response {
+            headers,
+            body: relay_list,
+        }
+    }
+
+    #[test]
+    fn downloaded_relay_list_is_cached_with_its_signature_and_etag() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let (key, verifier) = signing_key();
+        let parsed_relays = Arc::new(Mutex::new(ParsedRelays::empty()));
+        let (mut updater, events) =
+            updater(&parsed_relays, cache_dir.path(), verifier, move |request| {
+                assert!(request.headers().get::<IfNoneMatch>().is_none());
+                let relay_list = serde_json::to_vec(&relay_list()).unwrap();
+                let signature = sign(&key, &relay_list);
+                Ok(relay_list_response(relay_list, &signature, "2"))
+            });
+
+        updater.update().unwrap();
+
+        assert_eq!(parsed_relays.lock().unwrap().relays().len(), 3);
+        assert_eq!(parsed_relays.lock().unwrap().etag, Some("\"2\"".to_owned()));
+        match events.try_recv() {
+            Ok(DaemonEvent::RelayListUpdated(relay_list)) => {
+                assert_eq!(relay_list.countries.len(), 2)
+            }
+            _ => panic!("The relay list update was not sent"),
+        }
+        let cached_relays =
+            ParsedRelays::from_file(cache_dir.path().join(RELAYS_FILENAME), &updater.verifier)
+                .unwrap();
+        assert_eq!(cached_relays.relays().len(), 3);
+        assert_eq!(cached_relays.etag, Some("\"2\"".to_owned()));
+    }
+
+    #[test]
+    fn not_modified_relay_list_refreshes_the_cache() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join(RELAYS_FILENAME);
+        let (key, verifier) = signing_key();
+        write_signed_relay_list(
+            &cache_path,
+            &key,
+            &serde_json::to_vec(&relay_list()).unwrap(),
+        );
+        fs::write(etag_path(&cache_path), "\"1\"").unwrap();
+        let long_ago = FileTime::from_seconds_since_1970(60 * 60 * 24, 0);
+        filetime::set_file_times(&cache_path, long_ago, long_ago).unwrap();
+        let parsed_relays = Arc::new(Mutex::new(ParsedRelays::from_relay_list(
+            relay_list(),
+            Some("\"1\"".to_owned()),
+            RelayList::empty(),
+            time::UNIX_EPOCH,
+        )));
+        let (mut updater, events) =
+            updater(&parsed_relays, cache_dir.path(), verifier, |request| {
+                assert_eq!(
+                    request.headers().get::<IfNoneMatch>(),
+                    Some(&IfNoneMatch::Items(vec![EntityTag::strong("1".to_owned())]))
+                );
+                Err(rest::ErrorKind::HttpError(StatusCode::NotModified).into())
+            });
+
+        updater.update().unwrap();
+
+        assert!(parsed_relays.lock().unwrap().last_updated() > time::UNIX_EPOCH);
+        assert!(events.try_recv().is_err());
+        let cached_relays = ParsedRelays::from_file(&cache_path, &updater.verifier).unwrap();
+        let long_ago = time::UNIX_EPOCH + Duration::from_secs(60 * 60 * 24);
+        assert!(cached_relays.last_updated() > long_ago);
+        assert_eq!(cached_relays.relays().len(), 3);
+        assert_eq!(cached_relays.etag, Some("\"1\"".to_owned()));
+    }
+
+    #[test]
+    fn downloaded_relay_list_with_invalid_signature_is_rejected() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let (_, verifier) = signing_key();
+        let (other_key, _) = signing_key();
+        let parsed_relays = Arc::new(Mutex::new(ParsedRelays::empty()));
+        let (mut updater, events) =
+            updater(&parsed_relays, cache_dir.path(), verifier, move |_| {
+                let relay_list = serde_json::to_vec(&relay_list()).unwrap();
+                let signature = sign(&other_key, &relay_list);
+                Ok(relay_list_response(relay_list, &signature, "2"))
+            });
+
+        match updater.update() {
+            Err(Error(ErrorKind::RelayListRejected, _)) => (),
+            result => panic!("Relay list was not rejected: {:?}", result),
+        }
+        assert!(parsed_relays.lock().unwrap().relays().is_empty());
+        assert!(events.try_recv().is_err());
+        assert!(!cache_dir.path().join(RELAYS_FILENAME).exists());
+    }
+
+    #[test]
+    fn rejected_cached_relay_list_falls_back_to_bundled_list() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let resource_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join(RELAYS_FILENAME);
+        let resource_path = resource_dir.path().join(RELAYS_FILENAME);
+        let (key, verifier) = signing_key();
+        let (other_key, _) = signing_key();
+        let mut bundled_relay_list = relay_list();
+        bundled_relay_list.countries.truncate(1);
+        write_signed_relay_list(
+            &resource_path,
+            &key,
+            &serde_json::to_vec(&bundled_relay_list).unwrap(),
+        );
+        write_signed_relay_list(
+            &cache_path,
+            &other_key,
+            &serde_json::to_vec(&relay_list()).unwrap(),
+        );
+
+        let parsed_relays =
+            RelaySelector::read_cached_relays(&cache_path, &resource_path, &verifier).unwrap();
+
+        assert_eq!(hostnames(parsed_relays.relays()), vec!["se1", "se2"]);
+    }
+}
@@ -0,0 +1,78 @@
+use rand::distributions::Alphanumeric;
+use rand::{self, Rng};
+
+use std::path::{Path, PathBuf};
+
+use mullvad_types::secret_file;
+
+error_chain! {
+    errors {
+        ReadError(path: PathBuf) {
+            description("Unable to read remote management token file")
+            display("Unable to read remote management token from {}", path.display())
+        }
+        WriteError(path: PathBuf) {
+            description("Unable to write remote management token file")
+            display("Unable to write remote management token to {}", path.display())
+        }
+    }
+}
+
+static TOKEN_FILE: &str = "remote-management-token";
+
+/// How many characters the generated tokens have. 43 alphanumeric characters hold more than 256
+/// bits of randomness.
+const TOKEN_LENGTH: usize = 43;
+
+/// The token clients of the remote management interface authenticate with. It's generated the
+/// first time it's needed and kept in a file only readable by the daemon.
+pub struct RemoteManagementToken {
+    token: Option<String>,
+    path: PathBuf,
+}
+
+impl RemoteManagementToken {
+    /// Returns a `RemoteManagementToken` stored in the given settings dir. Nothing is read until
+    /// the token is needed.
+    pub fn new(settings_dir: &Path) -> RemoteManagementToken {
+        RemoteManagementToken {
+            token: None,
+            path: settings_dir.join(TOKEN_FILE),
+        }
+    }
+
+    /// Returns the token, reading it from its file or generating a new one if there is none.
+    pub fn get(&mut self) -> Result<String> {
+        if let Some(ref token) = self.token {
+            return Ok(token.clone());
+        }
+        let stored_token = secret_file::read(&self.path)
+            .chain_err(|| ErrorKind::ReadError(self.path.clone()))?
+            .and_then(|contents| String::from_utf8(contents).ok())
+            .map(|token| token.trim().to_owned())
+            .filter(|token| !token.is_empty());
+        match stored_token {
+            Some(token) => {
+                self.token = Some(token.clone());
+                Ok(token)
+            }
+            None => self.reset(),
+        }
+    }
+
+    /// Replaces the token with a newly generated one and returns it.
+    pub fn reset(&mut self) -> Result<String> {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LENGTH)
+            .collect();
+        info!(
+            "Writing a new remote management token to {}",
+            self.path.display()
+        );
+        secret_file::write(&self.path, token.as_bytes())
+            .chain_err(|| ErrorKind::WriteError(self.path.clone()))?;
+        self.token = Some(token.clone());
+        Ok(token)
+    }
+}
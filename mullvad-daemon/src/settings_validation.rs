@@ -0,0 +1,145 @@
+use management_interface::MIN_ROTATE_RELAY_INTERVAL;
+use mullvad_types::relay_constraints::{
+    Constraint, LocationConstraint, RelaySettings, TunnelConstraints,
+};
+use mullvad_types::relay_list::RelayList;
+use mullvad_types::settings::{FieldError, Settings};
+
+use std::fmt;
+
+/// The smallest and largest mssfix values that still let OpenVPN send packets through common
+/// networks. Only checked for settings changed in bulk, so `set_openvpn_mssfix` keeps accepting
+/// any value, as it always has.
+pub const MIN_MSSFIX: u16 = 1000;
+pub const MAX_MSSFIX: u16 = 1450;
+
+/// Checks the settings that can't be validated by their types alone. Relay locations are looked
+/// up in `locations`, unless it's empty since no relay list has been loaded yet.
+pub fn validate(settings: &Settings, locations: &RelayList) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if let Some(interval) = settings.get_rotate_relay_interval() {
+        if interval < MIN_ROTATE_RELAY_INTERVAL {
+            errors.push(FieldError::new(
+                "rotate_relay_interval",
+                format!("Must be at least {} seconds", MIN_ROTATE_RELAY_INTERVAL),
+            ));
+        }
+    }
+
+    if settings.get_account_expiry_warnings().contains(&0) {
+        errors.push(FieldError::new(
+            "account_expiry_warnings",
+            "Thresholds must be at least one second",
+        ));
+    }
+
+    if let Some(mssfix) = settings.get_tunnel_options().openvpn.mssfix {
+        if mssfix < MIN_MSSFIX || mssfix > MAX_MSSFIX {
+            errors.push(FieldError::new(
+                "tunnel_options.openvpn.mssfix",
+                format!("Must be between {} and {}", MIN_MSSFIX, MAX_MSSFIX),
+            ));
+        }
+    }
+
+    match settings.get_relay_settings() {
+        RelaySettings::CustomTunnelEndpoint(endpoint) => {
+            if endpoint.host.is_empty() {
+                errors.push(FieldError::new(
+                    "relay_settings.custom_tunnel_endpoint.host",
+                    "Must not be empty",
+                ));
+            }
+            if endpoint.tunnel.port() == 0 {
+                errors.push(FieldError::new(
+                    "relay_settings.custom_tunnel_endpoint.tunnel",
+                    "The port must be between 1 and 65535",
+                ));
+            }
+        }
+        RelaySettings::Normal(constraints) => {
+            if !locations.countries.is_empty() {
+                for location in constraint_values(&constraints.location) {
+                    if !location_exists(location, locations) {
+                        errors.push(FieldError::new(
+                            "relay_settings.normal.location",
+                            format!("No such location: {}", LocationDisplay(location)),
+                        ));
+                    }
+                }
+            }
+            for tunnel in constraint_values(&constraints.tunnel) {
+                let ports = match *tunnel {
+                    TunnelConstraints::OpenVpn(ref constraints) => &constraints.port,
+                    TunnelConstraints::Wireguard(ref constraints) => &constraints.port,
+                };
+                if constraint_values(ports)
+                    .iter()
+                    .any(|range| range.start == 0)
+                {
+                    errors.push(FieldError::new(
+                        "relay_settings.normal.tunnel",
+                        "Ports must be between 1 and 65535",
+                    ));
+                }
+            }
+        }
+    }
+
+    errors
+}
+
+/// Returns all values in `constraint`, including negated ones.
+fn constraint_values<T>(constraint: &Constraint<T>) -> Vec<&T>
+where
+    T: fmt::Debug + Clone + Eq + PartialEq,
+{
+    match *constraint {
+        Constraint::Any => vec![],
+        Constraint::Only(ref value) => vec![value],
+        Constraint::OneOf(ref values) => values.iter().collect(),
+        Constraint::Not(ref constraint) => constraint_values(constraint),
+    }
+}
+
+fn location_exists(location: &LocationConstraint, locations: &RelayList) -> bool {
+    let (country_code, city_code, hostname) = match *location {
+        LocationConstraint::Country(ref country) => (country, None, None),
+        LocationConstraint::City(ref country, ref city) => (country, Some(city), None),
+        LocationConstraint::Hostname(ref country, ref city, ref hostname) => {
+            (country, Some(city), Some(hostname))
+        }
+    };
+    locations
+        .countries
+        .iter()
+        .filter(|country| country.code == *country_code)
+        .any(|country| {
+            city_code.map_or(true, |city_code| {
+                country
+                    .cities
+                    .iter()
+                    .filter(|city| city.code == *city_code)
+                    .any(|city| {
+                        hostname.map_or(true, |hostname| {
+                            city.relays.iter().any(|relay| relay.hostname == *hostname)
+                        })
+                    })
+            })
+        })
+}
+
+struct LocationDisplay<'a>(&'a LocationConstraint);
+
+impl<'a> fmt::Display for LocationDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            LocationConstraint::Country(ref country) => write!(f, "{}", country),
+            LocationConstraint::City(ref country, ref city) => write!(f, "{} {}", country, city),
+            LocationConstraint::Hostname(ref country, ref city, ref hostname) => {
+                write!(f, "{} {} {}", country, city, hostname)
+            }
+        }
+    }
+}
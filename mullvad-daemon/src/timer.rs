@@ -0,0 +1,112 @@
+//! Timers running a callback on a separate thread after a delay, that can be cancelled while
+//! waiting without leaving the thread behind.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// A scheduled callback. Cancelled when `cancel` is called or the timer is dropped, in which case
+/// the callback is never run and the thread waiting for the delay exits right away.
+pub struct CancellableTimer {
+    _cancel_tx: mpsc::Sender<()>,
+}
+
+impl CancellableTimer {
+    /// Runs `callback` on a new thread after `delay`. The callback is given the `Cancellation`
+    /// of the timer, so it can stop any further waiting of its own if the timer is cancelled.
+    pub fn spawn<F>(delay: Duration, callback: F) -> Self
+    where
+        F: FnOnce(&Cancellation) + Send + 'static,
+    {
+        let (cancel_tx, cancel_rx) = mpsc::channel();
+        let cancellation = Cancellation { cancel_rx };
+        thread::spawn(move || {
+            if cancellation.sleep(delay) {
+                callback(&cancellation);
+            }
+        });
+        CancellableTimer {
+            _cancel_tx: cancel_tx,
+        }
+    }
+
+    /// Cancels the timer. Same as dropping it.
+    pub fn cancel(self) {}
+}
+
+/// Tells the thread of a `CancellableTimer` when the timer has been cancelled.
+pub struct Cancellation {
+    cancel_rx: mpsc::Receiver<()>,
+}
+
+impl Cancellation {
+    /// Sleeps for `duration`. Returns early with `false` if the timer is cancelled, `true`
+    /// otherwise.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        match self.cancel_rx.recv_timeout(duration) {
+            Err(RecvTimeoutError::Timeout) => true,
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => false,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::TryRecvError;
+    use std::time::Instant;
+
+    const SHORT_DELAY: Duration = Duration::from_millis(50);
+    const LONG_DELAY: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn runs_callback_after_delay() {
+        let (tx, rx) = mpsc::channel();
+        let start = Instant::now();
+        let _timer = CancellableTimer::spawn(SHORT_DELAY, move |_| tx.send(()).unwrap());
+
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(start.elapsed() >= SHORT_DELAY);
+    }
+
+    #[test]
+    fn cancelled_timer_does_not_run_callback() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let timer = CancellableTimer::spawn(SHORT_DELAY, move |_| tx.send(()).unwrap());
+        timer.cancel();
+
+        // The sender is dropped without sending once the thread has exited.
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn dropping_timer_stops_thread_right_away() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let timer = CancellableTimer::spawn(LONG_DELAY, move |_| tx.send(()).unwrap());
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        drop(timer);
+
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn callback_can_tell_when_cancelled() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (result_tx, result_rx) = mpsc::channel();
+        let timer = CancellableTimer::spawn(Duration::from_millis(0), move |cancellation| {
+            started_tx.send(()).unwrap();
+            result_tx.send(cancellation.sleep(LONG_DELAY)).unwrap();
+        });
+        started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        drop(timer);
+
+        assert_eq!(result_rx.recv_timeout(Duration::from_secs(5)), Ok(false));
+    }
+}
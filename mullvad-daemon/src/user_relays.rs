@@ -0,0 +1,111 @@
+extern crate serde_json;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use mullvad_types::atomic_file;
+use mullvad_types::relay_list::RelayList;
+
+error_chain! {
+    errors {
+        ReadError(path: PathBuf) {
+            description("Unable to read user relays file")
+            display("Unable to read user relays from {}", path.display())
+        }
+        WriteError(path: PathBuf) {
+            description("Unable to write user relays file")
+            display("Unable to write user relays to {}", path.display())
+        }
+        ParseError {
+            description("Malformed user relays")
+        }
+        DuplicateHostname(hostname: String) {
+            description("Multiple user relays have the same hostname")
+            display("Multiple user relays have the hostname {}", hostname)
+        }
+        NoOpenVpnEndpoint(hostname: String) {
+            description("User relay has no OpenVPN endpoints")
+            display("User relay {} has no OpenVPN endpoints", hostname)
+        }
+    }
+}
+
+static USER_RELAYS_FILE: &str = "user-relays.json";
+
+/// Relays added by the user, on top of the ones in the relay list downloaded from the API. They
+/// are stored in the same format as the relay list, grouped by country and city.
+#[derive(Debug)]
+pub struct UserRelays {
+    relays: RelayList,
+    path: PathBuf,
+}
+
+impl UserRelays {
+    /// Returns a new empty `UserRelays` ready to load from, or save to, the given settings dir.
+    pub fn new(settings_dir: &Path) -> UserRelays {
+        UserRelays {
+            relays: RelayList::empty(),
+            path: settings_dir.join(USER_RELAYS_FILE),
+        }
+    }
+
+    /// Loads the user relays from file. If no file is present this does nothing.
+    pub fn load(&mut self) -> Result<()> {
+        match File::open(&self.path).map(io::BufReader::new) {
+            Ok(file) => {
+                info!("Loading user relays from {}", self.path.display());
+                let relays = serde_json::from_reader(file).chain_err(|| ErrorKind::ParseError)?;
+                validate(&relays)?;
+                self.relays = relays;
+                Ok(())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                info!("No user relays file at {}", self.path.display());
+                Ok(())
+            }
+            Err(e) => Err(e).chain_err(|| ErrorKind::ReadError(self.path.clone())),
+        }
+    }
+
+    pub fn get_relays(&self) -> &RelayList {
+        &self.relays
+    }
+
+    /// Replaces all user relays with the given ones and saves them to the file they were loaded
+    /// from.
+    pub fn set_relays(&mut self, relays: RelayList) -> Result<()> {
+        validate(&relays)?;
+        self.relays = relays;
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        debug!("Writing user relays to {}", self.path.display());
+        let relays = serde_json::to_vec_pretty(&self.relays)
+            .chain_err(|| ErrorKind::WriteError(self.path.clone()))?;
+        atomic_file::write(&self.path, &relays)
+            .chain_err(|| ErrorKind::WriteError(self.path.clone()))
+    }
+}
+
+/// Checks that the relays can be used by the relay selector. Every relay needs a unique hostname,
+/// since that is what location constraints refer to, and at least one OpenVPN endpoint.
+pub fn validate(relay_list: &RelayList) -> Result<()> {
+    let mut hostnames = HashSet::new();
+    let relays = relay_list
+        .countries
+        .iter()
+        .flat_map(|country| country.cities.iter())
+        .flat_map(|city| city.relays.iter());
+    for relay in relays {
+        if !hostnames.insert(relay.hostname.as_str()) {
+            bail!(ErrorKind::DuplicateHostname(relay.hostname.clone()));
+        }
+        if relay.tunnels.openvpn.is_empty() {
+            bail!(ErrorKind::NoOpenVpnEndpoint(relay.hostname.clone()));
+        }
+    }
+    Ok(())
+}
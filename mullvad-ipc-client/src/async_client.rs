@@ -0,0 +1,646 @@
+//! A futures based client for the daemon, that keeps working while the daemon restarts.
+//!
+//! The client connects to the daemon on a thread of its own, and connects again with increasing
+//! delays whenever the connection is lost. Calls made while the daemon is unavailable fail with
+//! `ErrorKind::DaemonUnavailable` instead of waiting for it to come back. Subscriptions to events
+//! are made again every time the client connects, so their streams keep going across restarts of
+//! the daemon. Events that happen while the daemon is unavailable are missed.
+
+use error_chain::ChainedError;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use futures::stream::{self, Stream};
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{future, Future};
+use jsonrpc_client_core::ClientHandle;
+use jsonrpc_client_ipc::IpcTransport;
+use serde::{Deserialize, Serialize};
+use tokio;
+
+use mullvad_types::account::{
+    AccountData, AccountExpiry, AccountHistoryEntry, AccountToken, VoucherSubmission,
+};
+use mullvad_types::api::{ApiInfo, API_VERSION};
+use mullvad_types::events::DaemonEvent;
+use mullvad_types::location::GeoIpLocation;
+use mullvad_types::relay_constraints::{
+    IpVersionPreference, RelayConstraints, RelaySettingsUpdate,
+};
+use mullvad_types::relay_list::{MatchingRelays, RelayInvalidated, RelayList};
+use mullvad_types::settings::{RemoteManagementSettings, Settings};
+use mullvad_types::version::AppVersionInfo;
+use serde_json;
+use talpid_types::tunnel::TunnelStateTransition;
+
+use super::{call_error, is_unavailable, Error, ErrorKind, RpcError, RpcErrorKind, NO_ARGS};
+
+/// How long to wait before the first attempt to connect again after losing the connection.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(250);
+/// The longest time to wait between two attempts to connect.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+pub type CallFuture<T> = Box<Future<Item = T, Error = Error> + Send>;
+
+/// A stream of notifications from the daemon. It never fails.
+pub type EventStream<T> = Box<Stream<Item = T, Error = Error> + Send>;
+
+/// Whether the client is connected to the daemon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connected,
+    /// The daemon is not running, or the connection to it was lost. The client keeps trying to
+    /// connect.
+    Unavailable,
+}
+
+/// Returns a client connecting to the daemon listening on `path`. The client starts out
+/// unavailable and connects in the background.
+pub fn new_async_ipc_client(path: &impl AsRef<Path>) -> AsyncDaemonRpcClient {
+    AsyncDaemonRpcClient::new(path.as_ref().to_string_lossy().to_string())
+}
+
+/// A client for the daemon that can be cloned and shared between tasks. All clones share the
+/// same connection, which is closed when the last clone is dropped.
+#[derive(Clone)]
+pub struct AsyncDaemonRpcClient {
+    connection: Arc<Connection>,
+}
+
+#[derive(Default)]
+struct Connection {
+    state: Mutex<ConnectionState>,
+}
+
+#[derive(Default)]
+struct ConnectionState {
+    rpc_client: Option<ClientHandle>,
+    status_listeners: Vec<UnboundedSender<ConnectionStatus>>,
+    subscriptions: Vec<Box<Resubscribe>>,
+}
+
+impl ConnectionState {
+    fn status(&self) -> ConnectionStatus {
+        if self.rpc_client.is_some() {
+            ConnectionStatus::Connected
+        } else {
+            ConnectionStatus::Unavailable
+        }
+    }
+}
+
+impl Connection {
+    fn rpc_client(&self) -> Option<ClientHandle> {
+        self.state.lock().unwrap().rpc_client.clone()
+    }
+
+    /// Replaces the client used for calls, and tells the status listeners if the client is
+    /// connected or not. All subscriptions are made again with a new client, and the ones whose
+    /// streams have been dropped are forgotten.
+    fn set_rpc_client(&self, rpc_client: Option<ClientHandle>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(ref rpc_client) = rpc_client {
+            state
+                .subscriptions
+                .retain(|subscription| !subscription.is_closed());
+            for subscription in &state.subscriptions {
+                subscription.subscribe(rpc_client);
+            }
+        }
+        state.rpc_client = rpc_client;
+        let status = state.status();
+        state
+            .status_listeners
+            .retain(|listener| listener.unbounded_send(status).is_ok());
+    }
+
+    /// Adds a subscription that is made every time the client connects, starting right away if
+    /// it's connected now.
+    fn add_subscription(&self, subscription: Box<Resubscribe>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(ref rpc_client) = state.rpc_client {
+            subscription.subscribe(rpc_client);
+        }
+        state.subscriptions.push(subscription);
+    }
+}
+
+/// A subscription to an event of the daemon, that can be made again on a new connection.
+trait Resubscribe: Send {
+    /// Subscribes to the event with `rpc_client`, and forwards the notifications to the stream
+    /// until the connection is lost or the stream is dropped.
+    fn subscribe(&self, rpc_client: &ClientHandle);
+
+    /// Returns true once the stream has been dropped.
+    fn is_closed(&self) -> bool;
+}
+
+struct Subscription<T> {
+    event: &'static str,
+    /// A method returning the current value, which is sent before the notifications.
+    current_value_method: Option<&'static str>,
+    tx: UnboundedSender<T>,
+    closed: Arc<AtomicBool>,
+}
+
+impl<T> Resubscribe for Subscription<T>
+where
+    T: for<'de> Deserialize<'de> + Send + 'static,
+{
+    fn subscribe(&self, rpc_client: &ClientHandle) {
+        let event = self.event;
+        let notifications = rpc_client.subscribe::<T, [u8; 0]>(
+            format!("{}_subscribe", event),
+            format!("{}_unsubscribe", event),
+            event.to_owned(),
+            0,
+            &NO_ARGS,
+        );
+        // Requested after subscribing, so no change is missed in between.
+        let current_value: Box<Future<Item = Option<T>, Error = RpcError> + Send> =
+            match self.current_value_method {
+                Some(method) => Box::new(rpc_client.call_method(method, &NO_ARGS).map(Some)),
+                None => Box::new(future::ok(None)),
+            };
+        let tx = self.tx.clone();
+        let closed = self.closed.clone();
+
+        thread::spawn(move || {
+            let result = notifications
+                .join(current_value)
+                .map(|(notifications, current_value)| {
+                    stream::iter_ok(current_value).chain(notifications)
+                }).flatten_stream()
+                .for_each(|value| {
+                    tx.unbounded_send(value).map_err(|_| {
+                        closed.store(true, Ordering::SeqCst);
+                        RpcError::from(RpcErrorKind::Shutdown)
+                    })
+                }).wait();
+            if let Err(error) = result {
+                if !closed.load(Ordering::SeqCst) && !is_unavailable(&error) {
+                    let error =
+                        Error::with_chain(error, ErrorKind::RpcSubscribeError(event.to_owned()));
+                    error!("{}", error.display_chain());
+                }
+            }
+        });
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl AsyncDaemonRpcClient {
+    fn new(path: String) -> Self {
+        let connection = Arc::new(Connection::default());
+        let weak_connection = Arc::downgrade(&connection);
+        thread::spawn(move || Self::maintain_connection(path, weak_connection));
+        AsyncDaemonRpcClient { connection }
+    }
+
+    /// Connects to the daemon, and connects again every time the connection is lost, until all
+    /// clients have been dropped.
+    fn maintain_connection(path: String, weak_connection: Weak<Connection>) {
+        let mut delay = INITIAL_RECONNECT_DELAY;
+        loop {
+            let connection = match weak_connection.upgrade() {
+                Some(connection) => connection,
+                None => return,
+            };
+            match IpcTransport::new(&path, &tokio::reactor::Handle::current()) {
+                Ok(transport) => {
+                    let (client, rpc_client) = transport.into_client();
+                    debug!("Connected to the daemon at {}", path);
+                    // Also subscribes again to the events the client was subscribed to.
+                    connection.set_rpc_client(Some(rpc_client));
+                    delay = INITIAL_RECONNECT_DELAY;
+                    // Let the client finish when the last `AsyncDaemonRpcClient` is dropped.
+                    drop(connection);
+
+                    if let Err(error) = client.wait() {
+                        debug!("Connection to the daemon failed: {}", error);
+                    }
+                    match weak_connection.upgrade() {
+                        Some(connection) => {
+                            warn!("Lost the connection to the daemon");
+                            connection.set_rpc_client(None);
+                        }
+                        None => return,
+                    }
+                }
+                Err(error) => {
+                    trace!("Unable to connect to the daemon at {}: {}", path, error);
+                    drop(connection);
+                    thread::sleep(delay);
+                    delay = next_reconnect_delay(delay);
+                }
+            }
+        }
+    }
+
+    /// Returns whether the client is currently connected to the daemon.
+    pub fn connection_status(&self) -> ConnectionStatus {
+        self.connection.state.lock().unwrap().status()
+    }
+
+    /// Returns a stream of the connection status, starting with the current status and then
+    /// every time the client connects to or loses the connection to the daemon.
+    pub fn connection_status_changes(&self) -> UnboundedReceiver<ConnectionStatus> {
+        let (tx, rx) = mpsc::unbounded();
+        let mut state = self.connection.state.lock().unwrap();
+        if tx.unbounded_send(state.status()).is_ok() {
+            state.status_listeners.push(tx);
+        }
+        rx
+    }
+
+    /// Returns a stream of tunnel states, starting with the current state and then every time it
+    /// changes. Nothing is sent while the daemon is unavailable, and the current state is sent
+    /// once the client has connected to the daemon again, if it changed in the meantime.
+    pub fn state_changes(&self) -> EventStream<TunnelStateTransition> {
+        let mut last_state = None;
+        let states = self.subscribe("new_state", Some("get_state")).filter(
+            move |state: &TunnelStateTransition| {
+                if last_state.as_ref() == Some(state) {
+                    false
+                } else {
+                    last_state = Some(state.clone());
+                    true
+                }
+            },
+        );
+        Box::new(states)
+    }
+
+    /// Returns a stream of the settings, sent every time they change.
+    pub fn settings_changes(&self) -> EventStream<Settings> {
+        self.subscribe("settings", None)
+    }
+
+    /// Returns a stream of the relay lists downloaded by the daemon.
+    pub fn relay_list_updates(&self) -> EventStream<RelayList> {
+        self.subscribe("relay_list_updated", None)
+    }
+
+    /// Returns a stream of the relays the daemon stopped using because they were removed from,
+    /// or no longer match, the relay list.
+    pub fn relay_invalidations(&self) -> EventStream<RelayInvalidated> {
+        self.subscribe("relay_invalidated", None)
+    }
+
+    /// Returns a stream of the account expiry events.
+    pub fn account_expiry_events(&self) -> EventStream<AccountExpiry> {
+        self.subscribe("account_expiry", None)
+    }
+
+    /// Returns a stream of all events of the daemon. The sequence numbers of the events start
+    /// over when the daemon restarts.
+    pub fn events(&self) -> EventStream<DaemonEvent> {
+        self.subscribe("events", None)
+    }
+
+    /// Subscribes to `event`, now if the client is connected and again every time it connects.
+    /// If `current_value_method` is given, it's called after subscribing and its result is sent
+    /// before the notifications.
+    fn subscribe<T>(
+        &self,
+        event: &'static str,
+        current_value_method: Option<&'static str>,
+    ) -> EventStream<T>
+    where
+        T: for<'de> Deserialize<'de> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded();
+        self.connection.add_subscription(Box::new(Subscription {
+            event,
+            current_value_method,
+            tx,
+            closed: Arc::new(AtomicBool::new(false)),
+        }));
+        Box::new(rx.map_err(|()| unreachable!("Unbounded receivers never fail")))
+    }
+
+    pub fn connect(&self) -> CallFuture<()> {
+        self.call("connect", &NO_ARGS)
+    }
+
+    pub fn disconnect(&self) -> CallFuture<()> {
+        self.call("disconnect", &NO_ARGS)
+    }
+
+    pub fn get_state(&self) -> CallFuture<TunnelStateTransition> {
+        self.call("get_state", &NO_ARGS)
+    }
+
+    pub fn get_current_location(&self) -> CallFuture<GeoIpLocation> {
+        self.call("get_current_location", &NO_ARGS)
+    }
+
+    pub fn shutdown(&self) -> CallFuture<()> {
+        self.call("shutdown", &NO_ARGS)
+    }
+
+    pub fn get_account_data(&self, account: AccountToken) -> CallFuture<AccountData> {
+        self.call("get_account_data", &[account])
+    }
+
+    pub fn get_account_expiry(&self) -> CallFuture<Option<AccountExpiry>> {
+        self.call("get_account_expiry", &NO_ARGS)
+    }
+
+    pub fn create_account(&self) -> CallFuture<AccountToken> {
+        self.call("create_account", &NO_ARGS)
+    }
+
+    pub fn submit_voucher(&self, voucher_code: String) -> CallFuture<VoucherSubmission> {
+        self.call("submit_voucher", &[voucher_code])
+    }
+
+    pub fn set_account(&self, account: Option<AccountToken>) -> CallFuture<()> {
+        self.call("set_account", &[account])
+    }
+
+    pub fn get_account_history(&self) -> CallFuture<Vec<AccountToken>> {
+        self.call("get_account_history", &NO_ARGS)
+    }
+
+    pub fn list_accounts(&self) -> CallFuture<Vec<AccountHistoryEntry>> {
+        self.call("list_accounts", &NO_ARGS)
+    }
+
+    pub fn set_account_label(
+        &self,
+        account: AccountToken,
+        label: Option<String>,
+    ) -> CallFuture<()> {
+        self.call("set_account_label", &(account, label))
+    }
+
+    pub fn set_account_history_limit(&self, limit: usize) -> CallFuture<()> {
+        self.call("set_account_history_limit", &[limit])
+    }
+
+    pub fn remove_account_from_history(&self, account: AccountToken) -> CallFuture<()> {
+        self.call("remove_account_from_history", &[account])
+    }
+
+    pub fn get_relay_locations(&self) -> CallFuture<RelayList> {
+        self.call("get_relay_locations", &NO_ARGS)
+    }
+
+    pub fn get_matching_relays(
+        &self,
+        constraints: RelayConstraints,
+        explain: bool,
+    ) -> CallFuture<MatchingRelays> {
+        self.call("get_matching_relays", &(constraints, explain))
+    }
+
+    pub fn update_relay_list(&self) -> CallFuture<()> {
+        self.call("update_relay_list", &NO_ARGS)
+    }
+
+    pub fn get_user_relays(&self) -> CallFuture<RelayList> {
+        self.call("get_user_relays", &NO_ARGS)
+    }
+
+    pub fn set_user_relays(&self, user_relays: RelayList) -> CallFuture<()> {
+        self.call("set_user_relays", &[user_relays])
+    }
+
+    pub fn update_relay_settings(&self, update: RelaySettingsUpdate) -> CallFuture<()> {
+        self.call("update_relay_settings", &[update])
+    }
+
+    pub fn set_relay_ip_version(&self, ip_version: IpVersionPreference) -> CallFuture<()> {
+        self.call("set_relay_ip_version", &[ip_version])
+    }
+
+    pub fn set_rotate_relay_interval(&self, interval: Option<u64>) -> CallFuture<()> {
+        self.call("set_rotate_relay_interval", &[interval])
+    }
+
+    pub fn set_allow_lan(&self, allow_lan: bool) -> CallFuture<()> {
+        self.call("set_allow_lan", &[allow_lan])
+    }
+
+    pub fn set_auto_connect(&self, auto_connect: bool) -> CallFuture<()> {
+        self.call("set_auto_connect", &[auto_connect])
+    }
+
+    pub fn set_openvpn_mssfix(&self, mssfix: Option<u16>) -> CallFuture<()> {
+        self.call("set_openvpn_mssfix", &[mssfix])
+    }
+
+    pub fn set_enable_ipv6(&self, enabled: bool) -> CallFuture<()> {
+        self.call("set_enable_ipv6", &[enabled])
+    }
+
+    pub fn get_settings(&self) -> CallFuture<Settings> {
+        self.call("get_settings", &NO_ARGS)
+    }
+
+    pub fn export_settings(&self, include_account_token: bool) -> CallFuture<Settings> {
+        self.call("export_settings", &[include_account_token])
+    }
+
+    pub fn import_settings(&self, settings: serde_json::Value) -> CallFuture<()> {
+        self.call("import_settings", &[settings])
+    }
+
+    pub fn update_settings(&self, patch: serde_json::Value) -> CallFuture<()> {
+        self.call("update_settings", &[patch])
+    }
+
+    pub fn get_profiles(&self) -> CallFuture<Vec<String>> {
+        self.call("get_profiles", &NO_ARGS)
+    }
+
+    pub fn save_profile(&self, name: String) -> CallFuture<()> {
+        self.call("save_profile", &[name])
+    }
+
+    pub fn remove_profile(&self, name: String) -> CallFuture<()> {
+        self.call("remove_profile", &[name])
+    }
+
+    pub fn switch_profile(&self, name: String) -> CallFuture<()> {
+        self.call("switch_profile", &[name])
+    }
+
+    pub fn set_remote_management(
+        &self,
+        remote_management: RemoteManagementSettings,
+    ) -> CallFuture<()> {
+        self.call("set_remote_management", &[remote_management])
+    }
+
+    pub fn get_remote_management_token(&self) -> CallFuture<String> {
+        self.call("get_remote_management_token", &NO_ARGS)
+    }
+
+    pub fn reset_remote_management_token(&self) -> CallFuture<String> {
+        self.call("reset_remote_management_token", &NO_ARGS)
+    }
+
+    pub fn get_api_info(&self) -> CallFuture<ApiInfo> {
+        self.call("get_api_info", &NO_ARGS)
+    }
+
+    /// Returns what the management interface of the daemon supports, or an
+    /// `IncompatibleApiVersion` error if its version is not compatible with the version this
+    /// client was built for.
+    pub fn check_api_version(&self) -> CallFuture<ApiInfo> {
+        Box::new(self.get_api_info().and_then(|api_info| {
+            if API_VERSION.is_compatible_with(&api_info.version) {
+                Ok(api_info)
+            } else {
+                Err(ErrorKind::IncompatibleApiVersion(api_info.version.to_string()).into())
+            }
+        }))
+    }
+
+    pub fn get_current_version(&self) -> CallFuture<String> {
+        self.call("get_current_version", &NO_ARGS)
+    }
+
+    pub fn get_version_info(&self) -> CallFuture<AppVersionInfo> {
+        self.call("get_version_info", &NO_ARGS)
+    }
+
+    pub fn call<A, O>(&self, method: &'static str, args: &A) -> CallFuture<O>
+    where
+        A: Serialize + Send + 'static,
+        O: for<'de> Deserialize<'de> + Send + 'static,
+    {
+        let rpc_client = match self.connection.rpc_client() {
+            Some(rpc_client) => rpc_client,
+            None => return Box::new(future::err(ErrorKind::DaemonUnavailable.into())),
+        };
+        Box::new(
+            rpc_client
+                .call_method(method, args)
+                .map_err(move |error| map_call_error(method, error)),
+        )
+    }
+}
+
+/// Returns how long to wait before the next attempt to connect, after waiting `delay`.
+fn next_reconnect_delay(delay: Duration) -> Duration {
+    (delay * 2).min(MAX_RECONNECT_DELAY)
+}
+
+/// Turns errors caused by a lost connection into `DaemonUnavailable` errors.
+fn map_call_error(method: &str, error: RpcError) -> Error {
+    if is_unavailable(&error) {
+        Error::with_chain(error, ErrorKind::DaemonUnavailable)
+    } else {
+        call_error(method, error)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate jsonrpc_core;
+
+    use self::jsonrpc_core::MetaIoHandler;
+    use super::*;
+    use std::env;
+    use std::process;
+    use talpid_ipc::IpcServer;
+
+    fn disconnected_client() -> AsyncDaemonRpcClient {
+        AsyncDaemonRpcClient {
+            connection: Arc::new(Connection::default()),
+        }
+    }
+
+    fn is_unavailable_error(error: &Error) -> bool {
+        match *error.kind() {
+            ErrorKind::DaemonUnavailable => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_the_max() {
+        let mut delay = INITIAL_RECONNECT_DELAY;
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            delays.push(delay);
+            delay = next_reconnect_delay(delay);
+        }
+        let expected: Vec<Duration> = [250, 500, 1000, 2000, 4000, 8000, 10000, 10000]
+            .iter()
+            .map(|millis| Duration::from_millis(*millis))
+            .collect();
+        assert_eq!(delays, expected);
+    }
+
+    #[test]
+    fn lost_connections_are_reported_as_unavailable() {
+        let error = map_call_error("get_state", RpcErrorKind::Shutdown.into());
+        assert!(is_unavailable_error(&error));
+        let error = map_call_error("get_state", RpcErrorKind::TransportError.into());
+        assert!(is_unavailable_error(&error));
+
+        let error = map_call_error("get_state", RpcError::from("Invalid response"));
+        match *error.kind() {
+            ErrorKind::RpcCallError(ref method) => assert_eq!(method, "get_state"),
+            ref kind => panic!("Unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn calls_fail_right_away_while_disconnected() {
+        let client = disconnected_client();
+        let error = client.get_state().wait().unwrap_err();
+        assert!(is_unavailable_error(&error));
+    }
+
+    #[test]
+    fn status_stream_starts_with_the_current_status() {
+        let client = disconnected_client();
+        let statuses = client.connection_status_changes();
+        client.connection.set_rpc_client(None);
+        drop(client);
+
+        let statuses: Vec<ConnectionStatus> = statuses.wait().map(Result::unwrap).collect();
+        assert_eq!(
+            statuses,
+            vec![ConnectionStatus::Unavailable, ConnectionStatus::Unavailable]
+        );
+    }
+
+    #[test]
+    fn dropped_status_streams_are_forgotten() {
+        let client = disconnected_client();
+        drop(client.connection_status_changes());
+        client.connection.set_rpc_client(None);
+        let state = client.connection.state.lock().unwrap();
+        assert!(state.status_listeners.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn connects_when_the_daemon_starts() {
+        let path = env::temp_dir().join(format!("mullvad-ipc-client-test-{}", process::id()));
+        let client = new_async_ipc_client(&path);
+        let mut statuses = client.connection_status_changes().wait();
+        assert_eq!(statuses.next(), Some(Ok(ConnectionStatus::Unavailable)));
+
+        let server = IpcServer::start(
+            MetaIoHandler::<()>::default(),
+            path.to_string_lossy().to_string(),
+        ).unwrap();
+        assert_eq!(statuses.next(), Some(Ok(ConnectionStatus::Connected)));
+        assert_eq!(client.connection_status(), ConnectionStatus::Connected);
+        server.close_handle().close();
+    }
+}
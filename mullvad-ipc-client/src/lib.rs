@@ -11,6 +11,7 @@ extern crate futures;
 extern crate mullvad_paths;
 extern crate mullvad_types;
 extern crate serde;
+extern crate serde_json;
 extern crate talpid_ipc;
 extern crate talpid_types;
 extern crate tokio;
@@ -21,11 +22,16 @@ use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
-use mullvad_types::account::{AccountData, AccountToken};
+use mullvad_types::account::{
+    AccountData, AccountExpiry, AccountHistoryEntry, AccountToken, VoucherSubmission,
+};
+use mullvad_types::api::ApiInfo;
 use mullvad_types::location::GeoIpLocation;
-use mullvad_types::relay_constraints::{RelaySettings, RelaySettingsUpdate};
-use mullvad_types::relay_list::RelayList;
-use mullvad_types::settings::Settings;
+use mullvad_types::relay_constraints::{
+    IpVersionPreference, RelayConstraints, RelaySettings, RelaySettingsUpdate,
+};
+use mullvad_types::relay_list::{MatchingRelays, RelayList};
+use mullvad_types::settings::{RemoteManagementSettings, Settings};
 use mullvad_types::version::AppVersionInfo;
 
 use serde::{Deserialize, Serialize};
@@ -38,12 +44,26 @@ use jsonrpc_client_core::{Client, ClientHandle, Future};
 pub use jsonrpc_client_core::{Error as RpcError, ErrorKind as RpcErrorKind};
 use jsonrpc_client_ipc::IpcTransport;
 
+mod async_client;
+pub use async_client::{
+    new_async_ipc_client, AsyncDaemonRpcClient, CallFuture, ConnectionStatus, EventStream,
+};
+
 error_chain! {
     errors {
         AuthenticationError {
             description("Failed to authenticate the connection with the daemon")
         }
 
+        IncompatibleApiVersion(version: String) {
+            description("The management interface of the daemon has an incompatible version")
+            display("The management interface of the daemon has incompatible version {}", version)
+        }
+
+        DaemonUnavailable {
+            description("The daemon is not running or the connection to it was lost")
+        }
+
         RpcCallError(method: String) {
             description("Failed to call RPC method")
             display("Failed to call RPC method \"{}\"", method)
@@ -71,6 +91,9 @@ error_chain! {
 
 static NO_ARGS: [u8; 0] = [];
 
+/// The error code the daemon answers with when the client is not allowed to call a method.
+const PERMISSION_DENIED_CODE: i64 = -903;
+
 
 pub fn new_standalone_ipc_client(path: &impl AsRef<Path>) -> Result<DaemonRpcClient> {
     let path = path.as_ref().to_string_lossy().to_string();
@@ -142,6 +165,34 @@ impl DaemonRpcClient {
         self.call("get_account_data", &[account])
     }
 
+    pub fn get_account_expiry(&mut self) -> Result<Option<AccountExpiry>> {
+        self.call("get_account_expiry", &NO_ARGS)
+    }
+
+    pub fn create_account(&mut self) -> Result<AccountToken> {
+        self.call("create_account", &NO_ARGS)
+    }
+
+    pub fn submit_voucher(&mut self, voucher_code: String) -> Result<VoucherSubmission> {
+        self.call("submit_voucher", &[voucher_code])
+    }
+
+    pub fn list_accounts(&mut self) -> Result<Vec<AccountHistoryEntry>> {
+        self.call("list_accounts", &NO_ARGS)
+    }
+
+    pub fn set_account_label(
+        &mut self,
+        account: AccountToken,
+        label: Option<String>,
+    ) -> Result<()> {
+        self.call("set_account_label", &(account, label))
+    }
+
+    pub fn set_account_history_limit(&mut self, limit: usize) -> Result<()> {
+        self.call("set_account_history_limit", &[limit])
+    }
+
     pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<()> {
         self.call("set_allow_lan", &[allow_lan])
     }
@@ -162,6 +213,10 @@ impl DaemonRpcClient {
         self.call("get_current_location", &NO_ARGS)
     }
 
+    pub fn get_api_info(&mut self) -> Result<ApiInfo> {
+        self.call("get_api_info", &NO_ARGS)
+    }
+
     pub fn get_current_version(&mut self) -> Result<String> {
         self.call("get_current_version", &NO_ARGS)
     }
@@ -170,6 +225,14 @@ impl DaemonRpcClient {
         self.call("get_relay_locations", &NO_ARGS)
     }
 
+    pub fn get_matching_relays(
+        &mut self,
+        constraints: RelayConstraints,
+        explain: bool,
+    ) -> Result<MatchingRelays> {
+        self.call("get_matching_relays", &(constraints, explain))
+    }
+
     pub fn get_relay_settings(&mut self) -> Result<RelaySettings> {
         self.call("get_relay_settings", &NO_ARGS)
     }
@@ -186,6 +249,49 @@ impl DaemonRpcClient {
         self.call("get_settings", &NO_ARGS)
     }
 
+    pub fn export_settings(&mut self, include_account_token: bool) -> Result<Settings> {
+        self.call("export_settings", &[include_account_token])
+    }
+
+    pub fn import_settings(&mut self, settings: serde_json::Value) -> Result<()> {
+        self.call("import_settings", &[settings])
+    }
+
+    pub fn update_settings(&mut self, patch: serde_json::Value) -> Result<()> {
+        self.call("update_settings", &[patch])
+    }
+
+    pub fn get_profiles(&mut self) -> Result<Vec<String>> {
+        self.call("get_profiles", &NO_ARGS)
+    }
+
+    pub fn save_profile(&mut self, name: String) -> Result<()> {
+        self.call("save_profile", &[name])
+    }
+
+    pub fn remove_profile(&mut self, name: String) -> Result<()> {
+        self.call("remove_profile", &[name])
+    }
+
+    pub fn switch_profile(&mut self, name: String) -> Result<()> {
+        self.call("switch_profile", &[name])
+    }
+
+    pub fn set_remote_management(
+        &mut self,
+        remote_management: RemoteManagementSettings,
+    ) -> Result<()> {
+        self.call("set_remote_management", &[remote_management])
+    }
+
+    pub fn get_remote_management_token(&mut self) -> Result<String> {
+        self.call("get_remote_management_token", &NO_ARGS)
+    }
+
+    pub fn reset_remote_management_token(&mut self) -> Result<String> {
+        self.call("reset_remote_management_token", &NO_ARGS)
+    }
+
     pub fn get_version_info(&mut self) -> Result<AppVersionInfo> {
         self.call("get_version_info", &NO_ARGS)
     }
@@ -194,6 +300,14 @@ impl DaemonRpcClient {
         self.call("set_account", &[account])
     }
 
+    pub fn set_relay_ip_version(&mut self, ip_version: IpVersionPreference) -> Result<()> {
+        self.call("set_relay_ip_version", &[ip_version])
+    }
+
+    pub fn set_rotate_relay_interval(&mut self, interval: Option<u64>) -> Result<()> {
+        self.call("set_rotate_relay_interval", &[interval])
+    }
+
     pub fn set_enable_ipv6(&mut self, enabled: bool) -> Result<()> {
         self.call("set_enable_ipv6", &[enabled])
     }
@@ -206,6 +320,18 @@ impl DaemonRpcClient {
         self.call("shutdown", &NO_ARGS)
     }
 
+    pub fn update_relay_list(&mut self) -> Result<()> {
+        self.call("update_relay_list", &NO_ARGS)
+    }
+
+    pub fn get_user_relays(&mut self) -> Result<RelayList> {
+        self.call("get_user_relays", &NO_ARGS)
+    }
+
+    pub fn set_user_relays(&mut self, user_relays: RelayList) -> Result<()> {
+        self.call("set_user_relays", &[user_relays])
+    }
+
     pub fn update_relay_settings(&mut self, update: RelaySettingsUpdate) -> Result<()> {
         self.call("update_relay_settings", &[update])
     }
@@ -218,7 +344,7 @@ impl DaemonRpcClient {
         self.rpc_client
             .call_method(method, args)
             .wait()
-            .chain_err(|| ErrorKind::RpcCallError(method.to_owned()))
+            .map_err(|error| call_error(method, error))
     }
 
     pub fn new_state_subscribe(&mut self) -> Result<mpsc::Receiver<TunnelStateTransition>> {
@@ -250,3 +376,29 @@ impl DaemonRpcClient {
         Ok(rx)
     }
 }
+
+fn call_error(method: &str, error: RpcError) -> Error {
+    let kind = if is_permission_denied(&error) {
+        ErrorKind::AuthenticationError
+    } else {
+        ErrorKind::RpcCallError(method.to_owned())
+    };
+    Error::with_chain(error, kind)
+}
+
+/// Returns true if the call failed because the connection to the daemon was lost.
+fn is_unavailable(error: &RpcError) -> bool {
+    match error.kind() {
+        &RpcErrorKind::Shutdown | &RpcErrorKind::TransportError => true,
+        _ => false,
+    }
+}
+
+fn is_permission_denied(error: &RpcError) -> bool {
+    match error.kind() {
+        &RpcErrorKind::JsonRpcError(ref rpc_error) => {
+            rpc_error.code.code() == PERMISSION_DENIED_CODE
+        }
+        _ => false,
+    }
+}
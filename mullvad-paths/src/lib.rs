@@ -48,6 +48,9 @@ pub use cache::cache_dir;
 mod logs;
 pub use logs::{get_log_dir, log_dir};
 
+mod policy;
+pub use policy::get_policy_path;
+
 pub mod resources;
 pub use resources::get_resource_dir;
 
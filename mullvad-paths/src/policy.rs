@@ -0,0 +1,24 @@
+use Result;
+
+use std::env;
+use std::path::PathBuf;
+
+/// Returns the path to the system policy file pointed to by `MULLVAD_POLICY_PATH`, or the default
+/// one if that variable is unset. The file is optional and only ever written by administrators.
+pub fn get_policy_path() -> Result<PathBuf> {
+    match env::var_os("MULLVAD_POLICY_PATH") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => get_default_policy_dir().map(|dir| dir.join(::PRODUCT_NAME).join("policy.json")),
+    }
+}
+
+fn get_default_policy_dir() -> Result<PathBuf> {
+    #[cfg(unix)]
+    {
+        Ok(PathBuf::from("/etc"))
+    }
+    #[cfg(windows)]
+    {
+        ::get_allusersprofile_dir()
+    }
+}
@@ -2,6 +2,7 @@ use std::env;
 use std::path::PathBuf;
 
 pub const API_CA_FILENAME: &str = "api_root_ca.pem";
+pub const RELAY_LIST_KEY_FILENAME: &str = "relay_list_signing_key.pem";
 
 pub fn get_resource_dir() -> PathBuf {
     match env::var_os("MULLVAD_RESOURCE_DIR") {
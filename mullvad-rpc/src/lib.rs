@@ -33,7 +33,7 @@ use tokio_core::reactor::Handle;
 pub use jsonrpc_client_core::{Error, ErrorKind};
 pub use jsonrpc_client_http::{Error as HttpError, HttpHandle};
 
-use mullvad_types::account::AccountToken;
+use mullvad_types::account::{AccountToken, VoucherSubmission};
 use mullvad_types::relay_list::RelayList;
 use mullvad_types::version;
 
@@ -43,6 +43,7 @@ use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 pub mod event_loop;
+pub mod relay_list;
 pub mod rest;
 
 mod cached_dns_resolver;
@@ -102,6 +103,19 @@ impl MullvadRpcFactory {
         self.setup_connection(move |transport| transport.shared(handle))
     }
 
+    /// Create and returns a `RelayListClient` running on the given core handle.
+    pub fn new_relay_list_client_on_event_loop(
+        &mut self,
+        handle: &Handle,
+    ) -> rest::Result<relay_list::RelayListClient> {
+        let request_sender =
+            rest::create_https_client_with_sni(&self.ca_path, API_HOST.to_owned(), handle)?;
+        Ok(relay_list::RelayListClient::new(
+            request_sender,
+            &self.api_address(),
+        ))
+    }
+
     fn setup_connection<F>(&mut self, create_transport: F) -> Result<HttpHandle, HttpError>
     where
         F: FnOnce(HttpTransportBuilder<HttpsClientWithSni>)
@@ -119,18 +133,26 @@ impl MullvadRpcFactory {
     }
 
     fn api_uri(&mut self) -> String {
-        let address = if let Some(ref mut address_cache) = self.address_cache {
+        format!("https://{}/rpc/", self.api_address())
+    }
+
+    fn api_address(&mut self) -> String {
+        if let Some(ref mut address_cache) = self.address_cache {
             address_cache.resolve().to_string()
         } else {
             API_HOST.to_owned()
-        };
-
-        format!("https://{}/rpc/", address)
+        }
     }
 }
 
 jsonrpc_client!(pub struct AccountsProxy {
+    pub fn create_account(&mut self) -> RpcRequest<AccountToken>;
     pub fn get_expiry(&mut self, account_token: AccountToken) -> RpcRequest<DateTime<Utc>>;
+    pub fn submit_voucher(
+        &mut self,
+        account_token: AccountToken,
+        voucher_code: String)
+        -> RpcRequest<VoucherSubmission>;
 });
 
 jsonrpc_client!(pub struct ProblemReportProxy {
@@ -0,0 +1,191 @@
+//! Conditional download of the signed relay list. Fetches the relay list with a plain HTTPS
+//! `GET`, so the request can carry an `If-None-Match` header with the `ETag` the server sent along
+//! with the last list, and a `304 Not Modified` response can be told apart from errors.
+
+use futures::{self, Future};
+use hyper;
+use hyper::header::{ETag, EntityTag, Host, IfNoneMatch};
+use hyper::{Method, Request, StatusCode};
+
+use rest;
+
+error_chain! {
+    errors {
+        NoResponse { description("The request was dropped without any response") }
+        InvalidEtag(etag: String) {
+            description("Invalid entity tag")
+            display("Invalid entity tag: {}", etag)
+        }
+    }
+    links {
+        Transport(rest::Error, rest::ErrorKind);
+    }
+    foreign_links {
+        Uri(hyper::error::UriError);
+    }
+}
+
+/// Where the relay list is fetched from on the API server.
+const RELAY_LIST_PATH: &str = "/app/v1/relays";
+/// The response header carrying the base64 encoded detached signature of the relay list.
+const SIGNATURE_HEADER: &str = "X-Relay-List-Signature";
+
+
+/// A relay list, its detached signature and the `ETag` the server identified that version of the
+/// list by, if any. The relay list is not checked against the signature here.
+pub struct DownloadedRelayList {
+    /// The relay list as JSON, exactly as it was signed.
+    pub relay_list: Vec<u8>,
+    /// Base64 encoded signature of `relay_list`, if the server sent one.
+    pub signature: Option<String>,
+    /// The entity tag, quoted as in the `ETag` header. Should be stored with the relay list and
+    /// passed back when the list is downloaded again.
+    pub etag: Option<String>,
+}
+
+
+/// A client for downloading the relay list from the Mullvad API.
+#[derive(Clone)]
+pub struct RelayListClient {
+    request_sender: rest::RequestSender,
+    relay_list_uri: String,
+}
+
+impl RelayListClient {
+    /// Creates a client fetching the relay list from the server at `api_address`, which can be
+    /// either a hostname or an IP address.
+    pub fn new(request_sender: rest::RequestSender, api_address: &str) -> Self {
+        RelayListClient {
+            request_sender,
+            relay_list_uri: format!("https://{}{}", api_address, RELAY_LIST_PATH),
+        }
+    }
+
+    /// Downloads the relay list. If `etag` is given and the relay list still has that entity
+    /// tag, the future resolves to `None` instead of a relay list.
+    pub fn relay_list(
+        &self,
+        etag: Option<&str>,
+    ) -> Box<Future<Item = Option<DownloadedRelayList>, Error = Error>> {
+        let request = match self.create_request(etag) {
+            Ok(request) => request,
+            Err(error) => return Box::new(futures::future::err(error)),
+        };
+        let (response_tx, response_rx) = futures::sync::oneshot::channel();
+        let future = futures::Sink::send(self.request_sender.clone(), (request, response_tx))
+            .map_err(|e| Error::with_chain(e, ErrorKind::NoResponse))
+            .and_then(|_| response_rx.map_err(|e| Error::with_chain(e, ErrorKind::NoResponse)))
+            .and_then(|response_result| match response_result {
+                Ok(response) => Ok(Some(Self::parse_response(response))),
+                Err(rest::Error(rest::ErrorKind::HttpError(StatusCode::NotModified), _)) => {
+                    Ok(None)
+                }
+                Err(error) => Err(Error::from(error)),
+            });
+        Box::new(future)
+    }
+
+    fn create_request(&self, etag: Option<&str>) -> Result<Request> {
+        let mut request = Request::new(Method::Get, self.relay_list_uri.parse()?);
+        {
+            let headers = request.headers_mut();
+            headers.set(Host::new(::API_HOST, None));
+            if let Some(etag) = etag {
+                let etag = etag
+                    .parse::<EntityTag>()
+                    .map_err(|_| ErrorKind::InvalidEtag(etag.to_owned()))?;
+                headers.set(IfNoneMatch::Items(vec![etag]));
+            }
+        }
+        Ok(request)
+    }
+
+    fn parse_response(response: rest::Response) -> DownloadedRelayList {
+        let signature = response
+            .headers
+            .get_raw(SIGNATURE_HEADER)
+            .and_then(|signature| signature.one())
+            .and_then(|signature| String::from_utf8(signature.to_vec()).ok());
+        let etag = response
+            .headers
+            .get::<ETag>()
+            .map(|&ETag(ref etag)| etag.to_string());
+        DownloadedRelayList {
+            relay_list: response.body,
+            signature,
+            etag,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::sync::mpsc;
+    use futures::Stream;
+    use std::thread;
+
+    /// Returns a client whose requests are answered by `respond`, and a handle to the thread
+    /// answering them.
+    fn client<F>(respond: F) -> (RelayListClient, thread::JoinHandle<()>)
+    where
+        F: Fn(&Request) -> rest::Result<rest::Response> + Send + 'static,
+    {
+        let (request_tx, request_rx) = mpsc::unbounded();
+        let client = RelayListClient::new(request_tx, "127.0.0.1");
+        let responder = thread::spawn(move || {
+            for (request, response_tx) in request_rx.wait().filter_map(|request| request.ok()) {
+                let _ = response_tx.send(respond(&request));
+            }
+        });
+        (client, responder)
+    }
+
+    fn relay_list_response(etag: &str) -> rest::Response {
+        let mut headers = hyper::Headers::new();
+        headers.set(ETag(EntityTag::strong(etag.to_owned())));
+        headers.set_raw(SIGNATURE_HEADER, "c2lnbmF0dXJl");
+        rest::Response {
+            headers,
+            body: br#"{ "countries": [] }"#.to_vec(),
+        }
+    }
+
+    #[test]
+    fn downloads_relay_list_with_its_signature_and_etag() {
+        let (client, _) = client(|request| {
+            assert_eq!(*request.method(), Method::Get);
+            assert_eq!(request.uri().path(), RELAY_LIST_PATH);
+            assert!(request.headers().get::<IfNoneMatch>().is_none());
+            Ok(relay_list_response("1"))
+        });
+
+        let downloaded = client.relay_list(None).wait().unwrap().unwrap();
+
+        assert_eq!(downloaded.relay_list, br#"{ "countries": [] }"#.to_vec());
+        assert_eq!(downloaded.signature, Some("c2lnbmF0dXJl".to_owned()));
+        assert_eq!(downloaded.etag, Some("\"1\"".to_owned()));
+    }
+
+    #[test]
+    fn not_modified_relay_list_is_none() {
+        let (client, _) = client(|request| {
+            assert_eq!(
+                request.headers().get::<IfNoneMatch>(),
+                Some(&IfNoneMatch::Items(vec![EntityTag::strong("1".to_owned())]))
+            );
+            Err(rest::ErrorKind::HttpError(StatusCode::NotModified).into())
+        });
+
+        assert!(client.relay_list(Some("\"1\"")).wait().unwrap().is_none());
+    }
+
+    #[test]
+    fn other_http_errors_are_errors() {
+        let (client, _) =
+            client(|_| Err(rest::ErrorKind::HttpError(StatusCode::InternalServerError).into()));
+
+        assert!(client.relay_list(Some("\"1\"")).wait().is_err());
+    }
+}
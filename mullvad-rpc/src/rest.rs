@@ -28,11 +28,36 @@ error_chain! {
 }
 
 
-pub type RequestSender = mpsc::UnboundedSender<(Request, oneshot::Sender<Result<Vec<u8>>>)>;
-type RequestReceiver = mpsc::UnboundedReceiver<(Request, oneshot::Sender<Result<Vec<u8>>>)>;
+/// The headers and body of a response with the status `200 OK`.
+pub struct Response {
+    pub headers: hyper::Headers,
+    pub body: Vec<u8>,
+}
+
+pub type RequestSender = mpsc::UnboundedSender<(Request, oneshot::Sender<Result<Response>>)>;
+type RequestReceiver = mpsc::UnboundedReceiver<(Request, oneshot::Sender<Result<Response>>)>;
 
 pub fn create_https_client<P: AsRef<Path>>(ca_path: P, handle: &Handle) -> Result<RequestSender> {
-    let connector = HttpsConnectorWithSni::new(ca_path, handle)?;
+    create_https_client_inner(ca_path, None, handle)
+}
+
+/// Same as `create_https_client`, but the TLS handshake requests a certificate for
+/// `sni_hostname` instead of the host in the request URI. Used when connecting to a server by IP.
+pub fn create_https_client_with_sni<P: AsRef<Path>>(
+    ca_path: P,
+    sni_hostname: String,
+    handle: &Handle,
+) -> Result<RequestSender> {
+    create_https_client_inner(ca_path, Some(sni_hostname), handle)
+}
+
+fn create_https_client_inner<P: AsRef<Path>>(
+    ca_path: P,
+    sni_hostname: Option<String>,
+    handle: &Handle,
+) -> Result<RequestSender> {
+    let mut connector = HttpsConnectorWithSni::new(ca_path, handle)?;
+    connector.set_sni_hostname(sni_hostname);
     let client = Client::configure().connector(connector).build(handle);
 
     let (request_tx, request_rx) = mpsc::unbounded();
@@ -55,8 +80,17 @@ fn create_request_processing_future<CC: hyper::client::Connect>(
                 } else {
                     future::err(ErrorKind::HttpError(response.status()).into())
                 }
-            }).and_then(|response: hyper::Response| response.body().concat2().from_err())
-            .map(|response_chunk| response_chunk.to_vec())
+            }).and_then(|response: hyper::Response| {
+                let headers = response.headers().clone();
+                response
+                    .body()
+                    .concat2()
+                    .from_err()
+                    .map(move |body| Response {
+                        headers,
+                        body: body.to_vec(),
+                    })
+            })
             .then(move |response_result| {
                 if let Err(_) = response_tx.send(response_result) {
                     warn!("Unable to send response back to caller");
@@ -0,0 +1,124 @@
+extern crate chrono;
+extern crate futures;
+extern crate hyper;
+extern crate jsonrpc_client_http;
+extern crate mullvad_rpc;
+#[macro_use]
+extern crate serde_json;
+
+use chrono::{DateTime, Utc};
+use futures::{Future, Stream};
+use hyper::header::ContentType;
+use hyper::server::{Http, Request, Response, Service};
+use jsonrpc_client_http::HttpTransport;
+use mullvad_rpc::{AccountsProxy, ErrorKind, HttpHandle};
+use serde_json::Value;
+
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread;
+
+const ACCOUNT_TOKEN: &str = "1234567890123456";
+const VOUCHER_CODE: &str = "ABCD-EFGH-JKLM-NPQR";
+const NEW_EXPIRY: &str = "2038-01-01T00:00:00Z";
+
+/// A stand-in for the accounts part of the Mullvad API.
+struct MockApi;
+
+impl MockApi {
+    fn handle(request: &Value) -> Value {
+        let params = &request["params"];
+        match request["method"].as_str() {
+            Some("create_account") => json!({ "result": ACCOUNT_TOKEN }),
+            Some("submit_voucher") if params[0] != ACCOUNT_TOKEN => {
+                json!({ "error": { "code": -200, "message": "Invalid account" } })
+            }
+            Some("submit_voucher") if params[1] != VOUCHER_CODE => {
+                json!({ "error": { "code": -400, "message": "Invalid voucher code" } })
+            }
+            Some("submit_voucher") => json!({
+                "result": { "time_added": 2592000, "new_expiry": NEW_EXPIRY }
+            }),
+            _ => json!({ "error": { "code": -32601, "message": "Method not found" } }),
+        }
+    }
+}
+
+impl Service for MockApi {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, request: Request) -> Self::Future {
+        Box::new(request.body().concat2().map(|body| {
+            let request: Value = serde_json::from_slice(&body).expect("Invalid JSON-RPC request");
+            let mut response = Self::handle(&request);
+            response["jsonrpc"] = json!("2.0");
+            response["id"] = request["id"].clone();
+            Response::new()
+                .with_header(ContentType::json())
+                .with_body(response.to_string())
+        }))
+    }
+}
+
+/// Starts the mock API on a free port and returns its address.
+fn start_mock_api() -> SocketAddr {
+    let (address_tx, address_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let server = Http::new()
+            .bind(&"127.0.0.1:0".parse().unwrap(), || Ok(MockApi))
+            .unwrap();
+        address_tx.send(server.local_addr().unwrap()).unwrap();
+        server.run().unwrap();
+    });
+    address_rx.recv().unwrap()
+}
+
+fn new_accounts_proxy() -> AccountsProxy<HttpHandle> {
+    let address = start_mock_api();
+    let transport = HttpTransport::new().standalone().unwrap();
+    let handle = transport
+        .handle(&format!("http://{}/rpc/", address))
+        .unwrap();
+    AccountsProxy::new(handle)
+}
+
+#[test]
+fn creates_account() {
+    let mut proxy = new_accounts_proxy();
+
+    assert_eq!(proxy.create_account().wait().unwrap(), ACCOUNT_TOKEN);
+}
+
+#[test]
+fn submits_voucher() {
+    let mut proxy = new_accounts_proxy();
+
+    let submission = proxy
+        .submit_voucher(ACCOUNT_TOKEN.to_owned(), VOUCHER_CODE.to_owned())
+        .wait()
+        .unwrap();
+
+    assert_eq!(submission.time_added, 30 * 24 * 60 * 60);
+    assert_eq!(
+        submission.new_expiry,
+        NEW_EXPIRY.parse::<DateTime<Utc>>().unwrap()
+    );
+}
+
+#[test]
+fn forwards_api_errors() {
+    let mut proxy = new_accounts_proxy();
+
+    let error = proxy
+        .submit_voucher(ACCOUNT_TOKEN.to_owned(), "INVALID".to_owned())
+        .wait()
+        .unwrap_err();
+
+    match error.kind() {
+        &ErrorKind::JsonRpcError(ref rpc_error) => assert_eq!(rpc_error.code.code(), -400),
+        kind => panic!("Unexpected error: {}", kind),
+    }
+}
@@ -1,4 +1,5 @@
 #[macro_use]
+extern crate base64;
 extern crate duct;
 extern crate jsonrpc_client_core;
 extern crate jsonrpc_client_ipc;
@@ -7,6 +8,7 @@ extern crate libc;
 extern crate mullvad_ipc_client;
 extern crate mullvad_paths;
 extern crate notify;
+extern crate openssl;
 extern crate openvpn_plugin;
 extern crate talpid_ipc;
 extern crate tempfile;
@@ -27,8 +29,13 @@ use futures::sync::oneshot;
 use jsonrpc_client_core::{Future, Transport};
 use jsonrpc_client_ipc::IpcTransport;
 use mullvad_ipc_client::{DaemonRpcClient, ResultExt};
-use mullvad_paths::resources::API_CA_FILENAME;
+use mullvad_paths::resources::{API_CA_FILENAME, RELAY_LIST_KEY_FILENAME};
 use notify::{RawEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
 use openvpn_plugin::types::OpenVpnPluginEvent;
 use tempfile::TempDir;
 use tokio::reactor::Handle;
@@ -234,13 +241,13 @@ fn prepare_resource_dir(resource_dir: &Path) {
         .expect("Failed to copy mock OpenVPN binary");
     File::create(talpid_openvpn_plugin).expect("Failed to create mock Talpid OpenVPN plugin");
 
-    prepare_relay_list(resource_dir.join("relays.json"));
+    prepare_relay_list(resource_dir);
 }
 
-fn prepare_relay_list<T: AsRef<Path>>(path: T) {
-    fs::write(
-        path,
-        r#"{
+/// Writes a relay list to `resource_dir`, signed with a new key whose public part is written
+/// there as well.
+fn prepare_relay_list(resource_dir: &Path) {
+    let relay_list = r#"{
             "countries": [{
                 "name": "Sweden",
                 "code": "se",
@@ -262,8 +269,28 @@ fn prepare_relay_list<T: AsRef<Path>>(path: T) {
                     }]
                 }]
             }]
-        }"#,
-    ).expect("Failed to create mock relay list file");
+        }"#;
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).expect("Unknown curve");
+    let key = EcKey::generate(&group)
+        .and_then(PKey::from_ec_key)
+        .expect("Failed to generate relay list signing key");
+    let mut signer =
+        Signer::new(MessageDigest::sha256(), &key).expect("Failed to create relay list signer");
+    signer
+        .update(relay_list.as_bytes())
+        .expect("Failed to sign mock relay list");
+    let signature = signer.sign_to_vec().expect("Failed to sign mock relay list");
+
+    fs::write(
+        resource_dir.join(RELAY_LIST_KEY_FILENAME),
+        key.public_key_to_pem()
+            .expect("Failed to encode relay list signing key"),
+    ).expect("Failed to create relay list signing key file");
+    fs::write(resource_dir.join("relays.json"), relay_list)
+        .expect("Failed to create mock relay list file");
+    fs::write(resource_dir.join("relays.sig"), base64::encode(&signature))
+        .expect("Failed to create mock relay list signature file");
 }
 
 pub struct DaemonRunner {
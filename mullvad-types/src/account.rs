@@ -7,3 +7,96 @@ pub type AccountToken = String;
 pub struct AccountData {
     pub expiry: DateTime<Utc>,
 }
+
+/// An account that has been used on this device, as remembered by the account history.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct AccountHistoryEntry {
+    pub account_token: AccountToken,
+    /// A name given to the account by the user, such as the team it belongs to.
+    pub label: Option<String>,
+    pub first_used: DateTime<Utc>,
+    pub last_used: DateTime<Utc>,
+    /// The expiry of the account when it was last checked, if it ever was.
+    pub last_known_expiry: Option<DateTime<Utc>>,
+}
+
+impl AccountHistoryEntry {
+    /// Returns an entry for an account used for the first time at `now`.
+    pub fn new(account_token: AccountToken, now: DateTime<Utc>) -> Self {
+        AccountHistoryEntry {
+            account_token,
+            label: None,
+            first_used: now,
+            last_used: now,
+            last_known_expiry: None,
+        }
+    }
+}
+
+/// The result of redeeming a voucher on an account.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct VoucherSubmission {
+    /// The time, in seconds, that the voucher added to the account.
+    pub time_added: u64,
+    pub new_expiry: DateTime<Utc>,
+}
+
+/// The expiry of the current account, as last checked by the daemon.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct AccountExpiry {
+    pub expiry: DateTime<Utc>,
+    /// The smallest warning threshold, in seconds, that the time left on the account is within.
+    /// `None` if the account has expired or has more time left than any threshold.
+    pub warning_threshold: Option<u64>,
+    pub expired: bool,
+}
+
+impl AccountExpiry {
+    /// Returns the state of an account expiring at `expiry`, at the time `now`, given the
+    /// thresholds at which to warn about the account expiring.
+    pub fn new(expiry: DateTime<Utc>, now: DateTime<Utc>, warning_thresholds: &[u64]) -> Self {
+        let expired = expiry <= now;
+        let time_left = (expiry - now).num_seconds();
+        let warning_threshold = if expired {
+            None
+        } else {
+            warning_thresholds
+                .iter()
+                .cloned()
+                .filter(|&threshold| time_left <= threshold as i64)
+                .min()
+        };
+        AccountExpiry {
+            expiry,
+            warning_threshold,
+            expired,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn warns_at_smallest_passed_threshold() {
+        let now = Utc::now();
+        let thresholds = [3 * 24 * 60 * 60, 24 * 60 * 60];
+
+        let active = AccountExpiry::new(now + Duration::days(5), now, &thresholds);
+        assert_eq!(active.warning_threshold, None);
+        assert!(!active.expired);
+
+        let two_days_left = AccountExpiry::new(now + Duration::days(2), now, &thresholds);
+        assert_eq!(two_days_left.warning_threshold, Some(3 * 24 * 60 * 60));
+
+        let hours_left = AccountExpiry::new(now + Duration::hours(5), now, &thresholds);
+        assert_eq!(hours_left.warning_threshold, Some(24 * 60 * 60));
+
+        let expired = AccountExpiry::new(now - Duration::hours(1), now, &thresholds);
+        assert_eq!(expired.warning_threshold, None);
+        assert!(expired.expired);
+    }
+}
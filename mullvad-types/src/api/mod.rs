@@ -0,0 +1,78 @@
+//! The version of the management interface, and a description of its methods and events that
+//! third party clients can check their assumptions against.
+
+mod schema;
+pub use self::schema::schema;
+
+use std::fmt;
+
+/// The version of the management interface. The minor version is increased when methods, events
+/// or fields are added, and the major version when anything is removed or changes shape, so
+/// clients can tell if they understand the daemon they are talking to.
+///
+/// Every change to the schema returned by `schema` needs a new version. A test fails if the
+/// schema differs from the one published in `api-schema.json` without a version bump.
+pub const API_VERSION: ApiVersion = ApiVersion { major: 1, minor: 0 };
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct ApiVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ApiVersion {
+    /// Returns true if a client written for this version can talk to a daemon with the version
+    /// `daemon_version`.
+    pub fn is_compatible_with(&self, daemon_version: &ApiVersion) -> bool {
+        self.major == daemon_version.major && self.minor <= daemon_version.minor
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// What the management interface of the running daemon supports, as returned by `get_api_info`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ApiInfo {
+    pub version: ApiVersion,
+    /// The names of all methods, not counting the methods to subscribe to and unsubscribe from
+    /// events.
+    pub methods: Vec<String>,
+    /// The names of all events. Clients subscribe to an event by calling `<event>_subscribe` and
+    /// unsubscribe by calling `<event>_unsubscribe`.
+    pub events: Vec<String>,
+}
+
+impl ApiInfo {
+    /// Returns the information about the management interface described by `schema`.
+    pub fn current() -> Self {
+        ApiInfo {
+            version: API_VERSION,
+            methods: schema::methods()
+                .into_iter()
+                .map(|method| method.name.to_owned())
+                .collect(),
+            events: schema::events()
+                .into_iter()
+                .map(|event| event.name.to_owned())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_understand_older_minor_versions_only() {
+        let client = ApiVersion { major: 1, minor: 2 };
+        assert!(client.is_compatible_with(&ApiVersion { major: 1, minor: 2 }));
+        assert!(client.is_compatible_with(&ApiVersion { major: 1, minor: 3 }));
+        assert!(!client.is_compatible_with(&ApiVersion { major: 1, minor: 1 }));
+        assert!(!client.is_compatible_with(&ApiVersion { major: 2, minor: 2 }));
+    }
+}
@@ -0,0 +1,1488 @@
+//! Generates a JSON Schema document describing the parameters and results of every method, and
+//! the notifications of every event, of the management interface.
+
+use serde_json::{Map, Value};
+
+use super::API_VERSION;
+
+/// A method of the management interface.
+pub struct Method {
+    pub name: &'static str,
+    pub params: Vec<Value>,
+    pub result: Value,
+}
+
+/// An event clients can subscribe to, and the value it notifies subscribers with.
+pub struct Event {
+    pub name: &'static str,
+    pub notification: Value,
+}
+
+/// Returns the schema of the management interface. The document has the version of the
+/// interface, the methods with their positional parameters and result, the events with the
+/// value of their notifications, and JSON Schema definitions of all types referred to by them.
+pub fn schema() -> Value {
+    let methods: Map<String, Value> = methods()
+        .into_iter()
+        .map(|method| {
+            (
+                method.name.to_owned(),
+                json!({ "params": method.params, "result": method.result }),
+            )
+        }).collect();
+    let events: Map<String, Value> = events()
+        .into_iter()
+        .map(|event| (event.name.to_owned(), event.notification))
+        .collect();
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Mullvad daemon management interface",
+        "version": API_VERSION,
+        "methods": methods,
+        "events": events,
+        "definitions": definitions(),
+    })
+}
+
+pub fn methods() -> Vec<Method> {
+    vec![
+        method("get_api_info", vec![], reference("ApiInfo")),
+        method("get_account_data", vec![string()], reference("AccountData")),
+        method(
+            "get_account_expiry",
+            vec![],
+            nullable(reference("AccountExpiry")),
+        ),
+        method("get_relay_locations", vec![], reference("RelayList")),
+        method(
+            "get_matching_relays",
+            vec![reference("RelayConstraints"), boolean()],
+            reference("MatchingRelays"),
+        ),
+        method("update_relay_list", vec![], null_type()),
+        method("get_user_relays", vec![], reference("RelayList")),
+        method("set_user_relays", vec![reference("RelayList")], null_type()),
+        method("create_account", vec![], string()),
+        method(
+            "submit_voucher",
+            vec![string()],
+            reference("VoucherSubmission"),
+        ),
+        method("set_account", vec![nullable(string())], null_type()),
+        method(
+            "update_relay_settings",
+            vec![reference("RelaySettingsUpdate")],
+            null_type(),
+        ),
+        method(
+            "set_relay_ip_version",
+            vec![reference("IpVersionPreference")],
+            null_type(),
+        ),
+        method(
+            "set_rotate_relay_interval",
+            vec![nullable(uint64())],
+            null_type(),
+        ),
+        method("set_allow_lan", vec![boolean()], null_type()),
+        method("set_auto_connect", vec![boolean()], null_type()),
+        method("connect", vec![], null_type()),
+        method("disconnect", vec![], null_type()),
+        method("get_state", vec![], reference("TunnelStateTransition")),
+        method("get_current_location", vec![], reference("GeoIpLocation")),
+        method("shutdown", vec![], null_type()),
+        method("get_account_history", vec![], array(string())),
+        method(
+            "list_accounts",
+            vec![],
+            array(reference("AccountHistoryEntry")),
+        ),
+        method(
+            "set_account_label",
+            vec![string(), nullable(string())],
+            null_type(),
+        ),
+        method("set_account_history_limit", vec![uint64()], null_type()),
+        method("remove_account_from_history", vec![string()], null_type()),
+        method("set_openvpn_mssfix", vec![nullable(uint16())], null_type()),
+        method("set_enable_ipv6", vec![boolean()], null_type()),
+        method("get_settings", vec![], reference("Settings")),
+        method("export_settings", vec![boolean()], reference("Settings")),
+        method(
+            "import_settings",
+            vec![reference("SettingsPatch")],
+            null_type(),
+        ),
+        method(
+            "update_settings",
+            vec![reference("SettingsPatch")],
+            null_type(),
+        ),
+        method("get_profiles", vec![], array(string())),
+        method("save_profile", vec![string()], null_type()),
+        method("remove_profile", vec![string()], null_type()),
+        method("switch_profile", vec![string()], null_type()),
+        method(
+            "set_remote_management",
+            vec![reference("RemoteManagementSettings")],
+            null_type(),
+        ),
+        method("get_remote_management_token", vec![], string()),
+        method("reset_remote_management_token", vec![], string()),
+        method("get_current_version", vec![], string()),
+        method("get_version_info", vec![], reference("AppVersionInfo")),
+    ]
+}
+
+pub fn events() -> Vec<Event> {
+    vec![
+        event("new_state", reference("TunnelStateTransition")),
+        event("settings", reference("Settings")),
+        event("relay_list_updated", reference("RelayList")),
+        event("relay_invalidated", reference("RelayInvalidated")),
+        event("account_expiry", reference("AccountExpiry")),
+        event("events", reference("DaemonEvent")),
+    ]
+}
+
+fn method(name: &'static str, params: Vec<Value>, result: Value) -> Method {
+    Method {
+        name,
+        params,
+        result,
+    }
+}
+
+fn event(name: &'static str, notification: Value) -> Event {
+    Event { name, notification }
+}
+
+fn definitions() -> Map<String, Value> {
+    let definitions = vec![
+        (
+            "ApiInfo",
+            object(
+                &[
+                    ("version", reference("ApiVersion")),
+                    ("methods", array(string())),
+                    ("events", array(string())),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "ApiVersion",
+            object(&[("major", uint32()), ("minor", uint32())], &[]),
+        ),
+        ("AccountData", object(&[("expiry", date_time())], &[])),
+        (
+            "AccountExpiry",
+            object(
+                &[
+                    ("expiry", date_time()),
+                    ("warning_threshold", nullable(uint64())),
+                    ("expired", boolean()),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "AccountHistoryEntry",
+            object(
+                &[
+                    ("account_token", string()),
+                    ("label", nullable(string())),
+                    ("first_used", date_time()),
+                    ("last_used", date_time()),
+                    ("last_known_expiry", nullable(date_time())),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "VoucherSubmission",
+            object(
+                &[("time_added", uint64()), ("new_expiry", date_time())],
+                &[],
+            ),
+        ),
+        (
+            "TunnelStateTransition",
+            adjacently_tagged(
+                "state",
+                "details",
+                &[
+                    ("disconnected", None),
+                    ("connecting", None),
+                    ("connected", None),
+                    ("disconnecting", Some(reference("ActionAfterDisconnect"))),
+                    ("blocked", Some(reference("BlockReason"))),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "ActionAfterDisconnect",
+            string_enum(&["nothing", "block", "reconnect"]),
+        ),
+        (
+            "BlockReason",
+            adjacently_tagged(
+                "reason",
+                "details",
+                &[
+                    ("auth_failed", Some(nullable(string()))),
+                    ("ipv6_unavailable", None),
+                    ("set_security_policy_error", None),
+                    ("start_tunnel_error", None),
+                    ("no_matching_relay", None),
+                    ("account_expired", None),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "GeoIpLocation",
+            object(
+                &[
+                    ("ip", ip_address()),
+                    ("country", string()),
+                    ("city", nullable(string())),
+                    ("latitude", number()),
+                    ("longitude", number()),
+                    ("mullvad_exit_ip", boolean()),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "RelayList",
+            object(&[("countries", array(reference("RelayListCountry")))], &[]),
+        ),
+        (
+            "RelayListCountry",
+            object(
+                &[
+                    ("name", string()),
+                    ("code", string()),
+                    ("cities", array(reference("RelayListCity"))),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "RelayListCity",
+            object(
+                &[
+                    ("name", string()),
+                    ("code", string()),
+                    ("latitude", number()),
+                    ("longitude", number()),
+                    ("relays", array(reference("Relay"))),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "Relay",
+            object(
+                &[
+                    ("hostname", string()),
+                    ("ipv4_addr_in", ipv4_address()),
+                    ("ipv4_addr_exit", ipv4_address()),
+                    ("include_in_country", boolean()),
+                    ("weight", uint64()),
+                    ("user_defined", boolean()),
+                ],
+                &[
+                    ("ipv6_addr_in", ipv6_address()),
+                    ("tunnels", reference("RelayTunnels")),
+                ],
+            ),
+        ),
+        (
+            "RelayTunnels",
+            object(
+                &[
+                    ("openvpn", array(reference("OpenVpnEndpointData"))),
+                    ("wireguard", array(reference("WireguardEndpointData"))),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "MatchingRelays",
+            object(
+                &[
+                    ("relays", array(reference("Relay"))),
+                    ("rejected", array(reference("RejectedRelay"))),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "RejectedRelay",
+            object(
+                &[
+                    ("hostname", string()),
+                    ("reason", reference("RejectReason")),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "RejectReason",
+            string_enum(&["location", "not_included_in_country", "no_matching_tunnel"]),
+        ),
+        (
+            "RelayInvalidated",
+            object(
+                &[
+                    ("hostname", string()),
+                    ("reason", reference("RelayInvalidReason")),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "RelayInvalidReason",
+            string_enum(&[
+                "removed",
+                "endpoint_removed",
+                "no_longer_matches_constraints",
+            ]),
+        ),
+        (
+            "TunnelEndpointData",
+            externally_tagged(&[
+                ("openvpn", Some(reference("OpenVpnEndpointData"))),
+                ("wireguard", Some(reference("WireguardEndpointData"))),
+            ]),
+        ),
+        (
+            "OpenVpnEndpointData",
+            object(
+                &[
+                    ("port", uint16()),
+                    ("protocol", reference("TransportProtocol")),
+                ],
+                &[],
+            ),
+        ),
+        ("WireguardEndpointData", object(&[("port", uint16())], &[])),
+        ("TransportProtocol", string_enum(&["udp", "tcp"])),
+        (
+            "CustomTunnelEndpoint",
+            object(
+                &[
+                    ("host", string()),
+                    ("tunnel", reference("TunnelEndpointData")),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "RelaySettings",
+            externally_tagged(&[
+                (
+                    "custom_tunnel_endpoint",
+                    Some(reference("CustomTunnelEndpoint")),
+                ),
+                ("normal", Some(reference("RelayConstraints"))),
+            ]),
+        ),
+        (
+            "RelaySettingsUpdate",
+            externally_tagged(&[
+                (
+                    "custom_tunnel_endpoint",
+                    Some(reference("CustomTunnelEndpoint")),
+                ),
+                ("normal", Some(reference("RelayConstraintsUpdate"))),
+            ]),
+        ),
+        (
+            "RelayConstraints",
+            object(
+                &[
+                    ("location", reference("Constraint.LocationConstraint")),
+                    ("tunnel", reference("Constraint.TunnelConstraints")),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "RelayConstraintsUpdate",
+            object(
+                &[],
+                &[
+                    (
+                        "location",
+                        nullable(reference("Constraint.LocationConstraint")),
+                    ),
+                    (
+                        "tunnel",
+                        nullable(reference("Constraint.TunnelConstraints")),
+                    ),
+                ],
+            ),
+        ),
+        (
+            "Constraint.LocationConstraint",
+            constraint(
+                "Constraint.LocationConstraint",
+                reference("LocationConstraint"),
+            ),
+        ),
+        (
+            "Constraint.TunnelConstraints",
+            constraint(
+                "Constraint.TunnelConstraints",
+                reference("TunnelConstraints"),
+            ),
+        ),
+        (
+            "Constraint.PortRange",
+            constraint("Constraint.PortRange", reference("PortRange")),
+        ),
+        (
+            "Constraint.TransportProtocol",
+            constraint(
+                "Constraint.TransportProtocol",
+                reference("TransportProtocol"),
+            ),
+        ),
+        (
+            "LocationConstraint",
+            externally_tagged(&[
+                ("country", Some(string())),
+                ("city", Some(tuple(&[string(), string()]))),
+                ("hostname", Some(tuple(&[string(), string(), string()]))),
+            ]),
+        ),
+        (
+            "TunnelConstraints",
+            externally_tagged(&[
+                ("openvpn", Some(reference("OpenVpnConstraints"))),
+                ("wireguard", Some(reference("WireguardConstraints"))),
+            ]),
+        ),
+        (
+            "OpenVpnConstraints",
+            object(
+                &[
+                    ("port", reference("Constraint.PortRange")),
+                    ("protocol", reference("Constraint.TransportProtocol")),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "WireguardConstraints",
+            object(&[("port", reference("Constraint.PortRange"))], &[]),
+        ),
+        (
+            "PortRange",
+            json!({
+                "description": "A single port, or an inclusive range of ports",
+                "oneOf": [
+                    uint16(),
+                    object(&[("start", uint16()), ("end", uint16())], &[]),
+                ],
+            }),
+        ),
+        (
+            "IpVersionPreference",
+            string_enum(&["auto", "ipv4", "ipv6"]),
+        ),
+        (
+            "Settings",
+            object(
+                &[
+                    ("settings_version", uint32()),
+                    ("revision", uint64()),
+                    ("active_profile", nullable(string())),
+                    ("account_token", nullable(string())),
+                    ("relay_settings", reference("RelaySettings")),
+                    ("relay_ip_version", reference("IpVersionPreference")),
+                    ("rotate_relay_interval", nullable(uint64())),
+                    ("allow_lan", boolean()),
+                    ("auto_connect", boolean()),
+                    ("account_expiry_warnings", array(uint64())),
+                    ("account_history_limit", uint64()),
+                    ("tunnel_options", reference("TunnelOptions")),
+                    ("remote_management", reference("RemoteManagementSettings")),
+                    ("locked_keys", array(string())),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "SettingsPatch",
+            json!({
+                "description": "Any part of `Settings`, applied as a JSON merge patch",
+                "type": "object",
+            }),
+        ),
+        (
+            "TunnelOptions",
+            object(
+                &[
+                    ("openvpn", reference("OpenVpnTunnelOptions")),
+                    ("enable_ipv6", boolean()),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "OpenVpnTunnelOptions",
+            object(&[("mssfix", nullable(uint16()))], &[]),
+        ),
+        (
+            "RemoteManagementSettings",
+            object(&[("address", nullable(socket_address()))], &[]),
+        ),
+        (
+            "AppVersionInfo",
+            object(
+                &[
+                    ("current_is_supported", boolean()),
+                    ("latest", reference("LatestReleases")),
+                ],
+                &[],
+            ),
+        ),
+        (
+            "LatestReleases",
+            object(&[("latest_stable", string()), ("latest", string())], &[]),
+        ),
+        (
+            "DaemonEvent",
+            adjacently_tagged(
+                "type",
+                "data",
+                &[
+                    ("state_transition", Some(reference("TunnelStateTransition"))),
+                    ("settings", Some(reference("Settings"))),
+                    ("relay_list_updated", Some(reference("RelayList"))),
+                    ("relay_invalidated", Some(reference("RelayInvalidated"))),
+                    ("location_updated", Some(reference("GeoIpLocation"))),
+                    ("account_expiry", Some(reference("AccountExpiry"))),
+                    ("error", Some(string())),
+                    ("shutting_down", None),
+                ],
+                &[("sequence", uint64()), ("timestamp", date_time())],
+            ),
+        ),
+    ];
+    definitions
+        .into_iter()
+        .map(|(name, schema)| (name.to_owned(), schema))
+        .collect()
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/definitions/{}", name) })
+}
+
+fn nullable(schema: Value) -> Value {
+    json!({ "oneOf": [schema, null_type()] })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn tuple(items: &[Value]) -> Value {
+    json!({
+        "type": "array",
+        "items": items,
+        "minItems": items.len(),
+        "maxItems": items.len(),
+    })
+}
+
+fn null_type() -> Value {
+    json!({ "type": "null" })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn number() -> Value {
+    json!({ "type": "number" })
+}
+
+fn uint16() -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": 65535 })
+}
+
+fn uint32() -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": 4_294_967_295u32 })
+}
+
+fn uint64() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn date_time() -> Value {
+    json!({ "type": "string", "format": "date-time" })
+}
+
+fn ip_address() -> Value {
+    json!({ "description": "An IPv4 or IPv6 address", "type": "string" })
+}
+
+fn ipv4_address() -> Value {
+    json!({ "type": "string", "format": "ipv4" })
+}
+
+fn ipv6_address() -> Value {
+    json!({ "type": "string", "format": "ipv6" })
+}
+
+fn socket_address() -> Value {
+    json!({
+        "description": "An IP address and a port, such as 127.0.0.1:5051 or [::1]:5051",
+        "type": "string",
+    })
+}
+
+fn string_enum(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+/// Returns the schema of an object that always has the `required` properties, and might have the
+/// `optional` ones.
+fn object(required: &[(&str, Value)], optional: &[(&str, Value)]) -> Value {
+    let properties: Map<String, Value> = required
+        .iter()
+        .chain(optional)
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+    let required: Vec<&str> = required.iter().map(|(name, _)| *name).collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// Returns the schema of an enum serialized by serde as `"variant"` for variants without data
+/// and as `{ "variant": data }` for variants with data.
+fn externally_tagged(variants: &[(&str, Option<Value>)]) -> Value {
+    let variants: Vec<Value> = variants
+        .iter()
+        .map(|(name, data)| match data {
+            None => string_enum(&[*name]),
+            Some(data) => object(&[(name, data.clone())], &[]),
+        }).collect();
+    json!({ "oneOf": variants })
+}
+
+/// Returns the schema of an enum serialized by serde as `{ tag: "variant", content: data }`,
+/// with the `common` properties next to the tag.
+fn adjacently_tagged(
+    tag: &str,
+    content: &str,
+    variants: &[(&str, Option<Value>)],
+    common: &[(&str, Value)],
+) -> Value {
+    let variants: Vec<Value> = variants
+        .iter()
+        .map(|(name, data)| {
+            let mut properties = common.to_vec();
+            properties.push((tag, string_enum(&[*name])));
+            if let Some(data) = data {
+                properties.push((content, data.clone()));
+            }
+            object(&properties, &[])
+        }).collect();
+    json!({ "oneOf": variants })
+}
+
+/// Returns the schema of a `Constraint` on values matching `inner`. `name` is the name of the
+/// definition it's stored under, since `not` refers back to it.
+fn constraint(name: &str, inner: Value) -> Value {
+    externally_tagged(&[
+        ("any", None),
+        ("only", Some(inner.clone())),
+        ("one_of", Some(array(inner))),
+        ("not", Some(reference(name))),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use account::{AccountData, AccountExpiry, AccountHistoryEntry, VoucherSubmission};
+    use api::{ApiInfo, API_VERSION};
+    use chrono::{Duration, TimeZone, Utc};
+    use events::{DaemonEvent, DaemonEventKind};
+    use location::GeoIpLocation;
+    use relay_constraints::{
+        Constraint, IpVersionPreference, LocationConstraint, OpenVpnConstraints, PortRange,
+        RelayConstraints, RelayConstraintsUpdate, RelaySettings, RelaySettingsUpdate,
+        TunnelConstraints, WireguardConstraints,
+    };
+    use relay_list::{
+        MatchingRelays, RejectReason, RejectedRelay, Relay, RelayInvalidReason, RelayInvalidated,
+        RelayList, RelayListCity, RelayListCountry, RelayTunnels,
+    };
+    use serde::Serialize;
+    use serde_json;
+    use settings::{merge_patch, RemoteManagementSettings, Settings};
+    use talpid_types::net::{
+        OpenVpnEndpointData, TransportProtocol, TunnelEndpointData, WireguardEndpointData,
+    };
+    use talpid_types::tunnel::{ActionAfterDisconnect, BlockReason, TunnelStateTransition};
+    use version::{AppVersionInfo, LatestReleases};
+    use CustomTunnelEndpoint;
+
+    use std::collections::HashSet;
+    use std::env;
+    use std::fmt;
+    use std::fs;
+    use std::path::Path;
+
+    /// Where the schema of the current API version is published.
+    const PUBLISHED_SCHEMA: &str = "api-schema.json";
+
+    #[test]
+    fn schema_changes_bump_the_api_version() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(PUBLISHED_SCHEMA);
+        let generated = schema();
+        let published: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap_or_default())
+            .unwrap_or(Value::Null);
+        if generated == published {
+            return;
+        }
+
+        let version_bumped = published["version"] != generated["version"];
+        if version_bumped && env::var_os("UPDATE_API_SCHEMA").is_some() {
+            let contents = serde_json::to_string_pretty(&generated).unwrap() + "\n";
+            fs::write(&path, contents).unwrap();
+            return;
+        }
+        assert!(
+            version_bumped,
+            "The API schema changed, but API_VERSION is still {}. Bump API_VERSION, then run this \
+             test with UPDATE_API_SCHEMA=1 to update {}",
+            API_VERSION,
+            path.display()
+        );
+        panic!(
+            "API_VERSION was bumped to {}. Run this test with UPDATE_API_SCHEMA=1 to update {}",
+            API_VERSION,
+            path.display()
+        );
+    }
+
+    #[test]
+    fn schema_matches_serialized_types() {
+        let schema = schema();
+        for (definition, sample) in samples() {
+            let result = validate(
+                &schema,
+                "",
+                &reference(definition),
+                &sample,
+                &mut HashSet::new(),
+            );
+            if let Err(error) = result {
+                panic!(
+                    "{} does not match its schema: {}\n{}",
+                    definition, error, sample
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_part_of_the_schema_is_sampled() {
+        let schema = schema();
+        let mut sampled = HashSet::new();
+        for (definition, sample) in samples() {
+            let _ = validate(&schema, "", &reference(definition), &sample, &mut sampled);
+        }
+
+        let mut parts = HashSet::new();
+        for (name, definition) in schema["definitions"].as_object().unwrap() {
+            parts.insert(name.clone());
+            collect_parts(name, definition, &mut parts);
+        }
+        let mut unsampled: Vec<&String> = parts.difference(&sampled).collect();
+        unsampled.sort();
+        assert!(
+            unsampled.is_empty(),
+            "No sample has these definitions, alternatives, properties or values: {:?}",
+            unsampled
+        );
+    }
+
+    #[test]
+    fn schema_rejects_other_shapes() {
+        let schema = schema();
+        let connected_with_details = json!({ "state": "connected", "details": "nothing" });
+        let unknown_block_reason = json!({ "state": "blocked", "details": { "reason": "bug" } });
+        for value in &[connected_with_details, unknown_block_reason] {
+            let result = validate(
+                &schema,
+                "",
+                &reference("TunnelStateTransition"),
+                value,
+                &mut HashSet::new(),
+            );
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn methods_and_events_refer_to_defined_types() {
+        let schema = schema();
+        let definitions = schema["definitions"].as_object().unwrap();
+        let text = schema.to_string();
+        for reference in text.split("\"#/definitions/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(definitions.contains_key(name), "{} is not defined", name);
+        }
+    }
+
+    /// Returns serialized values of the types in the schema, named by their definitions. Enums
+    /// are sampled by every variant, and optional fields both with and without a value.
+    fn samples() -> Vec<(&'static str, Value)> {
+        let time = Utc.ymd(2018, 10, 1).and_hms(12, 0, 0);
+        let relay = Relay {
+            hostname: "se1".to_owned(),
+            ipv4_addr_in: "10.0.0.1".parse().unwrap(),
+            ipv6_addr_in: Some("fd00::1".parse().unwrap()),
+            ipv4_addr_exit: "10.0.0.2".parse().unwrap(),
+            include_in_country: true,
+            weight: 100,
+            tunnels: RelayTunnels {
+                openvpn: vec![OpenVpnEndpointData {
+                    port: 1194,
+                    protocol: TransportProtocol::Udp,
+                }],
+                wireguard: vec![WireguardEndpointData { port: 51820 }],
+            },
+            user_defined: false,
+            location: None,
+        };
+        let bare_relay = Relay {
+            ipv6_addr_in: None,
+            tunnels: RelayTunnels::default(),
+            user_defined: true,
+            ..relay.clone()
+        };
+        let relay_list = RelayList {
+            countries: vec![RelayListCountry {
+                name: "Sweden".to_owned(),
+                code: "se".to_owned(),
+                cities: vec![RelayListCity {
+                    name: "Gothenburg".to_owned(),
+                    code: "got".to_owned(),
+                    latitude: 57.7,
+                    longitude: 11.97,
+                    relays: vec![relay.clone(), bare_relay],
+                }],
+            }],
+        };
+        let constraints = RelayConstraints {
+            location: Constraint::Not(Box::new(Constraint::OneOf(vec![
+                LocationConstraint::Country("de".to_owned()),
+                LocationConstraint::City("se".to_owned(), "got".to_owned()),
+            ]))),
+            tunnel: Constraint::Only(TunnelConstraints::OpenVpn(OpenVpnConstraints {
+                port: Constraint::OneOf(vec![
+                    PortRange::single(1194),
+                    PortRange::new(1, 2).unwrap(),
+                ]),
+                protocol: Constraint::Only(TransportProtocol::Tcp),
+            })),
+        };
+        let custom_endpoint = CustomTunnelEndpoint {
+            host: "example.com".to_owned(),
+            tunnel: TunnelEndpointData::Wireguard(WireguardEndpointData { port: 51820 }),
+        };
+        let location = GeoIpLocation {
+            ip: "fd00::2".parse().unwrap(),
+            country: "Sweden".to_owned(),
+            city: None,
+            latitude: 57.7,
+            longitude: 11.97,
+            mullvad_exit_ip: true,
+        };
+        let account_expiry = AccountExpiry::new(time, time, &[60]);
+        let settings_patch = json!({
+            "revision": 3,
+            "relay_ip_version": "ipv4",
+            "rotate_relay_interval": 3600,
+            "allow_lan": true,
+            "tunnel_options": { "openvpn": { "mssfix": null } },
+        });
+        assert!(Settings::default().patched(&settings_patch).is_ok());
+        let mut settings = to_value(&Settings::default());
+        merge_patch(
+            &mut settings,
+            &json!({
+                "active_profile": "work",
+                "account_token": "1234",
+                "rotate_relay_interval": 3600,
+                "tunnel_options": { "openvpn": { "mssfix": 1400 } },
+                "remote_management": { "address": "127.0.0.1:5051" },
+            }),
+        );
+        let settings = Settings::from_json(settings).unwrap();
+
+        let mut samples = vec![
+            ("ApiInfo", to_value(&ApiInfo::current())),
+            ("AccountData", to_value(&AccountData { expiry: time })),
+            ("AccountExpiry", to_value(&account_expiry)),
+            (
+                "AccountExpiry",
+                to_value(&AccountExpiry::new(
+                    time + Duration::seconds(30),
+                    time,
+                    &[60],
+                )),
+            ),
+            (
+                "AccountHistoryEntry",
+                to_value(&AccountHistoryEntry::new("1234".to_owned(), time)),
+            ),
+            (
+                "AccountHistoryEntry",
+                to_value(&AccountHistoryEntry {
+                    label: Some("Support".to_owned()),
+                    last_known_expiry: Some(time),
+                    ..AccountHistoryEntry::new("1234".to_owned(), time)
+                }),
+            ),
+            (
+                "VoucherSubmission",
+                to_value(&VoucherSubmission {
+                    time_added: 60,
+                    new_expiry: time,
+                }),
+            ),
+            ("GeoIpLocation", to_value(&location)),
+            (
+                "GeoIpLocation",
+                to_value(&GeoIpLocation {
+                    city: Some("Gothenburg".to_owned()),
+                    ..location.clone()
+                }),
+            ),
+            ("RelayList", to_value(&relay_list)),
+            (
+                "MatchingRelays",
+                to_value(&MatchingRelays {
+                    relays: vec![relay],
+                    rejected: every_reject_reason()
+                        .into_iter()
+                        .map(|reason| RejectedRelay {
+                            hostname: "se2".to_owned(),
+                            reason,
+                        }).collect(),
+                }),
+            ),
+            ("RelayConstraints", to_value(&constraints)),
+            (
+                "RelaySettings",
+                to_value(&RelaySettings::CustomTunnelEndpoint(
+                    custom_endpoint.clone(),
+                )),
+            ),
+            (
+                "RelaySettings",
+                to_value(&RelaySettings::Normal(constraints)),
+            ),
+            (
+                "RelaySettingsUpdate",
+                to_value(&RelaySettingsUpdate::CustomTunnelEndpoint(custom_endpoint)),
+            ),
+            (
+                "RelaySettingsUpdate",
+                to_value(&RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                    location: Some(Constraint::Any),
+                    tunnel: None,
+                })),
+            ),
+            (
+                "RelaySettingsUpdate",
+                to_value(&RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                    location: None,
+                    tunnel: Some(Constraint::Any),
+                })),
+            ),
+            ("Settings", to_value(&Settings::default())),
+            ("Settings", to_value(&settings)),
+            ("SettingsPatch", settings_patch),
+            (
+                "RemoteManagementSettings",
+                to_value(&RemoteManagementSettings::default()),
+            ),
+            (
+                "RemoteManagementSettings",
+                to_value(&settings.get_remote_management()),
+            ),
+            (
+                "AppVersionInfo",
+                to_value(&AppVersionInfo {
+                    current_is_supported: true,
+                    latest: LatestReleases {
+                        latest_stable: "2018.4".to_owned(),
+                        latest: "2018.5-beta1".to_owned(),
+                    },
+                }),
+            ),
+        ];
+
+        samples.extend(sample_all("TunnelStateTransition", every_tunnel_state()));
+        samples.extend(sample_all(
+            "RelayInvalidated",
+            every_relay_invalid_reason()
+                .into_iter()
+                .map(|reason| RelayInvalidated {
+                    hostname: "se1".to_owned(),
+                    reason,
+                }),
+        ));
+        samples.extend(sample_all("IpVersionPreference", every_ip_version()));
+        samples.extend(sample_all("TunnelEndpointData", every_tunnel_endpoint()));
+        samples.extend(sample_all(
+            "LocationConstraint",
+            every_location_constraint(),
+        ));
+        samples.extend(sample_all("TunnelConstraints", every_tunnel_constraint()));
+        samples.extend(sample_all(
+            "Constraint.LocationConstraint",
+            every_constraint(
+                LocationConstraint::Country("se".to_owned()),
+                LocationConstraint::Country("no".to_owned()),
+            ),
+        ));
+        samples.extend(sample_all(
+            "Constraint.TunnelConstraints",
+            every_constraint(
+                TunnelConstraints::OpenVpn(OpenVpnConstraints::default()),
+                TunnelConstraints::Wireguard(WireguardConstraints::default()),
+            ),
+        ));
+        samples.extend(sample_all(
+            "Constraint.PortRange",
+            every_constraint(PortRange::single(1194), PortRange::new(1, 2).unwrap()),
+        ));
+        samples.extend(sample_all(
+            "Constraint.TransportProtocol",
+            every_constraint(TransportProtocol::Udp, TransportProtocol::Tcp),
+        ));
+
+        let events = every_event_kind(relay_list, settings, location, account_expiry)
+            .into_iter()
+            .enumerate()
+            .map(|(sequence, kind)| DaemonEvent {
+                sequence: sequence as u64,
+                timestamp: time,
+                kind,
+            });
+        samples.extend(sample_all("DaemonEvent", events));
+        samples
+    }
+
+    fn sample_all<T, I>(definition: &'static str, values: I) -> Vec<(&'static str, Value)>
+    where
+        T: Serialize,
+        I: IntoIterator<Item = T>,
+    {
+        values
+            .into_iter()
+            .map(|value| (definition, to_value(&value)))
+            .collect()
+    }
+
+    // The functions below return every variant of an enum. Each matches on the variants it
+    // returns, so adding a variant fails to compile until it's sampled too.
+
+    fn every_tunnel_state() -> Vec<TunnelStateTransition> {
+        let mut states = vec![
+            TunnelStateTransition::Disconnected,
+            TunnelStateTransition::Connecting,
+            TunnelStateTransition::Connected,
+        ];
+        states.extend(
+            every_action_after_disconnect()
+                .into_iter()
+                .map(TunnelStateTransition::Disconnecting),
+        );
+        states.extend(
+            every_block_reason()
+                .into_iter()
+                .map(TunnelStateTransition::Blocked),
+        );
+        for state in &states {
+            match *state {
+                TunnelStateTransition::Disconnected
+                | TunnelStateTransition::Connecting
+                | TunnelStateTransition::Connected
+                | TunnelStateTransition::Disconnecting(_)
+                | TunnelStateTransition::Blocked(_) => (),
+            }
+        }
+        states
+    }
+
+    fn every_action_after_disconnect() -> Vec<ActionAfterDisconnect> {
+        let actions = vec![
+            ActionAfterDisconnect::Nothing,
+            ActionAfterDisconnect::Block,
+            ActionAfterDisconnect::Reconnect,
+        ];
+        for action in &actions {
+            match *action {
+                ActionAfterDisconnect::Nothing
+                | ActionAfterDisconnect::Block
+                | ActionAfterDisconnect::Reconnect => (),
+            }
+        }
+        actions
+    }
+
+    fn every_block_reason() -> Vec<BlockReason> {
+        let reasons = vec![
+            BlockReason::AuthFailed(None),
+            BlockReason::AuthFailed(Some("expired".to_owned())),
+            BlockReason::Ipv6Unavailable,
+            BlockReason::SetSecurityPolicyError,
+            BlockReason::StartTunnelError,
+            BlockReason::NoMatchingRelay,
+            BlockReason::AccountExpired,
+        ];
+        for reason in &reasons {
+            match *reason {
+                BlockReason::AuthFailed(_)
+                | BlockReason::Ipv6Unavailable
+                | BlockReason::SetSecurityPolicyError
+                | BlockReason::StartTunnelError
+                | BlockReason::NoMatchingRelay
+                | BlockReason::AccountExpired => (),
+            }
+        }
+        reasons
+    }
+
+    fn every_reject_reason() -> Vec<RejectReason> {
+        let reasons = vec![
+            RejectReason::Location,
+            RejectReason::NotIncludedInCountry,
+            RejectReason::NoMatchingTunnel,
+        ];
+        for reason in &reasons {
+            match *reason {
+                RejectReason::Location
+                | RejectReason::NotIncludedInCountry
+                | RejectReason::NoMatchingTunnel => (),
+            }
+        }
+        reasons
+    }
+
+    fn every_relay_invalid_reason() -> Vec<RelayInvalidReason> {
+        let reasons = vec![
+            RelayInvalidReason::Removed,
+            RelayInvalidReason::EndpointRemoved,
+            RelayInvalidReason::NoLongerMatchesConstraints,
+        ];
+        for reason in &reasons {
+            match *reason {
+                RelayInvalidReason::Removed
+                | RelayInvalidReason::EndpointRemoved
+                | RelayInvalidReason::NoLongerMatchesConstraints => (),
+            }
+        }
+        reasons
+    }
+
+    fn every_ip_version() -> Vec<IpVersionPreference> {
+        let versions = vec![
+            IpVersionPreference::Auto,
+            IpVersionPreference::Ipv4,
+            IpVersionPreference::Ipv6,
+        ];
+        for version in &versions {
+            match *version {
+                IpVersionPreference::Auto
+                | IpVersionPreference::Ipv4
+                | IpVersionPreference::Ipv6 => (),
+            }
+        }
+        versions
+    }
+
+    fn every_tunnel_endpoint() -> Vec<TunnelEndpointData> {
+        let endpoints = vec![
+            TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                port: 1194,
+                protocol: TransportProtocol::Udp,
+            }),
+            TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                port: 443,
+                protocol: TransportProtocol::Tcp,
+            }),
+            TunnelEndpointData::Wireguard(WireguardEndpointData { port: 51820 }),
+        ];
+        for endpoint in &endpoints {
+            match *endpoint {
+                TunnelEndpointData::OpenVpn(_) | TunnelEndpointData::Wireguard(_) => (),
+            }
+        }
+        endpoints
+    }
+
+    fn every_location_constraint() -> Vec<LocationConstraint> {
+        let locations = vec![
+            LocationConstraint::Country("se".to_owned()),
+            LocationConstraint::City("se".to_owned(), "got".to_owned()),
+            LocationConstraint::Hostname("se".to_owned(), "got".to_owned(), "se1".to_owned()),
+        ];
+        for location in &locations {
+            match *location {
+                LocationConstraint::Country(..)
+                | LocationConstraint::City(..)
+                | LocationConstraint::Hostname(..) => (),
+            }
+        }
+        locations
+    }
+
+    fn every_tunnel_constraint() -> Vec<TunnelConstraints> {
+        let tunnels = vec![
+            TunnelConstraints::OpenVpn(OpenVpnConstraints::default()),
+            TunnelConstraints::Wireguard(WireguardConstraints::default()),
+        ];
+        for tunnel in &tunnels {
+            match *tunnel {
+                TunnelConstraints::OpenVpn(_) | TunnelConstraints::Wireguard(_) => (),
+            }
+        }
+        tunnels
+    }
+
+    fn every_constraint<T>(value: T, other: T) -> Vec<Constraint<T>>
+    where
+        T: fmt::Debug + Clone + Eq + PartialEq,
+    {
+        let constraints = vec![
+            Constraint::Any,
+            Constraint::Only(value.clone()),
+            Constraint::OneOf(vec![value, other]),
+            Constraint::Not(Box::new(Constraint::Any)),
+        ];
+        for constraint in &constraints {
+            match *constraint {
+                Constraint::Any
+                | Constraint::Only(_)
+                | Constraint::OneOf(_)
+                | Constraint::Not(_) => (),
+            }
+        }
+        constraints
+    }
+
+    fn every_event_kind(
+        relay_list: RelayList,
+        settings: Settings,
+        location: GeoIpLocation,
+        account_expiry: AccountExpiry,
+    ) -> Vec<DaemonEventKind> {
+        let kinds = vec![
+            DaemonEventKind::StateTransition(TunnelStateTransition::Connected),
+            DaemonEventKind::Settings(settings),
+            DaemonEventKind::RelayListUpdated(relay_list),
+            DaemonEventKind::RelayInvalidated(RelayInvalidated {
+                hostname: "se1".to_owned(),
+                reason: RelayInvalidReason::Removed,
+            }),
+            DaemonEventKind::LocationUpdated(location),
+            DaemonEventKind::AccountExpiry(account_expiry),
+            DaemonEventKind::Error("Unable to save settings".to_owned()),
+            DaemonEventKind::ShuttingDown,
+        ];
+        for kind in &kinds {
+            match *kind {
+                DaemonEventKind::StateTransition(_)
+                | DaemonEventKind::Settings(_)
+                | DaemonEventKind::RelayListUpdated(_)
+                | DaemonEventKind::RelayInvalidated(_)
+                | DaemonEventKind::LocationUpdated(_)
+                | DaemonEventKind::AccountExpiry(_)
+                | DaemonEventKind::Error(_)
+                | DaemonEventKind::ShuttingDown => (),
+            }
+        }
+        kinds
+    }
+
+    fn to_value<T: Serialize>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    /// Adds the alternatives, properties and enum values within `schema` to `parts`, named the
+    /// way `validate` names the parts a value matches. Referred definitions are not followed.
+    fn collect_parts(path: &str, schema: &Value, parts: &mut HashSet<String>) {
+        if schema.get("$ref").is_some() {
+            return;
+        }
+        if let Some(variants) = schema["oneOf"].as_array() {
+            for (index, variant) in variants.iter().enumerate() {
+                let variant_path = format!("{}/{}", path, index);
+                collect_parts(&variant_path, variant, parts);
+                parts.insert(variant_path);
+            }
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            parts.extend(values.iter().map(|value| format!("{}={}", path, value)));
+        }
+        if let Some(properties) = schema["properties"].as_object() {
+            for (name, property) in properties {
+                let property_path = format!("{}.{}", path, name);
+                collect_parts(&property_path, property, parts);
+                parts.insert(property_path);
+            }
+        }
+        match schema["items"] {
+            Value::Array(ref item_schemas) => {
+                for (index, item_schema) in item_schemas.iter().enumerate() {
+                    collect_parts(&format!("{}[{}]", path, index), item_schema, parts);
+                }
+            }
+            Value::Null => (),
+            ref item_schema => collect_parts(&format!("{}[]", path), item_schema, parts),
+        }
+    }
+
+    /// Checks `value` against `schema`, resolving references in `root`. The definitions,
+    /// alternatives, properties and enum values that `value` matches are added to `matched`,
+    /// named by their path from the definition they're in. Only supports the parts of JSON
+    /// Schema used by `super::schema`.
+    fn validate(
+        root: &Value,
+        path: &str,
+        schema: &Value,
+        value: &Value,
+        matched: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.rsplit('/').next().unwrap();
+            let definition = root["definitions"]
+                .get(name)
+                .ok_or_else(|| format!("undefined reference {}", reference))?;
+            matched.insert(name.to_owned());
+            return validate(root, name, definition, value, matched);
+        }
+        if let Some(variants) = schema["oneOf"].as_array() {
+            let mut matching = Vec::new();
+            for (index, variant) in variants.iter().enumerate() {
+                let variant_path = format!("{}/{}", path, index);
+                let mut variant_matched = HashSet::new();
+                if validate(root, &variant_path, variant, value, &mut variant_matched).is_ok() {
+                    variant_matched.insert(variant_path);
+                    matching.push(variant_matched);
+                }
+            }
+            if matching.len() != 1 {
+                return Err(format!("{} alternatives match {}", matching.len(), value));
+            }
+            matched.extend(matching.remove(0));
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            if !values.contains(value) {
+                return Err(format!("{} is not one of {:?}", value, values));
+            }
+            matched.insert(format!("{}={}", path, value));
+        }
+        if let Some(expected_type) = schema["type"].as_str() {
+            let matches = match expected_type {
+                "null" => value.is_null(),
+                "boolean" => value.is_boolean(),
+                "string" => value.is_string(),
+                "number" => value.is_number(),
+                "integer" => value.is_u64() || value.is_i64(),
+                "array" => value.is_array(),
+                "object" => value.is_object(),
+                _ => return Err(format!("unsupported type {}", expected_type)),
+            };
+            if !matches {
+                return Err(format!("{} is not of type {}", value, expected_type));
+            }
+        }
+        if let Some(minimum) = schema["minimum"].as_f64() {
+            if value.as_f64().map_or(false, |number| number < minimum) {
+                return Err(format!("{} is less than {}", value, minimum));
+            }
+        }
+        if let Some(maximum) = schema["maximum"].as_f64() {
+            if value.as_f64().map_or(false, |number| number > maximum) {
+                return Err(format!("{} is greater than {}", value, maximum));
+            }
+        }
+        if let Some(items) = value.as_array() {
+            match schema["items"] {
+                Value::Array(ref item_schemas) => {
+                    if items.len() != item_schemas.len() {
+                        return Err(format!(
+                            "{} does not have {} items",
+                            value,
+                            item_schemas.len()
+                        ));
+                    }
+                    for (index, item) in items.iter().enumerate() {
+                        let item_path = format!("{}[{}]", path, index);
+                        validate(root, &item_path, &item_schemas[index], item, matched)?;
+                    }
+                }
+                Value::Null => (),
+                ref item_schema => {
+                    let item_path = format!("{}[]", path);
+                    for item in items {
+                        validate(root, &item_path, item_schema, item, matched)?;
+                    }
+                }
+            }
+        }
+        if let Some(object) = value.as_object() {
+            let properties = schema["properties"].as_object();
+            if let Some(required) = schema["required"].as_array() {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        return Err(format!("{} is missing {}", value, name));
+                    }
+                }
+            }
+            for (name, property) in object {
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property_schema) => {
+                        let property_path = format!("{}.{}", path, name);
+                        validate(root, &property_path, property_schema, property, matched)
+                            .map_err(|error| format!("{}: {}", name, error))?;
+                        matched.insert(property_path);
+                    }
+                    None if schema["additionalProperties"] == Value::Bool(false) => {
+                        return Err(format!("unexpected property {}", name));
+                    }
+                    None => (),
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! Crash safe writing of files that must never be left half written, such as settings.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes `contents` to `path` so that `path` always holds either the complete old contents or
+/// the complete new contents, even if the process or the system crashes halfway through.
+///
+/// The contents are written and flushed to disk in a temporary file next to `path`, which is
+/// then renamed over `path`. Before that, the previous file is kept as a backup at
+/// `backup_path(path)`.
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_file(path, contents, false)
+}
+
+/// Like `write`, but on Unix the new file is only readable and writable by its owner. The
+/// permissions of the backup are left as they were.
+pub fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_file(path, contents, true)
+}
+
+fn write_file(path: &Path, contents: &[u8], private: bool) -> io::Result<()> {
+    let temp_path = path_with_suffix(path, ".tmp");
+    let result = write_and_sync(&temp_path, contents, private).and_then(|_| {
+        keep_backup(path);
+        fs::rename(&temp_path, path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result?;
+    sync_parent_dir(path)
+}
+
+/// Returns where `write` keeps the previous version of the file at `path`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    path_with_suffix(path, ".bak")
+}
+
+/// Moves the file at `path` to `<path>.corrupt`, to be used when its contents can't be read and
+/// the backup is used instead. Otherwise the next `write` would replace the good backup with the
+/// corrupt file. The backup is kept until a new file has been written in its place.
+pub fn set_aside_corrupt(path: &Path) -> io::Result<()> {
+    fs::rename(path, path_with_suffix(path, ".corrupt"))
+}
+
+fn write_and_sync(path: &Path, contents: &[u8], private: bool) -> io::Result<()> {
+    let mut file = create_file(path, private)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Creates or truncates the file at `path`. A private file is created with its permissions
+/// restricted to the owner, and a file left behind by an earlier write is restricted before
+/// anything is written to it.
+#[cfg(unix)]
+fn create_file(path: &Path, private: bool) -> io::Result<File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    if private {
+        options.mode(0o600);
+    }
+    let file = options.open(path)?;
+    if private {
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_file(path: &Path, _private: bool) -> io::Result<File> {
+    File::create(path)
+}
+
+/// Hard links the current file at `path` to its backup path. The rename in `write` only replaces
+/// the directory entry, so the link keeps pointing to the old contents. If there is no current
+/// file, the existing backup is left as is. Failing to create the backup does not stop the write.
+fn keep_backup(path: &Path) {
+    match fs::symlink_metadata(path) {
+        Ok(_) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("Unable to back up {}: {}", path.display(), e);
+            return;
+        }
+    }
+    let backup_path = backup_path(path);
+    match fs::remove_file(&backup_path) {
+        Ok(()) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => {
+            warn!("Unable to remove {}: {}", backup_path.display(), e);
+            return;
+        }
+    }
+    match fs::hard_link(path, &backup_path) {
+        Ok(()) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => warn!("Unable to back up {}: {}", path.display(), e),
+    }
+}
+
+/// Makes the rename durable by flushing the directory entry to disk. Directories can't be opened
+/// as files on Windows, where the rename is flushed by the file system itself.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path
+        .file_name()
+        .map(|file_name| file_name.to_os_string())
+        .unwrap_or_else(OsString::new);
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mullvad-atomic-file-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn writes_new_file_without_backup() {
+        let dir = test_dir("new");
+        let path = dir.join("settings.json");
+
+        write(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!backup_path(&path).exists());
+        assert!(!path_with_suffix(&path, ".tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_previous_contents_as_backup() {
+        let dir = test_dir("backup");
+        let path = dir.join("settings.json");
+
+        write(&path, b"first").unwrap();
+        write(&path, b"second").unwrap();
+        write(&path, b"third").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"third");
+        assert_eq!(fs::read(backup_path(&path)).unwrap(), b"second");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_backup_when_corrupt_file_is_set_aside() {
+        let dir = test_dir("corrupt");
+        let path = dir.join("settings.json");
+        write(&path, b"good").unwrap();
+        write(&path, b"corrupt").unwrap();
+
+        set_aside_corrupt(&path).unwrap();
+        write(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read(backup_path(&path)).unwrap(), b"good");
+        assert_eq!(
+            fs::read(path_with_suffix(&path, ".corrupt")).unwrap(),
+            b"corrupt"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use account::AccountExpiry;
+use chrono::{DateTime, Utc};
+use location::GeoIpLocation;
+use relay_list::{RelayInvalidated, RelayList};
+use settings::Settings;
+use talpid_types::tunnel::TunnelStateTransition;
+
+/// An event sent to subscribers of `events`. The events are numbered in the order they happened,
+/// so subscribers can tell if they missed any.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonEvent {
+    /// Increased by one for every event sent by the daemon since it started.
+    pub sequence: u64,
+    /// When the event happened.
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: DaemonEventKind,
+}
+
+/// What happened, as `{ "type": ..., "data": ... }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum DaemonEventKind {
+    /// The tunnel changed state.
+    StateTransition(TunnelStateTransition),
+    /// The settings changed.
+    Settings(Settings),
+    /// A new relay list was downloaded, or the relays added by the user changed.
+    RelayListUpdated(RelayList),
+    /// The relay in use can't be used anymore, and the daemon reconnects to another one.
+    RelayInvalidated(RelayInvalidated),
+    /// The location as perceived by the public internet changed.
+    LocationUpdated(GeoIpLocation),
+    /// The expiry of the current account was fetched, changed, crossed a warning threshold or
+    /// passed.
+    AccountExpiry(AccountExpiry),
+    /// Something went wrong in the daemon outside of any call made by a client.
+    Error(String),
+    /// The daemon is shutting down.
+    ShuttingDown,
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn serializes_sequence_and_timestamp_next_to_tagged_event() {
+        let event = DaemonEvent {
+            sequence: 7,
+            timestamp: Utc.ymd(2018, 10, 1).and_hms(12, 0, 0),
+            kind: DaemonEventKind::StateTransition(TunnelStateTransition::Connected),
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        let expected: serde_json::Value = serde_json::from_str(
+            r#"{
+                "sequence": 7,
+                "timestamp": "2018-10-01T12:00:00Z",
+                "type": "state_transition",
+                "data": { "state": "connected" }
+            }"#,
+        ).unwrap();
+        assert_eq!(json, expected);
+
+        let event: DaemonEvent = serde_json::from_value(json).unwrap();
+        assert_eq!(event.sequence, 7);
+        match event.kind {
+            DaemonEventKind::StateTransition(TunnelStateTransition::Connected) => (),
+            kind => panic!("Unexpected event: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn serializes_events_without_data() {
+        let event = DaemonEvent {
+            sequence: 1,
+            timestamp: Utc.ymd(2018, 10, 1).and_hms(12, 0, 0),
+            kind: DaemonEventKind::ShuttingDown,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "shutting_down");
+        assert!(json.get("data").is_none());
+    }
+}
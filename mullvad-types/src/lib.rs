@@ -10,6 +10,8 @@ extern crate chrono;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 
 extern crate mullvad_paths;
 extern crate talpid_types;
@@ -21,9 +23,13 @@ extern crate log;
 extern crate error_chain;
 
 pub mod account;
+pub mod api;
+pub mod atomic_file;
+pub mod events;
 pub mod location;
 pub mod relay_constraints;
 pub mod relay_list;
+pub mod secret_file;
 pub mod settings;
 pub mod states;
 pub mod version;
@@ -14,7 +14,7 @@ pub struct Location {
     pub longitude: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GeoIpLocation {
     pub ip: IpAddr,
     pub country: String,
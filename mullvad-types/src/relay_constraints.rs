@@ -1,7 +1,11 @@
 use location::{CityCode, CountryCode, Hostname};
+use relay_list::Relay;
 use CustomTunnelEndpoint;
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 use std::fmt;
+use std::str::FromStr;
 
 use talpid_types::net::{OpenVpnEndpointData, TransportProtocol, WireguardEndpointData};
 
@@ -13,15 +17,61 @@ pub trait Match<T> {
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Constraint<T: fmt::Debug + Clone + Eq + PartialEq> {
+    /// Matches everything.
     Any,
+    /// Matches what the single value matches.
     Only(T),
+    /// Matches what any of the values match. An empty set matches nothing.
+    OneOf(Vec<T>),
+    /// Matches everything the inner constraint does not match.
+    Not(Box<Constraint<T>>),
 }
 
 impl<T: fmt::Debug + Clone + Eq + PartialEq> Constraint<T> {
-    pub fn unwrap_or(self, other: T) -> T {
+    pub fn is_any(&self) -> bool {
+        match *self {
+            Constraint::Any => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the simplest constraint matching the same values. Sets with a single value are
+    /// turned into `Only`, and double negations are removed.
+    pub fn normalize(self) -> Self {
         match self {
-            Constraint::Any => other,
-            Constraint::Only(value) => value,
+            Constraint::OneOf(mut values) => {
+                if values.len() == 1 {
+                    Constraint::Only(values.remove(0))
+                } else {
+                    Constraint::OneOf(values)
+                }
+            }
+            Constraint::Not(constraint) => match constraint.normalize() {
+                Constraint::Not(inner_constraint) => *inner_constraint,
+                constraint => Constraint::Not(Box::new(constraint)),
+            },
+            constraint => constraint,
+        }
+    }
+
+    /// Applies `f` to every value in the constraint.
+    pub fn map<U, F>(self, f: F) -> Constraint<U>
+    where
+        U: fmt::Debug + Clone + Eq + PartialEq,
+        F: Fn(T) -> U,
+    {
+        self.map_with(&f)
+    }
+
+    fn map_with<U>(self, f: &Fn(T) -> U) -> Constraint<U>
+    where
+        U: fmt::Debug + Clone + Eq + PartialEq,
+    {
+        match self {
+            Constraint::Any => Constraint::Any,
+            Constraint::Only(value) => Constraint::Only(f(value)),
+            Constraint::OneOf(values) => Constraint::OneOf(values.into_iter().map(f).collect()),
+            Constraint::Not(constraint) => Constraint::Not(Box::new(constraint.map_with(f))),
         }
     }
 }
@@ -32,13 +82,125 @@ impl<T: fmt::Debug + Clone + Eq + PartialEq> Default for Constraint<T> {
     }
 }
 
-impl<T: Copy + fmt::Debug + Clone + Eq + PartialEq> Copy for Constraint<T> {}
-
-impl<T: fmt::Debug + Clone + Eq + PartialEq> Match<T> for Constraint<T> {
-    fn matches(&self, other: &T) -> bool {
+impl<T, U> Match<U> for Constraint<T>
+where
+    T: Match<U> + fmt::Debug + Clone + Eq + PartialEq,
+{
+    fn matches(&self, other: &U) -> bool {
         match *self {
             Constraint::Any => true,
-            Constraint::Only(ref value) => value == other,
+            Constraint::Only(ref value) => value.matches(other),
+            Constraint::OneOf(ref values) => values.iter().any(|value| value.matches(other)),
+            Constraint::Not(ref constraint) => !constraint.matches(other),
+        }
+    }
+}
+
+impl Match<TransportProtocol> for TransportProtocol {
+    fn matches(&self, protocol: &TransportProtocol) -> bool {
+        self == protocol
+    }
+}
+
+
+/// An inclusive range of ports. A range with a single port is serialized as just the port
+/// number, which is how ports were stored before ranges were supported.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    /// Returns the range from `start` to `end`, or `None` if `start` is greater than `end`.
+    pub fn new(start: u16, end: u16) -> Option<Self> {
+        if start <= end {
+            Some(PortRange { start, end })
+        } else {
+            None
+        }
+    }
+
+    pub fn single(port: u16) -> Self {
+        PortRange {
+            start: port,
+            end: port,
+        }
+    }
+}
+
+impl From<u16> for PortRange {
+    fn from(port: u16) -> Self {
+        PortRange::single(port)
+    }
+}
+
+impl Match<u16> for PortRange {
+    fn matches(&self, port: &u16) -> bool {
+        self.start <= *port && *port <= self.end
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    /// Parses a single port, such as "1194", or a range, such as "1194-1197".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_port = |port: &str| {
+            u16::from_str(port.trim()).map_err(|_| format!("Invalid port: {}", port.trim()))
+        };
+        match s.find('-') {
+            Some(index) => {
+                let start = parse_port(&s[..index])?;
+                let end = parse_port(&s[index + 1..])?;
+                PortRange::new(start, end).ok_or_else(|| format!("Invalid port range: {}", s))
+            }
+            None => parse_port(s).map(PortRange::single),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum PortRangeRepr {
+    Single(u16),
+    Range { start: u16, end: u16 },
+}
+
+impl Serialize for PortRange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = if self.start == self.end {
+            PortRangeRepr::Single(self.start)
+        } else {
+            PortRangeRepr::Range {
+                start: self.start,
+                end: self.end,
+            }
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PortRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match PortRangeRepr::deserialize(deserializer)? {
+            PortRangeRepr::Single(port) => Ok(PortRange::single(port)),
+            PortRangeRepr::Range { start, end } => PortRange::new(start, end).ok_or_else(|| {
+                <D::Error as ::serde::de::Error>::custom(format!(
+                    "port range start {} is greater than end {}",
+                    start, end
+                ))
+            }),
         }
     }
 }
@@ -80,10 +242,49 @@ pub struct RelayConstraints {
 }
 
 impl RelayConstraints {
+    /// Returns these constraints with the ones present in `update` replaced. A constraint in the
+    /// update is not combined with the current one, so setting a location to a `OneOf` replaces
+    /// the whole set. The new constraints are normalized before they are stored.
     pub fn merge(&self, update: RelayConstraintsUpdate) -> Self {
         RelayConstraints {
-            location: update.location.unwrap_or_else(|| self.location.clone()),
-            tunnel: update.tunnel.unwrap_or_else(|| self.tunnel.clone()),
+            location: update
+                .location
+                .map(Constraint::normalize)
+                .unwrap_or_else(|| self.location.clone()),
+            tunnel: update
+                .tunnel
+                .map(|tunnel| tunnel.normalize().map(TunnelConstraints::normalize))
+                .unwrap_or_else(|| self.tunnel.clone()),
+        }
+    }
+}
+
+
+/// Which IP version to use when connecting to a relay.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpVersionPreference {
+    /// Use IPv4, unless the host has no route to the relay over IPv4 and the relay has an IPv6
+    /// address.
+    Auto,
+    /// Only connect to relays over IPv4.
+    Ipv4,
+    /// Only connect to relays over IPv6. Relays without an IPv6 address are never selected.
+    Ipv6,
+}
+
+impl Default for IpVersionPreference {
+    fn default() -> Self {
+        IpVersionPreference::Auto
+    }
+}
+
+impl fmt::Display for IpVersionPreference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IpVersionPreference::Auto => "auto".fmt(f),
+            IpVersionPreference::Ipv4 => "IPv4".fmt(f),
+            IpVersionPreference::Ipv6 => "IPv6".fmt(f),
         }
     }
 }
@@ -100,6 +301,28 @@ pub enum LocationConstraint {
     Hostname(CountryCode, CityCode, Hostname),
 }
 
+/// Matches relays located within the location. Whether a relay should be used when only its
+/// country is selected is not considered here.
+impl Match<Relay> for LocationConstraint {
+    fn matches(&self, relay: &Relay) -> bool {
+        let location = match relay.location {
+            Some(ref location) => location,
+            None => return false,
+        };
+        match *self {
+            LocationConstraint::Country(ref country) => location.country_code == *country,
+            LocationConstraint::City(ref country, ref city) => {
+                location.country_code == *country && location.city_code == *city
+            }
+            LocationConstraint::Hostname(ref country, ref city, ref hostname) => {
+                location.country_code == *country
+                    && location.city_code == *city
+                    && relay.hostname == *hostname
+            }
+        }
+    }
+}
+
 
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub enum TunnelConstraints {
@@ -109,6 +332,24 @@ pub enum TunnelConstraints {
     Wireguard(WireguardConstraints),
 }
 
+impl TunnelConstraints {
+    fn normalize(self) -> Self {
+        match self {
+            TunnelConstraints::OpenVpn(constraints) => {
+                TunnelConstraints::OpenVpn(OpenVpnConstraints {
+                    port: constraints.port.normalize(),
+                    protocol: constraints.protocol.normalize(),
+                })
+            }
+            TunnelConstraints::Wireguard(constraints) => {
+                TunnelConstraints::Wireguard(WireguardConstraints {
+                    port: constraints.port.normalize(),
+                })
+            }
+        }
+    }
+}
+
 impl Match<OpenVpnEndpointData> for TunnelConstraints {
     fn matches(&self, endpoint: &OpenVpnEndpointData) -> bool {
         match *self {
@@ -129,7 +370,7 @@ impl Match<WireguardEndpointData> for TunnelConstraints {
 
 #[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub struct OpenVpnConstraints {
-    pub port: Constraint<u16>,
+    pub port: Constraint<PortRange>,
     pub protocol: Constraint<TransportProtocol>,
 }
 
@@ -141,7 +382,7 @@ impl Match<OpenVpnEndpointData> for OpenVpnConstraints {
 
 #[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub struct WireguardConstraints {
-    pub port: Constraint<u16>,
+    pub port: Constraint<PortRange>,
 }
 
 impl Match<WireguardEndpointData> for WireguardConstraints {
@@ -164,3 +405,72 @@ pub struct RelayConstraintsUpdate {
     pub location: Option<Constraint<LocationConstraint>>,
     pub tunnel: Option<Constraint<TunnelConstraints>>,
 }
+
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+
+    #[test]
+    fn deserialize_settings_without_sets_or_ranges() {
+        let json = r#"{
+            "location": {"only": {"country": "se"}},
+            "tunnel": {"only": {"openvpn": {"port": {"only": 1194}, "protocol": "any"}}}
+        }"#;
+        let constraints: RelayConstraints = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            constraints,
+            RelayConstraints {
+                location: Constraint::Only(LocationConstraint::Country("se".to_owned())),
+                tunnel: Constraint::Only(TunnelConstraints::OpenVpn(OpenVpnConstraints {
+                    port: Constraint::Only(PortRange::single(1194)),
+                    protocol: Constraint::Any,
+                })),
+            }
+        );
+    }
+
+    #[test]
+    fn serialize_single_port_as_number() {
+        let port = Constraint::Only(PortRange::single(1194));
+        assert_eq!(serde_json::to_string(&port).unwrap(), r#"{"only":1194}"#);
+
+        let ports = Constraint::Not(Box::new(Constraint::OneOf(vec![
+            PortRange::new(1194, 1197).unwrap(),
+            PortRange::single(53),
+        ])));
+        let json = serde_json::to_string(&ports).unwrap();
+        assert_eq!(json, r#"{"not":{"one_of":[{"start":1194,"end":1197},53]}}"#);
+        assert_eq!(
+            serde_json::from_str::<Constraint<PortRange>>(&json).unwrap(),
+            ports
+        );
+    }
+
+    #[test]
+    fn match_port_sets_and_negations() {
+        let ports = Constraint::OneOf(vec![
+            PortRange::new(1194, 1197).unwrap(),
+            PortRange::single(53),
+        ]);
+        assert!(ports.matches(&1196));
+        assert!(ports.matches(&53));
+        assert!(!ports.matches(&443));
+
+        let not_ports = Constraint::Not(Box::new(ports));
+        assert!(!not_ports.matches(&1194));
+        assert!(not_ports.matches(&443));
+    }
+
+    #[test]
+    fn normalize() {
+        let single = Constraint::OneOf(vec![TransportProtocol::Udp]);
+        assert_eq!(single.normalize(), Constraint::Only(TransportProtocol::Udp));
+
+        let double_negation: Constraint<TransportProtocol> =
+            Constraint::Not(Box::new(Constraint::Not(Box::new(Constraint::Any))));
+        assert_eq!(double_negation.normalize(), Constraint::Any);
+    }
+}
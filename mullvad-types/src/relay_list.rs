@@ -1,6 +1,7 @@
 use location::{CityCode, CountryCode, Location};
 
-use std::net::Ipv4Addr;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 use talpid_types::net::{OpenVpnEndpointData, WireguardEndpointData};
 
@@ -38,11 +39,16 @@ pub struct RelayListCity {
 pub struct Relay {
     pub hostname: String,
     pub ipv4_addr_in: Ipv4Addr,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ipv6_addr_in: Option<Ipv6Addr>,
     pub ipv4_addr_exit: Ipv4Addr,
     pub include_in_country: bool,
     pub weight: u64,
     #[serde(skip_serializing_if = "RelayTunnels::is_empty", default)]
     pub tunnels: RelayTunnels,
+    /// True for relays added by the user, rather than being part of the downloaded relay list.
+    #[serde(default)]
+    pub user_defined: bool,
     #[serde(skip)]
     pub location: Option<Location>,
 }
@@ -64,3 +70,87 @@ impl RelayTunnels {
         self.wireguard.clear();
     }
 }
+
+
+/// The relays matching a set of constraints. Each relay only has the tunnel endpoints left that
+/// matched the tunnel constraints.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MatchingRelays {
+    pub relays: Vec<Relay>,
+    /// The relays that were filtered out, and why. Only populated when explicitly requested.
+    #[serde(default)]
+    pub rejected: Vec<RejectedRelay>,
+}
+
+impl MatchingRelays {
+    pub fn empty() -> Self {
+        MatchingRelays {
+            relays: Vec::new(),
+            rejected: Vec::new(),
+        }
+    }
+}
+
+/// A relay that did not match a set of constraints.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RejectedRelay {
+    pub hostname: String,
+    pub reason: RejectReason,
+}
+
+/// The rule that filtered out a relay.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// The relay is not in the country, city or hostname given by the location constraint.
+    Location,
+    /// The relay is in the constrained country, but should only be used if selected explicitly
+    /// by city or hostname.
+    NotIncludedInCountry,
+    /// None of the tunnel endpoints on the relay match the tunnel constraints.
+    NoMatchingTunnel,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            RejectReason::Location => "location does not match",
+            RejectReason::NotIncludedInCountry => "only used when selected by city or hostname",
+            RejectReason::NoMatchingTunnel => "no tunnel endpoint matches",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Sent when the relay the daemon is using can't be used anymore after the relay list changed,
+/// and the daemon reconnects to another relay because of it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RelayInvalidated {
+    pub hostname: String,
+    pub reason: RelayInvalidReason,
+}
+
+/// Why a relay in use is no longer valid.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayInvalidReason {
+    /// The relay is not in the relay list anymore.
+    Removed,
+    /// The relay is still in the list, but the address or tunnel endpoint in use is not.
+    EndpointRemoved,
+    /// The relay or the tunnel endpoint in use no longer matches the relay constraints.
+    NoLongerMatchesConstraints,
+}
+
+impl fmt::Display for RelayInvalidReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            RelayInvalidReason::Removed => "the relay was removed from the relay list",
+            RelayInvalidReason::EndpointRemoved => "the endpoint in use was removed from the relay",
+            RelayInvalidReason::NoLongerMatchesConstraints => {
+                "the relay no longer matches the relay constraints"
+            }
+        };
+        f.write_str(description)
+    }
+}
@@ -0,0 +1,116 @@
+//! Storage for secrets, such as account tokens, that other users on the system must not be able
+//! to read. On Unix the files are only readable and writable by the user running the daemon. On
+//! Windows they inherit the permissions of the directory they are in, which only administrators
+//! and the system can access.
+
+use atomic_file;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Reads the secret at `path`. Returns `None` if there is no such file. The permissions of a file
+/// written before secrets were stored privately are restricted before it's read.
+pub fn read(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    restrict_permissions(path)?;
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Atomically writes the secret `contents` to `path`. The previous contents are kept as a backup,
+/// like `atomic_file::write` does, but restricted the same way as the new file.
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    atomic_file::write_private(path, contents)?;
+    restrict_permissions(path)
+}
+
+/// Removes the secret at `path`, and its backup. Files that don't exist are ignored.
+pub fn remove(path: &Path) -> io::Result<()> {
+    for path in &[path.to_owned(), atomic_file::backup_path(path)] {
+        match fs::remove_file(path) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Makes the file at `path`, and its backup, only readable and writable by their owner. Used to
+/// protect files that were written with looser permissions by earlier versions. Files that don't
+/// exist are ignored.
+#[cfg(unix)]
+pub fn restrict_permissions(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    for path in &[path.to_owned(), atomic_file::backup_path(path)] {
+        match fs::metadata(path) {
+            Ok(ref metadata) if metadata.permissions().mode() & 0o077 != 0 => {
+                info!("Restricting permissions of {}", path.display());
+                fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+            }
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn restrict_permissions(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mullvad-secret-file-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn writes_secret_readable_by_owner_only() {
+        let dir = test_dir("write");
+        let path = dir.join("account-token");
+
+        write(&path, b"1234").unwrap();
+
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(read(&path).unwrap(), Some(b"1234".to_vec()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restricts_existing_plaintext_files() {
+        let dir = test_dir("restrict");
+        let path = dir.join("account-history.json");
+        atomic_file::write(&path, b"[\"1234\"]").unwrap();
+        atomic_file::write(&path, b"[\"5678\"]").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write(&path, b"[]").unwrap();
+
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&atomic_file::backup_path(&path)), 0o600);
+        remove(&path).unwrap();
+        assert_eq!(read(&path).unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+//! Upgrades settings stored in older formats to the current one. The settings are migrated as
+//! untyped JSON, one version at a time, before they are deserialized into `Settings`.
+
+use super::serde_json::{self, Value};
+use super::{ErrorKind, Result, ResultExt};
+
+/// The version of the settings format written by this version of the daemon. When the format
+/// changes in a way that older settings can't be deserialized as-is, increase this and append a
+/// migration to `MIGRATIONS`.
+pub const CURRENT_SETTINGS_VERSION: u32 = 1;
+
+/// The version of settings written before the format was versioned, without a version field.
+/// Every release up until then can be deserialized as is, so they all share the first version.
+const UNVERSIONED_SETTINGS_VERSION: u32 = 1;
+
+const VERSION_KEY: &str = "settings_version";
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// Ordered chain of migrations. `MIGRATIONS[i]` upgrades settings from version
+/// `UNVERSIONED_SETTINGS_VERSION + i` to the version after it.
+static MIGRATIONS: &[Migration] = &[];
+
+/// Migrates `settings` to `CURRENT_SETTINGS_VERSION`. Returns the version the settings were
+/// migrated from, or `None` if they were already in the current format.
+pub fn migrate(settings: &mut Value) -> Result<Option<u32>> {
+    migrate_with(settings, MIGRATIONS)
+}
+
+fn migrate_with(settings: &mut Value, migrations: &[Migration]) -> Result<Option<u32>> {
+    let current_version = UNVERSIONED_SETTINGS_VERSION + migrations.len() as u32;
+    let version = settings_version(settings)?;
+    if version > current_version {
+        warn!(
+            "Settings are of version {}, newer than the supported version {}. Reading them as is",
+            version, current_version
+        );
+        return Ok(None);
+    } else if version == current_version {
+        return Ok(None);
+    }
+
+    let first_migration = (version - UNVERSIONED_SETTINGS_VERSION) as usize;
+    for (from_version, migration) in (version..).zip(&migrations[first_migration..]) {
+        debug!(
+            "Migrating settings from version {} to {}",
+            from_version,
+            from_version + 1
+        );
+        migration(settings).chain_err(|| ErrorKind::MigrationError(from_version))?;
+        settings[VERSION_KEY] = Value::from(from_version + 1);
+    }
+    Ok(Some(version))
+}
+
+fn settings_version(settings: &Value) -> Result<u32> {
+    if !settings.is_object() {
+        bail!(ErrorKind::ParseError);
+    }
+    match settings.get(VERSION_KEY) {
+        None => Ok(UNVERSIONED_SETTINGS_VERSION),
+        Some(version) => {
+            let version = serde_json::from_value::<u32>(version.clone())
+                .chain_err(|| ErrorKind::ParseError)?;
+            if version < UNVERSIONED_SETTINGS_VERSION {
+                bail!(ErrorKind::UnsupportedVersion(version));
+            }
+            Ok(version)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::Settings;
+    use super::*;
+
+    use relay_constraints::{
+        Constraint, IpVersionPreference, LocationConstraint, OpenVpnConstraints, PortRange,
+        RelayConstraints, RelaySettings, TunnelConstraints,
+    };
+    use talpid_types::net::{OpenVpnEndpointData, TransportProtocol, TunnelEndpointData};
+
+    fn read_fixture(json: &str) -> (Settings, Option<u32>) {
+        Settings::read_settings(&mut json.as_bytes()).expect("Unable to read settings fixture")
+    }
+
+    fn parse(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn openvpn_constraints(
+        port: Constraint<PortRange>,
+        protocol: Constraint<TransportProtocol>,
+    ) -> Constraint<TunnelConstraints> {
+        Constraint::Only(TunnelConstraints::OpenVpn(OpenVpnConstraints {
+            port,
+            protocol,
+        }))
+    }
+
+    fn rename_tunnel_options(settings: &mut Value) -> Result<()> {
+        let tunnel_options = settings
+            .as_object_mut()
+            .and_then(|settings| settings.remove("tunnel_options"))
+            .ok_or(ErrorKind::ParseError)?;
+        settings["tunnel"] = tunnel_options;
+        Ok(())
+    }
+
+    fn drop_allow_lan(settings: &mut Value) -> Result<()> {
+        settings.as_object_mut().unwrap().remove("allow_lan");
+        Ok(())
+    }
+
+    #[test]
+    fn reads_settings_from_2018_2() {
+        let (settings, migrated_from) =
+            read_fixture(include_str!("../../test-data/settings/2018.2.json"));
+
+        assert_eq!(migrated_from, None);
+        assert_eq!(settings.settings_version, CURRENT_SETTINGS_VERSION);
+        assert_eq!(settings.get_account_token(), Some("1234567890".to_owned()));
+        assert_eq!(
+            settings.get_relay_settings(),
+            RelaySettings::Normal(RelayConstraints {
+                location: Constraint::Only(LocationConstraint::Country("de".to_owned())),
+                tunnel: openvpn_constraints(
+                    Constraint::Any,
+                    Constraint::Only(TransportProtocol::Tcp)
+                ),
+            })
+        );
+        assert!(!settings.get_allow_lan());
+        assert!(settings.get_auto_connect());
+        assert_eq!(settings.get_tunnel_options().openvpn.mssfix, None);
+        assert!(!settings.get_tunnel_options().enable_ipv6);
+    }
+
+    #[test]
+    fn reads_settings_from_2018_3() {
+        let (settings, migrated_from) =
+            read_fixture(include_str!("../../test-data/settings/2018.3.json"));
+
+        assert_eq!(migrated_from, None);
+        assert_eq!(settings.settings_version, CURRENT_SETTINGS_VERSION);
+        assert_eq!(settings.get_account_token(), Some("1234567890".to_owned()));
+        assert_eq!(
+            settings.get_relay_settings(),
+            RelaySettings::Normal(RelayConstraints {
+                location: Constraint::Only(LocationConstraint::City(
+                    "se".to_owned(),
+                    "got".to_owned()
+                )),
+                tunnel: openvpn_constraints(
+                    Constraint::Only(PortRange::single(1194)),
+                    Constraint::Only(TransportProtocol::Udp),
+                ),
+            })
+        );
+        assert_eq!(settings.get_relay_ip_version(), IpVersionPreference::Auto);
+        assert_eq!(settings.get_rotate_relay_interval(), None);
+        assert!(settings.get_allow_lan());
+        assert!(settings.get_auto_connect());
+        assert_eq!(settings.get_tunnel_options().openvpn.mssfix, Some(1400));
+        assert!(settings.get_tunnel_options().enable_ipv6);
+    }
+
+    #[test]
+    fn reads_custom_tunnel_endpoint_from_2018_3() {
+        let (settings, migrated_from) = read_fixture(include_str!(
+            "../../test-data/settings/2018.3-custom-tunnel-endpoint.json"
+        ));
+
+        assert_eq!(migrated_from, None);
+        match settings.get_relay_settings() {
+            RelaySettings::CustomTunnelEndpoint(endpoint) => {
+                assert_eq!(endpoint.host, "vpn.example.com");
+                assert_eq!(
+                    endpoint.tunnel,
+                    TunnelEndpointData::OpenVpn(OpenVpnEndpointData {
+                        port: 443,
+                        protocol: TransportProtocol::Tcp,
+                    })
+                );
+            }
+            relay_settings => panic!("Unexpected relay settings: {:?}", relay_settings),
+        }
+    }
+
+    #[test]
+    fn reads_current_version() {
+        let (settings, migrated_from) =
+            read_fixture(include_str!("../../test-data/settings/current.json"));
+
+        assert_eq!(migrated_from, None);
+        assert_eq!(settings.settings_version, CURRENT_SETTINGS_VERSION);
+        assert_eq!(settings.get_account_token(), Some("1234567890".to_owned()));
+        assert_eq!(settings.get_rotate_relay_interval(), Some(600));
+    }
+
+    #[test]
+    fn runs_all_migrations_on_unversioned_settings() {
+        let mut settings = parse(r#"{ "allow_lan": true, "tunnel_options": {} }"#);
+
+        let migrated_from =
+            migrate_with(&mut settings, &[rename_tunnel_options, drop_allow_lan]).unwrap();
+
+        assert_eq!(migrated_from, Some(1));
+        assert_eq!(
+            settings,
+            parse(r#"{ "settings_version": 3, "tunnel": {} }"#)
+        );
+    }
+
+    #[test]
+    fn runs_remaining_migrations_on_versioned_settings() {
+        let mut settings =
+            parse(r#"{ "settings_version": 2, "allow_lan": true, "tunnel_options": {} }"#);
+
+        let migrated_from =
+            migrate_with(&mut settings, &[rename_tunnel_options, drop_allow_lan]).unwrap();
+
+        assert_eq!(migrated_from, Some(2));
+        assert_eq!(
+            settings,
+            parse(r#"{ "settings_version": 3, "tunnel_options": {} }"#)
+        );
+    }
+
+    #[test]
+    fn reports_version_of_failed_migration() {
+        let mut settings = parse(r#"{ "allow_lan": true }"#);
+
+        let error =
+            migrate_with(&mut settings, &[drop_allow_lan, rename_tunnel_options]).unwrap_err();
+
+        match *error.kind() {
+            ErrorKind::MigrationError(2) => (),
+            ref kind => panic!("Unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn does_not_migrate_newer_version() {
+        let mut settings = parse(r#"{ "settings_version": 3 }"#);
+
+        assert_eq!(migrate(&mut settings).unwrap(), None);
+        assert_eq!(settings[VERSION_KEY], 3);
+    }
+
+    #[test]
+    fn rejects_invalid_version() {
+        assert!(migrate(&mut parse(r#"{ "settings_version": 0 }"#)).is_err());
+        assert!(migrate(&mut parse(r#"{ "settings_version": "2" }"#)).is_err());
+        assert!(migrate(&mut parse("[]")).is_err());
+    }
+
+    #[test]
+    fn migrations_cover_all_versions() {
+        assert_eq!(
+            MIGRATIONS.len() as u32,
+            CURRENT_SETTINGS_VERSION - UNVERSIONED_SETTINGS_VERSION
+        );
+    }
+}
@@ -0,0 +1,607 @@
+extern crate serde_json;
+
+use atomic_file;
+use error_chain::ChainedError;
+use relay_constraints::{
+    Constraint, IpVersionPreference, LocationConstraint, RelayConstraints, RelaySettings,
+    RelaySettingsUpdate,
+};
+use secret_file;
+use serde_json::{Map, Value};
+use talpid_types::net::TunnelOptions;
+
+use std::fs::{self, File};
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+mod migrations;
+pub use self::migrations::CURRENT_SETTINGS_VERSION;
+
+mod patch;
+pub use self::patch::{merge_patch, FieldError, REVISION_KEY};
+
+pub mod policy;
+
+error_chain! {
+    errors {
+        DirectoryError {
+            description("Unable to create settings directory for program")
+        }
+        ReadError(path: PathBuf) {
+            description("Unable to read settings file")
+            display("Unable to read settings from {}", path.display())
+        }
+        WriteError(path: PathBuf) {
+            description("Unable to write settings file")
+            display("Unable to write settings to {}", path.display())
+        }
+        ParseError {
+            description("Malformed settings")
+        }
+        UnsupportedVersion(version: u32) {
+            description("Unsupported settings version")
+            display("Unsupported settings version: {}", version)
+        }
+        MigrationError(from_version: u32) {
+            description("Unable to migrate settings")
+            display("Unable to migrate settings from version {}", from_version)
+        }
+        BackupError(path: PathBuf) {
+            description("Unable to back up settings file")
+            display("Unable to back up settings to {}", path.display())
+        }
+        InvalidPolicy(path: PathBuf) {
+            description("Invalid system policy")
+            display("Invalid system policy in {}", path.display())
+        }
+        UnknownPolicyKey(key: String) {
+            description("The system policy contains a setting that can't be locked")
+            display("The system policy contains a setting that can't be locked: {}", key)
+        }
+    }
+}
+
+static SETTINGS_FILE: &str = "settings.json";
+/// Where the account token is kept, next to the settings file but only readable by the daemon.
+static ACCOUNT_TOKEN_FILE: &str = "account-token";
+
+const LOCKED_KEYS_KEY: &str = "locked_keys";
+
+/// Keys describing the settings themselves rather than user choices. Clients can't change them,
+/// and the system policy can't lock them.
+const READ_ONLY_KEYS: &[&str] = &[
+    "settings_version",
+    "revision",
+    "active_profile",
+    LOCKED_KEYS_KEY,
+];
+
+
+/// Mullvad daemon settings.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    /// The version of the format these settings are stored in. Older formats are migrated to
+    /// `CURRENT_SETTINGS_VERSION` when loaded.
+    settings_version: u32,
+    /// Increased every time the settings are saved, so clients can tell if the settings were
+    /// changed by someone else since they last read them.
+    revision: u64,
+    /// The name of the profile these settings were last switched to, if any. Settings changed
+    /// after switching are not written back to the profile.
+    active_profile: Option<String>,
+    /// Stored in `ACCOUNT_TOKEN_FILE` rather than in the settings file. Settings files written by
+    /// older versions have it here, and it's moved when they are loaded.
+    account_token: Option<String>,
+    relay_settings: RelaySettings,
+    /// Which IP version to use when connecting to relays.
+    relay_ip_version: IpVersionPreference,
+    /// How often, in seconds, to switch to another relay while connected. Never if `None`.
+    rotate_relay_interval: Option<u64>,
+    /// If the daemon should allow communication with private (LAN) networks.
+    allow_lan: bool,
+    /// If the daemon should connect the VPN tunnel directly on start or not.
+    auto_connect: bool,
+    /// How long, in seconds, before the account expires to warn subscribers about it.
+    account_expiry_warnings: Vec<u64>,
+    /// How many of the most recently used accounts to remember in the account history.
+    account_history_limit: usize,
+    /// Options that should be applied to tunnels of a specific type regardless of where the relays
+    /// might be located.
+    tunnel_options: TunnelOptions,
+    /// Where clients on other machines can manage the daemon, if anywhere.
+    remote_management: RemoteManagementSettings,
+    /// The settings that are set by the system policy and can't be changed. Only reported to
+    /// clients, never stored or read back.
+    #[serde(skip_deserializing)]
+    locked_keys: Vec<String>,
+    /// The user's own values of the settings overridden by the system policy. They are saved in
+    /// place of the policy's values, so the user's settings are back once the policy is removed.
+    #[serde(skip)]
+    overridden_values: Map<String, Value>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            settings_version: CURRENT_SETTINGS_VERSION,
+            revision: 0,
+            active_profile: None,
+            account_token: None,
+            relay_settings: RelaySettings::Normal(RelayConstraints {
+                location: Constraint::Only(LocationConstraint::Country("se".to_owned())),
+                tunnel: Constraint::Any,
+            }),
+            relay_ip_version: IpVersionPreference::Auto,
+            rotate_relay_interval: None,
+            allow_lan: false,
+            auto_connect: false,
+            account_expiry_warnings: vec![3 * 24 * 60 * 60, 24 * 60 * 60],
+            account_history_limit: 3,
+            tunnel_options: TunnelOptions::default(),
+            remote_management: RemoteManagementSettings::default(),
+            locked_keys: Vec::new(),
+            overridden_values: Map::new(),
+        }
+    }
+}
+
+/// Settings for the remote management interface, which accepts JSON-RPC over WebSocket from
+/// clients presenting the token generated by the daemon. Disabled by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RemoteManagementSettings {
+    /// The address to listen on. The remote management interface is disabled if `None`. The
+    /// firewall accepts incoming connections to it while the interface is running.
+    pub address: Option<SocketAddr>,
+}
+
+impl Settings {
+    /// Loads user settings from file. If no file is present it returns the defaults. Settings
+    /// stored in an older format are migrated to the current one, and the original file is
+    /// backed up before the migrated settings are saved.
+    ///
+    /// If the settings file is corrupt, it's moved aside and the backup of the previous settings
+    /// is used instead, or the defaults if that can't be read either.
+    pub fn load() -> Result<Settings> {
+        let settings_path = Self::get_settings_path()?;
+        match Self::load_file(&settings_path) {
+            Err(ref error) if Self::is_corrupt(error) => {
+                error!("{}", error.display_chain());
+                error!("The settings file is corrupt, falling back to the backup");
+                if let Err(e) = atomic_file::set_aside_corrupt(&settings_path) {
+                    warn!("Unable to move the corrupt settings file: {}", e);
+                }
+                Ok(Self::load_backup(&settings_path))
+            }
+            result => result.map(|settings| {
+                settings.unwrap_or_else(|| {
+                    info!(
+                        "No settings file at {}, using defaults",
+                        settings_path.display()
+                    );
+                    Settings::default()
+                })
+            }),
+        }
+    }
+
+    /// Loads settings from the given file, and the account token from the file next to it.
+    /// Returns `None` if the settings file does not exist.
+    fn load_file(path: &Path) -> Result<Option<Settings>> {
+        match File::open(path) {
+            Ok(file) => {
+                info!("Loading settings from {}", path.display());
+                let (mut settings, migrated_from) =
+                    Self::read_settings(&mut io::BufReader::new(file))?;
+                let has_plaintext_account_token = settings.account_token.is_some();
+                if !has_plaintext_account_token {
+                    settings.account_token = Self::load_account_token(path)?;
+                }
+                if let Some(version) = migrated_from {
+                    settings.save_migrated(path, version);
+                } else if has_plaintext_account_token {
+                    settings.move_account_token(path);
+                }
+                Ok(Some(settings))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).chain_err(|| ErrorKind::ReadError(path.to_owned())),
+        }
+    }
+
+    fn load_backup(settings_path: &Path) -> Settings {
+        let backup_path = atomic_file::backup_path(settings_path);
+        match Self::load_file(&backup_path) {
+            Ok(Some(settings)) => {
+                warn!("Using settings from {}", backup_path.display());
+                settings
+            }
+            Ok(None) => {
+                error!("No settings backup found, using default settings");
+                Settings::default()
+            }
+            Err(error) => {
+                let chained_error =
+                    error.chain_err(|| "Unable to load settings backup, using default settings");
+                error!("{}", chained_error.display_chain());
+                Settings::default()
+            }
+        }
+    }
+
+    /// Returns true if the error means the contents of the settings file can't be used, as
+    /// opposed to the file not being readable at all.
+    fn is_corrupt(error: &Error) -> bool {
+        match *error.kind() {
+            ErrorKind::ParseError
+            | ErrorKind::UnsupportedVersion(_)
+            | ErrorKind::MigrationError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Increases the revision, then serializes the settings and saves them to the file it was
+    /// loaded from. The file is replaced atomically, so it's never left half written. The account
+    /// token is saved to its own file, before the settings, so it's never lost.
+    fn save(&mut self) -> Result<()> {
+        let path = Self::get_settings_path()?;
+        self.revision += 1;
+        self.save_account_token(&path)?;
+
+        debug!("Writing settings to {}", path.display());
+        let settings = serde_json::to_vec_pretty(&self.to_stored_value())
+            .chain_err(|| ErrorKind::WriteError(path.clone()))?;
+        atomic_file::write(&path, &settings).chain_err(|| ErrorKind::WriteError(path))
+    }
+
+    /// Returns the settings as they are stored in the settings file. The locked keys come from
+    /// the policy, so they are only reported to clients, and the settings the policy overrides are
+    /// stored with the user's own values.
+    fn to_stored_value(&self) -> Value {
+        let mut settings = match serde_json::to_value(self.without_account_token()) {
+            Ok(Value::Object(settings)) => settings,
+            _ => unreachable!("Settings are not serialized as an object"),
+        };
+        settings.remove(LOCKED_KEYS_KEY);
+        for (key, value) in &self.overridden_values {
+            settings.insert(key.clone(), value.clone());
+        }
+        Value::Object(settings)
+    }
+
+    fn get_settings_path() -> Result<PathBuf> {
+        let dir = ::mullvad_paths::settings_dir().chain_err(|| ErrorKind::DirectoryError)?;
+        Ok(dir.join(SETTINGS_FILE))
+    }
+
+    fn load_account_token(settings_path: &Path) -> Result<Option<String>> {
+        let path = settings_path.with_file_name(ACCOUNT_TOKEN_FILE);
+        let contents = match secret_file::read(&path) {
+            Ok(Some(contents)) => contents,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(e).chain_err(|| ErrorKind::ReadError(path)),
+        };
+        let account_token =
+            String::from_utf8(contents).chain_err(|| ErrorKind::ReadError(path.clone()))?;
+        let account_token = account_token.trim();
+        if account_token.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(account_token.to_owned()))
+        }
+    }
+
+    fn save_account_token(&self, settings_path: &Path) -> Result<()> {
+        let path = settings_path.with_file_name(ACCOUNT_TOKEN_FILE);
+        match self.account_token {
+            Some(ref account_token) => secret_file::write(&path, account_token.as_bytes()),
+            None => secret_file::remove(&path),
+        }.chain_err(|| ErrorKind::WriteError(path))
+    }
+
+    /// Moves an account token stored in plain text in the settings file at `settings_path` to
+    /// its own file, and protects the backup of the old settings file, which still contains it.
+    /// Failures are only logged, since the move is retried the next time the settings are loaded.
+    fn move_account_token(&mut self, settings_path: &Path) {
+        info!(
+            "Moving the account token out of {}",
+            settings_path.display()
+        );
+        let backup_path = atomic_file::backup_path(settings_path);
+        let result = self.save().and_then(|_| {
+            secret_file::restrict_permissions(&backup_path)
+                .chain_err(|| ErrorKind::WriteError(backup_path))
+        });
+        if let Err(error) = result {
+            let chained_error = error.chain_err(|| "Unable to move the account token");
+            error!("{}", chained_error.display_chain());
+        }
+    }
+
+    /// Reads and migrates settings. Returns the version the settings were migrated from, if they
+    /// were not already in the current format.
+    fn read_settings<T: io::Read>(file: &mut T) -> Result<(Settings, Option<u32>)> {
+        let settings = serde_json::from_reader(file).chain_err(|| ErrorKind::ParseError)?;
+        Self::migrate_and_parse(settings)
+    }
+
+    fn migrate_and_parse(mut settings: serde_json::Value) -> Result<(Settings, Option<u32>)> {
+        let migrated_from = migrations::migrate(&mut settings)?;
+        let settings = serde_json::from_value(settings).chain_err(|| ErrorKind::ParseError)?;
+        Ok((settings, migrated_from))
+    }
+
+    /// Parses settings in the format they are stored and exported in, migrating them first if
+    /// they are in an older format.
+    pub fn from_json(settings: serde_json::Value) -> Result<Settings> {
+        Self::migrate_and_parse(settings).map(|(settings, _)| settings)
+    }
+
+    /// Returns a copy of the settings to export. The account token is left out unless
+    /// `include_account_token` is true. The revision, the active profile, the locked keys and the
+    /// remote management settings are always left out.
+    pub fn export(&self, include_account_token: bool) -> Settings {
+        let mut settings = self.clone();
+        settings.revision = 0;
+        settings.active_profile = None;
+        settings.locked_keys.clear();
+        settings.overridden_values.clear();
+        settings.remote_management = RemoteManagementSettings::default();
+        if !include_account_token {
+            settings.account_token = None;
+        }
+        settings
+    }
+
+    /// Replaces all settings with imported ones and saves them to disk. The current account token
+    /// is kept if the imported settings have none. The remote management settings are always
+    /// kept. The boolean in the Result indicates if the
+    /// settings changed or not.
+    pub fn import(&mut self, settings: Settings) -> Result<bool> {
+        info!("Importing settings");
+        self.replace(settings, None)
+    }
+
+    /// Replaces all settings with the ones in the profile `name`, marks the profile as active and
+    /// saves the settings to disk. The account token and the remote management settings are kept.
+    /// The boolean in the Result indicates
+    /// if the settings changed or not.
+    pub fn switch_profile(&mut self, name: String, profile: Settings) -> Result<bool> {
+        info!("Switching to profile {}", name);
+        self.replace(profile, Some(name))
+    }
+
+    fn replace(&mut self, mut settings: Settings, active_profile: Option<String>) -> Result<bool> {
+        if settings.account_token.as_ref().map_or(true, String::is_empty) {
+            settings.account_token = self.account_token.clone();
+        }
+        settings.remote_management = self.remote_management;
+        settings.settings_version = CURRENT_SETTINGS_VERSION;
+        settings.revision = self.revision;
+        settings.active_profile = active_profile;
+        if settings != *self {
+            *self = settings;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Replaces the settings with `settings`, such as ones returned by `patched`, and saves them
+    /// to disk. The boolean in the Result indicates if the settings changed or not.
+    pub fn update(&mut self, settings: Settings) -> Result<bool> {
+        if settings != *self {
+            *self = settings;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn get_active_profile(&self) -> Option<&str> {
+        self.active_profile.as_ref().map(String::as_str)
+    }
+
+    /// Returns the settings that are set by the system policy and can't be changed.
+    pub fn get_locked_keys(&self) -> &[String] {
+        &self.locked_keys
+    }
+
+    /// Backs up the settings file as it was before being migrated from `version`, then saves the
+    /// migrated settings in its place. The backups may contain the account token, so only the
+    /// daemon can read them. Failures are only logged, since the migration is redone the next
+    /// time the settings are loaded.
+    fn save_migrated(&mut self, settings_path: &Path, version: u32) {
+        info!(
+            "Migrated settings from version {} to {}",
+            version, CURRENT_SETTINGS_VERSION
+        );
+        let backup_path =
+            settings_path.with_file_name(format!("{}.v{}.bak", SETTINGS_FILE, version));
+        let result = fs::copy(settings_path, &backup_path)
+            .and_then(|_| secret_file::restrict_permissions(&backup_path))
+            .chain_err(|| ErrorKind::BackupError(backup_path.clone()))
+            .and_then(|_| {
+                info!("Backed up old settings to {}", backup_path.display());
+                self.save()
+            }).and_then(|_| {
+                let overwritten_path = atomic_file::backup_path(settings_path);
+                secret_file::restrict_permissions(&overwritten_path)
+                    .chain_err(|| ErrorKind::WriteError(overwritten_path))
+            });
+        if let Err(error) = result {
+            let chained_error = error.chain_err(|| "Unable to save migrated settings");
+            error!("{}", chained_error.display_chain());
+        }
+    }
+
+    pub fn get_account_token(&self) -> Option<String> {
+        self.account_token.clone()
+    }
+
+    /// Returns a copy of the settings without the account token, for clients that may not know
+    /// it.
+    pub fn without_account_token(&self) -> Settings {
+        Settings {
+            account_token: None,
+            ..self.clone()
+        }
+    }
+
+    /// Changes account number to the one given. Also saves the new settings to disk.
+    /// The boolean in the Result indicates if the account token changed or not
+    pub fn set_account_token(&mut self, mut account_token: Option<String>) -> Result<bool> {
+        if account_token.as_ref().map(String::len) == Some(0) {
+            debug!("Setting empty account token is treated as unsetting it");
+            account_token = None;
+        }
+        if account_token != self.account_token {
+            if account_token.is_none() {
+                info!("Unsetting account token");
+            } else if self.account_token.is_none() {
+                info!("Setting account token");
+            } else {
+                info!("Changing account token")
+            }
+            self.account_token = account_token;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_relay_settings(&self) -> RelaySettings {
+        self.relay_settings.clone()
+    }
+
+    pub fn update_relay_settings(&mut self, update: RelaySettingsUpdate) -> Result<bool> {
+        let new_settings = self.relay_settings.merge(update);
+        if self.relay_settings != new_settings {
+            debug!(
+                "changing relay settings from {:?} to {:?}",
+                self.relay_settings, new_settings
+            );
+
+            self.relay_settings = new_settings;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_relay_ip_version(&self) -> IpVersionPreference {
+        self.relay_ip_version
+    }
+
+    pub fn set_relay_ip_version(&mut self, relay_ip_version: IpVersionPreference) -> Result<bool> {
+        if relay_ip_version != self.relay_ip_version {
+            self.relay_ip_version = relay_ip_version;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_rotate_relay_interval(&self) -> Option<u64> {
+        self.rotate_relay_interval
+    }
+
+    pub fn set_rotate_relay_interval(
+        &mut self,
+        rotate_relay_interval: Option<u64>,
+    ) -> Result<bool> {
+        if rotate_relay_interval != self.rotate_relay_interval {
+            self.rotate_relay_interval = rotate_relay_interval;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_allow_lan(&self) -> bool {
+        self.allow_lan
+    }
+
+    pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<bool> {
+        if allow_lan != self.allow_lan {
+            self.allow_lan = allow_lan;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_auto_connect(&self) -> bool {
+        self.auto_connect
+    }
+
+    pub fn set_auto_connect(&mut self, auto_connect: bool) -> Result<bool> {
+        if auto_connect != self.auto_connect {
+            self.auto_connect = auto_connect;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_account_expiry_warnings(&self) -> &[u64] {
+        &self.account_expiry_warnings
+    }
+
+    pub fn get_account_history_limit(&self) -> usize {
+        self.account_history_limit
+    }
+
+    pub fn set_account_history_limit(&mut self, account_history_limit: usize) -> Result<bool> {
+        if account_history_limit != self.account_history_limit {
+            self.account_history_limit = account_history_limit;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn set_openvpn_mssfix(&mut self, openvpn_mssfix: Option<u16>) -> Result<bool> {
+        if self.tunnel_options.openvpn.mssfix != openvpn_mssfix {
+            self.tunnel_options.openvpn.mssfix = openvpn_mssfix;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn set_enable_ipv6(&mut self, enable_ipv6: bool) -> Result<bool> {
+        if self.tunnel_options.enable_ipv6 != enable_ipv6 {
+            self.tunnel_options.enable_ipv6 = enable_ipv6;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_tunnel_options(&self) -> &TunnelOptions {
+        &self.tunnel_options
+    }
+
+    pub fn get_remote_management(&self) -> RemoteManagementSettings {
+        self.remote_management
+    }
+
+    pub fn set_remote_management(
+        &mut self,
+        remote_management: RemoteManagementSettings,
+    ) -> Result<bool> {
+        if remote_management != self.remote_management {
+            self.remote_management = remote_management;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+}
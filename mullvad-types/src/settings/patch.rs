@@ -0,0 +1,195 @@
+//! Updating several settings at once with a JSON merge patch, as described in RFC 7396. An object
+//! in the patch is merged into the current value, `null` removes or unsets a value and anything
+//! else replaces it. `{ "allow_lan": true, "tunnel_options": { "openvpn": { "mssfix": null } } }`
+//! enables LAN access and unsets mssfix, keeping all other settings.
+
+use super::serde_json::{self, Map, Value};
+use super::{Settings, READ_ONLY_KEYS};
+
+/// The key in a patch holding the revision of the settings the patch was based on.
+pub const REVISION_KEY: &str = "revision";
+
+/// Changing the account also updates the account history and the account expiry, so it's not done
+/// with patches. A patch may still contain the current account token, as returned by
+/// `get_settings`.
+const ACCOUNT_TOKEN_KEY: &str = "account_token";
+
+/// A problem with one setting, identified by its path in the settings, such as
+/// `tunnel_options.openvpn.mssfix`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new<F: Into<String>, M: Into<String>>(field: F, message: M) -> Self {
+        FieldError {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Applies the JSON merge `patch` to `target`.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let patch = match *patch {
+        Value::Object(ref patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(ref mut target) = *target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+impl Settings {
+    /// Returns these settings with the JSON merge `patch` applied. Settings removed by the patch
+    /// get their default values. Each top level setting in the patch is checked on its own, so
+    /// all invalid settings are reported, not just the first one.
+    ///
+    /// The account token can't be changed by a patch.
+    ///
+    /// A `revision` in the patch is not applied. It's the revision the patch was based on, which
+    /// is up to the caller to compare with the current one.
+    pub fn patched(&self, patch: &Value) -> Result<Settings, Vec<FieldError>> {
+        let mut patch = match *patch {
+            Value::Object(ref patch) => patch.clone(),
+            _ => return Err(vec![FieldError::new("", "The patch must be an object")]),
+        };
+        patch.remove(REVISION_KEY);
+        let current = serde_json::to_value(self).expect("Unable to serialize settings");
+
+        let errors: Vec<FieldError> = patch
+            .iter()
+            .filter_map(|(key, value)| {
+                if current.get(key).is_none() {
+                    Some(FieldError::new(key.as_str(), "Unknown setting"))
+                } else if READ_ONLY_KEYS.contains(&key.as_str()) {
+                    Some(FieldError::new(
+                        key.as_str(),
+                        "The setting can't be changed",
+                    ))
+                } else if key == ACCOUNT_TOKEN_KEY && current.get(key) != Some(value) {
+                    Some(FieldError::new(
+                        key.as_str(),
+                        "The account can only be changed with set_account",
+                    ))
+                } else {
+                    let mut single_patch = Map::new();
+                    single_patch.insert(key.clone(), value.clone());
+                    Self::from_patched_value(&current, &Value::Object(single_patch))
+                        .err()
+                        .map(|error| FieldError::new(key.as_str(), error.to_string()))
+                }
+            }).collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut settings = Self::from_patched_value(&current, &Value::Object(patch))
+            .map_err(|error| vec![FieldError::new("", error.to_string())])?;
+        settings.locked_keys = self.locked_keys.clone();
+        settings.overridden_values = self.overridden_values.clone();
+        Ok(settings)
+    }
+
+    fn from_patched_value(current: &Value, patch: &Value) -> serde_json::Result<Settings> {
+        let mut settings = current.clone();
+        merge_patch(&mut settings, patch);
+        serde_json::from_value(settings)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn merges_objects_and_removes_nulls() {
+        let mut target = parse(r#"{ "a": 1, "b": { "c": 2, "d": 3 } }"#);
+        merge_patch(
+            &mut target,
+            &parse(r#"{ "a": [1], "b": { "c": null, "e": 4 } }"#),
+        );
+        assert_eq!(target, parse(r#"{ "a": [1], "b": { "d": 3, "e": 4 } }"#));
+    }
+
+    #[test]
+    fn patches_nested_settings() {
+        let mut settings = Settings::default();
+        settings.tunnel_options.openvpn.mssfix = Some(1300);
+        let patch =
+            parse(r#"{ "allow_lan": true, "tunnel_options": { "openvpn": { "mssfix": null } } }"#);
+
+        let patched = settings.patched(&patch).unwrap();
+
+        assert!(patched.get_allow_lan());
+        assert_eq!(patched.get_tunnel_options().openvpn.mssfix, None);
+        assert_eq!(patched.relay_settings, settings.relay_settings);
+    }
+
+    #[test]
+    fn ignores_revision() {
+        let settings = Settings::default();
+        let patched = settings
+            .patched(&parse(r#"{ "revision": 7, "auto_connect": true }"#))
+            .unwrap();
+
+        assert_eq!(patched.get_revision(), settings.get_revision());
+        assert!(patched.get_auto_connect());
+    }
+
+    #[test]
+    fn reports_each_invalid_field() {
+        let patch = parse(
+            r#"{"allow_lan": "no", "auto_connect": true, "lockdown": 1, "settings_version": 1}"#,
+        );
+
+        let mut fields = Settings::default()
+            .patched(&patch)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.field)
+            .collect::<Vec<_>>();
+        fields.sort();
+
+        assert_eq!(fields, ["allow_lan", "lockdown", "settings_version"]);
+    }
+
+    #[test]
+    fn refuses_to_change_account_token() {
+        let mut settings = Settings::default();
+        settings.account_token = Some("1234".to_owned());
+
+        let errors = settings
+            .patched(&parse(r#"{ "account_token": "5678" }"#))
+            .unwrap_err();
+        assert_eq!(errors[0].field, "account_token");
+        assert!(settings
+            .patched(&parse(r#"{ "account_token": null }"#))
+            .is_err());
+
+        let patched = settings
+            .patched(&parse(r#"{ "account_token": "1234", "allow_lan": true }"#))
+            .unwrap();
+        assert_eq!(patched.get_account_token(), Some("1234".to_owned()));
+        assert!(patched.get_allow_lan());
+    }
+}
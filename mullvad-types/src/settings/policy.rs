@@ -0,0 +1,154 @@
+//! Settings enforced by a system administrator. The policy file holds a JSON object with a subset
+//! of the settings keys, such as `{ "allow_lan": false }`. Each key replaces the whole setting and
+//! locks it, so clients can't change it.
+//!
+//! Only existing settings can be locked. The daemon has no lockdown mode that blocks traffic while
+//! disconnected, so a policy can't enforce one.
+
+use super::serde_json::{self, Map, Value};
+use super::{ErrorKind, Result, ResultExt, Settings, READ_ONLY_KEYS};
+
+use std::fs::File;
+use std::io;
+
+/// A read-only set of settings that override the user's settings.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    settings: Map<String, Value>,
+}
+
+impl Policy {
+    /// Loads the system policy. If no policy file is present, nothing is locked. A policy that
+    /// can't be read, or doesn't describe valid settings, is an error.
+    pub fn load() -> Result<Policy> {
+        let path = ::mullvad_paths::get_policy_path().chain_err(|| ErrorKind::DirectoryError)?;
+        match File::open(&path) {
+            Ok(file) => {
+                info!("Loading system policy from {}", path.display());
+                let settings = serde_json::from_reader(io::BufReader::new(file))
+                    .chain_err(|| ErrorKind::InvalidPolicy(path.clone()))?;
+                Self::from_settings(settings).chain_err(|| ErrorKind::InvalidPolicy(path))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("No system policy at {}", path.display());
+                Ok(Policy::default())
+            }
+            Err(e) => Err(e).chain_err(|| ErrorKind::ReadError(path)),
+        }
+    }
+
+    fn from_settings(settings: Map<String, Value>) -> Result<Policy> {
+        let lockable_keys = Self::lockable_keys();
+        if let Some(key) = settings.keys().find(|key| !lockable_keys.contains(key)) {
+            bail!(ErrorKind::UnknownPolicyKey(key.clone()));
+        }
+        let policy = Policy { settings };
+        policy.enforce(&mut Settings::default())?;
+        Ok(policy)
+    }
+
+    fn lockable_keys() -> Vec<String> {
+        match serde_json::to_value(Settings::default()) {
+            Ok(Value::Object(settings)) => settings
+                .into_iter()
+                .map(|(key, _)| key)
+                .filter(|key| !READ_ONLY_KEYS.contains(&key.as_str()))
+                .collect(),
+            _ => unreachable!("Settings are not serialized as an object"),
+        }
+    }
+
+    /// Returns true if the setting `key` is set by the policy and can't be changed.
+    pub fn is_locked(&self, key: &str) -> bool {
+        self.settings.contains_key(key)
+    }
+
+    /// Overrides `settings` with the values in the policy and marks those settings as locked.
+    /// The policy is only an overlay. The user's own values of the overridden settings are kept,
+    /// and saved in place of the policy's values.
+    pub fn enforce(&self, settings: &mut Settings) -> Result<()> {
+        if self.settings.is_empty() {
+            return Ok(());
+        }
+        let mut merged_settings = match serde_json::to_value(&*settings) {
+            Ok(Value::Object(merged_settings)) => merged_settings,
+            _ => unreachable!("Settings are not serialized as an object"),
+        };
+        let mut overridden_values = Map::new();
+        for (key, value) in &self.settings {
+            // Settings that are already enforced still hold the user's value on the side.
+            let user_value = match settings.overridden_values.get(key) {
+                Some(user_value) => Some(user_value.clone()),
+                None => merged_settings.get(key).cloned(),
+            };
+            if let Some(user_value) = user_value {
+                overridden_values.insert(key.clone(), user_value);
+            }
+            merged_settings.insert(key.clone(), value.clone());
+        }
+        let mut merged_settings: Settings = serde_json::from_value(Value::Object(merged_settings))
+            .chain_err(|| ErrorKind::ParseError)?;
+        merged_settings.locked_keys = self.settings.keys().cloned().collect();
+        merged_settings.overridden_values = overridden_values;
+        *settings = merged_settings;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(json: &str) -> Result<Policy> {
+        let settings = serde_json::from_str(json).unwrap();
+        Policy::from_settings(settings)
+    }
+
+    #[test]
+    fn enforces_and_locks_settings() {
+        let policy = policy(r#"{ "allow_lan": false, "auto_connect": true }"#).unwrap();
+        let mut settings = Settings::default();
+        settings.allow_lan = true;
+
+        policy.enforce(&mut settings).unwrap();
+
+        assert!(!settings.get_allow_lan());
+        assert!(settings.get_auto_connect());
+        assert!(policy.is_locked("allow_lan"));
+        assert!(!policy.is_locked("relay_settings"));
+        let mut locked_keys = settings.get_locked_keys().to_vec();
+        locked_keys.sort();
+        assert_eq!(locked_keys, ["allow_lan", "auto_connect"]);
+    }
+
+    #[test]
+    fn keeps_the_overridden_user_settings() {
+        let policy = policy(r#"{ "allow_lan": false }"#).unwrap();
+        let mut settings = Settings::default();
+        settings.allow_lan = true;
+        settings.auto_connect = true;
+
+        policy.enforce(&mut settings).unwrap();
+        // Enforcing the policy again must not replace the user's value with the policy's.
+        policy.enforce(&mut settings).unwrap();
+        let stored_settings = settings.to_stored_value();
+
+        assert!(!settings.get_allow_lan());
+        assert_eq!(stored_settings["allow_lan"], Value::Bool(true));
+        assert!(stored_settings.get("locked_keys").is_none());
+        // With the policy removed, the stored settings are the user's settings from before.
+        let restored_settings = Settings::from_json(stored_settings).unwrap();
+        assert!(restored_settings.get_allow_lan());
+        assert!(restored_settings.get_auto_connect());
+        assert!(restored_settings.get_locked_keys().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_policies() {
+        assert!(policy(r#"{ "allow_lan": "no" }"#).is_err());
+        // There is no lockdown mode to lock.
+        assert!(policy(r#"{ "lockdown": true }"#).is_err());
+        assert!(policy(r#"{ "settings_version": 1 }"#).is_err());
+    }
+}
@@ -2,7 +2,7 @@ extern crate mnl;
 
 use error_chain::ChainedError;
 
-use ipnetwork::IpNetwork;
+use ipnetwork::{IpNetwork, Ipv6Network};
 use libc;
 use nftnl::{
     self,
@@ -15,7 +15,7 @@ use tunnel;
 use std::env;
 use std::ffi::CString;
 use std::io;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::Path;
 
 use super::{NetworkSecurityT, SecurityPolicy};
@@ -52,6 +52,12 @@ lazy_static! {
     static ref IN_CHAIN_NAME: CString = CString::new("in").unwrap();
     static ref OUT_CHAIN_NAME: CString = CString::new("out").unwrap();
 
+    /// Link-local unicast and multicast networks used by the IPv6 neighbor discovery protocol.
+    static ref NDP_INET6_NETS: [Ipv6Network; 2] = [
+        Ipv6Network::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).unwrap(),
+        Ipv6Network::new(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0), 16).unwrap(),
+    ];
+
     /// Allows controlling whether firewall rules should have packet counters or not from an env
     /// variable. Useful for debugging the rules.
     static ref ADD_COUNTERS: bool = env::var("TALPID_NFTABLES_COUNTERS")
@@ -87,14 +93,18 @@ impl NetworkSecurityT for NetworkSecurity {
         })
     }
 
-    fn apply_policy(&mut self, policy: SecurityPolicy) -> Result<()> {
+    fn apply_policy(
+        &mut self,
+        policy: SecurityPolicy,
+        allowed_incoming: Option<SocketAddr>,
+    ) -> Result<()> {
         if let SecurityPolicy::Connected { ref tunnel, .. } = policy {
             self.dns_settings
                 .set_dns(&tunnel.interface, vec![tunnel.gateway.into()])?;
         }
 
         let table = Table::new(&self.table_name, ProtoFamily::Inet)?;
-        let batch = PolicyBatch::new(&table)?.finalize(&policy)?;
+        let batch = PolicyBatch::new(&table)?.finalize(&policy, allowed_incoming)?;
         self.send_and_process(&batch)
     }
 
@@ -186,11 +196,21 @@ impl<'a> PolicyBatch<'a> {
     }
 
     /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
-    /// policy.
-    pub fn finalize(mut self, policy: &SecurityPolicy) -> Result<FinalizedBatch> {
+    /// policy, and to accept incoming connections to `allowed_incoming`.
+    pub fn finalize(
+        mut self,
+        policy: &SecurityPolicy,
+        allowed_incoming: Option<SocketAddr>,
+    ) -> Result<FinalizedBatch> {
         self.add_loopback_rules()?;
         self.add_dhcp_rules()?;
+        if allows_ndp(policy) {
+            self.add_ndp_rules()?;
+        }
         self.add_policy_specific_rules(policy)?;
+        if let Some(address) = allowed_incoming {
+            self.add_allow_incoming_rules(address)?;
+        }
 
         Ok(self.batch.finalize()?)
     }
@@ -220,6 +240,26 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Allows ICMPv6 to and from link-local addresses. Without neighbor discovery the host can't
+    /// reach its IPv6 router, and thereby not an IPv6 relay endpoint either. Only added when
+    /// `allows_ndp` is true for the policy.
+    fn add_ndp_rules(&mut self) -> Result<()> {
+        for net in &*NDP_INET6_NETS {
+            let mut out_rule = Rule::new(&self.out_chain)?;
+            check_net(&mut out_rule, End::Dst, IpNetwork::V6(*net))?;
+            check_icmpv6(&mut out_rule)?;
+            add_verdict(&mut out_rule, Verdict::Accept)?;
+            self.batch.add(&out_rule, nftnl::MsgType::Add)?;
+        }
+
+        let mut in_rule = Rule::new(&self.in_chain)?;
+        check_net(&mut in_rule, End::Src, IpNetwork::V6(NDP_INET6_NETS[0]))?;
+        check_icmpv6(&mut in_rule)?;
+        add_verdict(&mut in_rule, Verdict::Accept)?;
+        self.batch.add(&in_rule, nftnl::MsgType::Add)?;
+        Ok(())
+    }
+
     fn add_policy_specific_rules(&mut self, policy: &SecurityPolicy) -> Result<()> {
         let allow_lan = match policy {
             SecurityPolicy::Connecting {
@@ -271,6 +311,36 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Accepts TCP connections from any host to the local `address`, and the replies to them.
+    fn add_allow_incoming_rules(&mut self, address: SocketAddr) -> Result<()> {
+        let (ip, port) = (address.ip(), address.port());
+
+        let mut in_rule = Rule::new(&self.in_chain)?;
+        if !ip.is_unspecified() {
+            check_ip(&mut in_rule, End::Dst, ip)?;
+        }
+        check_port(&mut in_rule, TransportProtocol::Tcp, End::Dst, port)?;
+        add_verdict(&mut in_rule, Verdict::Accept)?;
+
+        self.batch.add(&in_rule, nftnl::MsgType::Add)?;
+
+        let mut out_rule = Rule::new(&self.out_chain)?;
+        if !ip.is_unspecified() {
+            check_ip(&mut out_rule, End::Src, ip)?;
+        }
+        check_port(&mut out_rule, TransportProtocol::Tcp, End::Src, port)?;
+
+        out_rule.add_expr(&nft_expr!(ct state))?;
+        let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
+        out_rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32))?;
+        out_rule.add_expr(&nft_expr!(cmp != 0u32))?;
+        add_verdict(&mut out_rule, Verdict::Accept)?;
+
+        self.batch.add(&out_rule, nftnl::MsgType::Add)?;
+
+        Ok(())
+    }
+
     fn add_dns_rule(
         &mut self,
         tunnel: &tunnel::TunnelMetadata,
@@ -353,6 +423,16 @@ impl<'a> PolicyBatch<'a> {
     }
 }
 
+/// Returns true if the policy needs the IPv6 neighbor discovery protocol, which is only the case
+/// when connecting to, or connected through, a relay over IPv6. The tunnel itself is IPv4 only.
+fn allows_ndp(policy: &SecurityPolicy) -> bool {
+    match policy {
+        SecurityPolicy::Connecting { relay_endpoint, .. }
+        | SecurityPolicy::Connected { relay_endpoint, .. } => relay_endpoint.address.is_ipv6(),
+        SecurityPolicy::Blocked { .. } => false,
+    }
+}
+
 fn allow_dhcp_rule<'a>(chain: &'a Chain, direction: Direction) -> Result<Rule<'a>> {
     const SERVER_PORT: u16 = 67;
     const CLIENT_PORT: u16 = 68;
@@ -489,6 +569,13 @@ fn check_l4proto(rule: &mut Rule, protocol: TransportProtocol) -> Result<()> {
     Ok(())
 }
 
+fn check_icmpv6(rule: &mut Rule) -> Result<()> {
+    let icmpv6 = libc::IPPROTO_ICMPV6 as u8;
+    rule.add_expr(&nft_expr!(meta l4proto))?;
+    rule.add_expr(&nft_expr!(cmp == icmpv6))?;
+    Ok(())
+}
+
 fn l4proto(protocol: TransportProtocol) -> u8 {
     match protocol {
         TransportProtocol::Udp => libc::IPPROTO_UDP as u8,
@@ -502,3 +589,56 @@ fn add_verdict(rule: &mut Rule, verdict: expr::Verdict) -> Result<()> {
     }
     Ok(rule.add_expr(&verdict)?)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(address: IpAddr) -> Endpoint {
+        Endpoint::new(address, 1194, TransportProtocol::Udp)
+    }
+
+    fn tunnel() -> tunnel::TunnelMetadata {
+        tunnel::TunnelMetadata {
+            interface: "tun0".to_owned(),
+            ip: Ipv4Addr::new(10, 8, 0, 2),
+            gateway: Ipv4Addr::new(10, 8, 0, 1),
+        }
+    }
+
+    #[test]
+    fn blocked_policy_does_not_allow_ndp() {
+        for &allow_lan in &[false, true] {
+            assert!(!allows_ndp(&SecurityPolicy::Blocked { allow_lan }));
+        }
+    }
+
+    #[test]
+    fn ipv4_relays_do_not_allow_ndp() {
+        let relay_endpoint = endpoint(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(!allows_ndp(&SecurityPolicy::Connecting {
+            relay_endpoint,
+            allow_lan: false,
+        }));
+        assert!(!allows_ndp(&SecurityPolicy::Connected {
+            relay_endpoint,
+            tunnel: tunnel(),
+            allow_lan: false,
+        }));
+    }
+
+    #[test]
+    fn ipv6_relays_allow_ndp() {
+        let relay_endpoint = endpoint(IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)));
+        assert!(allows_ndp(&SecurityPolicy::Connecting {
+            relay_endpoint,
+            allow_lan: false,
+        }));
+        assert!(allows_ndp(&SecurityPolicy::Connected {
+            relay_endpoint,
+            tunnel: tunnel(),
+            allow_lan: false,
+        }));
+    }
+}
@@ -5,7 +5,7 @@ use super::{NetworkSecurityT, SecurityPolicy};
 
 use ipnetwork::IpNetwork;
 
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::path::Path;
 
 use talpid_types::net;
@@ -43,10 +43,14 @@ impl NetworkSecurityT for NetworkSecurity {
         })
     }
 
-    fn apply_policy(&mut self, policy: SecurityPolicy) -> Result<()> {
+    fn apply_policy(
+        &mut self,
+        policy: SecurityPolicy,
+        allowed_incoming: Option<SocketAddr>,
+    ) -> Result<()> {
         self.enable()?;
         self.add_anchor()?;
-        self.set_rules(policy)
+        self.set_rules(policy, allowed_incoming)
     }
 
     fn reset_policy(&mut self) -> Result<()> {
@@ -62,12 +66,19 @@ impl NetworkSecurityT for NetworkSecurity {
 }
 
 impl NetworkSecurity {
-    fn set_rules(&mut self, policy: SecurityPolicy) -> Result<()> {
+    fn set_rules(
+        &mut self,
+        policy: SecurityPolicy,
+        allowed_incoming: Option<SocketAddr>,
+    ) -> Result<()> {
         let mut new_filter_rules = vec![];
 
         new_filter_rules.append(&mut Self::get_allow_loopback_rules()?);
         new_filter_rules.append(&mut Self::get_allow_dhcp_rules()?);
         new_filter_rules.append(&mut self.get_policy_specific_rules(policy)?);
+        if let Some(address) = allowed_incoming {
+            new_filter_rules.push(Self::get_allow_incoming_rule(address)?);
+        }
 
         let drop_all_rule = pfctl::FilterRuleBuilder::default()
             .action(pfctl::FilterRuleAction::Drop)
@@ -171,6 +182,24 @@ impl NetworkSecurity {
             .build()?)
     }
 
+    /// Accepts TCP connections from any host to the local `address`, and the replies to them.
+    fn get_allow_incoming_rule(address: SocketAddr) -> Result<pfctl::FilterRule> {
+        let mut rule_builder = pfctl::FilterRuleBuilder::default();
+        rule_builder
+            .action(pfctl::FilterRuleAction::Pass)
+            .direction(pfctl::Direction::In)
+            .proto(pfctl::Proto::Tcp)
+            .keep_state(pfctl::StatePolicy::Keep)
+            .tcp_flags(Self::get_tcp_flags())
+            .quick(true);
+        if address.ip().is_unspecified() {
+            rule_builder.to(pfctl::Port::from(address.port()));
+        } else {
+            rule_builder.to(address);
+        }
+        Ok(rule_builder.build()?)
+    }
+
     fn get_allow_tunnel_rule(tunnel_interface: &str) -> Result<pfctl::FilterRule> {
         Ok(pfctl::FilterRuleBuilder::default()
             .action(pfctl::FilterRuleAction::Pass)
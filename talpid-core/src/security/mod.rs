@@ -1,6 +1,7 @@
 #[cfg(unix)]
 use ipnetwork::{Ipv4Network, Ipv6Network};
 use std::fmt;
+use std::net::SocketAddr;
 #[cfg(unix)]
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::Path;
@@ -104,6 +105,9 @@ impl fmt::Display for SecurityPolicy {
 /// by manipulating the OS firewall and DNS settings.
 pub struct NetworkSecurity {
     inner: imp::NetworkSecurity,
+    /// The policy currently being enforced, if any.
+    policy: Option<SecurityPolicy>,
+    allowed_incoming: Option<SocketAddr>,
 }
 
 impl NetworkSecurity {
@@ -111,22 +115,55 @@ impl NetworkSecurity {
     pub fn new(cache_dir: impl AsRef<Path>) -> Result<Self, Error> {
         Ok(NetworkSecurity {
             inner: imp::NetworkSecurity::new(cache_dir)?,
+            policy: None,
+            allowed_incoming: None,
         })
     }
 
     /// Applies and starts enforcing the given `SecurityPolicy` Makes sure it is being kept in place
     /// until this method is called again with another policy, or until `reset_policy` is called.
     pub fn apply_policy(&mut self, policy: SecurityPolicy) -> Result<(), Error> {
-        info!("Applying security policy: {}", policy);
-        self.inner.apply_policy(policy)
+        match self.allowed_incoming {
+            Some(address) => info!(
+                "Applying security policy: {}, accepting connections to {}",
+                policy, address
+            ),
+            None => info!("Applying security policy: {}", policy),
+        }
+        self.policy = Some(policy.clone());
+        self.inner.apply_policy(policy, self.allowed_incoming)
     }
 
     /// Resets/removes any currently enforced `SecurityPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `NetworkSecurity` instance.
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         info!("Resetting security policy");
+        self.policy = None;
         self.inner.reset_policy()
     }
+
+    /// Accepts incoming TCP connections from any host to a listener on the local `address`, or
+    /// stops accepting them if `None`. Should be set while the listener is running, and only
+    /// then. Applies to the policy currently being enforced and all policies applied after it.
+    /// Nothing is blocked while no policy is enforced, so then this only takes effect with the
+    /// next policy.
+    pub fn set_allowed_incoming(&mut self, address: Option<SocketAddr>) -> Result<(), Error> {
+        let address = incoming_allowance(address);
+        if address == self.allowed_incoming {
+            return Ok(());
+        }
+        self.allowed_incoming = address;
+        match self.policy.take() {
+            Some(policy) => self.apply_policy(policy),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the address to accept incoming connections to for a listener on `address`. Loopback
+/// traffic is always accepted, so listeners on loopback addresses need no rules of their own.
+fn incoming_allowance(address: Option<SocketAddr>) -> Option<SocketAddr> {
+    address.filter(|address| !address.ip().is_loopback())
 }
 
 
@@ -138,10 +175,38 @@ trait NetworkSecurityT: Sized {
     /// Create new instance
     fn new(cache_dir: impl AsRef<Path>) -> ::std::result::Result<Self, Self::Error>;
 
-    /// Enable the given SecurityPolicy
-    fn apply_policy(&mut self, policy: SecurityPolicy) -> ::std::result::Result<(), Self::Error>;
+    /// Enable the given SecurityPolicy, also accepting incoming TCP connections to
+    /// `allowed_incoming` if it's set.
+    fn apply_policy(
+        &mut self,
+        policy: SecurityPolicy,
+        allowed_incoming: Option<SocketAddr>,
+    ) -> ::std::result::Result<(), Self::Error>;
 
     /// Revert the system network security state to what it was before this instance started
     /// modifying the system.
     fn reset_policy(&mut self) -> ::std::result::Result<(), Self::Error>;
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_incoming_connections_only_to_a_configured_listener() {
+        assert_eq!(incoming_allowance(None), None);
+
+        let lan_address = "192.168.1.2:5051".parse().unwrap();
+        assert_eq!(incoming_allowance(Some(lan_address)), Some(lan_address));
+        let any_address = "[::]:5051".parse().unwrap();
+        assert_eq!(incoming_allowance(Some(any_address)), Some(any_address));
+    }
+
+    #[test]
+    fn loopback_listeners_need_no_allowance() {
+        for address in &["127.0.0.1:5051", "[::1]:5051"] {
+            assert_eq!(incoming_allowance(Some(address.parse().unwrap())), None);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
 use std::ptr;
 
@@ -82,7 +82,17 @@ impl NetworkSecurityT for NetworkSecurity {
         Ok(NetworkSecurity { dns: windns })
     }
 
-    fn apply_policy(&mut self, policy: SecurityPolicy) -> Result<()> {
+    fn apply_policy(
+        &mut self,
+        policy: SecurityPolicy,
+        allowed_incoming: Option<SocketAddr>,
+    ) -> Result<()> {
+        if let Some(address) = allowed_incoming {
+            warn!(
+                "Unable to accept incoming connections to {}, not supported on Windows",
+                address
+            );
+        }
         match policy {
             SecurityPolicy::Connecting {
                 relay_endpoint,
@@ -52,6 +52,10 @@ impl TunnelState for BlockedState {
                 Self::set_security_policy(shared_values, allow_lan);
                 SameState(self)
             }
+            Ok(TunnelCommand::AllowIncoming(address)) => {
+                shared_values.set_allowed_incoming(address);
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect(parameters)) => {
                 NewState(ConnectingState::enter(shared_values, parameters))
             }
@@ -86,6 +86,10 @@ impl ConnectedState {
                     }
                 }
             }
+            Ok(TunnelCommand::AllowIncoming(address)) => {
+                shared_values.set_allowed_incoming(address);
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect(parameters)) => {
                 if parameters != self.tunnel_parameters {
                     NewState(DisconnectingState::enter(
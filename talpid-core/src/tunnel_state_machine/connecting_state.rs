@@ -206,6 +206,10 @@ impl ConnectingState {
                     }
                 }
             }
+            Ok(TunnelCommand::AllowIncoming(address)) => {
+                shared_values.set_allowed_incoming(address);
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect(parameters)) => {
                 if parameters != self.tunnel_parameters {
                     NewState(DisconnectingState::enter(
@@ -42,6 +42,10 @@ impl TunnelState for DisconnectedState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, commands.poll()) {
+            Ok(TunnelCommand::AllowIncoming(address)) => {
+                shared_values.set_allowed_incoming(address);
+                SameState(self)
+            }
             Ok(TunnelCommand::Connect(parameters)) => {
                 NewState(ConnectingState::enter(shared_values, parameters))
             }
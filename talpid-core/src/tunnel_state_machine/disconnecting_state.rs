@@ -24,10 +24,15 @@ impl DisconnectingState {
     fn handle_commands(
         mut self,
         commands: &mut mpsc::UnboundedReceiver<TunnelCommand>,
+        shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence<Self> {
         use self::AfterDisconnect::*;
 
         let event = try_handle_event!(self, commands.poll());
+        if let Ok(TunnelCommand::AllowIncoming(address)) = event {
+            shared_values.set_allowed_incoming(address);
+            return EventConsequence::SameState(self);
+        }
         let after_disconnect = self.after_disconnect;
 
         self.after_disconnect = match after_disconnect {
@@ -49,6 +54,7 @@ impl DisconnectingState {
                     tunnel_parameters.allow_lan = allow_lan;
                     Reconnect(tunnel_parameters)
                 }
+                Ok(TunnelCommand::AllowIncoming(_)) => Reconnect(tunnel_parameters),
                 Ok(TunnelCommand::Connect(parameters)) => Reconnect(parameters),
                 Ok(TunnelCommand::Disconnect) | Err(_) => Nothing,
                 Ok(TunnelCommand::Block(reason, allow_lan)) => Block(reason, allow_lan),
@@ -119,7 +125,7 @@ impl TunnelState for DisconnectingState {
         commands: &mut mpsc::UnboundedReceiver<TunnelCommand>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence<Self> {
-        self.handle_commands(commands)
+        self.handle_commands(commands, shared_values)
             .or_else(Self::handle_exit_event, shared_values)
     }
 }
@@ -8,6 +8,7 @@ mod disconnected_state;
 mod disconnecting_state;
 
 use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc as sync_mpsc;
 use std::thread;
@@ -106,6 +107,9 @@ where
 pub enum TunnelCommand {
     /// Enable or disable LAN access in the firewall.
     AllowLan(bool),
+    /// Accept incoming TCP connections to a local address in the firewall, or stop accepting them
+    /// if `None`.
+    AllowIncoming(Option<SocketAddr>),
     /// Open tunnel connection.
     Connect(TunnelParameters),
     /// Close tunnel connection.
@@ -217,6 +221,19 @@ struct SharedTunnelStateValues {
     security: NetworkSecurity,
 }
 
+impl SharedTunnelStateValues {
+    /// Accepts incoming connections to `address` in the current and future security policies.
+    fn set_allowed_incoming(&mut self, address: Option<SocketAddr>) {
+        if let Err(error) = self
+            .security
+            .set_allowed_incoming(address)
+            .chain_err(|| "Failed to apply security policy with incoming connections")
+        {
+            error!("{}", error.display_chain());
+        }
+    }
+}
+
 /// Asynchronous result of an attempt to progress a state.
 enum EventConsequence<T: TunnelState> {
     /// Transition to a new state.
@@ -23,11 +23,23 @@ extern crate jsonrpc_client_core;
 extern crate jsonrpc_client_ipc;
 extern crate tokio;
 
+#[cfg(unix)]
+extern crate libc;
+#[macro_use]
+extern crate log;
+#[cfg(test)]
+extern crate uuid;
+
 use futures::Future;
 use std::thread;
 
 use jsonrpc_core::{MetaIoHandler, Metadata};
-use jsonrpc_ipc_server::{MetaExtractor, NoopExtractor, SecurityAttributes, Server, ServerBuilder};
+use jsonrpc_ipc_server::{
+    MetaExtractor, NoopExtractor, RequestContext, SecurityAttributes, Server, ServerBuilder,
+};
+
+mod peer_credentials;
+pub use peer_credentials::PeerCredentials;
 
 
 use std::fmt;
@@ -51,6 +63,9 @@ error_chain!{
 pub struct IpcServer {
     path: String,
     server: Server,
+    /// The proxy reading the credentials of clients, if the server was started with one.
+    #[cfg(unix)]
+    proxy: Option<peer_credentials::ProxyHandle>,
 }
 
 impl IpcServer {
@@ -84,6 +99,8 @@ impl IpcServer {
             }).map(|server| IpcServer {
                 path: path.to_owned(),
                 server,
+                #[cfg(unix)]
+                proxy: None,
             })?;
 
         #[cfg(unix)]
@@ -96,6 +113,68 @@ impl IpcServer {
         Ok(server)
     }
 
+    /// Starts a server where `meta_extractor` is also given the credentials of the process that
+    /// opened each connection. The server itself listens on a socket next to `path`, only
+    /// accessible to the current user, and a proxy listening on `path` forwards each client to it
+    /// after reading the credentials of the client.
+    #[cfg(unix)]
+    pub fn start_with_peer_credentials<M, F>(
+        handler: MetaIoHandler<M>,
+        meta_extractor: F,
+        path: String,
+    ) -> Result<Self>
+    where
+        M: Metadata + Default,
+        F: Fn(&RequestContext, Option<PeerCredentials>) -> M + Send + Sync + 'static,
+    {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        use std::sync::Arc;
+
+        let server_path = peer_credentials::private_server_path(&path)
+            .chain_err(|| ErrorKind::PermissionsError)?;
+        let handoff = Arc::new(peer_credentials::CredentialsHandoff::default());
+        let extractor_handoff = handoff.clone();
+        let mut server = Self::start_with_metadata(
+            handler,
+            move |context: &RequestContext| meta_extractor(context, extractor_handoff.take()),
+            server_path.to_string_lossy().to_string(),
+        )?;
+
+        let proxy = match peer_credentials::start_proxy(&path, server_path, handoff) {
+            Ok(proxy) => proxy,
+            Err(error) => {
+                server.close_handle().close();
+                return Err(error).chain_err(|| ErrorKind::IpcServerError);
+            }
+        };
+        server.proxy = Some(proxy);
+        fs::set_permissions(&path, PermissionsExt::from_mode(0o766))
+            .chain_err(|| ErrorKind::PermissionsError)?;
+        server.path = path;
+        Ok(server)
+    }
+
+    /// Starts a server where `meta_extractor` is also given the credentials of the process that
+    /// opened each connection. Credentials are not available on Windows, so they are always
+    /// `None`.
+    #[cfg(not(unix))]
+    pub fn start_with_peer_credentials<M, F>(
+        handler: MetaIoHandler<M>,
+        meta_extractor: F,
+        path: String,
+    ) -> Result<Self>
+    where
+        M: Metadata + Default,
+        F: Fn(&RequestContext, Option<PeerCredentials>) -> M + Send + Sync + 'static,
+    {
+        Self::start_with_metadata(
+            handler,
+            move |context: &RequestContext| meta_extractor(context, None),
+            path,
+        )
+    }
+
     /// Returns the uds/named pipe path this `IpcServer` is listening on.
     pub fn path(&self) -> &str {
         &self.path
@@ -103,13 +182,24 @@ impl IpcServer {
 
     /// Creates a handle bound to this `IpcServer` that can be used to shut it down.
     pub fn close_handle(&self) -> CloseHandle {
-        CloseHandle(self.server.close_handle())
+        CloseHandle {
+            server: self.server.close_handle(),
+            #[cfg(unix)]
+            proxy: self.proxy.clone(),
+        }
     }
 
     /// Consumes the server and waits for it to finish. Get a `CloseHandle` before calling this
-    /// if you want to be able to shut the server down.
+    /// if you want to be able to shut the server down. The proxy in front of the server, if any,
+    /// is closed once the server has finished.
     pub fn wait(self) {
         self.server.wait();
+        #[cfg(unix)]
+        {
+            if let Some(proxy) = self.proxy {
+                proxy.close();
+            }
+        }
     }
 }
 
@@ -124,10 +214,22 @@ impl fmt::Debug for IpcServer {
 }
 
 #[derive(Clone)]
-pub struct CloseHandle(jsonrpc_ipc_server::CloseHandle);
+pub struct CloseHandle {
+    server: jsonrpc_ipc_server::CloseHandle,
+    #[cfg(unix)]
+    proxy: Option<peer_credentials::ProxyHandle>,
+}
 
 impl CloseHandle {
+    /// Shuts the server down, along with the proxy in front of it, if any. Clients connected
+    /// through the proxy are disconnected.
     pub fn close(self) {
-        self.0.close();
+        self.server.close();
+        #[cfg(unix)]
+        {
+            if let Some(proxy) = self.proxy {
+                proxy.close();
+            }
+        }
     }
 }
@@ -0,0 +1,367 @@
+//! Identifies the processes connecting to an `IpcServer`.
+//!
+//! The JSON-RPC server does not expose the sockets of its connections, so on Unix the server
+//! listens on a socket in a directory only its own user can access, and clients instead connect
+//! to a proxy listening on the public path. The proxy reads the credentials of each client from
+//! its socket and hands them over to the metadata extractor of the server while it connects the
+//! client to the server. After that it only forwards bytes in both directions, until the proxy
+//! is closed along with the server.
+
+/// The user and group of a process connected to an `IpcServer`, as recorded by the kernel when it
+/// connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// The process id of the peer. Not available on macOS.
+    pub pid: Option<u32>,
+}
+
+#[cfg(unix)]
+pub(crate) use self::unix::{private_server_path, start_proxy, CredentialsHandoff, ProxyHandle};
+
+#[cfg(unix)]
+mod unix {
+    use super::PeerCredentials;
+    use libc;
+
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io;
+    use std::mem;
+    use std::net::Shutdown;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// How long the proxy waits for the server to accept a connection and take its credentials.
+    const HANDOFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Returns the credentials of the process on the other end of `stream`.
+    #[cfg(target_os = "linux")]
+    pub fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+        let mut ucred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut length = mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut ucred as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            uid: ucred.uid,
+            gid: ucred.gid,
+            pid: Some(ucred.pid as u32),
+        })
+    }
+
+    /// Returns the credentials of the process on the other end of `stream`.
+    #[cfg(not(target_os = "linux"))]
+    pub fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+        let mut uid = 0;
+        let mut gid = 0;
+        if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            uid,
+            gid,
+            pid: None,
+        })
+    }
+
+    /// Passes the credentials of a client from the proxy to the metadata extractor of the server.
+    /// Only one connection is made to the server at a time, so the connection the server accepts
+    /// while credentials are pending is the one made by the proxy for that client.
+    #[derive(Default)]
+    pub struct CredentialsHandoff {
+        connect_lock: Mutex<()>,
+        pending: Mutex<Option<PeerCredentials>>,
+        taken: Condvar,
+    }
+
+    impl CredentialsHandoff {
+        /// Takes the credentials of the client currently being connected to the server, if any.
+        pub fn take(&self) -> Option<PeerCredentials> {
+            let credentials = self.pending.lock().unwrap().take();
+            self.taken.notify_all();
+            credentials
+        }
+
+        /// Connects to the server on behalf of a client with the given credentials, and waits
+        /// until the server has taken them.
+        fn connect(
+            &self,
+            server_path: &Path,
+            credentials: PeerCredentials,
+        ) -> io::Result<UnixStream> {
+            let _connect_guard = self.connect_lock.lock().unwrap();
+            *self.pending.lock().unwrap() = Some(credentials);
+
+            let result = UnixStream::connect(server_path);
+            let mut pending = self.pending.lock().unwrap();
+            if result.is_ok() {
+                while pending.is_some() {
+                    let (guard, wait_result) =
+                        self.taken.wait_timeout(pending, HANDOFF_TIMEOUT).unwrap();
+                    pending = guard;
+                    if wait_result.timed_out() {
+                        break;
+                    }
+                }
+            }
+            if pending.take().is_some() && result.is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "The server did not accept the connection",
+                ));
+            }
+            result
+        }
+    }
+
+    /// Returns the path of the socket the server listens on when clients connect through a proxy
+    /// at `path`. The directory of the socket is created and made accessible only to the current
+    /// user, and any old socket in it is removed.
+    pub fn private_server_path(path: &str) -> io::Result<PathBuf> {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+        let dir = PathBuf::from(format!("{}.private", path));
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+
+        let server_path = dir.join("socket");
+        match fs::remove_file(&server_path) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        Ok(server_path)
+    }
+
+    /// Stops a proxy started by `start_proxy`. Closing the proxy stops it from accepting new
+    /// clients and disconnects the clients it forwards, so all of its threads exit.
+    #[derive(Clone)]
+    pub struct ProxyHandle {
+        path: PathBuf,
+        connections: Arc<Mutex<Connections>>,
+    }
+
+    /// The streams of the clients currently forwarded by a proxy, and of their connections to the
+    /// server.
+    #[derive(Default)]
+    struct Connections {
+        closed: bool,
+        next_id: u64,
+        streams: HashMap<u64, (UnixStream, UnixStream)>,
+    }
+
+    /// Removes the streams of a client from `Connections` once both directions of it have been
+    /// forwarded to the end.
+    struct Connection {
+        id: u64,
+        connections: Arc<Mutex<Connections>>,
+    }
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            self.connections.lock().unwrap().streams.remove(&self.id);
+        }
+    }
+
+    impl ProxyHandle {
+        /// Closes the proxy. Does nothing if it's already closed.
+        pub fn close(&self) {
+            let mut connections = self.connections.lock().unwrap();
+            if connections.closed {
+                return;
+            }
+            connections.closed = true;
+            for (_, (client, server)) in connections.streams.drain() {
+                let _ = client.shutdown(Shutdown::Both);
+                let _ = server.shutdown(Shutdown::Both);
+            }
+            // Wakes the listener thread up, so it sees that the proxy is closed.
+            let _ = UnixStream::connect(&self.path);
+        }
+
+        fn is_closed(&self) -> bool {
+            self.connections.lock().unwrap().closed
+        }
+
+        /// Keeps track of the streams of a client, so they can be shut down when the proxy is
+        /// closed. Fails if the proxy has already been closed.
+        fn add_connection(
+            &self,
+            client: &UnixStream,
+            server: &UnixStream,
+        ) -> io::Result<Connection> {
+            let mut connections = self.connections.lock().unwrap();
+            if connections.closed {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "The proxy has been closed",
+                ));
+            }
+            let id = connections.next_id;
+            connections.next_id += 1;
+            connections
+                .streams
+                .insert(id, (client.try_clone()?, server.try_clone()?));
+            Ok(Connection {
+                id,
+                connections: self.connections.clone(),
+            })
+        }
+    }
+
+    /// Listens for clients on `path` and forwards each of them to the server at `server_path`,
+    /// until the proxy is closed with the returned handle.
+    pub fn start_proxy(
+        path: &str,
+        server_path: PathBuf,
+        handoff: Arc<CredentialsHandoff>,
+    ) -> io::Result<ProxyHandle> {
+        match fs::remove_file(path) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(path)?;
+        let proxy = ProxyHandle {
+            path: PathBuf::from(path),
+            connections: Arc::new(Mutex::new(Connections::default())),
+        };
+        let listener_proxy = proxy.clone();
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                if listener_proxy.is_closed() {
+                    break;
+                }
+                let result = client
+                    .and_then(|client| forward(client, &server_path, &handoff, &listener_proxy));
+                if let Err(error) = result {
+                    warn!("Unable to forward IPC client to the server: {}", error);
+                }
+            }
+            debug!("IPC proxy closed");
+        });
+        Ok(proxy)
+    }
+
+    fn forward(
+        client: UnixStream,
+        server_path: &Path,
+        handoff: &CredentialsHandoff,
+        proxy: &ProxyHandle,
+    ) -> io::Result<()> {
+        let credentials = peer_credentials(&client)?;
+        trace!("IPC client connected: {:?}", credentials);
+        let server = handoff.connect(server_path, credentials)?;
+        let connection = Arc::new(proxy.add_connection(&client, &server)?);
+        pipe(client.try_clone()?, server.try_clone()?, connection.clone());
+        pipe(server, client, connection);
+        Ok(())
+    }
+
+    /// Copies everything read from `from` to `to` on a new thread, until `from` is closed or the
+    /// proxy is closed.
+    fn pipe(mut from: UnixStream, mut to: UnixStream, connection: Arc<Connection>) {
+        thread::spawn(move || {
+            let _ = io::copy(&mut from, &mut to);
+            let _ = to.shutdown(Shutdown::Write);
+            drop(connection);
+        });
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::env;
+        use std::io::{Read, Write};
+        use std::sync::mpsc;
+        use std::time::Instant;
+        use uuid;
+
+        /// Starts a proxy in front of a server echoing everything back to its clients. The
+        /// credentials of the clients are sent to the returned channel.
+        fn start_echo_proxy() -> (
+            PathBuf,
+            ProxyHandle,
+            mpsc::Receiver<Option<PeerCredentials>>,
+        ) {
+            let path = env::temp_dir().join(format!("talpid-ipc-proxy-{}", uuid::Uuid::new_v4()));
+            let path = path.to_string_lossy().to_string();
+            let server_path = private_server_path(&path).unwrap();
+            let server = UnixListener::bind(&server_path).unwrap();
+            let handoff = Arc::new(CredentialsHandoff::default());
+            let server_handoff = handoff.clone();
+            let (credentials_tx, credentials_rx) = mpsc::channel();
+            thread::spawn(move || {
+                for stream in server.incoming() {
+                    let _ = credentials_tx.send(server_handoff.take());
+                    let mut stream = stream.unwrap();
+                    let mut echo = stream.try_clone().unwrap();
+                    thread::spawn(move || io::copy(&mut stream, &mut echo));
+                }
+            });
+            let proxy = start_proxy(&path, server_path, handoff).unwrap();
+            (PathBuf::from(path), proxy, credentials_rx)
+        }
+
+        #[test]
+        fn forwards_clients_with_their_credentials() {
+            let (path, proxy, credentials_rx) = start_echo_proxy();
+            let mut client = UnixStream::connect(&path).unwrap();
+
+            client.write_all(b"ping").unwrap();
+            let mut response = [0; 4];
+            client.read_exact(&mut response).unwrap();
+
+            assert_eq!(&response, b"ping");
+            let credentials = credentials_rx
+                .recv_timeout(HANDOFF_TIMEOUT)
+                .unwrap()
+                .unwrap();
+            assert_eq!(credentials.uid, unsafe { libc::getuid() });
+            proxy.close();
+        }
+
+        #[test]
+        fn closing_disconnects_clients_and_stops_listening() {
+            let (path, proxy, _credentials_rx) = start_echo_proxy();
+            let mut client = UnixStream::connect(&path).unwrap();
+            client.write_all(b"ping").unwrap();
+            client.read_exact(&mut [0; 4]).unwrap();
+
+            proxy.close();
+
+            client.set_read_timeout(Some(HANDOFF_TIMEOUT)).unwrap();
+            assert_eq!(client.read(&mut [0; 4]).unwrap(), 0);
+            assert!(proxy.connections.lock().unwrap().streams.is_empty());
+
+            // The listener is dropped once its thread has seen that the proxy is closed.
+            let start = Instant::now();
+            while UnixStream::connect(&path).is_ok() {
+                assert!(start.elapsed() < HANDOFF_TIMEOUT);
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
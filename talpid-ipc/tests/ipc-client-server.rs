@@ -6,6 +6,8 @@ extern crate jsonrpc_client_ipc;
 extern crate jsonrpc_core;
 #[macro_use]
 extern crate jsonrpc_macros;
+#[cfg(unix)]
+extern crate libc;
 extern crate talpid_ipc;
 extern crate tokio;
 extern crate uuid;
@@ -16,7 +18,7 @@ use futures::sync::oneshot;
 use futures::Future;
 
 use jsonrpc_client_core::{Error as ClientError, Transport};
-use jsonrpc_core::{Error, IoHandler};
+use jsonrpc_core::{Error, IoHandler, MetaIoHandler, Metadata, Value};
 use std::sync::{mpsc, Mutex};
 use std::time::Duration;
 
@@ -56,6 +58,34 @@ fn can_call_rpcs_on_server() {
     server.close_handle().close();
 }
 
+#[cfg(unix)]
+#[derive(Clone, Default)]
+struct CredentialsMeta(Option<talpid_ipc::PeerCredentials>);
+
+#[cfg(unix)]
+impl Metadata for CredentialsMeta {}
+
+#[cfg(unix)]
+#[test]
+fn passes_peer_credentials_to_metadata() {
+    let mut io = MetaIoHandler::<CredentialsMeta>::default();
+    io.add_method_with_meta("uid", |_, meta: CredentialsMeta| {
+        Ok(meta
+            .0
+            .map_or(Value::Null, |credentials| Value::from(credentials.uid)))
+    });
+    let server = talpid_ipc::IpcServer::start_with_peer_credentials(
+        io,
+        |_, credentials| CredentialsMeta(credentials),
+        new_ipc_path(),
+    ).unwrap();
+    let client = create_client(server.path().to_owned());
+
+    let uid: Option<u32> = client.call_method("uid", &()).wait().unwrap();
+    assert_eq!(uid, Some(unsafe { libc::getuid() }));
+    server.close_handle().close();
+}
+
 // TODO fix this test on Windows
 #[cfg(not(windows))]
 #[test]
@@ -70,14 +100,17 @@ fn create_server() -> (talpid_ipc::IpcServer, mpsc::Receiver<i64>) {
     let mut io = IoHandler::new();
     io.extend_with(rpc.to_delegate());
 
+    let server = talpid_ipc::IpcServer::start(io.into(), new_ipc_path()).unwrap();
+    (server, rx)
+}
+
+fn new_ipc_path() -> String {
     let uuid = uuid::Uuid::new_v4().to_string();
-    let ipc_path = if cfg!(windows) {
+    if cfg!(windows) {
         format!(r"\\.\pipe\ipc-test-{}", uuid)
     } else {
         format!("/tmp/ipc-test-{}", uuid)
-    };
-    let server = talpid_ipc::IpcServer::start(io.into(), ipc_path).unwrap();
-    (server, rx)
+    }
 }
 
 fn create_client(ipc_path: String) -> jsonrpc_client_core::ClientHandle {
@@ -50,6 +50,8 @@ pub enum BlockReason {
     StartTunnelError,
     /// No relay server matching the current filter parameters.
     NoMatchingRelay,
+    /// The account has no time left.
+    AccountExpired,
 }
 
 impl fmt::Display for BlockReason {
@@ -71,6 +73,7 @@ impl fmt::Display for BlockReason {
             BlockReason::SetSecurityPolicyError => "Failed to set security policy",
             BlockReason::StartTunnelError => "Failed to start connection to remote server",
             BlockReason::NoMatchingRelay => "No relay server matches the current settings",
+            BlockReason::AccountExpired => "The account has no time left",
         };
 
         write!(formatter, "{}", description)